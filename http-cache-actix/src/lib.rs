@@ -0,0 +1,364 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The actix-web middleware implementation for http-cache, caching the responses of an
+//! app's handlers.
+//!
+//! Responses are stored under the method and URL of the request, following the
+//! `Cache-Control` the handlers set. As for any shared cache, responses marked `private`
+//! or answering requests with an `Authorization` header aren't stored unless they allow
+//! it. Responses served from the cache carry an `Age` header.
+//!
+//! The body of every response to a request the cache handles is buffered in full before
+//! the response is returned, even if it ends up not being stored, since that's only
+//! decided once the response is complete. Requests the cache doesn't handle, such as
+//! `POST`s, are passed through and their responses streamed. Routes serving large or
+//! endless bodies, like downloads or server-sent events, should be bypassed the same way
+//! by returning [`CacheMode::NoStore`] for them from [`HttpCacheOptions::cache_mode_fn`]:
+//! ```no_run
+//! # use http_cache_actix::{CacheMode, HttpCacheOptions};
+//! # use std::sync::Arc;
+//! let options = HttpCacheOptions {
+//!     cache_mode_fn: Some(Arc::new(|parts: &http::request::Parts| {
+//!         if parts.uri.path().starts_with("/downloads/") {
+//!             CacheMode::NoStore
+//!         } else {
+//!             CacheMode::Default
+//!         }
+//!     })),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! The futures of actix-web services aren't `Send`, so the middleware drives a
+//! [`CacheTransaction`] instead of implementing [`Middleware`](http_cache::Middleware),
+//! and the options a transaction doesn't apply aren't applied here either.
+//! ```no_run
+//! use actix_web::{web, App, HttpResponse, HttpServer};
+//! use http_cache_actix::{Cache, CacheMode, CACacheManager, HttpCache, HttpCacheOptions};
+//!
+//! #[actix_web::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let cache = Cache::new(HttpCache {
+//!         mode: CacheMode::Default,
+//!         manager: CACacheManager::default(),
+//!         options: HttpCacheOptions::default(),
+//!     });
+//!     HttpServer::new(move || {
+//!         App::new().wrap(cache.clone()).route(
+//!             "/",
+//!             web::get().to(|| async {
+//!                 HttpResponse::Ok()
+//!                     .insert_header(("cache-control", "max-age=60"))
+//!                     .body("Hello")
+//!             }),
+//!         )
+//!     })
+//!     .bind(("127.0.0.1", 8080))?
+//!     .run()
+//!     .await
+//! }
+//! ```
+use std::{
+    fmt,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_web::{
+    body::{self, BoxBody, EitherBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::StatusCode,
+    Error, HttpRequest,
+};
+use http::{request, HeaderMap, HeaderName, HeaderValue, Request};
+use http_cache::{BoxError, HitOrMiss, XCACHE, XCACHELOOKUP, XCACHEREASON};
+use url::Url;
+
+pub use http_cache::{
+    Admission, CacheDecision, CacheEvent, CacheManager, CacheMetadata,
+    CacheMode, CacheOptions, CacheState, CacheTransaction, CorruptEntry,
+    DecodedBodies, Divergence, DynCacheManager, DynHttpCache, Encode,
+    EntryCost, EntryWeigher, Event, Generations, HeaderLimit, HeaderLimits,
+    HostCounters, HostStats, HttpCache, HttpCacheOptions, HttpResponse,
+    LookupOutcome, Namespace, PauseHandle, RevalidationHeaders,
+    RevalidationRateLimit, Step, StoreMetadata, StorePredicate,
+    UnexpectedEvent,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{
+    MokaCache, MokaCacheBuilder, MokaManager, MokaManagerBuilder,
+};
+
+#[cfg(feature = "manager-postgres")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-postgres")))]
+pub use http_cache::PostgresManager;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+pub use http_cache::{CacheConfig, ConfiguredManager, ManagerConfig};
+
+/// Error type for the failures of the wrapped service, whose errors are neither `Send`
+/// nor `Sync` and can't be passed to the cache as they are
+#[derive(Debug, Clone)]
+pub struct ServiceError(String);
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Service error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+/// Caches the responses of the services it wraps, with an [`HttpCache`] shared by all of
+/// them. Clones share the cache, so one can be passed to the `App` of every worker.
+#[derive(Debug)]
+pub struct Cache<T: CacheManager> {
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<T: CacheManager> Cache<T> {
+    /// Creates a new middleware caching with `cache`
+    pub fn new(cache: HttpCache<T>) -> Self {
+        Self { cache: Arc::new(cache) }
+    }
+}
+
+impl<T: CacheManager> Clone for Cache<T> {
+    fn clone(&self) -> Self {
+        Self { cache: self.cache.clone() }
+    }
+}
+
+impl<S, B, T> Transform<S, ServiceRequest> for Cache<T>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+    T: CacheManager,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CacheMiddleware<S, T>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CacheMiddleware {
+            service: Rc::new(service),
+            cache: self.cache.clone(),
+        }))
+    }
+}
+
+/// The service applying [`Cache`] to the service it wraps
+#[derive(Debug)]
+pub struct CacheMiddleware<S, T: CacheManager> {
+    service: Rc<S>,
+    cache: Arc<HttpCache<T>>,
+}
+
+fn internal_error(e: BoxError) -> Error {
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+// Cache keys are built from the URI, so it carries the scheme and host too
+fn request_parts(req: &ServiceRequest) -> Result<(request::Parts, Url), Error> {
+    let url = {
+        let info = req.connection_info();
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        Url::parse(&format!("{}://{}{}", info.scheme(), info.host(), path))
+            .map_err(actix_web::error::ErrorBadRequest)?
+    };
+    let mut converted = Request::builder()
+        .method(req.method().clone())
+        .uri(url.as_str())
+        .version(req.version())
+        .body(())
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let headers = converted.headers_mut();
+    for (name, value) in req.headers().iter() {
+        headers.append(name, value.clone());
+    }
+    Ok((converted.into_parts().0, url))
+}
+
+// Converts the response of the wrapped service to an [`HttpResponse`], buffering its body
+async fn convert_service_response<B: MessageBody>(
+    response: ServiceResponse<B>,
+    url: Url,
+) -> Result<(HttpRequest, HttpResponse), Error> {
+    let (req, res) = response.into_parts();
+    let mut headers = HeaderMap::new();
+    for (name, value) in res.headers().iter() {
+        headers.append(name, value.clone());
+    }
+    let status = res.status().as_u16();
+    let version = res.head().version.try_into().map_err(internal_error)?;
+    let body = body::to_bytes(res.into_body()).await.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e.into();
+        internal_error(Box::new(ServiceError(e.to_string())))
+    })?;
+    Ok((req, HttpResponse { body, headers, status, url, version }))
+}
+
+// Converts an [`HttpResponse`] to a buffered actix-web response
+fn convert_response(
+    req: HttpRequest,
+    mut response: HttpResponse,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let metadata = response.take_metadata();
+    let status = StatusCode::from_u16(response.status)
+        .map_err(|e| internal_error(Box::new(e)))?;
+    let mut converted =
+        actix_web::HttpResponse::with_body(status, BoxBody::new(response.body));
+    let headers = converted.headers_mut();
+    for (name, value) in &response.headers {
+        headers.append(name.clone(), value.clone());
+    }
+    if let Some(metadata) = metadata {
+        converted.extensions_mut().insert(CacheMetadata(metadata));
+    }
+    Ok(ServiceResponse::new(req, converted))
+}
+
+impl<S, B, T> Service<ServiceRequest> for CacheMiddleware<S, T>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+    T: CacheManager,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let (parts, url) = request_parts(&req)?;
+            // Requests the cache doesn't handle are passed through without buffering
+            let decision =
+                cache.explain(&parts, None).map_err(internal_error)?;
+            let bypass = matches!(
+                decision,
+                CacheDecision::Bypass(_) | CacheDecision::MethodNotCacheable
+            );
+            // Taken by the fetch, whose response hands the `HttpRequest` back
+            let mut req = Some(req);
+            let mut fetched_req = None;
+            let mut transaction = cache.transaction(parts);
+            let mut step = transaction.start().map_err(internal_error)?;
+            loop {
+                let event = match step {
+                    Step::Delete(key) => {
+                        cache
+                            .manager
+                            .delete(&key)
+                            .await
+                            .map_err(|e| internal_error(e.into()))?;
+                        Event::Deleted
+                    }
+                    Step::Lookup(key) => Event::LookedUp(
+                        cache
+                            .manager
+                            .get(&key)
+                            .await
+                            .map_err(|e| internal_error(e.into()))?,
+                    ),
+                    Step::Fetch(headers) => {
+                        let Some(mut sent) = req.take() else {
+                            return Err(internal_error(Box::new(
+                                ServiceError("request already sent".into()),
+                            )));
+                        };
+                        *sent.headers_mut() = headers.into();
+                        let res = service.call(sent).await?;
+                        if bypass {
+                            return bypass_response(&cache, decision, res);
+                        }
+                        let (http_req, response) =
+                            convert_service_response(res, url.clone()).await?;
+                        fetched_req = Some(http_req);
+                        Event::Fetched(response)
+                    }
+                    Step::Store { cache_key, response, policy } => {
+                        Event::Stored(
+                            cache
+                                .manager
+                                .put(cache_key, response, policy)
+                                .await
+                                .map_err(|e| internal_error(e.into()))?,
+                        )
+                    }
+                    Step::Respond(response) => {
+                        let http_req = match (req, fetched_req) {
+                            (Some(req), _) => req.into_parts().0,
+                            (None, Some(http_req)) => http_req,
+                            // Only a fetch takes the request
+                            (None, None) => unreachable!(),
+                        };
+                        return Ok(convert_response(http_req, response)?
+                            .map_into_right_body());
+                    }
+                };
+                step = transaction.next(event).map_err(internal_error)?;
+            }
+        })
+    }
+}
+
+// Marks a response that passed through the cache, as `HttpCache::run` does
+fn bypass_response<B, T: CacheManager>(
+    cache: &HttpCache<T>,
+    decision: CacheDecision,
+    mut res: ServiceResponse<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let miss = HeaderValue::from_str(HitOrMiss::MISS.to_string().as_ref())
+        .map_err(|e| internal_error(Box::new(e)))?;
+    let state = if cache.options.cache_state_header {
+        HeaderValue::from_str(CacheState::Bypass.to_string().as_ref())
+            .map_err(|e| internal_error(Box::new(e)))?
+    } else {
+        miss.clone()
+    };
+    let headers = res.headers_mut();
+    headers.insert(HeaderName::from_static(XCACHE), state);
+    headers.insert(HeaderName::from_static(XCACHELOOKUP), miss);
+    if cache.options.cache_reason_header {
+        headers.insert(
+            HeaderName::from_static(XCACHEREASON),
+            HeaderValue::from_str(decision.to_string().as_ref())
+                .map_err(|e| internal_error(Box::new(e)))?,
+        );
+    }
+    Ok(res.map_into_left_body())
+}
+
+#[cfg(test)]
+mod test;
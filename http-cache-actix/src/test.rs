@@ -0,0 +1,189 @@
+use crate::Cache;
+use std::{
+    convert::Infallible,
+    future::poll_fn,
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::{BodySize, MessageBody},
+    http::header::AGE,
+    test, web, App, HttpResponse as ActixResponse,
+};
+use http_cache::*;
+
+const GET: &str = "GET";
+
+const TEST_BODY: &[u8] = b"test";
+
+const CACHEABLE_PUBLIC: &str = "max-age=86400, public";
+
+const CACHEABLE_PRIVATE: &str = "max-age=86400, private";
+
+fn cache(manager: MokaManager) -> Cache<MokaManager> {
+    Cache::new(HttpCache {
+        mode: CacheMode::Default,
+        manager,
+        options: HttpCacheOptions::default(),
+    })
+}
+
+// A handler answering with `cache_control`, counting how often it was called
+fn handler(
+    cache_control: &'static str,
+    calls: Arc<AtomicUsize>,
+) -> actix_web::Route {
+    web::route().to(move || {
+        calls.fetch_add(1, Ordering::SeqCst);
+        async move {
+            ActixResponse::Ok()
+                .insert_header(("cache-control", cache_control))
+                .body(TEST_BODY)
+        }
+    })
+}
+
+#[actix_web::test]
+async fn default_mode() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let manager = MokaManager::default();
+    let app = test::init_service(
+        App::new()
+            .wrap(cache(manager.clone()))
+            .route("/", handler(CACHEABLE_PUBLIC, calls.clone())),
+    )
+    .await;
+
+    // Cold pass to load cache
+    let req = test::TestRequest::get().uri("/").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(
+        res.headers().get(XCACHE).unwrap().to_str()?,
+        HitOrMiss::MISS.to_string()
+    );
+    assert_eq!(test::read_body(res).await, TEST_BODY);
+
+    // Try to load cached object
+    let data = manager.get(&format!("{GET}:http://localhost:8080/")).await?;
+    assert!(data.is_some());
+
+    // Hot pass, served without calling the handler
+    let req = test::TestRequest::get().uri("/").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(
+        res.headers().get(XCACHE).unwrap().to_str()?,
+        HitOrMiss::HIT.to_string()
+    );
+    assert!(res.headers().contains_key(AGE));
+    assert_eq!(test::read_body(res).await, TEST_BODY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[actix_web::test]
+async fn private_not_stored() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let manager = MokaManager::default();
+    let app = test::init_service(
+        App::new()
+            .wrap(cache(manager.clone()))
+            .route("/", handler(CACHEABLE_PRIVATE, calls.clone())),
+    )
+    .await;
+
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(XCACHE).unwrap().to_str()?,
+            HitOrMiss::MISS.to_string()
+        );
+        assert_eq!(test::read_body(res).await, TEST_BODY);
+    }
+    let data = manager.get(&format!("{GET}:http://localhost:8080/")).await?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[actix_web::test]
+async fn unsafe_method_bypasses() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let manager = MokaManager::default();
+    let app = test::init_service(
+        App::new()
+            .wrap(cache(manager.clone()))
+            .route("/", handler(CACHEABLE_PUBLIC, calls.clone())),
+    )
+    .await;
+
+    // Stored by a GET, invalidated by the POST
+    let req = test::TestRequest::get().uri("/").to_request();
+    test::call_service(&app, req).await;
+    let req = test::TestRequest::post().uri("/").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(
+        res.headers().get(XCACHE).unwrap().to_str()?,
+        HitOrMiss::MISS.to_string()
+    );
+    assert_eq!(test::read_body(res).await, TEST_BODY);
+    let data = manager.get(&format!("{GET}:http://localhost:8080/")).await?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+// A body whose first chunk is `TEST_BODY` and that never ends
+struct Endless(bool);
+
+impl MessageBody for Endless {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Bytes, Infallible>>> {
+        if mem::replace(&mut self.0, true) {
+            Poll::Pending
+        } else {
+            Poll::Ready(Some(Ok(Bytes::from_static(TEST_BODY))))
+        }
+    }
+}
+
+#[actix_web::test]
+async fn bypassed_streams() -> Result<()> {
+    let cache = Cache::new(HttpCache {
+        mode: CacheMode::Default,
+        manager: MokaManager::default(),
+        options: HttpCacheOptions {
+            cache_mode_fn: Some(Arc::new(|_: &http::request::Parts| {
+                CacheMode::NoStore
+            })),
+            ..Default::default()
+        },
+    });
+    let app = test::init_service(App::new().wrap(cache).route(
+        "/",
+        web::get().to(|| async { ActixResponse::Ok().body(Endless(false)) }),
+    ))
+    .await;
+
+    // The response arrives although its body doesn't end
+    let req = test::TestRequest::get().uri("/").to_request();
+    let res = test::call_service(&app, req).await;
+    let mut body = Box::pin(res.into_body());
+    let chunk = poll_fn(|cx| body.as_mut().poll_next(cx)).await;
+    assert_eq!(chunk.and_then(|chunk| chunk.ok()).as_deref(), Some(TEST_BODY));
+    Ok(())
+}
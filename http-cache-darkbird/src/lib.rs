@@ -1,6 +1,6 @@
 mod error;
 
-use http_cache::{CacheManager, HttpResponse, Result};
+use http_cache::{BoxError, CacheManager, HttpResponse, Result};
 
 use std::{fmt, sync::Arc, time::SystemTime};
 
@@ -10,6 +10,7 @@ use darkbird::{
 };
 use http_cache_semantics::CachePolicy;
 use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
 
 /// Implements [`CacheManager`] with [`darkbird`](https://github.com/Rustixir/darkbird) as the backend.
 #[derive(Clone)]
@@ -18,6 +19,9 @@ pub struct DarkbirdManager {
     pub cache: Arc<Storage<String, Store>>,
     /// Whether full text search should be enabled
     pub full_text: bool,
+    // darkbird refuses to insert a key that is already stored, so replacing an entry is a
+    // delete and an insert that concurrent puts of the same key mustn't interleave
+    writes: Arc<Mutex<()>>,
 }
 
 impl fmt::Debug for DarkbirdManager {
@@ -98,6 +102,7 @@ impl DarkbirdManager {
         Ok(Self {
             cache: Arc::new(Storage::<String, Store>::open(options).await?),
             full_text,
+            writes: Arc::default(),
         })
     }
 
@@ -116,6 +121,8 @@ impl DarkbirdManager {
 
 #[async_trait::async_trait]
 impl CacheManager for DarkbirdManager {
+    type Error = BoxError;
+
     async fn get(
         &self,
         cache_key: &str,
@@ -139,11 +146,8 @@ impl CacheManager for DarkbirdManager {
             cache_key: cache_key.clone(),
             full_text: self.full_text,
         };
-        let mut exists = false;
-        if self.cache.lookup(&cache_key.to_string()).is_some() {
-            exists = true;
-        }
-        if exists {
+        let _write = self.writes.lock().await;
+        if self.cache.lookup(&cache_key).is_some() {
             self.delete(&cache_key).await?;
         }
         match self.cache.insert(cache_key, data).await {
@@ -40,7 +40,7 @@ async fn darkbird() -> Result<()> {
     let url = Url::parse("http://example.com")?;
     let manager = Arc::new(DarkbirdManager::new_with_defaults().await?);
     let http_res = HttpResponse {
-        body: TEST_BODY.to_vec(),
+        body: Bytes::from_static(TEST_BODY),
         headers: Default::default(),
         status: 200,
         url: url.clone(),
@@ -93,6 +93,44 @@ async fn darkbird() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_puts() -> Result<()> {
+    let url = Url::parse("http://example.com")?;
+    let manager = DarkbirdManager::new_with_defaults().await?;
+    let http_res = HttpResponse {
+        body: Bytes::from_static(TEST_BODY),
+        headers: Default::default(),
+        status: 200,
+        url: url.clone(),
+        version: HttpVersion::Http11,
+    };
+    let req = http::Request::get("http://example.com").body(())?;
+    let res = http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+    let policy = CachePolicy::new(&req, &res);
+
+    // Puts of the same key replace each other instead of failing as duplicates
+    let workers: Vec<_> = (0..8)
+        .map(|_| {
+            let manager = manager.clone();
+            let (http_res, policy) = (http_res.clone(), policy.clone());
+            tokio::spawn(async move {
+                for i in 0..500 {
+                    let cache_key = format!("{GET}:http://example.com/{i}");
+                    manager
+                        .put(cache_key, http_res.clone(), policy.clone())
+                        .await?;
+                }
+                Ok::<_, BoxError>(())
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.await??;
+    }
+    assert!(manager.get("GET:http://example.com/0").await?.is_some());
+    Ok(())
+}
+
 #[tokio::test]
 async fn default_mode() -> Result<()> {
     let mock_server = MockServer::start().await;
@@ -150,6 +188,32 @@ async fn default_mode_with_options() -> Result<()> {
                 }),
                 cache_mode_fn: None,
                 cache_bust: None,
+                modify_response: None,
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
             },
         }))
         .build();
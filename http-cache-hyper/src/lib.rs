@@ -0,0 +1,453 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The hyper 1.x implementation for http-cache, a tower layer caching the responses of
+//! a client such as `hyper_util::client::legacy::Client`.
+//!
+//! It works with any tower service taking and returning `http` 1.x messages whose
+//! bodies implement the `http-body` 1.x traits, so it isn't limited to the hyper-util
+//! client. The layer fails with a [`BoxError`] when the cache or the inner service does.
+//!
+//! The body of every response to a request the cache handles is buffered in full before
+//! the response is returned, even if it ends up not being stored, since that's only
+//! decided once the response is complete. Requests the cache doesn't handle, such as
+//! `POST`s, are passed through and their responses streamed. Requests for large or
+//! endless bodies, like downloads or server-sent events, should be bypassed the same way
+//! by returning [`CacheMode::NoStore`] for them from [`HttpCacheOptions::cache_mode_fn`]:
+//! ```no_run
+//! # use http_cache_hyper::{CacheMode, HttpCacheOptions, Parts};
+//! # use std::sync::Arc;
+//! let options = HttpCacheOptions {
+//!     cache_mode_fn: Some(Arc::new(|parts: &Parts| {
+//!         if parts.uri.path().starts_with("/downloads/") {
+//!             CacheMode::NoStore
+//!         } else {
+//!             CacheMode::Default
+//!         }
+//!     })),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! http-cache itself is built on `http` 0.2, so the [`Parts`] passed to the options and
+//! the [`HttpResponse`]s stored are `http` 0.2 types, converted from and to the `http`
+//! 1.x messages the service handles.
+//! ```no_run
+//! use bytes::Bytes;
+//! use http_body_util::Empty;
+//! use http_cache_hyper::{CACacheManager, CacheMode, HttpCache, HttpCacheLayer, HttpCacheOptions};
+//! use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+//! use tower::{Layer, ServiceExt};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), http_cache::BoxError> {
+//!     let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+//!     let client = HttpCacheLayer::new(HttpCache {
+//!         mode: CacheMode::Default,
+//!         manager: CACacheManager::default(),
+//!         options: HttpCacheOptions::default(),
+//!     })
+//!     .layer(client);
+//!     let req = http::Request::get("http://example.com/").body(Empty::new())?;
+//!     let res = client.oneshot(req).await?;
+//!     println!("{}", res.status());
+//!     Ok(())
+//! }
+//! ```
+use std::{
+    convert::TryInto,
+    future::{poll_fn, Future},
+    mem,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+use http::{
+    header::{CACHE_CONTROL, HOST},
+    HeaderMap, HeaderName, HeaderValue, Method, Request, Response, Version,
+};
+pub use http_02::request::Parts;
+use http_body::Body as HttpBody;
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full};
+use http_cache::{
+    BoxError, DetachedFetch, HitOrMiss, Middleware, Result, XCACHE,
+    XCACHELOOKUP, XCACHEREASON,
+};
+use http_cache_semantics::CachePolicy;
+use tower_layer::Layer;
+use tower_service::Service;
+use url::Url;
+
+pub use http_cache::{
+    Admission, BackgroundRevalidation, CacheDecision, CacheEvent, CacheManager,
+    CacheMetadata, CacheMode, CacheOptions, CacheRevalidationRequest,
+    CacheState, CacheTransaction, CorruptEntry, DecodedBodies, Divergence,
+    DynCacheManager, DynHttpCache, Encode, EntryCost, EntryWeigher, Event,
+    Generations, HeaderLimit, HeaderLimits, HostCounters, HostStats, HttpCache,
+    HttpCacheOptions, HttpResponse, LookupOutcome, MaxStale, Namespace,
+    PauseHandle, RefreshAhead, RevalidationHeaders, RevalidationRateLimit,
+    StaleOnTimeout, Step, StoreMetadata, StorePredicate, UnexpectedEvent,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{
+    MokaCache, MokaCacheBuilder, MokaManager, MokaManagerBuilder,
+};
+
+#[cfg(feature = "manager-postgres")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-postgres")))]
+pub use http_cache::PostgresManager;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+pub use http_cache::{CacheConfig, ConfiguredManager, ManagerConfig};
+
+/// The body of the responses returned by [`HttpCacheService`]. Responses to requests the
+/// cache handles are buffered, whether or not they're stored, and responses to requests it
+/// bypasses are streamed from the inner service.
+pub type ResponseBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// Applies [`HttpCacheService`] to a service, with an [`HttpCache`] shared by every
+/// service it wraps
+#[derive(Debug)]
+pub struct HttpCacheLayer<T: CacheManager> {
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<T: CacheManager> HttpCacheLayer<T> {
+    /// Creates a new layer caching with `cache`
+    pub fn new(cache: HttpCache<T>) -> Self {
+        Self { cache: Arc::new(cache) }
+    }
+}
+
+impl<T: CacheManager> Clone for HttpCacheLayer<T> {
+    fn clone(&self) -> Self {
+        Self { cache: self.cache.clone() }
+    }
+}
+
+impl<S, T: CacheManager> Layer<S> for HttpCacheLayer<T> {
+    type Service = HttpCacheService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpCacheService { inner, cache: self.cache.clone() }
+    }
+}
+
+/// Caches the responses of the inner service, see [`HttpCacheLayer`]
+#[derive(Debug)]
+pub struct HttpCacheService<S, T: CacheManager> {
+    inner: S,
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<S: Clone, T: CacheManager> Clone for HttpCacheService<S, T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), cache: self.cache.clone() }
+    }
+}
+
+/// Implements ['Middleware'] for hyper 1.x services
+pub(crate) struct HyperMiddleware<S, B> {
+    pub req: Request<B>,
+    pub url: Url,
+    pub service: S,
+}
+
+// Client requests carry the whole URL, the `Host` header completes the others
+fn request_url<B>(req: &Request<B>) -> Result<Url> {
+    let uri = req.uri();
+    if uri.scheme().is_some() {
+        return Ok(Url::parse(&uri.to_string())?);
+    }
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| uri.authority().map(|authority| authority.as_str()))
+        .unwrap_or("localhost");
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Ok(Url::parse(&format!("http://{host}{path}"))?)
+}
+
+fn to_http02_version(version: Version) -> http_02::Version {
+    match version {
+        Version::HTTP_09 => http_02::Version::HTTP_09,
+        Version::HTTP_10 => http_02::Version::HTTP_10,
+        Version::HTTP_2 => http_02::Version::HTTP_2,
+        Version::HTTP_3 => http_02::Version::HTTP_3,
+        _ => http_02::Version::HTTP_11,
+    }
+}
+
+fn from_http02_version(version: http_02::Version) -> Version {
+    match version {
+        http_02::Version::HTTP_09 => Version::HTTP_09,
+        http_02::Version::HTTP_10 => Version::HTTP_10,
+        http_02::Version::HTTP_2 => Version::HTTP_2,
+        http_02::Version::HTTP_3 => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    }
+}
+
+fn to_http02_headers(headers: &HeaderMap) -> Result<http_02::HeaderMap> {
+    let mut converted = http_02::HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        converted.append(
+            http_02::HeaderName::from_bytes(name.as_str().as_bytes())?,
+            http_02::HeaderValue::from_bytes(value.as_bytes())?,
+        );
+    }
+    Ok(converted)
+}
+
+fn from_http02_headers(headers: &http_02::HeaderMap) -> Result<HeaderMap> {
+    let mut converted = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        converted.append(
+            HeaderName::from_bytes(name.as_str().as_bytes())?,
+            HeaderValue::from_bytes(value.as_bytes())?,
+        );
+    }
+    Ok(converted)
+}
+
+#[async_trait::async_trait]
+impl<S, ReqBody, ResBody> Middleware for HyperMiddleware<S, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    ReqBody: Default + Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    fn is_method_get_head(&self) -> bool {
+        self.req.method() == Method::GET || self.req.method() == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        Ok(CachePolicy::new(&self.parts()?, &response.parts()?))
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            SystemTime::now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        let headers = self.req.headers_mut();
+        for name in parts.headers.keys() {
+            let converted = HeaderName::from_bytes(name.as_str().as_bytes())?;
+            headers.remove(&converted);
+            for value in parts.headers.get_all(name) {
+                headers.append(
+                    &converted,
+                    HeaderValue::from_bytes(value.as_bytes())?,
+                );
+            }
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.req
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
+        Ok(())
+    }
+    fn mark_revalidation(
+        &mut self,
+        revalidation: CacheRevalidationRequest,
+    ) -> Result<()> {
+        self.req.extensions_mut().insert(revalidation);
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        // Cache keys are built from the URI, so it carries the host too
+        let mut converted = http_02::Request::builder()
+            .method(self.req.method().as_str())
+            .uri(self.url.as_str())
+            .version(to_http02_version(self.req.version()))
+            .body(())?;
+        *converted.headers_mut() = to_http02_headers(self.req.headers())?;
+        let mut parts = converted.into_parts().0;
+        if let Some(max_stale) = self.req.extensions().get::<MaxStale>() {
+            parts.extensions.insert(*max_stale);
+        }
+        Ok(parts)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.req.method().as_ref().to_string())
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        let req = self.take_request();
+        fetch(&mut self.service, req, self.url.clone()).await
+    }
+    fn detached_fetch(&mut self) -> Result<Option<DetachedFetch>> {
+        // A clone of the inner service sends it, so it doesn't borrow the middleware
+        let req = self.take_request();
+        let mut service = self.service.clone();
+        let url = self.url.clone();
+        Ok(Some(Box::pin(async move { fetch(&mut service, req, url).await })))
+    }
+}
+
+impl<S, B: Default> HyperMiddleware<S, B> {
+    // The body and extensions move to the request sent to the inner service
+    fn take_request(&mut self) -> Request<B> {
+        let mut req = Request::new(mem::take(self.req.body_mut()));
+        *req.method_mut() = self.req.method().clone();
+        *req.uri_mut() = self.req.uri().clone();
+        *req.version_mut() = self.req.version();
+        *req.headers_mut() = self.req.headers().clone();
+        *req.extensions_mut() = mem::take(self.req.extensions_mut());
+        req
+    }
+}
+
+async fn fetch<S, ReqBody, ResBody>(
+    service: &mut S,
+    req: Request<ReqBody>,
+    url: Url,
+) -> Result<HttpResponse>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: Into<BoxError>,
+    ResBody: HttpBody<Data = Bytes>,
+    ResBody::Error: Into<BoxError>,
+{
+    poll_fn(|cx| service.poll_ready(cx)).await.map_err(Into::into)?;
+    let res = service.call(req).await.map_err(Into::into)?;
+    let (parts, body) = res.into_parts();
+    Ok(HttpResponse {
+        body: body.collect().await.map_err(Into::into)?.to_bytes(),
+        headers: to_http02_headers(&parts.headers)?,
+        status: parts.status.into(),
+        url,
+        version: to_http02_version(parts.version).try_into()?,
+    })
+}
+
+// Converts an [`HttpResponse`] to a buffered [`Response`]
+fn convert_response(
+    mut response: HttpResponse,
+) -> Result<Response<ResponseBody>> {
+    let metadata = response.take_metadata();
+    let body = Full::new(response.body).map_err(|never| match never {});
+    let mut converted = Response::builder()
+        .status(response.status)
+        .version(from_http02_version(response.version.into()))
+        .body(body.boxed_unsync())?;
+    *converted.headers_mut() = from_http02_headers(&response.headers)?;
+    if let Some(metadata) = metadata {
+        converted.extensions_mut().insert(CacheMetadata(metadata));
+    }
+    Ok(converted)
+}
+
+impl<S, T, ReqBody, ResBody> Service<Request<ReqBody>>
+    for HttpCacheService<S, T>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    T: CacheManager,
+    ReqBody: Default + Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // The clone might not be ready, the service that was polled handles the request
+        let clone = self.inner.clone();
+        let service = mem::replace(&mut self.inner, clone);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let url = request_url(&req)?;
+            let mut middleware = HyperMiddleware { req, url, service };
+            if cache.can_cache_request(&middleware)? {
+                let res = cache.run(middleware).await?;
+                return convert_response(res);
+            }
+            cache.run_no_cache(&mut middleware).await?;
+            let reason = if cache.options.cache_reason_header {
+                Some(cache.explain(&middleware.parts()?, None)?)
+            } else {
+                None
+            };
+            if let Some(refresh_header) = &cache.options.refresh_header {
+                middleware.req.headers_mut().remove(refresh_header.as_str());
+            }
+            let res = middleware
+                .service
+                .call(middleware.req)
+                .await
+                .map_err(Into::into)?;
+            let mut res =
+                res.map(|body| body.map_err(Into::into).boxed_unsync());
+            let miss =
+                HeaderValue::from_str(HitOrMiss::MISS.to_string().as_ref())?;
+            let state = if cache.options.cache_state_header {
+                HeaderValue::from_str(CacheState::Bypass.to_string().as_ref())?
+            } else {
+                miss.clone()
+            };
+            res.headers_mut().insert(XCACHE, state);
+            res.headers_mut().insert(XCACHELOOKUP, miss);
+            if let Some(reason) = reason {
+                res.headers_mut().insert(
+                    XCACHEREASON,
+                    HeaderValue::from_str(reason.to_string().as_ref())?,
+                );
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,190 @@
+use crate::HttpCacheLayer;
+use std::{
+    convert::Infallible,
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use http::{header::AGE, Request, Response};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full};
+use http_cache::*;
+use hyper::{server::conn::http1, service::service_fn};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::{TokioExecutor, TokioIo},
+};
+use tokio::net::TcpListener;
+use tower::{Layer, ServiceExt};
+
+const GET: &str = "GET";
+
+const TEST_BODY: &[u8] = b"test";
+
+const CACHEABLE_PUBLIC: &str = "max-age=86400, public";
+
+const CACHEABLE_PRIVATE: &str = "max-age=86400, private";
+
+type TestClient = Client<HttpConnector, Full<Bytes>>;
+
+// Answers every request with `cache_control`, counting them. Returns the URL to request.
+async fn server(
+    cache_control: &'static str,
+    calls: Arc<AtomicUsize>,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { return };
+            let calls = calls.clone();
+            let service = service_fn(move |_req| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header("cache-control", cache_control)
+                            .body(Full::new(Bytes::from_static(TEST_BODY)))
+                            .unwrap(),
+                    )
+                }
+            });
+            tokio::spawn(
+                http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service),
+            );
+        }
+    });
+    url
+}
+
+fn client() -> TestClient {
+    Client::builder(TokioExecutor::new()).build_http()
+}
+
+fn cache(manager: MokaManager) -> HttpCacheLayer<MokaManager> {
+    HttpCacheLayer::new(HttpCache {
+        mode: CacheMode::Default,
+        manager,
+        options: HttpCacheOptions::default(),
+    })
+}
+
+fn request(method: &str, url: &str) -> Request<Full<Bytes>> {
+    Request::builder().method(method).uri(url).body(Full::default()).unwrap()
+}
+
+async fn body(res: Response<crate::ResponseBody>) -> Result<Bytes> {
+    Ok(res.into_body().collect().await?.to_bytes())
+}
+
+#[tokio::test]
+async fn default_mode() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let url = server(CACHEABLE_PUBLIC, calls.clone()).await;
+    let manager = MokaManager::default();
+    let service = cache(manager.clone()).layer(client());
+
+    // Cold pass to load cache
+    let res = service.clone().oneshot(request(GET, &url)).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::MISS.to_string());
+    assert_eq!(body(res).await?, TEST_BODY);
+
+    // Try to load cached object
+    let data = manager.get(&format!("{GET}:{url}")).await?;
+    assert!(data.is_some());
+
+    // Hot pass, served without sending the request
+    let res = service.oneshot(request(GET, &url)).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::HIT.to_string());
+    assert!(res.headers().contains_key(AGE));
+    assert_eq!(body(res).await?, TEST_BODY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn private_not_stored() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let url = server(CACHEABLE_PRIVATE, calls.clone()).await;
+    let manager = MokaManager::default();
+    let service = cache(manager.clone()).layer(client());
+
+    for _ in 0..2 {
+        let res = service.clone().oneshot(request(GET, &url)).await?;
+        assert_eq!(res.headers()[XCACHE], HitOrMiss::MISS.to_string());
+        assert_eq!(body(res).await?, TEST_BODY);
+    }
+    let data = manager.get(&format!("{GET}:{url}")).await?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn unsafe_method_bypasses() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let url = server(CACHEABLE_PUBLIC, calls.clone()).await;
+    let manager = MokaManager::default();
+    let service = cache(manager.clone()).layer(client());
+
+    let res = service.oneshot(request("POST", &url)).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::MISS.to_string());
+    assert_eq!(body(res).await?, TEST_BODY);
+    let data = manager.get(&format!("POST:{url}")).await?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+// A body whose first chunk is `TEST_BODY` and that never ends
+struct Endless(bool);
+
+impl http_body::Body for Endless {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Infallible>>> {
+        if mem::replace(&mut self.0, true) {
+            Poll::Pending
+        } else {
+            Poll::Ready(Some(Ok(Frame::data(Bytes::from_static(TEST_BODY)))))
+        }
+    }
+}
+
+#[tokio::test]
+async fn bypassed_streams() -> Result<()> {
+    let inner = tower::service_fn(|_req: Request<Full<Bytes>>| async {
+        Ok::<_, Infallible>(Response::new(Endless(false)))
+    });
+    let service = HttpCacheLayer::new(HttpCache {
+        mode: CacheMode::Default,
+        manager: MokaManager::default(),
+        options: HttpCacheOptions {
+            cache_mode_fn: Some(Arc::new(|_: &crate::Parts| {
+                CacheMode::NoStore
+            })),
+            ..Default::default()
+        },
+    })
+    .layer(inner);
+
+    // The response arrives although its body doesn't end
+    let res = service.oneshot(request(GET, "http://example.com/")).await?;
+    let frame = res.into_body().frame().await.transpose()?;
+    assert_eq!(
+        frame.and_then(|frame| frame.into_data().ok()).as_deref(),
+        Some(TEST_BODY)
+    );
+    Ok(())
+}
@@ -33,11 +33,14 @@ const CACHEABLE_PUBLIC: &str = "max-age=86400, public";
 #[tokio::test]
 async fn moka() -> Result<()> {
     // Added to test custom Debug impl
-    assert_eq!(format!("{:?}", MokaManager::default()), "MokaManager { .. }",);
+    assert_eq!(
+        format!("{:?}", MokaManager::default()),
+        "MokaArcManager { .. }",
+    );
     let url = Url::parse("http://example.com")?;
     let manager = Arc::new(MokaManager::default());
     let http_res = HttpResponse {
-        body: TEST_BODY.to_vec(),
+        body: Bytes::from_static(TEST_BODY),
         headers: Default::default(),
         status: 200,
         url: url.clone(),
@@ -109,6 +112,32 @@ async fn default_mode_with_options() -> Result<()> {
                 }),
                 cache_mode_fn: None,
                 cache_bust: None,
+                modify_response: None,
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
             },
         }))
         .build();
@@ -1,4 +1,4 @@
-use http_cache::{CacheManager, HttpResponse, Result};
+use http_cache::{BoxError, CacheManager, CorruptEntry, HttpResponse, Result};
 
 use std::{fmt, sync::Arc};
 
@@ -41,12 +41,14 @@ impl QuickManager {
 
 #[async_trait::async_trait]
 impl CacheManager for QuickManager {
+    type Error = BoxError;
+
     async fn get(
         &self,
         cache_key: &str,
     ) -> Result<Option<(HttpResponse, CachePolicy)>> {
         let store: Store = match self.cache.get(cache_key) {
-            Some(d) => bincode::deserialize(&d)?,
+            Some(d) => bincode::deserialize(&d).map_err(CorruptEntry::new)?,
             None => return Ok(None),
         };
         Ok(Some((store.response, store.policy)))
@@ -0,0 +1,85 @@
+//! Serves a cacache store to `RemoteManager`s.
+//!
+//! ```sh
+//! http-cache-server --cache-dir ./http-cacache --unix /tmp/http-cache.sock
+//! http-cache-server --cache-dir ./http-cacache --tcp 127.0.0.1:7070
+//! ```
+use http_cache::CACacheManager;
+
+use std::{env, path::PathBuf, process};
+
+use tokio::net::TcpListener;
+
+const USAGE: &str =
+    "usage: http-cache-server [--cache-dir DIR] (--unix PATH | --tcp ADDR)";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    process::exit(2)
+}
+
+fn accept_failed(e: &std::io::Error) {
+    eprintln!("failed to accept a connection: {e}");
+}
+
+#[tokio::main]
+async fn main() {
+    let mut cache_dir = PathBuf::from("./http-cacache");
+    let mut unix = None;
+    let mut tcp = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| fail(&format!("{arg} needs a value")))
+        };
+        match arg.as_str() {
+            "--cache-dir" => cache_dir = PathBuf::from(value()),
+            "--unix" => unix = Some(PathBuf::from(value())),
+            "--tcp" => tcp = Some(value()),
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => fail(&format!("unknown argument {arg}")),
+        }
+    }
+    let manager = CACacheManager { path: cache_dir, ..Default::default() };
+    let served = match (unix, tcp) {
+        (Some(path), None) => serve_unix(path, manager).await,
+        (None, Some(addr)) => match TcpListener::bind(&addr).await {
+            Ok(listener) => {
+                eprintln!("serving on {addr}");
+                http_cache_remote::serve_tcp(listener, manager, accept_failed)
+                    .await
+            }
+            Err(e) => Err(e),
+        },
+        _ => fail("exactly one of --unix and --tcp is required"),
+    };
+    if let Err(e) = served {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    path: PathBuf,
+    manager: CACacheManager,
+) -> std::io::Result<()> {
+    // A socket left behind by a previous run would make binding fail
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    eprintln!("serving on {}", path.display());
+    http_cache_remote::serve_unix(listener, manager, accept_failed).await
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(
+    _path: PathBuf,
+    _manager: CACacheManager,
+) -> std::io::Result<()> {
+    fail("--unix is only supported on Unix")
+}
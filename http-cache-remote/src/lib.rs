@@ -0,0 +1,478 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! Shares one cache between many processes, such as short-lived CLI invocations on one
+//! machine or the workers of a build farm.
+//!
+//! A server, usually the `http-cache-server` binary, owns the cache and the
+//! [`RemoteManager`] client forwards each [`CacheManager`] operation to it over TCP or a
+//! Unix socket. Every operation uses its own connection, so a manager can be used from
+//! any number of tasks. The protocol has no authentication, only listen where every
+//! client may read and write the cache.
+//!
+//! The server also relays invalidations between the [`RemoteInvalidationBus`]es connected
+//! to it, which lets instances keeping a local tier in front of the shared cache evict a
+//! purged key everywhere, see [`InvalidatingManager`](http_cache::InvalidatingManager).
+//!
+//! ```no_run
+//! use http_cache_remote::{Endpoint, RemoteManager};
+//!
+//! let manager = RemoteManager::new(Endpoint::Tcp("127.0.0.1:7070".parse().unwrap()));
+//! ```
+use http_cache::{
+    BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse,
+    InvalidationBus, Result,
+};
+
+use std::{fmt, io, net::SocketAddr, sync::Arc, time::Duration};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use http_cache_semantics::CachePolicy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, Mutex},
+    time,
+};
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+// Frames larger than this are refused. Frames are read as their bytes arrive, so a
+// length prefix alone never allocates this much.
+const MAX_FRAME: u64 = 64 << 20;
+
+// How long to wait before accepting again after accepting a connection failed, e.g.
+// because the process ran out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Invalidations a slow subscriber can fall behind by before it misses some.
+const INVALIDATION_BACKLOG: usize = 1024;
+
+/// Where the cache server listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A TCP address
+    Tcp(SocketAddr),
+    /// The path of a Unix socket
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// An error reported by the cache server, such as a failure of its manager.
+#[derive(Debug, Clone)]
+pub struct RemoteError {
+    message: String,
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cache server error: {}", self.message)
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+#[derive(Debug, Deserialize, Serialize)]
+enum Request {
+    Get { cache_key: String },
+    Put { cache_key: String, entry: Box<(HttpResponse, CachePolicy)> },
+    Delete { cache_key: String },
+    Clear,
+    List,
+    Stats,
+    Shutdown,
+    Publish { cache_key: String },
+    Subscribe,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+enum Reply {
+    Entry(Option<Box<(HttpResponse, CachePolicy)>>),
+    Entries(Vec<CachedEntry>),
+    Stats(CacheStats),
+    Done,
+    Invalidated(String),
+    Error(String),
+}
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Connection for S {}
+
+async fn connect(endpoint: &Endpoint) -> io::Result<Box<dyn Connection>> {
+    Ok(match endpoint {
+        Endpoint::Tcp(addr) => Box::new(TcpStream::connect(addr).await?),
+        #[cfg(unix)]
+        Endpoint::Unix(path) => Box::new(UnixStream::connect(path).await?),
+    })
+}
+
+async fn write_frame<S, T>(stream: &mut S, value: &T) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let bytes = bincode::serialize(value)?;
+    stream.write_u64(u64::try_from(bytes.len())?).await?;
+    stream.write_all(&bytes).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+// Returns `None` if the stream ended before a new frame.
+async fn read_frame<S, T>(stream: &mut S) -> Result<Option<T>>
+where
+    S: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let len = match stream.read_u64().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_FRAME {
+        return Err(format!("frame of {len} bytes is too large").into());
+    }
+    let mut bytes = Vec::new();
+    (&mut *stream).take(len).read_to_end(&mut bytes).await?;
+    if u64::try_from(bytes.len())? != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Some(bincode::deserialize(&bytes)?))
+}
+
+/// Implements [`CacheManager`] by forwarding every operation to a cache server, see
+/// [`serve_tcp`] and [`serve_unix`].
+///
+/// That includes [`shutdown`](CacheManager::shutdown), which shuts down the manager of the
+/// server for every client, e.g. to apply the queue of a write-behind manager before the
+/// server stops. Clients that exit while others keep using the server shouldn't call it.
+#[derive(Debug, Clone)]
+pub struct RemoteManager {
+    endpoint: Endpoint,
+}
+
+impl RemoteManager {
+    /// Creates a new manager using the server listening at `endpoint`. No connection is
+    /// made until the first operation.
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self { endpoint }
+    }
+
+    async fn call(&self, request: &Request) -> Result<Reply> {
+        call(&self.endpoint, request).await
+    }
+}
+
+async fn call(endpoint: &Endpoint, request: &Request) -> Result<Reply> {
+    match exchange(&mut connect(endpoint).await?, request).await? {
+        Reply::Error(message) => Err(Box::new(RemoteError { message })),
+        reply => Ok(reply),
+    }
+}
+
+async fn exchange<S>(stream: &mut S, request: &Request) -> Result<Reply>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_frame(stream, request).await?;
+    read_frame(stream)
+        .await?
+        .ok_or_else(|| "cache server closed the connection".into())
+}
+
+fn unexpected(reply: Reply) -> BoxError {
+    format!("unexpected reply from cache server: {reply:?}").into()
+}
+
+#[async_trait::async_trait]
+impl CacheManager for RemoteManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        match self
+            .call(&Request::Get { cache_key: cache_key.to_string() })
+            .await?
+        {
+            Reply::Entry(entry) => Ok(entry.map(|entry| *entry)),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let request = Request::Put {
+            cache_key,
+            entry: Box::new((response.clone(), policy)),
+        };
+        match self.call(&request).await? {
+            Reply::Done => Ok(response),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        match self
+            .call(&Request::Delete { cache_key: cache_key.to_string() })
+            .await?
+        {
+            Reply::Done => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        match self.call(&Request::Shutdown).await? {
+            Reply::Done => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn clear(&self) -> Result<()> {
+        match self.call(&Request::Clear).await? {
+            Reply::Done => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        match self.call(&Request::List).await? {
+            Reply::Entries(entries) => Ok(entries),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        match self.call(&Request::Stats).await? {
+            Reply::Stats(stats) => Ok(stats),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+/// Implements [`InvalidationBus`] through a cache server, which relays every published key
+/// to the buses connected to it.
+///
+/// The bus keeps a connection open to receive keys. If it fails, [`receive`] returns the
+/// error and the next call reconnects, keys published in between are missed.
+///
+/// [`receive`]: InvalidationBus::receive
+pub struct RemoteInvalidationBus {
+    endpoint: Endpoint,
+    subscription: Mutex<Option<Box<dyn Connection>>>,
+}
+
+impl fmt::Debug for RemoteInvalidationBus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RemoteInvalidationBus")
+            .field("endpoint", &self.endpoint)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RemoteInvalidationBus {
+    /// Connects to the server listening at `endpoint`. Keys published from then on are
+    /// received.
+    pub async fn connect(endpoint: Endpoint) -> Result<Self> {
+        let subscription = subscribe(&endpoint).await?;
+        Ok(Self { endpoint, subscription: Mutex::new(Some(subscription)) })
+    }
+}
+
+async fn subscribe(endpoint: &Endpoint) -> Result<Box<dyn Connection>> {
+    let mut stream = connect(endpoint).await?;
+    // The server only acknowledges once it relays keys to this connection
+    match exchange(&mut stream, &Request::Subscribe).await? {
+        Reply::Done => Ok(stream),
+        Reply::Error(message) => Err(Box::new(RemoteError { message })),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+#[async_trait::async_trait]
+impl InvalidationBus for RemoteInvalidationBus {
+    async fn publish(&self, cache_key: &str) -> Result<()> {
+        let request = Request::Publish { cache_key: cache_key.to_string() };
+        match call(&self.endpoint, &request).await? {
+            Reply::Done => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn receive(&self) -> Result<Option<String>> {
+        let mut subscription = self.subscription.lock().await;
+        let stream = match subscription.as_mut() {
+            Some(stream) => stream,
+            None => subscription.insert(subscribe(&self.endpoint).await?),
+        };
+        let frame = read_frame(stream).await;
+        match frame {
+            Ok(Some(Reply::Invalidated(cache_key))) => Ok(Some(cache_key)),
+            Ok(None) => Ok(None),
+            Ok(Some(reply)) => {
+                *subscription = None;
+                Err(unexpected(reply))
+            }
+            Err(e) => {
+                *subscription = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+async fn handle<S, T>(
+    mut stream: S,
+    manager: Arc<T>,
+    invalidations: broadcast::Sender<String>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: CacheManager,
+{
+    while let Some(request) = read_frame(&mut stream).await? {
+        let reply: Result<Reply> = match request {
+            Request::Get { cache_key } => manager
+                .get(&cache_key)
+                .await
+                .map(|entry| Reply::Entry(entry.map(Box::new)))
+                .map_err(Into::into),
+            Request::Put { cache_key, entry } => {
+                let (response, policy) = *entry;
+                manager
+                    .put(cache_key, response, policy)
+                    .await
+                    .map(|_| Reply::Done)
+                    .map_err(Into::into)
+            }
+            Request::Delete { cache_key } => manager
+                .delete(&cache_key)
+                .await
+                .map(|_| Reply::Done)
+                .map_err(Into::into),
+            Request::Clear => manager.clear().await.map(|_| Reply::Done),
+            Request::List => manager.list().await.map(Reply::Entries),
+            Request::Stats => manager.stats().await.map(Reply::Stats),
+            Request::Shutdown => manager
+                .shutdown()
+                .await
+                .map(|_| Reply::Done)
+                .map_err(Into::into),
+            Request::Publish { cache_key } => {
+                // Having no subscribers isn't an error
+                invalidations.send(cache_key).ok();
+                Ok(Reply::Done)
+            }
+            Request::Subscribe => {
+                let receiver = invalidations.subscribe();
+                write_frame(&mut stream, &Reply::Done).await?;
+                return relay(stream, receiver).await;
+            }
+        };
+        let reply = reply.unwrap_or_else(|e| Reply::Error(e.to_string()));
+        write_frame(&mut stream, &reply).await?;
+    }
+    Ok(())
+}
+
+// Forwards invalidations to a subscribed connection until it or the server goes away.
+async fn relay<S>(
+    mut stream: S,
+    mut receiver: broadcast::Receiver<String>,
+) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    loop {
+        match receiver.recv().await {
+            Ok(cache_key) => {
+                write_frame(&mut stream, &Reply::Invalidated(cache_key)).await?
+            }
+            // Keys that fell out of the backlog are lost, as documented on the bus
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Serves `manager` to [`RemoteManager`]s and relays invalidations between
+/// [`RemoteInvalidationBus`]es connecting to `listener`, handling each connection on its
+/// own task. Never returns: a connection that fails is dropped and a failure to accept
+/// one is passed to `on_error`, e.g. to log it, before accepting again.
+pub async fn serve_tcp<T: CacheManager>(
+    listener: TcpListener,
+    manager: T,
+    on_error: impl Fn(&io::Error),
+) -> io::Result<()> {
+    let manager = Arc::new(manager);
+    let (invalidations, _) = broadcast::channel(INVALIDATION_BACKLOG);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(
+                    stream,
+                    manager.clone(),
+                    invalidations.clone(),
+                ));
+            }
+            Err(e) => {
+                on_error(&e);
+                time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Serves `manager` to [`RemoteManager`]s and relays invalidations between
+/// [`RemoteInvalidationBus`]es connecting to `listener`, handling each connection on its
+/// own task. Never returns: a connection that fails is dropped and a failure to accept
+/// one is passed to `on_error`, e.g. to log it, before accepting again.
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+pub async fn serve_unix<T: CacheManager>(
+    listener: UnixListener,
+    manager: T,
+    on_error: impl Fn(&io::Error),
+) -> io::Result<()> {
+    let manager = Arc::new(manager);
+    let (invalidations, _) = broadcast::channel(INVALIDATION_BACKLOG);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(
+                    stream,
+                    manager.clone(),
+                    invalidations.clone(),
+                ));
+            }
+            Err(e) => {
+                on_error(&e);
+                time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
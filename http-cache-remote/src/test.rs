@@ -0,0 +1,125 @@
+use crate::{
+    read_frame, serve_tcp, Endpoint, RemoteInvalidationBus, RemoteManager,
+};
+
+use http_cache::{
+    Bytes, CACacheManager, CacheManager, HttpResponse, HttpVersion,
+    InvalidationBus, Result,
+};
+use http_cache_semantics::CachePolicy;
+use tokio::{io::AsyncWriteExt, net::TcpListener};
+use url::Url;
+
+const GET: &str = "GET";
+
+const TEST_BODY: &[u8] = b"test";
+
+async fn exercise(manager: RemoteManager) -> Result<()> {
+    let url = Url::parse("http://example.com")?;
+    let key = format!("{}:{}", GET, &url);
+    let http_res = HttpResponse {
+        body: Bytes::from_static(TEST_BODY),
+        headers: Default::default(),
+        status: 200,
+        url: url.clone(),
+        version: HttpVersion::Http11,
+    };
+    let req = http::Request::get("http://example.com").body(())?;
+    let res = http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+    let policy = CachePolicy::new(&req, &res);
+    assert!(manager.get(&key).await?.is_none());
+    manager.put(key.clone(), http_res.clone(), policy.clone()).await?;
+    let data = manager.get(&key).await?;
+    assert_eq!(data.unwrap().0.body, TEST_BODY);
+    manager.delete(&key).await?;
+    assert!(manager.get(&key).await?.is_none());
+
+    // Listing, measuring and clearing reach the manager of the server
+    manager.put(key.clone(), http_res, policy).await?;
+    let entries = manager.list().await?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].cache_key, key);
+    assert_eq!(entries[0].method, http::Method::GET);
+    assert_eq!(manager.stats().await?.entries, 1);
+    manager.clear().await?;
+    assert!(manager.get(&key).await?.is_none());
+    manager.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn remote_tcp() -> Result<()> {
+    let path = "./http-cacache-remote-tcp-test";
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let server = tokio::spawn(serve_tcp(
+        listener,
+        CACacheManager { path: path.into(), ..Default::default() },
+        |_| {},
+    ));
+    let manager = RemoteManager::new(Endpoint::Tcp(addr));
+    assert_eq!(
+        format!("{:?}", manager.clone()),
+        format!("RemoteManager {{ endpoint: Tcp({addr}) }}")
+    );
+    exercise(manager).await?;
+    server.abort();
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn remote_invalidation() -> Result<()> {
+    let path = "./http-cacache-remote-invalidation-test";
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let server = tokio::spawn(serve_tcp(
+        listener,
+        CACacheManager { path: path.into(), ..Default::default() },
+        |_| {},
+    ));
+    let a = RemoteInvalidationBus::connect(Endpoint::Tcp(addr)).await?;
+    let b = RemoteInvalidationBus::connect(Endpoint::Tcp(addr)).await?;
+
+    // Every subscriber receives the key, including the publisher
+    a.publish("GET:http://example.com/").await?;
+    assert_eq!(b.receive().await?.as_deref(), Some("GET:http://example.com/"));
+    assert_eq!(a.receive().await?.as_deref(), Some("GET:http://example.com/"));
+    server.abort();
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn remote_unix() -> Result<()> {
+    let path = "./http-cacache-remote-unix-test";
+    let socket = std::env::temp_dir().join("http-cache-remote-test.sock");
+    let _ = std::fs::remove_file(&socket);
+    let listener = tokio::net::UnixListener::bind(&socket)?;
+    let server = tokio::spawn(crate::serve_unix(
+        listener,
+        CACacheManager { path: path.into(), ..Default::default() },
+        |_| {},
+    ));
+    exercise(RemoteManager::new(Endpoint::Unix(socket.clone()))).await?;
+    server.abort();
+    std::fs::remove_file(socket)?;
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn frame_limits() -> Result<()> {
+    // A length prefix beyond the limit is refused before anything is read
+    let (mut client, mut server) = tokio::io::duplex(64);
+    client.write_u64(u64::MAX).await?;
+    assert!(read_frame::<_, String>(&mut server).await.is_err());
+
+    // A frame that ends early is an error rather than a short value
+    let (mut client, mut server) = tokio::io::duplex(64);
+    client.write_u64(1024).await?;
+    client.write_all(b"short").await?;
+    drop(client);
+    assert!(read_frame::<_, String>(&mut server).await.is_err());
+    Ok(())
+}
@@ -40,19 +40,14 @@ use anyhow::anyhow;
 pub use error::BadRequest;
 
 use std::{
-    collections::HashMap,
     convert::{TryFrom, TryInto},
-    str::FromStr,
     time::SystemTime,
 };
 
 pub use http::request::Parts;
-use http::{
-    header::{HeaderName, CACHE_CONTROL},
-    HeaderValue, Method,
-};
+use http::{header::CACHE_CONTROL, HeaderValue, Method};
 use http_cache::{
-    BoxError, HitOrMiss, Middleware, Result, XCACHE, XCACHELOOKUP,
+    BoxError, HitOrMiss, Middleware, Result, XCACHE, XCACHELOOKUP, XCACHEREASON,
 };
 use http_cache_semantics::CachePolicy;
 use reqwest::{Request, Response, ResponseBuilderExt};
@@ -61,8 +56,14 @@ use task_local_extensions::Extensions;
 use url::Url;
 
 pub use http_cache::{
-    CacheManager, CacheMode, CacheOptions, HttpCache, HttpCacheOptions,
-    HttpResponse,
+    Admission, BackgroundRevalidation, CacheDecision, CacheEvent, CacheManager,
+    CacheMetadata, CacheMode, CacheOptions, CacheRevalidationRequest,
+    CacheState, CacheTransaction, CorruptEntry, DecodedBodies, Divergence,
+    DynCacheManager, DynHttpCache, Encode, EntryCost, EntryWeigher, Event,
+    Generations, HeaderLimit, HeaderLimits, HostCounters, HostStats, HttpCache,
+    HttpCacheOptions, HttpResponse, LookupOutcome, MaxStale, Namespace,
+    PauseHandle, RefreshAhead, RevalidationHeaders, RevalidationRateLimit,
+    StaleOnTimeout, Step, StoreMetadata, StorePredicate, UnexpectedEvent,
 };
 
 #[cfg(feature = "manager-cacache")]
@@ -71,7 +72,20 @@ pub use http_cache::CACacheManager;
 
 #[cfg(feature = "manager-moka")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
-pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+pub use http_cache::{
+    MokaCache, MokaCacheBuilder, MokaManager, MokaManagerBuilder,
+};
+
+#[cfg(feature = "manager-postgres")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-postgres")))]
+pub use http_cache::PostgresManager;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+pub use http_cache::{CacheConfig, ConfiguredManager, ManagerConfig};
 
 /// Wrapper for [`HttpCache`]
 #[derive(Debug)]
@@ -112,8 +126,12 @@ impl Middleware for ReqwestMiddleware<'_> {
         ))
     }
     fn update_headers(&mut self, parts: &Parts) -> Result<()> {
-        for header in parts.headers.iter() {
-            self.req.headers_mut().insert(header.0.clone(), header.1.clone());
+        let headers = self.req.headers_mut();
+        for name in parts.headers.keys() {
+            headers.remove(name);
+            for value in parts.headers.get_all(name) {
+                headers.append(name, value.clone());
+            }
         }
         Ok(())
     }
@@ -123,13 +141,27 @@ impl Middleware for ReqwestMiddleware<'_> {
             .insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
         Ok(())
     }
+    fn mark_revalidation(
+        &mut self,
+        revalidation: CacheRevalidationRequest,
+    ) -> Result<()> {
+        self.extensions.insert(revalidation);
+        Ok(())
+    }
     fn parts(&self) -> Result<Parts> {
         let copied_req = clone_req(&self.req)?;
         let converted = match http::Request::try_from(copied_req) {
             Ok(r) => r,
             Err(e) => return Err(Box::new(e)),
         };
-        Ok(converted.into_parts().0)
+        let mut parts = converted.into_parts().0;
+        if let Some(max_stale) = self.extensions.get::<MaxStale>() {
+            parts.extensions.insert(*max_stale);
+        }
+        if let Some(mode) = self.extensions.get::<CacheMode>() {
+            parts.extensions.insert(*mode);
+        }
+        Ok(parts)
     }
     fn url(&self) -> Result<Url> {
         Ok(self.req.url().clone())
@@ -144,21 +176,14 @@ impl Middleware for ReqwestMiddleware<'_> {
             Ok(r) => r,
             Err(e) => return Err(Box::new(e)),
         };
-        let mut headers = HashMap::new();
-        for header in res.headers() {
-            headers.insert(
-                header.0.as_str().to_owned(),
-                header.1.to_str()?.to_owned(),
-            );
-        }
+        let headers = res.headers().clone();
         let url = res.url().clone();
         let status = res.status().into();
         let version = res.version();
-        let body: Vec<u8> = match res.bytes().await {
+        let body = match res.bytes().await {
             Ok(b) => b,
             Err(e) => return Err(Box::new(e)),
-        }
-        .to_vec();
+        };
         Ok(HttpResponse {
             body,
             headers,
@@ -170,17 +195,16 @@ impl Middleware for ReqwestMiddleware<'_> {
 }
 
 // Converts an [`HttpResponse`] to a reqwest [`Response`]
-fn convert_response(response: HttpResponse) -> anyhow::Result<Response> {
+fn convert_response(mut response: HttpResponse) -> anyhow::Result<Response> {
+    let metadata = response.take_metadata();
     let mut ret_res = http::Response::builder()
         .status(response.status)
         .url(response.url)
-        .version(response.version.try_into()?)
+        .version(response.version.into())
         .body(response.body)?;
-    for header in response.headers {
-        ret_res.headers_mut().insert(
-            HeaderName::from_str(header.0.clone().as_str())?,
-            HeaderValue::from_str(header.1.clone().as_str())?,
-        );
+    *ret_res.headers_mut() = response.headers;
+    if let Some(metadata) = metadata {
+        ret_res.extensions_mut().insert(CacheMetadata(metadata));
     }
     Ok(Response::from(ret_res))
 }
@@ -215,6 +239,15 @@ impl<T: CacheManager> reqwest_middleware::Middleware for Cache<T> {
                 .run_no_cache(&mut middleware)
                 .await
                 .map_err(from_box_error)?;
+            let reason = if self.0.options.cache_reason_header {
+                let parts = middleware.parts().map_err(from_box_error)?;
+                Some(self.0.explain(&parts, None).map_err(from_box_error)?)
+            } else {
+                None
+            };
+            if let Some(refresh_header) = &self.0.options.refresh_header {
+                middleware.req.headers_mut().remove(refresh_header);
+            }
             let mut res = middleware
                 .next
                 .run(middleware.req, middleware.extensions)
@@ -223,8 +256,21 @@ impl<T: CacheManager> reqwest_middleware::Middleware for Cache<T> {
             let miss =
                 HeaderValue::from_str(HitOrMiss::MISS.to_string().as_ref())
                     .map_err(bad_header)?;
-            res.headers_mut().insert(XCACHE, miss.clone());
+            let state = if self.0.options.cache_state_header {
+                HeaderValue::from_str(CacheState::Bypass.to_string().as_ref())
+                    .map_err(bad_header)?
+            } else {
+                miss.clone()
+            };
+            res.headers_mut().insert(XCACHE, state);
             res.headers_mut().insert(XCACHELOOKUP, miss);
+            if let Some(reason) = reason {
+                res.headers_mut().insert(
+                    XCACHEREASON,
+                    HeaderValue::from_str(reason.to_string().as_ref())
+                        .map_err(bad_header)?,
+                );
+            }
             Ok(res)
         }
     }
@@ -1,11 +1,14 @@
 use crate::{error, Cache};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use http_cache::*;
 use reqwest::Client;
 use reqwest_middleware::ClientBuilder;
 use url::Url;
-use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+use wiremock::{
+    matchers::{header, header_exists, method},
+    Mock, MockServer, ResponseTemplate,
+};
 
 pub(crate) fn build_mock(
     cache_control_val: &str,
@@ -93,6 +96,32 @@ async fn default_mode_with_options() -> Result<()> {
                 }),
                 cache_mode_fn: None,
                 cache_bust: None,
+                modify_response: None,
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
             },
         }))
         .build();
@@ -106,6 +135,37 @@ async fn default_mode_with_options() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn default_mode_pragma_no_cache() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // Cold pass to load cache
+    client.get(url.clone()).send().await?;
+
+    // Try to load cached object
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_some());
+
+    // A fresh response must not be served for a Pragma: no-cache request
+    let res = client.get(url).header("pragma", "no-cache").send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), "HIT");
+    Ok(())
+}
+
 #[tokio::test]
 async fn no_cache_mode() -> Result<()> {
     let mock_server = MockServer::start().await;
@@ -135,6 +195,68 @@ async fn no_cache_mode() -> Result<()> {
     Ok(())
 }
 
+// Records the revalidation marker of every request passing through it
+struct RecordRevalidation(
+    Arc<std::sync::Mutex<Vec<Option<CacheRevalidationRequest>>>>,
+);
+
+#[async_trait::async_trait]
+impl reqwest_middleware::Middleware for RecordRevalidation {
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut task_local_extensions::Extensions,
+        next: reqwest_middleware::Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        self.0
+            .lock()
+            .unwrap()
+            .push(extensions.get::<CacheRevalidationRequest>().copied());
+        next.run(req, extensions).await
+    }
+}
+
+#[tokio::test]
+async fn revalidation_request_marked() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("max-age=0, public", TEST_BODY, 200, 3);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let marked = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .with(RecordRevalidation(marked.clone()))
+        .build();
+    client.get(url.clone()).send().await?;
+    client.get(url.clone()).send().await?;
+
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::NoCache,
+            manager,
+            options: HttpCacheOptions::default(),
+        }))
+        .with(RecordRevalidation(marked.clone()))
+        .build();
+    client.get(url).send().await?;
+
+    assert_eq!(
+        *marked.lock().unwrap(),
+        vec![
+            None,
+            Some(CacheRevalidationRequest::Conditional),
+            Some(CacheRevalidationRequest::NoCache)
+        ]
+    );
+    Ok(())
+}
+
 #[tokio::test]
 async fn custom_cache_key() -> Result<()> {
     let mock_server = MockServer::start().await;
@@ -155,6 +277,32 @@ async fn custom_cache_key() -> Result<()> {
                 cache_options: None,
                 cache_mode_fn: None,
                 cache_bust: None,
+                modify_response: None,
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
             },
         }))
         .build();
@@ -195,6 +343,32 @@ async fn custom_cache_mode_fn() -> Result<()> {
                     }
                 })),
                 cache_bust: None,
+                modify_response: None,
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
             },
         }))
         .build();
@@ -249,6 +423,32 @@ async fn cache_bust() -> Result<()> {
                         }
                     },
                 )),
+                modify_response: None,
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
             },
         }))
         .build();
@@ -270,6 +470,2013 @@ async fn cache_bust() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn custom_modify_response() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "no-store")
+                .insert_header("set-cookie", "session=abc")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client that rewrites the upstream caching headers
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                cache_key: None,
+                cache_options: None,
+                cache_mode_fn: None,
+                cache_bust: None,
+                modify_response: Some(Arc::new(
+                    |_: &http::request::Parts, res: &mut HttpResponse| {
+                        res.headers.insert(
+                            http::header::CACHE_CONTROL,
+                            http::HeaderValue::from_static(CACHEABLE_PUBLIC),
+                        );
+                        res.headers.remove("set-cookie");
+                    },
+                )),
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
+            },
+        }))
+        .build();
+
+    // Remote request and should cache despite the upstream no-store
+    let res = client.get(url.clone()).send().await?;
+    assert!(res.headers().get("set-cookie").is_none());
+
+    // Try to load cached object
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_some());
+    assert!(!data.unwrap().0.headers.contains_key("set-cookie"));
+
+    // Hot pass should be served from cache
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_reason_header() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with the cache reason header enabled
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                cache_reason_header: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request and should cache
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHEREASON).unwrap(), "fresh");
+
+    // Reason header should not be stored
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(!data.unwrap().0.headers.contains_key(XCACHEREASON));
+
+    // Non GET/HEAD requests bypass the cache
+    let res = client.post(url).send().await?;
+    assert_eq!(
+        res.headers().get(XCACHEREASON).unwrap(),
+        "method-not-cacheable"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_reason_header_revalidated() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let revalidate = Mock::given(method(GET))
+        .and(header_exists("if-none-match"))
+        .respond_with(ResponseTemplate::new(304))
+        .with_priority(1)
+        .expect(1);
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "no-cache")
+                .insert_header("etag", "\"abc\"")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _revalidate_guard = mock_server.register_as_scoped(revalidate).await;
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with the cache reason header enabled
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                cache_reason_header: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request, stored but must be revalidated before it is served
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHEREASON).unwrap(), "stale");
+
+    // Conditional request answered with a 304
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHEREASON).unwrap(), "revalidated");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+
+    // Reason header should not be stored
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(!data.unwrap().0.headers.contains_key(XCACHEREASON));
+    Ok(())
+}
+
+#[tokio::test]
+async fn revalidation_headers_etag_only() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let revalidate = Mock::given(method(GET))
+        .and(header_exists("if-none-match"))
+        .and(|req: &wiremock::Request| {
+            !req.headers.keys().any(|name| name.as_str() == "if-modified-since")
+        })
+        .respond_with(ResponseTemplate::new(304))
+        .with_priority(1)
+        .expect(1);
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "no-cache")
+                .insert_header("etag", "\"abc\"")
+                .insert_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _revalidate_guard = mock_server.register_as_scoped(revalidate).await;
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client that only sends If-None-Match when revalidating
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                revalidation_headers: RevalidationHeaders::ETagOnly,
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request, stored but must be revalidated before it is served
+    client.get(url.clone()).send().await?;
+
+    // Conditional request without If-Modified-Since answered with a 304
+    let res = client.get(url).send().await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_on_timeout() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let revalidate = Mock::given(method(GET))
+        .and(header_exists("if-none-match"))
+        .respond_with(
+            ResponseTemplate::new(304).set_delay(Duration::from_secs(5)),
+        )
+        .with_priority(1);
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "no-cache")
+                .insert_header("etag", "\"abc\"")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _revalidate_guard = mock_server.register_as_scoped(revalidate).await;
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let refreshed = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let refreshed_urls = refreshed.clone();
+
+    // Construct reqwest client that waits up to 50ms for revalidation
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                cache_reason_header: true,
+                stale_on_timeout: Some(StaleOnTimeout {
+                    timeout: Duration::from_millis(50),
+                    sleep: Arc::new(|d| Box::pin(tokio::time::sleep(d))),
+                    refresh: Some(Arc::new(
+                        move |parts: &http::request::Parts| {
+                            // Given the request without the validators
+                            assert!(!parts
+                                .headers
+                                .contains_key("if-none-match"));
+                            refreshed_urls
+                                .lock()
+                                .unwrap()
+                                .push(parts.uri.to_string());
+                        },
+                    )),
+                    background: None,
+                }),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request, stored but must be revalidated before it is served
+    client.get(url.clone()).send().await?;
+
+    // Revalidation takes too long, the stale response is served
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(
+        res.headers().get(XCACHEREASON).unwrap(),
+        "revalidation-deadline-exceeded"
+    );
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    assert_eq!(*refreshed.lock().unwrap(), vec![url]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn revalidation_rate_limit() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let revalidate = Mock::given(method(GET))
+        .and(header_exists("if-none-match"))
+        .respond_with(ResponseTemplate::new(304))
+        .with_priority(1)
+        .expect(1);
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "no-cache")
+                .insert_header("etag", "\"abc\"")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _revalidate_guard = mock_server.register_as_scoped(revalidate).await;
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client that revalidates each origin once, then rarely
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                cache_reason_header: true,
+                revalidation_rate_limit: Some(RevalidationRateLimit::new(
+                    0.001, 1,
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request, stored but must be revalidated before it is served
+    client.get(url.clone()).send().await?;
+
+    // Revalidated with the origin
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHEREASON).unwrap(), "revalidated");
+
+    // Rate limited, the stale response is served
+    let res = client.get(url).send().await?;
+    assert_eq!(
+        res.headers().get(XCACHEREASON).unwrap(),
+        "revalidation-rate-limited"
+    );
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+#[tokio::test]
+async fn shadow_mode() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let first = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("etag", "\"abc\"")
+                .set_body_bytes(TEST_BODY),
+        )
+        .up_to_n_times(1)
+        .with_priority(1)
+        .expect(1);
+    let changed = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("etag", "\"def\"")
+                .set_body_bytes(b"changed"),
+        )
+        .expect(1);
+    let _first_guard = mock_server.register_as_scoped(first).await;
+    let _changed_guard = mock_server.register_as_scoped(changed).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let observed = events.clone();
+
+    // Construct reqwest client in shadow mode sharing the manager
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Shadow,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                observer: Some(Arc::new(move |event: &CacheEvent| {
+                    if let CacheEvent::ShadowCompared { .. } = event {
+                        observed.lock().unwrap().push(event.clone());
+                    }
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Nothing stored yet, so nothing is compared
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    assert!(events.lock().unwrap().is_empty());
+
+    // Served from the network, compared with the stored response
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    assert_eq!(res.bytes().await?, &b"changed"[..]);
+    let key = format!("{}:{}", GET, &Url::parse(&url)?);
+    let events = events.lock().unwrap().clone();
+    match &events[..] {
+        [CacheEvent::ShadowCompared { cache_key, divergences }] => {
+            assert_eq!(cache_key, &key);
+            assert_eq!(divergences.len(), 2);
+            assert_eq!(
+                divergences[0],
+                Divergence::Header {
+                    name: "etag",
+                    cached: Some("\"abc\"".into()),
+                    fresh: Some("\"def\"".into()),
+                }
+            );
+            assert!(matches!(divergences[1], Divergence::Body { .. }));
+        }
+        _ => panic!("unexpected events {events:?}"),
+    }
+
+    // The response from the network is stored
+    let data = manager.get(&key).await?;
+    assert_eq!(data.unwrap().0.body, &b"changed"[..]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn dry_run_mode() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let observed = events.clone();
+
+    // Construct reqwest client in dry run mode
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::DryRun,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                observer: Some(Arc::new(move |event: &CacheEvent| {
+                    observed.lock().unwrap().push(event.clone());
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Both requests go to the network, the second would have been a hit
+    client.get(url.clone()).send().await?;
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    let cache_key = format!("{}:{}", GET, &Url::parse(&url)?);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            CacheEvent::Lookup {
+                cache_key: cache_key.clone(),
+                outcome: LookupOutcome::Miss
+            },
+            CacheEvent::Stored {
+                cache_key: cache_key.clone(),
+                body_size: TEST_BODY.len()
+            },
+            CacheEvent::Served {
+                cache_key: cache_key.clone(),
+                state: CacheState::Miss
+            },
+            CacheEvent::Lookup {
+                cache_key: cache_key.clone(),
+                outcome: LookupOutcome::Fresh
+            },
+            CacheEvent::Stored {
+                cache_key: cache_key.clone(),
+                body_size: TEST_BODY.len()
+            },
+            CacheEvent::Served { cache_key, state: CacheState::Miss },
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_only_mode() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 3);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1);
+    let _mock_guard_post = mock_server.register_as_scoped(m_post).await;
+    let url = format!("{}/", &mock_server.uri());
+    let other_url = format!("{}/other", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Populate the cache with a client in the default mode
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+    client.get(url.clone()).send().await?;
+
+    // Construct reqwest client in read only mode
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::ReadOnly,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // The stored response is served
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+
+    // Unsafe requests don't invalidate it
+    client.post(url.clone()).send().await?;
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_some());
+
+    // Responses from the network aren't stored
+    let res = client.get(other_url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    client.get(other_url.clone()).send().await?;
+    let data =
+        manager.get(&format!("{}:{}", GET, &Url::parse(&other_url)?)).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_only_mode_stale() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("max-age=0, public", TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Populate the cache with a client in the default mode
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+    client.get(url.clone()).send().await?;
+
+    // The stale response is fetched anew, without updating the stored one
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::ReadOnly,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), "HIT");
+
+    // Unless stale responses are allowed
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::ReadOnly,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                read_only_stale: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert!(res.headers().get("warning").unwrap().to_str()?.starts_with("110"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn corrupt_entry() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let cache_key = format!("{}:{}", GET, &Url::parse(&url)?);
+    let manager = MokaManager::default();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let observed = events.clone();
+
+    // Store an entry that can't be read back
+    manager.cache.insert(cache_key.clone(), Arc::new(vec![0xff; 4])).await;
+
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                observer: Some(Arc::new(move |event: &CacheEvent| {
+                    if let CacheEvent::CorruptEntry { cache_key, .. } = event {
+                        observed.lock().unwrap().push(cache_key.clone());
+                    }
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // It's treated as a miss and replaced with the response from the network
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    assert_eq!(*events.lock().unwrap(), vec![cache_key.clone()]);
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn corrupt_entry_read_only() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let cache_key = format!("{}:{}", GET, &Url::parse(&url)?);
+    let manager = MokaManager::default();
+
+    // Store an entry that can't be read back
+    manager.cache.insert(cache_key.clone(), Arc::new(vec![0xff; 4])).await;
+
+    // Construct reqwest client making every request read-only
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                cache_mode_fn: Some(Arc::new(|_: &http::request::Parts| {
+                    CacheMode::ReadOnly
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // It's treated as a miss but left in place
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    assert!(manager.cache.get(&cache_key).await.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn refresh_ahead() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let refreshed = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let refreshed_urls = refreshed.clone();
+
+    // Construct reqwest client that refreshes every entry when it is served
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                refresh_ahead: Some(RefreshAhead::new(
+                    1.0,
+                    Arc::new(move |parts: &http::request::Parts| {
+                        refreshed_urls
+                            .lock()
+                            .unwrap()
+                            .push(parts.uri.to_string());
+                    }),
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request, nothing to refresh
+    client.get(url.clone()).send().await?;
+    assert!(refreshed.lock().unwrap().is_empty());
+
+    // Cached responses are served, but a refresh is only requested once
+    for _ in 0..2 {
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    }
+    assert_eq!(*refreshed.lock().unwrap(), vec![url]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn admission() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let decisions = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let observed = decisions.clone();
+
+    // Construct reqwest client that only stores responses on their second request
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                admission: Some(Admission::new(2, 1024)),
+                observer: Some(Arc::new(move |event: &CacheEvent| {
+                    if let CacheEvent::Admission { admitted, .. } = event {
+                        observed.lock().unwrap().push(*admitted);
+                    }
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote requests, only the second is stored
+    for _ in 0..2 {
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    }
+    assert_eq!(*decisions.lock().unwrap(), vec![false, true]);
+
+    // Served from the cache
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_predicate() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("x-no-store-hint", "1")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client that skips responses with a custom header
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                store_predicate: Some(Arc::new(|res: &HttpResponse| {
+                    !res.headers.contains_key("x-no-store-hint")
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote requests, neither is stored
+    for _ in 0..2 {
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    }
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn revalidated_not_storable() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let url = format!("{}/", &mock_server.uri());
+    let key = format!("{}:{}", GET, &Url::parse(&url)?);
+    let manager = MokaManager::default();
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                store_predicate: Some(Arc::new(|res: &HttpResponse| {
+                    !res.headers.contains_key("x-no-store-hint")
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Stored, stale right away
+    let first = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "no-cache")
+                .insert_header("etag", "\"v1\"")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let guard = mock_server.register_as_scoped(first).await;
+    client.get(url.clone()).send().await?;
+    drop(guard);
+
+    // Neither changed response the revalidations get back is stored
+    for (name, value) in
+        [("cache-control", "no-store"), ("x-no-store-hint", "1")]
+    {
+        let changed = Mock::given(method(GET))
+            .and(header_exists("if-none-match"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", "no-cache")
+                    .insert_header("etag", "\"v2\"")
+                    .insert_header(name, value)
+                    .set_body_bytes(&b"changed"[..]),
+            )
+            .expect(1);
+        let _guard = mock_server.register_as_scoped(changed).await;
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.bytes().await?, &b"changed"[..]);
+        let (stored, _) = manager.get(&key).await?.unwrap();
+        assert_eq!(stored.body, TEST_BODY);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn refresh_header() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let forwarded = Mock::given(method(GET))
+        .and(header_exists("x-cache-refresh"))
+        .respond_with(ResponseTemplate::new(500))
+        .with_priority(1)
+        .expect(0);
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _forwarded_guard = mock_server.register_as_scoped(forwarded).await;
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client that reloads requests carrying a header
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                refresh_header: Some(http::HeaderName::from_static(
+                    "x-cache-refresh",
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request and should cache
+    client.get(url.clone()).send().await?;
+
+    // Reloaded from the origin without the header, the stored response is dropped
+    let res =
+        client.get(url.clone()).header("x-cache-refresh", "1").send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn variant_cookies() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .and(header_exists("cookie"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("vary", "cookie")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client that only tells variants apart by the lang cookie
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                variant_cookies: Some(vec!["lang".to_string()]),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request and should cache
+    let res = client
+        .get(url.clone())
+        .header("cookie", "session=1; lang=en")
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+
+    // A different session is served the same variant
+    let res = client
+        .get(url.clone())
+        .header("cookie", "lang=en; session=2")
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+
+    // A different language is a different variant
+    let res =
+        client.get(url).header("cookie", "session=1; lang=fr").send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    Ok(())
+}
+
+#[tokio::test]
+async fn normalize_accept_encoding() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("vary", "accept-encoding")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client that normalizes accept-encoding
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                normalize_accept_encoding: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request and should cache
+    let res = client
+        .get(url.clone())
+        .header("accept-encoding", "gzip, deflate, br")
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+
+    // Accepts the same encodings, written differently
+    let res = client
+        .get(url.clone())
+        .header("accept-encoding", "br;q=1.0, gzip;q=0.8, identity")
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+
+    // Doesn't accept brotli
+    let res = client
+        .get(url)
+        .header("accept-encoding", "gzip, br;q=0")
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_variants() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    for (language, expect) in [("en", 2), ("fr", 2)] {
+        let m = Mock::given(method(GET))
+            .and(header("accept-language", language))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("vary", "accept-language")
+                    .set_body_bytes(language),
+            )
+            .expect(expect);
+        mock_server.register(m).await;
+    }
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client that stores each variant
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                store_variants: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+    let get = |language: &'static str| {
+        client.get(url.clone()).header("accept-language", language).send()
+    };
+
+    // Remote requests and should cache both variants
+    for language in ["en", "fr"] {
+        let res = get(language).await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+        assert_eq!(res.bytes().await?, language);
+    }
+
+    // Each is served to the requests it matches
+    for language in ["en", "fr", "en"] {
+        let res = get(language).await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+        assert!(!res.headers().contains_key("x-http-cache-variants"));
+        assert_eq!(res.bytes().await?, language);
+    }
+
+    // An unsafe request drops all variants
+    client.post(url.clone()).send().await?;
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_none());
+    for language in ["fr", "en"] {
+        let res = get(language).await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+        assert_eq!(res.bytes().await?, language);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_variants_deleted() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("vary", "accept-language")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(40);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client that stores each variant
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                store_variants: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Only the latest 32 variants are kept, next to the entry
+    for i in 0..40 {
+        client
+            .get(url.clone())
+            .header("accept-language", format!("l{i}"))
+            .send()
+            .await?;
+    }
+    assert_eq!(manager.list().await?.len(), 33);
+
+    // An unsafe request deletes them with the entry
+    client.post(url).send().await?;
+    assert!(manager.list().await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn decoded_bodies_identity() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    // The body isn't gzip, as if the client had already decoded it
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("content-encoding", "gzip")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // Served as identity, from the network and from the cache
+    for _ in 0..2 {
+        let res = client.get(url.clone()).send().await?;
+        assert!(res.headers().get("content-encoding").is_none());
+        assert_eq!(res.headers().get("content-length").unwrap(), "4");
+        assert_eq!(res.bytes().await?, TEST_BODY);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn decoded_bodies_reencode() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("content-encoding", "gzip")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let encoded = [&[0x1f, 0x8b][..], TEST_BODY].concat();
+
+    // Construct reqwest client that encodes decoded bodies again
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                decoded_bodies: DecodedBodies::Reencode(Arc::new(
+                    |coding: &str, body: &[u8]| {
+                        (coding == "gzip")
+                            .then(|| [&[0x1f, 0x8b], body].concat())
+                    },
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Encoded again, from the network and from the cache
+    for _ in 0..2 {
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.headers().get("content-encoding").unwrap(), "gzip");
+        assert_eq!(res.headers().get("content-length").unwrap(), "6");
+        assert_eq!(res.bytes().await?, encoded);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn generations() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let generations =
+        Generations::new(Arc::new(|parts: &http::request::Parts| {
+            parts.uri.host().map(str::to_string)
+        }));
+
+    // Construct reqwest client namespacing entries by host
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                generations: Some(generations.clone()),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request and should cache, then hot pass
+    client.get(url.clone()).send().await?;
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+
+    // Bumping the generation invalidates the namespace
+    assert_eq!(generations.bump_generation("127.0.0.1"), 1);
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn pause() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let pause = PauseHandle::new();
+
+    // Construct reqwest client that can be paused
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                pause: Some(pause.clone()),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request and should cache
+    client.get(url.clone()).send().await?;
+
+    // While paused the cache is bypassed, but the entry is kept
+    pause.pause();
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_some());
+
+    // Once resumed the entry is served again
+    pause.resume();
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn host_stats() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let other_url = url.replace("127.0.0.1", "localhost");
+    let host_stats = HostStats::new(1);
+
+    // Construct reqwest client counting a single host on its own
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                host_stats: Some(host_stats.clone()),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    client.get(url.clone()).send().await?;
+    client.get(url).send().await?;
+    client.get(other_url).send().await?;
+    let snapshot = host_stats.snapshot();
+    assert_eq!(
+        snapshot["127.0.0.1"],
+        HostCounters { hits: 1, misses: 1, ..Default::default() }
+    );
+    assert_eq!(snapshot["127.0.0.1"].hit_ratio(), 0.5);
+    assert_eq!(
+        snapshot[HostStats::OTHER],
+        HostCounters { hits: 0, misses: 1, ..Default::default() }
+    );
+
+    host_stats.reset();
+    assert!(host_stats.snapshot().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_state_header() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let not_modified = Mock::given(method(GET))
+        .and(header_exists("if-none-match"))
+        .respond_with(
+            ResponseTemplate::new(304)
+                .insert_header("cache-control", "max-age=0")
+                .insert_header("etag", "\"abc\""),
+        )
+        .with_priority(1)
+        .expect(1);
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "max-age=0")
+                .insert_header("etag", "\"abc\"")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1);
+    let _not_modified_guard =
+        mock_server.register_as_scoped(not_modified).await;
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let _mock_guard_post = mock_server.register_as_scoped(m_post).await;
+    let url = format!("{}/", &mock_server.uri());
+    let host_stats = HostStats::new(1);
+    let states = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let observed = states.clone();
+
+    // Construct reqwest client reporting the detailed cache state
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                cache_state_header: true,
+                store_variants: false,
+                host_stats: Some(host_stats.clone()),
+                observer: Some(Arc::new(move |event: &CacheEvent| {
+                    if let CacheEvent::Served { state, .. } = event {
+                        observed.lock().unwrap().push(*state);
+                    }
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+
+    // The stored response is stale, so it's revalidated
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "REVALIDATED");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+
+    // Unsafe requests bypass the cache
+    let res = client.post(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "BYPASS");
+
+    assert_eq!(
+        *states.lock().unwrap(),
+        vec![CacheState::Miss, CacheState::Revalidated]
+    );
+    assert_eq!(
+        host_stats.snapshot()["127.0.0.1"],
+        HostCounters {
+            hits: 1,
+            misses: 1,
+            revalidated: 1,
+            ..Default::default()
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_sample_rate() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client that stores none of the keys
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                store_sample_rate: Some(0.0),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Both requests go to the network
+    client.get(url.clone()).send().await?;
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn dyn_manager() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client with a manager chosen at runtime
+    let cache: DynHttpCache = HttpCache {
+        mode: CacheMode::Default,
+        manager: MokaManager::default(),
+        options: HttpCacheOptions::default(),
+    }
+    .into_dyn();
+    let client = ClientBuilder::new(Client::new()).with(Cache(cache)).build();
+
+    // Remote request and should cache, then hot pass
+    client.get(url.clone()).send().await?;
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_metadata() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client recording the mirror used with each entry
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                store_metadata: Some(Arc::new(
+                    |_: &http::request::Parts, _: &HttpResponse| {
+                        Some(r#"{"mirror":"eu-1"}"#.to_string())
+                    },
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Returned when stored and when served from the cache, never as a header
+    for _ in 0..2 {
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(
+            res.extensions().get::<CacheMetadata>(),
+            Some(&CacheMetadata(r#"{"mirror":"eu-1"}"#.to_string()))
+        );
+        assert!(res.headers().get("x-http-cache-metadata").is_none());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_metadata_not_from_origin() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("x-http-cache-metadata", "forged")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client without metadata
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // The header sent by the origin is neither stored nor returned as metadata
+    for _ in 0..2 {
+        let res = client.get(url.clone()).send().await?;
+        assert!(res.extensions().get::<CacheMetadata>().is_none());
+        assert!(res.headers().get("x-http-cache-metadata").is_none());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_metadata_invalid() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with metadata that can't be stored
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                store_metadata: Some(Arc::new(
+                    |_: &http::request::Parts, _: &HttpResponse| {
+                        Some("mirror: zürich".to_string())
+                    },
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // The request fails instead of storing the entry without its metadata
+    assert!(client.get(url.clone()).send().await.is_err());
+    assert!(manager.get(&format!("{GET}:{url}")).await?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn store_metadata_revalidated() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                store_metadata: Some(Arc::new(
+                    |_: &http::request::Parts, res: &HttpResponse| {
+                        let etag = res.headers.get("etag")?.to_str().ok()?;
+                        Some(format!("etag {etag}"))
+                    },
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+    let response = |etag: &str, body: &'static [u8]| {
+        ResponseTemplate::new(200)
+            .insert_header("cache-control", "no-cache")
+            .insert_header("etag", etag)
+            .set_body_bytes(body)
+    };
+
+    let first =
+        Mock::given(method(GET)).respond_with(response("\"v1\"", TEST_BODY));
+    let guard = mock_server.register_as_scoped(first.expect(1)).await;
+    client.get(url.clone()).send().await?;
+    drop(guard);
+
+    // The entry replaced by the revalidation keeps metadata, for the new response
+    let changed = Mock::given(method(GET))
+        .and(header_exists("if-none-match"))
+        .respond_with(response("\"v2\"", b"changed"));
+    let _guard = mock_server.register_as_scoped(changed.expect(1)).await;
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(
+        res.extensions().get::<CacheMetadata>(),
+        Some(&CacheMetadata("etag \"v2\"".to_string()))
+    );
+    let (stored, _) =
+        manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?.unwrap();
+    assert_eq!(stored.body, &b"changed"[..]);
+    assert_eq!(stored.metadata(), Some("etag \"v2\""));
+    Ok(())
+}
+
+#[tokio::test]
+async fn key_headers() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client keying entries on the Accept header
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                key_headers: Some(vec![http::header::ACCEPT]),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Each Accept value has its own entry
+    client.get(url.clone()).header("accept", "text/html").send().await?;
+    let res =
+        client.get(url.clone()).header("accept", "text/plain").send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    let res =
+        client.get(url.clone()).header("accept", "text/html").send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    let key =
+        format!("{}:{}:headers:accept=text/plain", GET, &Url::parse(&url)?);
+    assert!(manager.get(&key).await?.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn qualified_directives() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "cache-control",
+                    "max-age=86400, private=\"set-cookie\", no-cache=\"x-token\"",
+                )
+                .insert_header("set-cookie", "session=1")
+                .insert_header("x-token", "secret")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client with a shared cache
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // The listed fields are returned to the request that fetched them
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get("set-cookie").unwrap(), "session=1");
+    assert_eq!(res.headers().get("x-token").unwrap(), "secret");
+
+    // The rest of the response is reused without them
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert!(res.headers().get("set-cookie").is_none());
+    assert!(res.headers().get("x-token").is_none());
+    assert_eq!(res.headers().get("cache-control").unwrap(), "max-age=86400");
+    Ok(())
+}
+
+#[tokio::test]
+async fn qualified_directives_multiple_lines() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .append_header("cache-control", "max-age=86400")
+                .append_header("cache-control", "private=\"set-cookie\"")
+                .insert_header("set-cookie", "session=1")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client with a shared cache
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+    client.get(url.clone()).send().await?;
+
+    // The directive on the second line applies, and the first line is kept
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert!(res.headers().get("set-cookie").is_none());
+    assert_eq!(res.headers().get("cache-control").unwrap(), "max-age=86400");
+    Ok(())
+}
+
+#[tokio::test]
+async fn fetch_latency() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // The latency is stored with the entry but not served
+    let res = client.get(url.clone()).send().await?;
+    assert!(res.headers().get("x-http-cache-fetch-latency").is_none());
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert!(res.headers().get("x-http-cache-fetch-latency").is_none());
+    let (stored, _) =
+        manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?.unwrap();
+    assert!(stored.fetch_latency().is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn max_stale() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("max-age=0, public", TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                cache_reason_header: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Cold pass to load cache
+    client.get(url.clone()).send().await?;
+
+    // The stale entry is served without revalidation within the accepted staleness
+    let res = client
+        .get(url.clone())
+        .with_extension(MaxStale(Duration::from_secs(60)))
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert_eq!(res.headers().get(XCACHEREASON).unwrap(), "stale-accepted");
+    assert!(res.headers().get("warning").unwrap().to_str()?.starts_with("110"));
+
+    // Other requests still revalidate it
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_mode_fn_force_cache() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("max-age=0, public", TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/static/app.js", &mock_server.uri());
+
+    // Construct reqwest client serving static assets from the cache
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions {
+                cache_mode_fn: Some(Arc::new(
+                    |parts: &http::request::Parts| {
+                        if parts.uri.path().starts_with("/static/") {
+                            CacheMode::ForceCache
+                        } else {
+                            CacheMode::Default
+                        }
+                    },
+                )),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Cold pass to load cache
+    client.get(url.clone()).send().await?;
+
+    // The stale entry is served without revalidation
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert!(res.headers().get("warning").unwrap().to_str()?.starts_with("112"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_if_cached_directive() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("max-age=0, public", TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // Cold pass to load cache
+    client.get(url.clone()).send().await?;
+
+    // The stale entry is served without reaching the network
+    let res = client
+        .get(url.clone())
+        .header("cache-control", "max-stale, only-if-cached")
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+
+    // A miss is a gateway timeout
+    let res = client
+        .get(format!("{url}missing"))
+        .header("cache-control", "only-if-cached")
+        .send()
+        .await?;
+    assert_eq!(res.status(), 504);
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_mode() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // Nothing is stored for a request made without the cache
+    let res = client
+        .get(url.clone())
+        .with_extension(CacheMode::NoStore)
+        .send()
+        .await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    let cache_key = format!("{}:{}", GET, &Url::parse(&url)?);
+    assert!(manager.get(&cache_key).await?.is_none());
+
+    // A request only served from the cache doesn't reach the network on a miss
+    let res = client
+        .get(url.clone())
+        .with_extension(CacheMode::OnlyIfCached)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 504);
+
+    // Other requests use the mode of the cache
+    client.get(url.clone()).send().await?;
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn assemble_partial() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let range = |range: &str, content_range: &str, body: &[u8]| {
+        Mock::given(method(GET))
+            .and(header("range", range))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("etag", "\"v1\"")
+                    .insert_header("content-range", content_range)
+                    .set_body_bytes(body),
+            )
+            .expect(1)
+    };
+    let _first = mock_server
+        .register_as_scoped(range("bytes=0-1", "bytes 0-1/4", b"te"))
+        .await;
+    let _second = mock_server
+        .register_as_scoped(range("bytes=2-3", "bytes 2-3/4", b"st"))
+        .await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client assembling partial responses
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                assemble_partial: true,
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Partial responses are returned as they are
+    let res =
+        client.get(url.clone()).header("range", "bytes=0-1").send().await?;
+    assert_eq!(res.status(), 206);
+    assert_eq!(res.bytes().await?, &TEST_BODY[..2]);
+    let key = format!("{}:{}", GET, &Url::parse(&url)?);
+    assert!(manager.get(&key).await?.is_none());
+
+    // Once the ranges cover the object it's served from the cache
+    client.get(url.clone()).header("range", "bytes=2-3").send().await?;
+    let res = client.get(url).send().await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    assert!(manager.get(&format!("{key}:partial")).await?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn header_limits() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("x-padding", "a".repeat(1024).as_str())
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let observed = events.clone();
+
+    // Construct reqwest client limiting the size of stored headers
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                header_limits: Some(HeaderLimits {
+                    max_header_size: 512,
+                    ..Default::default()
+                }),
+                observer: Some(Arc::new(move |event: &CacheEvent| {
+                    if let CacheEvent::HeadersRejected { .. } = event {
+                        observed.lock().unwrap().push(event.clone());
+                    }
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // The response is served but not stored
+    let res = client.get(url.clone()).send().await?;
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    let key = format!("{}:{}", GET, &Url::parse(&url)?);
+    assert!(manager.get(&key).await?.is_none());
+    assert_eq!(
+        events.lock().unwrap()[..],
+        [CacheEvent::HeadersRejected {
+            cache_key: key,
+            limit: HeaderLimit::HeaderSize,
+        }]
+    );
+
+    // So the next request goes to the network again
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "MISS");
+    Ok(())
+}
+
+#[tokio::test]
+async fn assume_cacheable() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(TEST_BODY))
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client that assumes a TTL for responses without caching headers
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                assume_cacheable: Some(Arc::new(|_: &http::request::Parts| {
+                    Some(Duration::from_secs(60))
+                })),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Remote request and should cache
+    client.get(url.clone()).send().await?;
+
+    // Try to load cached object
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert_eq!(
+        data.unwrap().0.headers.get("cache-control").unwrap(),
+        "max-age=60"
+    );
+
+    // Hot pass should be served from cache
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+    Ok(())
+}
+
+#[tokio::test]
+async fn isolation_key() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct two reqwest clients sharing one manager with different isolation keys
+    let build_client = |isolation_key: &str| {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    isolation_key: Some(isolation_key.to_string()),
+                    ..Default::default()
+                },
+            }))
+            .build()
+    };
+    let alice = build_client("alice");
+    let bob = build_client("bob");
+
+    // Each client should go to the remote once, then be served from its own entry
+    alice.get(url.clone()).send().await?;
+    bob.get(url.clone()).send().await?;
+    let res = alice.get(url.clone()).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), "HIT");
+
+    let data =
+        manager.get(&format!("alice:{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_some());
+    let data =
+        manager.get(&format!("bob:{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_some());
+    let data = manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
 #[tokio::test]
 async fn delete_after_non_get_head_method_request() -> Result<()> {
     let mock_server = MockServer::start().await;
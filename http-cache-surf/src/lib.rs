@@ -31,15 +31,16 @@
 //! ```
 mod error;
 
+pub use error::Error;
+
 use anyhow::anyhow;
-use std::{
-    collections::HashMap, convert::TryInto, str::FromStr, time::SystemTime,
-};
+use std::{convert::TryInto, str::FromStr, time::SystemTime};
 
 pub use http::request::Parts;
 use http::{header::CACHE_CONTROL, request};
 use http_cache::{
     BadHeader, BoxError, HitOrMiss, Middleware, Result, XCACHE, XCACHELOOKUP,
+    XCACHEREASON,
 };
 use http_cache_semantics::CachePolicy;
 use http_types::{headers::HeaderValue, Method, Response, StatusCode, Version};
@@ -47,8 +48,14 @@ use surf::{middleware::Next, Client, Request};
 use url::Url;
 
 pub use http_cache::{
-    CacheManager, CacheMode, CacheOptions, HttpCache, HttpCacheOptions,
-    HttpResponse,
+    Admission, BackgroundRevalidation, CacheDecision, CacheEvent, CacheManager,
+    CacheMetadata, CacheMode, CacheOptions, CacheRevalidationRequest,
+    CacheState, CacheTransaction, CorruptEntry, DecodedBodies, Divergence,
+    DynCacheManager, DynHttpCache, Encode, EntryCost, EntryWeigher, Event,
+    Generations, HeaderLimit, HeaderLimits, HostCounters, HostStats, HttpCache,
+    HttpCacheOptions, HttpResponse, LookupOutcome, MaxStale, Namespace,
+    PauseHandle, RefreshAhead, RevalidationHeaders, RevalidationRateLimit,
+    StaleOnTimeout, Step, StoreMetadata, StorePredicate, UnexpectedEvent,
 };
 
 #[cfg(feature = "manager-cacache")]
@@ -57,7 +64,20 @@ pub use http_cache::CACacheManager;
 
 #[cfg(feature = "manager-moka")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
-pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+pub use http_cache::{
+    MokaCache, MokaCacheBuilder, MokaManager, MokaManagerBuilder,
+};
+
+#[cfg(feature = "manager-postgres")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-postgres")))]
+pub use http_cache::PostgresManager;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+pub use http_cache::{CacheConfig, ConfiguredManager, ManagerConfig};
 
 /// Wrapper for [`HttpCache`]
 #[derive(Debug)]
@@ -91,12 +111,15 @@ impl Middleware for SurfMiddleware<'_> {
         ))
     }
     fn update_headers(&mut self, parts: &Parts) -> Result<()> {
-        for header in parts.headers.iter() {
-            let value = match HeaderValue::from_str(header.1.to_str()?) {
-                Ok(v) => v,
-                Err(_e) => return Err(Box::new(BadHeader)),
-            };
-            self.req.set_header(header.0.as_str(), value);
+        for name in parts.headers.keys() {
+            self.req.remove_header(name.as_str());
+            for value in parts.headers.get_all(name) {
+                let value = match HeaderValue::from_str(value.to_str()?) {
+                    Ok(v) => v,
+                    Err(_e) => return Err(Box::new(BadHeader)),
+                };
+                self.req.append_header(name.as_str(), value);
+            }
         }
         Ok(())
     }
@@ -104,6 +127,13 @@ impl Middleware for SurfMiddleware<'_> {
         self.req.insert_header(CACHE_CONTROL.as_str(), "no-cache");
         Ok(())
     }
+    fn mark_revalidation(
+        &mut self,
+        revalidation: CacheRevalidationRequest,
+    ) -> Result<()> {
+        self.req.set_ext(revalidation);
+        Ok(())
+    }
     fn parts(&self) -> Result<Parts> {
         let mut converted = request::Builder::new()
             .method(self.req.method().as_ref())
@@ -118,7 +148,14 @@ impl Middleware for SurfMiddleware<'_> {
                 );
             }
         }
-        Ok(converted.into_parts().0)
+        let mut parts = converted.into_parts().0;
+        if let Some(max_stale) = self.req.ext::<MaxStale>() {
+            parts.extensions.insert(*max_stale);
+        }
+        if let Some(mode) = self.req.ext::<CacheMode>() {
+            parts.extensions.insert(*mode);
+        }
+        Ok(parts)
     }
     fn url(&self) -> Result<Url> {
         Ok(self.req.url().clone())
@@ -130,18 +167,21 @@ impl Middleware for SurfMiddleware<'_> {
         let url = self.req.url().clone();
         let mut res =
             self.next.run(self.req.clone(), self.client.clone()).await?;
-        let mut headers = HashMap::new();
-        for header in res.iter() {
-            headers.insert(
-                header.0.as_str().to_owned(),
-                header.1.as_str().to_owned(),
-            );
+        let mut headers = http::HeaderMap::new();
+        for (name, values) in res.iter() {
+            let name = http::header::HeaderName::from_str(name.as_str())?;
+            for value in values {
+                headers.append(
+                    &name,
+                    http::HeaderValue::from_str(value.as_str())?,
+                );
+            }
         }
         let status = res.status().into();
         let version = res.version().unwrap_or(Version::Http1_1);
-        let body: Vec<u8> = res.body_bytes().await?;
+        let body = res.body_bytes().await?;
         Ok(HttpResponse {
-            body,
+            body: body.into(),
             headers,
             status,
             url,
@@ -168,28 +208,51 @@ impl<T: CacheManager> surf::middleware::Middleware for Cache<T> {
             .can_cache_request(&middleware)
             .map_err(|e| http_types::Error::from(anyhow!(e)))?
         {
-            let res =
+            let mut res =
                 self.0.run(middleware).await.map_err(to_http_types_error)?;
             let mut converted = Response::new(StatusCode::Ok);
-            for header in &res.headers {
-                let val =
-                    HeaderValue::from_bytes(header.1.as_bytes().to_vec())?;
-                converted.insert_header(header.0.as_str(), val);
+            if let Some(metadata) = res.take_metadata() {
+                converted.ext_mut().insert(CacheMetadata(metadata));
+            }
+            for (name, value) in &res.headers {
+                let val = HeaderValue::from_bytes(value.as_bytes().to_vec())?;
+                converted.append_header(name.as_str(), val);
             }
             converted.set_status(res.status.try_into()?);
-            converted.set_version(Some(res.version.try_into()?));
-            converted.set_body(res.body);
+            converted.set_version(Some(res.version.into()));
+            converted.set_body(Vec::from(res.body));
             Ok(surf::Response::from(converted))
         } else {
             self.0
                 .run_no_cache(&mut middleware)
                 .await
                 .map_err(to_http_types_error)?;
+            let reason = if self.0.options.cache_reason_header {
+                let parts = middleware.parts().map_err(to_http_types_error)?;
+                Some(
+                    self.0
+                        .explain(&parts, None)
+                        .map_err(to_http_types_error)?,
+                )
+            } else {
+                None
+            };
+            if let Some(refresh_header) = &self.0.options.refresh_header {
+                middleware.req.remove_header(refresh_header.as_str());
+            }
             let mut res =
                 middleware.next.run(middleware.req, middleware.client).await?;
             let miss = HitOrMiss::MISS.to_string();
-            res.append_header(XCACHE, miss.clone());
+            let state = if self.0.options.cache_state_header {
+                CacheState::Bypass.to_string()
+            } else {
+                miss.clone()
+            };
+            res.append_header(XCACHE, state);
             res.append_header(XCACHELOOKUP, miss);
+            if let Some(reason) = reason {
+                res.append_header(XCACHEREASON, reason.to_string());
+            }
             Ok(res)
         }
     }
@@ -104,6 +104,32 @@ mod with_moka {
                 }),
                 cache_mode_fn: None,
                 cache_bust: None,
+                modify_response: None,
+                cache_reason_header: false,
+                isolation_key: None,
+                assume_cacheable: None,
+                revalidation_headers: RevalidationHeaders::Both,
+                stale_on_timeout: None,
+                observer: None,
+                refresh_ahead: None,
+                admission: None,
+                revalidation_rate_limit: None,
+                store_predicate: None,
+                refresh_header: None,
+                variant_cookies: None,
+                normalize_accept_encoding: false,
+                decoded_bodies: DecodedBodies::Identity,
+                generations: None,
+                pause: None,
+                host_stats: None,
+                store_sample_rate: None,
+                store_metadata: None,
+                key_headers: None,
+                assemble_partial: false,
+                header_limits: None,
+                read_only_stale: false,
+                cache_state_header: false,
+                store_variants: false,
             },
         }));
 
@@ -368,6 +394,70 @@ mod with_moka {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn request_mode() -> Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let req = Request::new(Method::Get, Url::parse(&url)?);
+
+        // Construct Surf client with cache defaults
+        let client = Client::new().with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }));
+
+        // Nothing is stored for a request made without the cache
+        let mut no_store = req.clone();
+        no_store.set_ext(CacheMode::NoStore);
+        let res = client.send(no_store).await?;
+        assert_eq!(res.header(XCACHE).unwrap(), MISS);
+        let data =
+            manager.get(&format!("{}:{}", GET, &Url::parse(&url)?)).await?;
+        assert!(data.is_none());
+
+        // A request only served from the cache doesn't reach the network on a miss
+        let mut only_if_cached = req.clone();
+        only_if_cached.set_ext(CacheMode::OnlyIfCached);
+        let res = client.send(only_if_cached).await?;
+        assert_eq!(res.status(), 504);
+
+        // Other requests use the mode of the cache
+        client.send(req.clone()).await?;
+        let res = client.send(req).await?;
+        assert_eq!(res.header(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn max_stale() -> Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock("max-age=0, public", TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let req = Request::new(Method::Get, Url::parse(&url)?);
+
+        // Construct Surf client with cache defaults
+        let client = Client::new().with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions::default(),
+        }));
+
+        // Cold pass to load cache
+        client.send(req.clone()).await?;
+
+        // The stale entry is served without revalidation
+        let mut req = req;
+        req.set_ext(MaxStale(std::time::Duration::from_secs(60)));
+        let res = client.send(req).await?;
+        assert_eq!(res.header(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
     #[async_std::test]
     async fn revalidation_304() -> Result<()> {
         let mock_server = MockServer::start().await;
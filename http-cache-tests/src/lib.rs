@@ -0,0 +1,117 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates
+)]
+//! Shared pieces of the concurrency stress tests in `tests/`, which fire many concurrent
+//! requests for overlapping keys through the client implementations and check how often
+//! the origin was reached and that every stored entry is intact.
+//!
+//! Not published, run with `cargo test -p http-cache-tests`.
+use std::collections::HashMap;
+
+use http_cache::{CacheManager, HttpResponse};
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// The number of distinct keys requested
+pub const KEYS: usize = 8;
+
+/// The number of concurrent requests, spread evenly over the keys
+pub const REQUESTS: usize = 256;
+
+/// An origin serving a distinct, cacheable body for each key, large enough that a torn
+/// write would show up as a mismatch.
+#[derive(Debug)]
+pub struct Origin {
+    server: MockServer,
+}
+
+impl Origin {
+    /// Starts the origin.
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        for key in 0..KEYS {
+            Mock::given(method("GET"))
+                .and(path(format!("/{key}")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("cache-control", "max-age=86400, public")
+                        .set_body_bytes(body(key)),
+                )
+                .mount(&server)
+                .await;
+        }
+        Self { server }
+    }
+
+    /// Returns the url of `key`.
+    pub fn url(&self, key: usize) -> String {
+        format!("{}/{key}", self.server.uri())
+    }
+
+    /// Returns the cache key of `key` under the default cache key scheme.
+    pub fn cache_key(&self, key: usize) -> String {
+        format!("GET:{}", self.url(key))
+    }
+
+    /// Returns the url of the key requested by the `request`th request.
+    pub fn request_url(&self, request: usize) -> String {
+        self.url(request % KEYS)
+    }
+
+    /// Returns how many requests reached the origin for each key.
+    pub async fn requests_per_key(&self) -> HashMap<usize, usize> {
+        let mut counts = HashMap::new();
+        for request in self.server.received_requests().await.unwrap_or_default()
+        {
+            let key =
+                request.url.path().trim_start_matches('/').parse().unwrap();
+            *counts.entry(key).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Asserts that every key reached the origin at least once and at most `max` times.
+    pub async fn assert_requests_per_key(&self, max: usize) {
+        let counts = self.requests_per_key().await;
+        for key in 0..KEYS {
+            let count = counts.get(&key).copied().unwrap_or_default();
+            assert!(
+                (1..=max).contains(&count),
+                "key {key} reached the origin {count} times, expected 1 to {max}"
+            );
+        }
+    }
+
+    /// Asserts that `manager` holds an intact entry for every key.
+    pub async fn assert_entries_intact(&self, manager: &impl CacheManager) {
+        for key in 0..KEYS {
+            let stored = manager
+                .get(&self.cache_key(key))
+                .await
+                .map_err(Into::into)
+                .unwrap_or_else(|e| panic!("reading key {key} failed: {e}"));
+            let (response, _) =
+                stored.unwrap_or_else(|| panic!("key {key} wasn't stored"));
+            assert_intact(key, &response);
+        }
+    }
+}
+
+/// Returns the body served for `key`.
+pub fn body(key: usize) -> Vec<u8> {
+    format!("{key:08}").repeat(8 * 1024).into_bytes()
+}
+
+/// Asserts that `response` is the complete response of `key`.
+pub fn assert_intact(key: usize, response: &HttpResponse) {
+    assert_eq!(response.status, 200, "key {key} has the wrong status");
+    assert!(response.body == body(key), "key {key} has a torn or foreign body");
+}
@@ -0,0 +1,167 @@
+// Entries persisted by earlier versions, which must stay readable. The files in `golden/`
+// are never regenerated, see `golden/README.md`.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+use http_cache::{
+    Bytes, CACacheManager, CacheManager, HttpResponse, HttpVersion,
+    MokaManager, Result,
+};
+use http_cache_semantics::{BeforeRequest, CachePolicy};
+use url::Url;
+
+fn golden(version: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("golden").join(version)
+}
+
+// Works on a copy, so reading can't touch the committed files
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+// The entries every golden store holds, with the validator each revalidates with
+fn expected() -> Vec<(&'static str, HttpResponse, (&'static str, &'static str))>
+{
+    let response =
+        |url: &str, version, headers: &[(&'static str, &'static str)], body| {
+            HttpResponse {
+                body,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| {
+                        (
+                            HeaderName::from_static(name),
+                            HeaderValue::from_static(value),
+                        )
+                    })
+                    .collect::<HeaderMap>(),
+                status: 200,
+                url: Url::parse(url).unwrap(),
+                version,
+            }
+        };
+    vec![
+        (
+            "GET:https://example.com/",
+            response(
+                "https://example.com/",
+                HttpVersion::Http11,
+                &[
+                    ("cache-control", "max-age=60"),
+                    ("etag", "\"v1\""),
+                    ("content-type", "text/plain"),
+                ],
+                Bytes::from_static(b"test"),
+            ),
+            ("if-none-match", "\"v1\""),
+        ),
+        (
+            "GET:https://example.com/large",
+            response(
+                "https://example.com/large",
+                HttpVersion::H2,
+                &[
+                    ("cache-control", "public, max-age=3600"),
+                    ("last-modified", "Tue, 15 Nov 1994 08:12:31 GMT"),
+                ],
+                (0..=255u8).cycle().take(4096).collect(),
+            ),
+            ("if-modified-since", "Tue, 15 Nov 1994 08:12:31 GMT"),
+        ),
+    ]
+}
+
+async fn assert_readable(manager: &impl CacheManager) -> Result<()> {
+    for (cache_key, expected, (validator, value)) in expected() {
+        let Some((response, policy)) =
+            manager.get(cache_key).await.map_err(Into::into)?
+        else {
+            panic!("{cache_key} is missing");
+        };
+        assert_eq!(response.body, expected.body, "{cache_key}");
+        assert_eq!(response.headers, expected.headers, "{cache_key}");
+        assert_eq!(response.status, expected.status, "{cache_key}");
+        assert_eq!(response.url, expected.url, "{cache_key}");
+        assert_eq!(response.version, expected.version, "{cache_key}");
+        assert_stale_with(&policy, expected.url.as_str(), validator, value);
+    }
+    Ok(())
+}
+
+// A day after they were stored the entries have gone stale, so their policies must ask for
+// revalidation with the stored validator.
+fn assert_stale_with(
+    policy: &CachePolicy,
+    url: &str,
+    validator: &str,
+    value: &str,
+) {
+    let req = http::Request::get(url).body(()).unwrap();
+    let later = SystemTime::now() + Duration::from_secs(24 * 60 * 60);
+    match policy.before_request(&req, later) {
+        BeforeRequest::Stale { request, .. } => {
+            assert_eq!(request.headers[validator], value, "{url}");
+        }
+        BeforeRequest::Fresh(_) => panic!("{url} is still fresh"),
+    }
+}
+
+#[tokio::test]
+async fn cacache_0_17_0() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    copy_dir(&golden("0.17.0").join("cacache"), dir.path())?;
+    let manager =
+        CACacheManager { path: dir.path().into(), ..Default::default() };
+    assert_readable(&manager).await
+}
+
+#[tokio::test]
+async fn cacache_unreleased() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    copy_dir(&golden("unreleased").join("cacache"), dir.path())?;
+    let manager =
+        CACacheManager { path: dir.path().into(), ..Default::default() };
+    assert_readable(&manager).await
+}
+
+#[tokio::test]
+async fn cacache_compact_unreleased() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    copy_dir(&golden("unreleased").join("cacache-compact"), dir.path())?;
+    let manager =
+        CACacheManager { path: dir.path().into(), ..Default::default() };
+    assert_readable(&manager).await
+}
+
+#[tokio::test]
+async fn moka_snapshot_unreleased() -> Result<()> {
+    let manager = MokaManager::default();
+    let loaded =
+        manager.load_from(golden("unreleased").join("moka.snapshot")).await?;
+    assert_eq!(loaded, expected().len());
+    assert_readable(&manager).await
+}
+
+#[tokio::test]
+async fn moka_compact_snapshot_unreleased() -> Result<()> {
+    let manager = MokaManager::default();
+    let loaded = manager
+        .load_from(golden("unreleased").join("moka-compact.snapshot"))
+        .await?;
+    assert_eq!(loaded, expected().len());
+    assert_readable(&manager).await
+}
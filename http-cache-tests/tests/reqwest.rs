@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use http_cache::Result;
+use http_cache_darkbird::DarkbirdManager;
+use http_cache_quickcache::QuickManager;
+use http_cache_reqwest::{
+    CACacheManager, Cache, CacheManager, CacheMode, HttpCache,
+    HttpCacheOptions, MokaManager,
+};
+use http_cache_tests::{body, Origin, KEYS, REQUESTS};
+use reqwest::Client;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+fn client<T: CacheManager>(manager: Arc<T>) -> ClientWithMiddleware {
+    ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager,
+            options: HttpCacheOptions::default(),
+        }))
+        .build()
+}
+
+// Fires every request at once and checks each response body
+async fn burst(origin: &Origin, client: &ClientWithMiddleware) -> Result<()> {
+    let mut tasks = Vec::with_capacity(REQUESTS);
+    for request in 0..REQUESTS {
+        let client = client.clone();
+        let url = origin.request_url(request);
+        tasks.push(tokio::spawn(async move {
+            client.get(url).send().await?.bytes().await.map_err(Into::into)
+        }));
+    }
+    for (request, task) in tasks.into_iter().enumerate() {
+        let bytes: Result<_> = task.await?;
+        let key = request % KEYS;
+        assert!(bytes? == body(key), "request {request} got a torn body");
+    }
+    Ok(())
+}
+
+async fn stress<T: CacheManager>(manager: T) -> Result<()> {
+    let origin = Origin::start().await;
+    let manager = Arc::new(manager);
+    let client = client(manager.clone());
+
+    // Concurrent misses may each reach the origin, but never more than once per request
+    burst(&origin, &client).await?;
+    origin.assert_requests_per_key(REQUESTS / KEYS).await;
+    origin.assert_entries_intact(&manager).await;
+
+    // Once stored, every request is served from the cache
+    let stored = origin.requests_per_key().await;
+    burst(&origin, &client).await?;
+    assert_eq!(origin.requests_per_key().await, stored);
+    origin.assert_entries_intact(&manager).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn moka() -> Result<()> {
+    stress(MokaManager::default()).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn cacache() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    stress(CACacheManager { path: dir.path().into(), ..Default::default() })
+        .await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn quickcache() -> Result<()> {
+    stress(QuickManager::default()).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn mokadeser() -> Result<()> {
+    stress(http_cache_mokadeser::MokaManager::default()).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn darkbird() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let options = darkbird::Options::new(
+        dir.path().to_str().unwrap(),
+        "http-darkbird",
+        42,
+        darkbird::StorageType::RamCopies,
+        true,
+    );
+    stress(DarkbirdManager::new(options, false).await?).await
+}
+
+// Concurrent misses for a key should share one origin request
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[ignore = "needs request coalescing"]
+async fn no_stampede() -> Result<()> {
+    let origin = Origin::start().await;
+    let client = client(Arc::new(MokaManager::default()));
+    burst(&origin, &client).await?;
+    origin.assert_requests_per_key(1).await;
+    Ok(())
+}
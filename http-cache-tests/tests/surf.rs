@@ -0,0 +1,67 @@
+use http_cache::Result;
+use http_cache_quickcache::QuickManager;
+use http_cache_surf::{
+    Cache, CacheManager, CacheMode, HttpCache, HttpCacheOptions, MokaManager,
+};
+use http_cache_tests::{body, Origin, KEYS, REQUESTS};
+use std::sync::Arc;
+use surf::Client;
+
+fn client<T: CacheManager>(manager: Arc<T>) -> Client {
+    Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager,
+        options: HttpCacheOptions::default(),
+    }))
+}
+
+// Fires every request at once and checks each response body
+async fn burst(origin: &Origin, client: &Client) -> Result<()> {
+    let mut tasks = Vec::with_capacity(REQUESTS);
+    for request in 0..REQUESTS {
+        let client = client.clone();
+        let url = origin.request_url(request);
+        tasks.push(async_std::task::spawn(async move {
+            client.get(url).recv_bytes().await
+        }));
+    }
+    for (request, task) in tasks.into_iter().enumerate() {
+        let bytes = task.await?;
+        let key = request % KEYS;
+        assert!(bytes == body(key), "request {request} got a torn body");
+    }
+    Ok(())
+}
+
+async fn stress<T: CacheManager>(manager: T) -> Result<()> {
+    let origin = Origin::start().await;
+    let manager = Arc::new(manager);
+    let client = client(manager.clone());
+
+    // Concurrent misses may each reach the origin, but never more than once per request
+    burst(&origin, &client).await?;
+    origin.assert_requests_per_key(REQUESTS / KEYS).await;
+    origin.assert_entries_intact(&manager).await;
+
+    // Once stored, every request is served from the cache
+    let stored = origin.requests_per_key().await;
+    burst(&origin, &client).await?;
+    assert_eq!(origin.requests_per_key().await, stored);
+    origin.assert_entries_intact(&manager).await;
+    Ok(())
+}
+
+#[async_std::test]
+async fn moka() -> Result<()> {
+    stress(MokaManager::default()).await
+}
+
+#[async_std::test]
+async fn quickcache() -> Result<()> {
+    stress(QuickManager::default()).await
+}
+
+#[async_std::test]
+async fn mokadeser() -> Result<()> {
+    stress(http_cache_mokadeser::MokaManager::default()).await
+}
@@ -0,0 +1,420 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The tower layer implementation for http-cache, caching the responses of a server's
+//! handlers instead of the responses a client receives.
+//!
+//! Responses are stored under the method and URL of the request, following the
+//! `Cache-Control` the inner service sets. As for any shared cache, responses marked
+//! `private` or answering requests with an `Authorization` header aren't stored unless
+//! they allow it. Responses served from the cache carry an `Age` header.
+//!
+//! The body of every response to a request the cache handles is buffered in full before
+//! the response is returned, even if it ends up not being stored, since that's only
+//! decided once the response is complete. Requests the cache doesn't handle, such as
+//! `POST`s, are passed through and their responses streamed. Routes serving large or
+//! endless bodies, like downloads or server-sent events, should be bypassed the same way
+//! by returning [`CacheMode::NoStore`] for them from [`HttpCacheOptions::cache_mode_fn`]:
+//! ```no_run
+//! # use http_cache_tower::{CacheMode, HttpCacheOptions, Parts};
+//! # use std::sync::Arc;
+//! let options = HttpCacheOptions {
+//!     cache_mode_fn: Some(Arc::new(|parts: &Parts| {
+//!         if parts.uri.path().starts_with("/downloads/") {
+//!             CacheMode::NoStore
+//!         } else {
+//!             CacheMode::Default
+//!         }
+//!     })),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! The layer fails with a [`BoxError`] when the cache or the inner service does, so axum
+//! apps handle its errors with `axum::error_handling::HandleErrorLayer`.
+//! ```no_run
+//! use axum::{
+//!     error_handling::HandleErrorLayer, http::StatusCode, routing::get, BoxError,
+//!     Router,
+//! };
+//! use http_cache_tower::{CACacheManager, CacheMode, HttpCache, HttpCacheLayer, HttpCacheOptions};
+//! use tower::ServiceBuilder;
+//!
+//! let app: Router = Router::new()
+//!     .route("/", get(|| async { ([("cache-control", "max-age=60")], "Hello") }))
+//!     .layer(
+//!         ServiceBuilder::new()
+//!             .layer(HandleErrorLayer::new(|_: BoxError| async {
+//!                 StatusCode::INTERNAL_SERVER_ERROR
+//!             }))
+//!             .layer(HttpCacheLayer::new(HttpCache {
+//!                 mode: CacheMode::Default,
+//!                 manager: CACacheManager::default(),
+//!                 options: HttpCacheOptions::default(),
+//!             })),
+//!     );
+//! ```
+use std::{
+    convert::TryInto,
+    future::{poll_fn, Future},
+    mem,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use bytes::{Bytes, BytesMut};
+pub use http::request::Parts;
+use http::{
+    header::{CACHE_CONTROL, HOST},
+    HeaderValue, Method, Request, Response,
+};
+use http_body::{combinators::UnsyncBoxBody, Body as HttpBody, Full};
+use http_cache::{
+    BoxError, DetachedFetch, HitOrMiss, Middleware, Result, XCACHE,
+    XCACHELOOKUP, XCACHEREASON,
+};
+use http_cache_semantics::CachePolicy;
+use tower_layer::Layer;
+use tower_service::Service;
+use url::Url;
+
+pub use http_cache::{
+    Admission, BackgroundRevalidation, CacheDecision, CacheEvent, CacheManager,
+    CacheMetadata, CacheMode, CacheOptions, CacheRevalidationRequest,
+    CacheState, CacheTransaction, CorruptEntry, DecodedBodies, Divergence,
+    DynCacheManager, DynHttpCache, Encode, EntryCost, EntryWeigher, Event,
+    Generations, HeaderLimit, HeaderLimits, HostCounters, HostStats, HttpCache,
+    HttpCacheOptions, HttpResponse, LookupOutcome, MaxStale, Namespace,
+    PauseHandle, RefreshAhead, RevalidationHeaders, RevalidationRateLimit,
+    StaleOnTimeout, Step, StoreMetadata, StorePredicate, UnexpectedEvent,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{
+    MokaCache, MokaCacheBuilder, MokaManager, MokaManagerBuilder,
+};
+
+#[cfg(feature = "manager-postgres")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-postgres")))]
+pub use http_cache::PostgresManager;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+pub use http_cache::{CacheConfig, ConfiguredManager, ManagerConfig};
+
+/// The body of the responses returned by [`HttpCacheService`]. Responses to requests the
+/// cache handles are buffered, whether or not they're stored, and responses to requests it
+/// bypasses are streamed from the inner service.
+pub type ResponseBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// Applies [`HttpCacheService`] to a service, with an [`HttpCache`] shared by every
+/// service it wraps
+#[derive(Debug)]
+pub struct HttpCacheLayer<T: CacheManager> {
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<T: CacheManager> HttpCacheLayer<T> {
+    /// Creates a new layer caching with `cache`
+    pub fn new(cache: HttpCache<T>) -> Self {
+        Self { cache: Arc::new(cache) }
+    }
+}
+
+impl<T: CacheManager> Clone for HttpCacheLayer<T> {
+    fn clone(&self) -> Self {
+        Self { cache: self.cache.clone() }
+    }
+}
+
+impl<S, T: CacheManager> Layer<S> for HttpCacheLayer<T> {
+    type Service = HttpCacheService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpCacheService { inner, cache: self.cache.clone() }
+    }
+}
+
+/// Serves the responses of the inner service from the cache, see [`HttpCacheLayer`]
+#[derive(Debug)]
+pub struct HttpCacheService<S, T: CacheManager> {
+    inner: S,
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<S: Clone, T: CacheManager> Clone for HttpCacheService<S, T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), cache: self.cache.clone() }
+    }
+}
+
+/// Implements ['Middleware'] for tower services
+pub(crate) struct TowerMiddleware<S, B> {
+    pub req: Request<B>,
+    pub url: Url,
+    pub service: S,
+}
+
+// Requests reaching a server usually only carry a path, the URL is completed from the
+// `Host` header
+fn request_url<B>(req: &Request<B>) -> Result<Url> {
+    let uri = req.uri();
+    if uri.scheme().is_some() {
+        return Ok(Url::parse(&uri.to_string())?);
+    }
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| uri.authority().map(|authority| authority.as_str()))
+        .unwrap_or("localhost");
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Ok(Url::parse(&format!("http://{host}{path}"))?)
+}
+
+async fn collect<B>(body: B) -> Result<Bytes>
+where
+    B: HttpBody<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let mut body = Box::pin(body);
+    let mut collected = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        collected.extend_from_slice(&chunk.map_err(Into::into)?);
+    }
+    Ok(collected.freeze())
+}
+
+#[async_trait::async_trait]
+impl<S, ReqBody, ResBody> Middleware for TowerMiddleware<S, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    ReqBody: Default + Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    fn is_method_get_head(&self) -> bool {
+        self.req.method() == Method::GET || self.req.method() == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        Ok(CachePolicy::new(&self.parts()?, &response.parts()?))
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            SystemTime::now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        let headers = self.req.headers_mut();
+        for name in parts.headers.keys() {
+            headers.remove(name);
+            for value in parts.headers.get_all(name) {
+                headers.append(name, value.clone());
+            }
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.req
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
+        Ok(())
+    }
+    fn mark_revalidation(
+        &mut self,
+        revalidation: CacheRevalidationRequest,
+    ) -> Result<()> {
+        self.req.extensions_mut().insert(revalidation);
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        // Cache keys are built from the URI, so it carries the host too
+        let mut converted = Request::builder()
+            .method(self.req.method().clone())
+            .uri(self.url.as_str())
+            .version(self.req.version())
+            .body(())?;
+        *converted.headers_mut() = self.req.headers().clone();
+        let mut parts = converted.into_parts().0;
+        if let Some(max_stale) = self.req.extensions().get::<MaxStale>() {
+            parts.extensions.insert(*max_stale);
+        }
+        Ok(parts)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.req.method().as_ref().to_string())
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        let req = self.take_request();
+        fetch(&mut self.service, req, self.url.clone()).await
+    }
+    fn detached_fetch(&mut self) -> Result<Option<DetachedFetch>> {
+        // A clone of the inner service sends it, so it doesn't borrow the middleware
+        let req = self.take_request();
+        let mut service = self.service.clone();
+        let url = self.url.clone();
+        Ok(Some(Box::pin(async move { fetch(&mut service, req, url).await })))
+    }
+}
+
+impl<S, B: Default> TowerMiddleware<S, B> {
+    // The body and extensions move to the request sent to the inner service
+    fn take_request(&mut self) -> Request<B> {
+        let mut req = Request::new(mem::take(self.req.body_mut()));
+        *req.method_mut() = self.req.method().clone();
+        *req.uri_mut() = self.req.uri().clone();
+        *req.version_mut() = self.req.version();
+        *req.headers_mut() = self.req.headers().clone();
+        *req.extensions_mut() = mem::take(self.req.extensions_mut());
+        req
+    }
+}
+
+async fn fetch<S, ReqBody, ResBody>(
+    service: &mut S,
+    req: Request<ReqBody>,
+    url: Url,
+) -> Result<HttpResponse>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: Into<BoxError>,
+    ResBody: HttpBody<Data = Bytes>,
+    ResBody::Error: Into<BoxError>,
+{
+    poll_fn(|cx| service.poll_ready(cx)).await.map_err(Into::into)?;
+    let res = service.call(req).await.map_err(Into::into)?;
+    let (parts, body) = res.into_parts();
+    Ok(HttpResponse {
+        body: collect(body).await?,
+        headers: parts.headers,
+        status: parts.status.into(),
+        url,
+        version: parts.version.try_into()?,
+    })
+}
+
+// Converts an [`HttpResponse`] to a buffered [`Response`]
+fn convert_response(
+    mut response: HttpResponse,
+) -> Result<Response<ResponseBody>> {
+    let metadata = response.take_metadata();
+    let body = Full::new(response.body).map_err(|never| match never {});
+    let mut converted = Response::builder()
+        .status(response.status)
+        .version(response.version.into())
+        .body(body.boxed_unsync())?;
+    *converted.headers_mut() = response.headers;
+    if let Some(metadata) = metadata {
+        converted.extensions_mut().insert(CacheMetadata(metadata));
+    }
+    Ok(converted)
+}
+
+impl<S, T, ReqBody, ResBody> Service<Request<ReqBody>>
+    for HttpCacheService<S, T>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    T: CacheManager,
+    ReqBody: Default + Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // The clone might not be ready, the service that was polled handles the request
+        let clone = self.inner.clone();
+        let service = mem::replace(&mut self.inner, clone);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let url = request_url(&req)?;
+            let mut middleware = TowerMiddleware { req, url, service };
+            if cache.can_cache_request(&middleware)? {
+                let res = cache.run(middleware).await?;
+                return convert_response(res);
+            }
+            cache.run_no_cache(&mut middleware).await?;
+            let reason = if cache.options.cache_reason_header {
+                Some(cache.explain(&middleware.parts()?, None)?)
+            } else {
+                None
+            };
+            if let Some(refresh_header) = &cache.options.refresh_header {
+                middleware.req.headers_mut().remove(refresh_header);
+            }
+            let res = middleware
+                .service
+                .call(middleware.req)
+                .await
+                .map_err(Into::into)?;
+            let mut res =
+                res.map(|body| body.map_err(Into::into).boxed_unsync());
+            let miss =
+                HeaderValue::from_str(HitOrMiss::MISS.to_string().as_ref())?;
+            let state = if cache.options.cache_state_header {
+                HeaderValue::from_str(CacheState::Bypass.to_string().as_ref())?
+            } else {
+                miss.clone()
+            };
+            res.headers_mut().insert(XCACHE, state);
+            res.headers_mut().insert(XCACHELOOKUP, miss);
+            if let Some(reason) = reason {
+                res.headers_mut().insert(
+                    XCACHEREASON,
+                    HeaderValue::from_str(reason.to_string().as_ref())?,
+                );
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test;
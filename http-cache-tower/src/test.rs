@@ -0,0 +1,264 @@
+use crate::HttpCacheLayer;
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use http::{header::AGE, Request, Response};
+use http_body::Body as _;
+use http_cache::*;
+use hyper::Body;
+use tower::{service_fn, Layer, ServiceExt};
+
+const GET: &str = "GET";
+
+const TEST_BODY: &[u8] = b"test";
+
+const CACHEABLE_PUBLIC: &str = "max-age=86400, public";
+
+const CACHEABLE_PRIVATE: &str = "max-age=86400, private";
+
+fn cache(manager: MokaManager) -> HttpCacheLayer<MokaManager> {
+    HttpCacheLayer::new(HttpCache {
+        mode: CacheMode::Default,
+        manager,
+        options: HttpCacheOptions::default(),
+    })
+}
+
+// A handler answering with `cache_control`, counting how often it was called
+fn handler(
+    cache_control: &'static str,
+    calls: Arc<AtomicUsize>,
+) -> impl tower::Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = Infallible,
+    Future = impl Send,
+> + Clone
+       + Send
+       + 'static {
+    service_fn(move |_req: Request<Body>| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        async move {
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .header("cache-control", cache_control)
+                    .body(Body::from(TEST_BODY))
+                    .unwrap(),
+            )
+        }
+    })
+}
+
+fn request(method: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri("/")
+        .header("host", "example.com")
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn body(res: Response<crate::ResponseBody>) -> Result<Vec<u8>> {
+    let mut body = res.into_body();
+    let mut collected = Vec::new();
+    while let Some(chunk) = body.data().await {
+        collected.extend_from_slice(&chunk?);
+    }
+    Ok(collected)
+}
+
+#[tokio::test]
+async fn default_mode() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let manager = MokaManager::default();
+    let service =
+        cache(manager.clone()).layer(handler(CACHEABLE_PUBLIC, calls.clone()));
+
+    // Cold pass to load cache
+    let res = service.clone().oneshot(request(GET)).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::MISS.to_string());
+    assert_eq!(body(res).await?, TEST_BODY);
+
+    // Try to load cached object
+    let data = manager.get(&format!("{GET}:http://example.com/")).await?;
+    assert!(data.is_some());
+
+    // Hot pass, served without calling the handler
+    let res = service.oneshot(request(GET)).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::HIT.to_string());
+    assert!(res.headers().contains_key(AGE));
+    assert_eq!(body(res).await?, TEST_BODY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn private_not_stored() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let manager = MokaManager::default();
+    let service =
+        cache(manager.clone()).layer(handler(CACHEABLE_PRIVATE, calls.clone()));
+
+    for _ in 0..2 {
+        let res = service.clone().oneshot(request(GET)).await?;
+        assert_eq!(res.headers()[XCACHE], HitOrMiss::MISS.to_string());
+        assert_eq!(body(res).await?, TEST_BODY);
+    }
+    let data = manager.get(&format!("{GET}:http://example.com/")).await?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn unsafe_method_bypasses() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let manager = MokaManager::default();
+    let service =
+        cache(manager.clone()).layer(handler(CACHEABLE_PUBLIC, calls.clone()));
+
+    let res = service.oneshot(request("POST")).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::MISS.to_string());
+    assert_eq!(body(res).await?, TEST_BODY);
+    let data = manager.get("POST:http://example.com/").await?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn bypassed_streams() -> Result<()> {
+    // A body that never ends, so the response only arrives if it isn't buffered
+    let (mut sender, streamed) = Body::channel();
+    sender.send_data(TEST_BODY.into()).await?;
+    let streamed = Arc::new(Mutex::new(Some(streamed)));
+    let inner = service_fn(move |_req: Request<Body>| {
+        let body = streamed.lock().unwrap().take().unwrap_or_else(Body::empty);
+        async move { Ok::<_, Infallible>(Response::new(body)) }
+    });
+    let service = HttpCacheLayer::new(HttpCache {
+        mode: CacheMode::Default,
+        manager: MokaManager::default(),
+        options: HttpCacheOptions {
+            cache_mode_fn: Some(Arc::new(|_: &http::request::Parts| {
+                CacheMode::NoStore
+            })),
+            ..Default::default()
+        },
+    })
+    .layer(inner);
+
+    let res = service.oneshot(request(GET)).await?;
+    let mut body = res.into_body();
+    assert_eq!(body.data().await.transpose()?.as_deref(), Some(TEST_BODY));
+    drop(sender);
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_on_timeout_background() -> Result<()> {
+    let (release, released) = tokio::sync::oneshot::channel::<()>();
+    let released = Arc::new(Mutex::new(Some(released)));
+    let inner = service_fn(move |req: Request<Body>| {
+        // The revalidation is answered once it's released
+        let released = req
+            .headers()
+            .contains_key("if-none-match")
+            .then(|| released.lock().unwrap().take())
+            .flatten();
+        async move {
+            let res = match released {
+                Some(released) => {
+                    released.await.ok();
+                    Response::builder()
+                        .header("cache-control", CACHEABLE_PUBLIC)
+                        .body(Body::from("refreshed"))
+                }
+                None => Response::builder()
+                    .header("cache-control", "no-cache")
+                    .header("etag", "\"v1\"")
+                    .body(Body::from(TEST_BODY)),
+            };
+            Ok::<_, Infallible>(res.unwrap())
+        }
+    });
+    let spawned = Arc::new(Mutex::new(Vec::new()));
+    let handles = spawned.clone();
+    let service = HttpCacheLayer::new(HttpCache {
+        mode: CacheMode::Default,
+        manager: MokaManager::default(),
+        options: HttpCacheOptions {
+            stale_on_timeout: Some(StaleOnTimeout {
+                // The timeout has always passed
+                timeout: Duration::ZERO,
+                sleep: Arc::new(|_| Box::pin(std::future::ready(()))),
+                refresh: None,
+                background: Some(BackgroundRevalidation::new(Arc::new(
+                    move |future| {
+                        handles.lock().unwrap().push(tokio::spawn(future));
+                    },
+                ))),
+            }),
+            ..Default::default()
+        },
+    })
+    .layer(inner);
+
+    // Stored, but must be revalidated before it is served
+    service.clone().oneshot(request(GET)).await?;
+
+    // The stale response is served while the revalidation goes on
+    let res = service.clone().oneshot(request(GET)).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::HIT.to_string());
+    assert_eq!(body(res).await?, TEST_BODY);
+
+    // Once it finishes, its response refreshes the entry
+    release.send(()).unwrap();
+    let revalidation = spawned.lock().unwrap().pop().unwrap();
+    revalidation.await?;
+    let res = service.oneshot(request(GET)).await?;
+    assert_eq!(res.headers()[XCACHE], HitOrMiss::HIT.to_string());
+    assert_eq!(body(res).await?, b"refreshed");
+    Ok(())
+}
+
+#[tokio::test]
+async fn axum_router() -> Result<()> {
+    use axum::{
+        error_handling::HandleErrorLayer, http::StatusCode, routing::get,
+        BoxError, Router,
+    };
+    use tower::ServiceBuilder;
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    let app: Router = Router::new()
+        .route(
+            "/",
+            get(move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+                async { ([("cache-control", CACHEABLE_PUBLIC)], TEST_BODY) }
+            }),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|_: BoxError| async {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }))
+                .layer(cache(MokaManager::default())),
+        );
+
+    for _ in 0..2 {
+        let res = app.clone().oneshot(request(GET)).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(res.into_body()).await?, TEST_BODY);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
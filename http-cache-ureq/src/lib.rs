@@ -0,0 +1,206 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The ureq middleware implementation for http-cache, for synchronous clients that
+//! don't run an async runtime.
+//!
+//! The middleware drives a [`CacheTransaction`] with a [`BlockingCacheManager`], such as
+//! [`CACacheManager`], storing and reading records on the calling thread. The options a
+//! transaction doesn't apply aren't applied here either.
+//!
+//! A few limits come from ureq:
+//! - Headers can't be removed from a request, so the cache can add the headers it needs
+//!   when revalidating but can't drop any, as [`HttpCacheOptions::refresh_header`] would.
+//! - Responses built by the cache, cached or fetched, report `https://example.com/` as
+//!   their URL.
+//! - Requests the cache doesn't handle, e.g. a `POST`, are passed through as they are, so
+//!   their responses don't carry the `x-cache` headers.
+//! ```no_run
+//! use http_cache_ureq::{Cache, CacheMode, CACacheManager, HttpCache, HttpCacheOptions};
+//!
+//! fn main() -> Result<(), ureq::Error> {
+//!     let agent = ureq::builder()
+//!         .middleware(Cache::new(HttpCache {
+//!             mode: CacheMode::Default,
+//!             manager: CACacheManager::default(),
+//!             options: HttpCacheOptions::default(),
+//!         }))
+//!         .build();
+//!     agent.get("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching").call()?;
+//!     Ok(())
+//! }
+//! ```
+use std::{io, io::Read, sync::Arc};
+
+use http::{request, HeaderMap, HeaderName, HeaderValue};
+use http_cache::BoxError;
+use ureq::{Middleware, MiddlewareNext, Request, Response};
+use url::Url;
+
+pub use http_cache::{
+    Admission, BlockingCacheManager, CacheDecision, CacheEvent, CacheManager,
+    CacheMetadata, CacheMode, CacheOptions, CacheState, CacheTransaction,
+    CorruptEntry, DecodedBodies, Divergence, Encode, EntryCost, EntryWeigher,
+    Event, Generations, HeaderLimit, HeaderLimits, HostCounters, HostStats,
+    HttpCache, HttpCacheOptions, HttpResponse, HttpVersion, LookupOutcome,
+    Namespace, PauseHandle, RevalidationHeaders, RevalidationRateLimit, Step,
+    StoreMetadata, StorePredicate, UnexpectedEvent,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+/// Caches the responses of the requests an agent sends, with an [`HttpCache`] shared by
+/// the clones of the agent.
+#[derive(Debug)]
+pub struct Cache<T: BlockingCacheManager> {
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<T: BlockingCacheManager> Cache<T> {
+    /// Creates a new middleware caching with `cache`
+    pub fn new(cache: HttpCache<T>) -> Self {
+        Self { cache: Arc::new(cache) }
+    }
+}
+
+impl<T: BlockingCacheManager> Clone for Cache<T> {
+    fn clone(&self) -> Self {
+        Self { cache: self.cache.clone() }
+    }
+}
+
+fn to_io_error(e: impl Into<BoxError>) -> ureq::Error {
+    io::Error::new(io::ErrorKind::Other, e.into()).into()
+}
+
+// Sets the headers of a `Step::Fetch` that differ from the request's own
+fn set_headers(mut request: Request, headers: &HeaderMap) -> Request {
+    for name in headers.keys() {
+        let values = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>();
+        if request.all(name.as_str()) != values {
+            request = request.set(name.as_str(), &values.join(", "));
+        }
+    }
+    request
+}
+
+// Converts a fetched response to an [`HttpResponse`], reading its body
+fn convert_response(response: Response) -> Result<HttpResponse, BoxError> {
+    let url = Url::parse(response.get_url())?;
+    let version = match response.http_version() {
+        "HTTP/0.9" => HttpVersion::Http09,
+        "HTTP/1.0" => HttpVersion::Http10,
+        "HTTP/2.0" => HttpVersion::H2,
+        "HTTP/3.0" => HttpVersion::H3,
+        _ => HttpVersion::Http11,
+    };
+    let status = response.status();
+    let mut headers = HeaderMap::new();
+    for name in response.headers_names() {
+        let name = HeaderName::from_bytes(name.as_bytes())?;
+        for value in response.all(name.as_str()) {
+            headers.append(name.clone(), HeaderValue::from_str(value)?);
+        }
+    }
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body)?;
+    Ok(HttpResponse { body: body.into(), headers, status, url, version })
+}
+
+impl<T: BlockingCacheManager> Middleware for Cache<T> {
+    fn handle(
+        &self,
+        request: Request,
+        next: MiddlewareNext,
+    ) -> Result<Response, ureq::Error> {
+        let parts: request::Parts = request::Builder::from(request.clone())
+            .body(())
+            .map_err(to_io_error)?
+            .into_parts()
+            .0;
+        // Requests the cache doesn't handle are passed through without buffering
+        let decision = self.cache.explain(&parts, None).map_err(to_io_error)?;
+        let bypass = matches!(
+            decision,
+            CacheDecision::Bypass(_) | CacheDecision::MethodNotCacheable
+        );
+        let mut request = Some(request);
+        let mut next = Some(next);
+        let mut transaction = self.cache.transaction(parts);
+        let mut step = transaction.start().map_err(to_io_error)?;
+        loop {
+            let event = match step {
+                Step::Delete(key) => {
+                    self.cache
+                        .manager
+                        .delete_blocking(&key)
+                        .map_err(to_io_error)?;
+                    Event::Deleted
+                }
+                Step::Lookup(key) => Event::LookedUp(
+                    self.cache
+                        .manager
+                        .get_blocking(&key)
+                        .map_err(to_io_error)?,
+                ),
+                Step::Fetch(headers) => {
+                    // The transaction fetches at most once
+                    let (Some(sent), Some(next)) =
+                        (request.take(), next.take())
+                    else {
+                        return Err(to_io_error("request already sent"));
+                    };
+                    match next.handle(set_headers(sent, &headers)) {
+                        Ok(res) if bypass => return Ok(res),
+                        Ok(res) => Event::Fetched(
+                            convert_response(res).map_err(to_io_error)?,
+                        ),
+                        Err(e) if bypass => return Err(e),
+                        Err(e) => Event::FetchFailed(Box::new(e)),
+                    }
+                }
+                Step::Store { cache_key, response, policy } => Event::Stored(
+                    self.cache
+                        .manager
+                        .put_blocking(cache_key, response, policy)
+                        .map_err(to_io_error)?,
+                ),
+                Step::Respond(response) => {
+                    return build_response(response).map_err(to_io_error);
+                }
+            };
+            step = transaction.next(event).map_err(to_io_error)?;
+        }
+    }
+}
+
+// Converts an [`HttpResponse`] to a ureq response, which reports `https://example.com/`
+// as its URL
+fn build_response(response: HttpResponse) -> Result<Response, BoxError> {
+    let mut converted = http::Response::builder()
+        .status(response.status)
+        .version(response.version.into())
+        .body(response.body.to_vec())
+        .map_err(to_io_error)?;
+    *converted.headers_mut() = response.headers;
+    Ok(converted.into())
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,131 @@
+use crate::*;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use http_cache::{HitOrMiss, XCACHE};
+
+type Result<T> = std::result::Result<T, BoxError>;
+
+const GET: &str = "GET";
+
+const TEST_BODY: &[u8] = b"test";
+
+const CACHEABLE_PUBLIC: &str = "max-age=86400, public";
+
+const CACHEABLE_PRIVATE: &str = "max-age=86400, private";
+
+// Answers every request with `cache_control`, counting them. Returns the URL to request.
+fn server(cache_control: &'static str, calls: Arc<AtomicUsize>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { return };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            // Skips the request head, the requests have no body
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok() && line != "\r\n" {
+                line.clear();
+            }
+            calls.fetch_add(1, Ordering::SeqCst);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncache-control: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                cache_control,
+                TEST_BODY.len()
+            );
+            let _ = stream.write_all(TEST_BODY);
+        }
+    });
+    url
+}
+
+fn agent(path: &str) -> (ureq::Agent, CACacheManager) {
+    let manager = CACacheManager { path: path.into(), ..Default::default() };
+    let agent = ureq::builder()
+        .middleware(Cache::new(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+    (agent, manager)
+}
+
+fn body(res: Response) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    res.into_reader().read_to_end(&mut body)?;
+    Ok(body)
+}
+
+#[test]
+fn default_mode() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let url = server(CACHEABLE_PUBLIC, calls.clone());
+    let path = "./http-cacache-ureq-default";
+    let (agent, manager) = agent(path);
+
+    // Cold pass to load cache
+    let res = agent.get(&url).call()?;
+    assert_eq!(res.header(XCACHE), Some(HitOrMiss::MISS.to_string().as_ref()));
+    assert_eq!(body(res)?, TEST_BODY);
+
+    // Try to load cached object
+    let data = manager.get_blocking(&format!("{GET}:{url}"))?;
+    assert!(data.is_some());
+
+    // Hot pass, served without sending the request
+    let res = agent.get(&url).call()?;
+    assert_eq!(res.header(XCACHE), Some(HitOrMiss::HIT.to_string().as_ref()));
+    assert!(res.has("age"));
+    assert_eq!(body(res)?, TEST_BODY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn private_not_stored() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let url = server(CACHEABLE_PRIVATE, calls.clone());
+    let path = "./http-cacache-ureq-private";
+    let (agent, manager) = agent(path);
+
+    for _ in 0..2 {
+        let res = agent.get(&url).call()?;
+        assert_eq!(
+            res.header(XCACHE),
+            Some(HitOrMiss::MISS.to_string().as_ref())
+        );
+        assert_eq!(body(res)?, TEST_BODY);
+    }
+    let data = manager.get_blocking(&format!("{GET}:{url}"))?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    let _ = std::fs::remove_dir_all(path);
+    Ok(())
+}
+
+#[test]
+fn unsafe_method_bypasses() -> Result<()> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let url = server(CACHEABLE_PUBLIC, calls.clone());
+    let path = "./http-cacache-ureq-post";
+    let (agent, manager) = agent(path);
+
+    let res = agent.post(&url).call()?;
+    assert!(res.header(XCACHE).is_none());
+    assert_eq!(body(res)?, TEST_BODY);
+    let data = manager.get_blocking(&format!("POST:{url}"))?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    let _ = std::fs::remove_dir_all(path);
+    Ok(())
+}
@@ -0,0 +1,401 @@
+use crate::{
+    BoxError, CacheManager, CacheMode, CacheStats, CachedEntry, HttpCache,
+    HttpCacheOptions, HttpResponse, NullManager, Result, RevalidationHeaders,
+};
+
+#[cfg(feature = "manager-cacache")]
+use crate::CACacheManager;
+#[cfg(feature = "manager-moka")]
+use crate::MokaManager;
+
+use std::{env, str::FromStr, sync::Arc};
+
+#[cfg(feature = "manager-cacache")]
+use std::path::PathBuf;
+#[cfg(feature = "manager-moka")]
+use std::time::Duration;
+
+use http::{header::HeaderName, request};
+use http_cache_semantics::{CacheOptions, CachePolicy};
+use serde::{de::IntoDeserializer, Deserialize};
+
+/// Configures an [`HttpCache`] from a configuration file, in any format supported by
+/// `serde`. Build the cache with [`HttpCache::from_config`].
+///
+/// ```toml
+/// mode = "default"
+/// max-body-size = 10485760
+/// bypass-hosts = ["internal.example.com"]
+///
+/// [manager]
+/// type = "cacache"
+/// path = "./http-cacache"
+/// ```
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CacheConfig {
+    /// The cache mode, `default` unless set.
+    #[serde(default = "default_mode")]
+    pub mode: CacheMode,
+    /// The manager storing the entries.
+    pub manager: ManagerConfig,
+    /// Whether the cache is shared between users, see [`CacheOptions::shared`].
+    #[serde(default)]
+    pub shared: Option<bool>,
+    /// Responses with a larger body are not stored.
+    #[serde(default)]
+    pub max_body_size: Option<usize>,
+    /// Requests to these hosts bypass the cache, as with [`CacheMode::NoStore`].
+    #[serde(default)]
+    pub bypass_hosts: Vec<String>,
+    /// See [`HttpCacheOptions::cache_reason_header`].
+    #[serde(default)]
+    pub cache_reason_header: bool,
+    /// See [`HttpCacheOptions::isolation_key`].
+    #[serde(default)]
+    pub isolation_key: Option<String>,
+    /// See [`HttpCacheOptions::revalidation_headers`].
+    #[serde(default)]
+    pub revalidation_headers: RevalidationHeaders,
+    /// See [`HttpCacheOptions::refresh_header`].
+    #[serde(default)]
+    pub refresh_header: Option<String>,
+    /// See [`HttpCacheOptions::variant_cookies`].
+    #[serde(default)]
+    pub variant_cookies: Option<Vec<String>>,
+    /// See [`HttpCacheOptions::normalize_accept_encoding`].
+    #[serde(default)]
+    pub normalize_accept_encoding: bool,
+}
+
+fn default_mode() -> CacheMode {
+    CacheMode::Default
+}
+
+/// Selects the manager of a [`CacheConfig`] and its settings, by its `type`.
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+// Only `Copy` when built without the cacache manager
+#[allow(missing_copy_implementations)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ManagerConfig {
+    /// A [`CACacheManager`]
+    #[cfg(feature = "manager-cacache")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+    Cacache {
+        /// The directory of the cache
+        path: PathBuf,
+        /// See [`CACacheManager::inline_threshold`]
+        #[serde(default, rename = "inline-threshold")]
+        inline_threshold: Option<usize>,
+        /// See [`CACacheManager::max_bytes`]
+        #[serde(default, rename = "max-bytes")]
+        max_bytes: Option<u64>,
+        /// See [`CACacheManager::max_entries`]
+        #[serde(default, rename = "max-entries")]
+        max_entries: Option<u64>,
+    },
+    /// A [`MokaManager`]
+    #[cfg(feature = "manager-moka")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+    Moka {
+        /// The maximum number of entries
+        #[serde(default, rename = "max-capacity")]
+        max_capacity: Option<u64>,
+        /// The maximum total size of the entries, replacing `max-capacity`
+        #[serde(default, rename = "max-bytes")]
+        max_bytes: Option<u64>,
+        /// How many seconds entries are kept after they are stored
+        #[serde(default, rename = "time-to-live")]
+        time_to_live: Option<u64>,
+    },
+    /// A [`NullManager`], which stores nothing, to disable caching
+    None,
+}
+
+/// The manager selected by a [`ManagerConfig`].
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+)]
+#[derive(Debug, Clone)]
+pub enum ConfiguredManager {
+    /// A [`CACacheManager`]
+    #[cfg(feature = "manager-cacache")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+    Cacache(CACacheManager),
+    /// A [`MokaManager`]
+    #[cfg(feature = "manager-moka")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+    Moka(MokaManager),
+    /// A [`NullManager`]
+    Null(NullManager),
+}
+
+impl ManagerConfig {
+    /// Builds the configured manager.
+    pub fn build(&self) -> ConfiguredManager {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache {
+                path,
+                inline_threshold,
+                max_bytes,
+                max_entries,
+            } => ConfiguredManager::Cacache(CACacheManager {
+                path: path.clone(),
+                inline_threshold: *inline_threshold,
+                max_bytes: *max_bytes,
+                max_entries: *max_entries,
+                ..Default::default()
+            }),
+            #[cfg(feature = "manager-moka")]
+            Self::Moka { max_capacity, max_bytes, time_to_live } => {
+                let mut builder = MokaManager::builder();
+                if let Some(entries) = max_capacity {
+                    builder = builder.max_capacity(*entries);
+                }
+                if let Some(bytes) = max_bytes {
+                    builder = builder.max_bytes(*bytes);
+                }
+                if let Some(secs) = time_to_live {
+                    builder = builder.time_to_live(Duration::from_secs(*secs));
+                }
+                ConfiguredManager::Moka(builder.build())
+            }
+            Self::None => ConfiguredManager::Null(NullManager),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheManager for ConfiguredManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.get(cache_key).await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.get(cache_key).await,
+            Self::Null(manager) => manager.get(cache_key).await,
+        }
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => {
+                manager.put(cache_key, response, policy).await
+            }
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => {
+                manager.put(cache_key, response, policy).await
+            }
+            Self::Null(manager) => {
+                manager.put(cache_key, response, policy).await
+            }
+        }
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.delete(cache_key).await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.delete(cache_key).await,
+            Self::Null(manager) => manager.delete(cache_key).await,
+        }
+    }
+    async fn get_many(
+        &self,
+        cache_keys: &[&str],
+    ) -> Result<Vec<Option<(HttpResponse, CachePolicy)>>> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.get_many(cache_keys).await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.get_many(cache_keys).await,
+            Self::Null(manager) => manager.get_many(cache_keys).await,
+        }
+    }
+    async fn delete_many(&self, cache_keys: &[&str]) -> Result<()> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.delete_many(cache_keys).await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.delete_many(cache_keys).await,
+            Self::Null(manager) => manager.delete_many(cache_keys).await,
+        }
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.shutdown().await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.shutdown().await,
+            Self::Null(manager) => manager.shutdown().await,
+        }
+    }
+
+    async fn clear(&self) -> Result<()> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.clear().await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.clear().await,
+            Self::Null(manager) => manager.clear().await,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.list().await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.list().await,
+            Self::Null(manager) => manager.list().await,
+        }
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        match self {
+            #[cfg(feature = "manager-cacache")]
+            Self::Cacache(manager) => manager.stats().await,
+            #[cfg(feature = "manager-moka")]
+            Self::Moka(manager) => manager.stats().await,
+            Self::Null(manager) => manager.stats().await,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Builds the [`HttpCacheOptions`] described by the configuration. Fails if the
+    /// refresh header isn't a valid header name.
+    pub fn options(&self) -> Result<HttpCacheOptions> {
+        let mut options = HttpCacheOptions {
+            cache_reason_header: self.cache_reason_header,
+            isolation_key: self.isolation_key.clone(),
+            revalidation_headers: self.revalidation_headers,
+            variant_cookies: self.variant_cookies.clone(),
+            normalize_accept_encoding: self.normalize_accept_encoding,
+            ..Default::default()
+        };
+        if let Some(shared) = self.shared {
+            options.cache_options =
+                Some(CacheOptions { shared, ..Default::default() });
+        }
+        if let Some(max_body_size) = self.max_body_size {
+            options.store_predicate =
+                Some(Arc::new(move |response: &HttpResponse| {
+                    response.body.len() <= max_body_size
+                }));
+        }
+        if !self.bypass_hosts.is_empty() {
+            let mode = self.mode;
+            let bypass_hosts = self.bypass_hosts.clone();
+            options.cache_mode_fn =
+                Some(Arc::new(move |parts: &request::Parts| {
+                    let host = parts.uri.host().unwrap_or_default();
+                    if bypass_hosts.iter().any(|bypass| bypass == host) {
+                        CacheMode::NoStore
+                    } else {
+                        mode
+                    }
+                }));
+        }
+        if let Some(refresh_header) = &self.refresh_header {
+            options.refresh_header =
+                Some(HeaderName::from_str(refresh_header)?);
+        }
+        Ok(options)
+    }
+
+    /// Applies the overrides set in the environment, so operators can change a deployed
+    /// cache without a rebuild:
+    ///
+    /// - `HTTP_CACHE_MODE`: the cache mode, such as `no-store`
+    /// - `HTTP_CACHE_DIR`: the directory of a `cacache` manager
+    /// - `HTTP_CACHE_MAX_SIZE`: the maximum total size in bytes of a `moka` manager
+    ///
+    /// Fails if a value is invalid or doesn't apply to the configured manager.
+    pub fn apply_env(&mut self) -> Result<()> {
+        self.apply_overrides(|name| env::var(name).ok())
+    }
+
+    pub(crate) fn apply_overrides(
+        &mut self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<()> {
+        if let Some(mode) = var("HTTP_CACHE_MODE") {
+            self.mode = CacheMode::deserialize(
+                mode.trim().to_ascii_lowercase().into_deserializer(),
+            )
+            .map_err(|e: serde::de::value::Error| {
+                format!("invalid HTTP_CACHE_MODE: {e}")
+            })?;
+        }
+        if let Some(dir) = var("HTTP_CACHE_DIR") {
+            match &mut self.manager {
+                #[cfg(feature = "manager-cacache")]
+                ManagerConfig::Cacache { path, .. } => *path = dir.into(),
+                #[allow(unreachable_patterns)]
+                _ => {
+                    let message =
+                        format!("HTTP_CACHE_DIR={dir} needs a cacache manager");
+                    return Err(message.into());
+                }
+            }
+        }
+        if let Some(size) = var("HTTP_CACHE_MAX_SIZE") {
+            match &mut self.manager {
+                #[cfg(feature = "manager-moka")]
+                ManagerConfig::Moka { max_capacity, max_bytes, .. } => {
+                    *max_bytes = Some(size.trim().parse().map_err(|e| {
+                        format!("invalid HTTP_CACHE_MAX_SIZE: {e}")
+                    })?);
+                    *max_capacity = None;
+                }
+                #[allow(unreachable_patterns)]
+                _ => {
+                    let message = format!(
+                        "HTTP_CACHE_MAX_SIZE={size} needs a moka manager"
+                    );
+                    return Err(message.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HttpCache<ConfiguredManager> {
+    /// Creates a cache with the manager, mode and options described by `config`.
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(feature = "manager-cacache", feature = "manager-moka")))
+    )]
+    pub fn from_config(config: &CacheConfig) -> Result<Self> {
+        Ok(Self {
+            mode: config.mode,
+            manager: config.manager.build(),
+            options: config.options()?,
+        })
+    }
+}
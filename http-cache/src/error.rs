@@ -29,3 +29,102 @@ impl fmt::Display for BadHeader {
 }
 
 impl std::error::Error for BadHeader {}
+
+/// Error type for metadata that can't be stored with an entry, because the
+/// [`HttpCacheOptions::store_metadata`](crate::HttpCacheOptions::store_metadata) closure
+/// returned text that isn't visible ASCII or spans lines
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BadMetadata;
+
+impl fmt::Display for BadMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Metadata must be visible ASCII on a single line")
+    }
+}
+
+impl std::error::Error for BadMetadata {}
+
+/// Error type for managers that can't remove all of their entries, returned by the default
+/// [`CacheManager::clear`](crate::CacheManager::clear)
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ClearUnsupported;
+
+impl fmt::Display for ClearUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("The cache manager doesn't support clearing")
+    }
+}
+
+impl std::error::Error for ClearUnsupported {}
+
+/// Error type for managers that can't enumerate their entries, returned by the default
+/// [`CacheManager::list`](crate::CacheManager::list)
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ListUnsupported;
+
+impl fmt::Display for ListUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("The cache manager doesn't support listing entries")
+    }
+}
+
+impl std::error::Error for ListUnsupported {}
+
+/// Error type for managers that can't measure their entries, returned by the default
+/// [`CacheManager::stats`](crate::CacheManager::stats)
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StatsUnsupported;
+
+impl fmt::Display for StatsUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("The cache manager doesn't support statistics")
+    }
+}
+
+impl std::error::Error for StatsUnsupported {}
+
+/// Error type for events passed to a [`CacheTransaction`](crate::CacheTransaction) that
+/// don't answer its previous step
+#[derive(Debug, Copy, Clone)]
+pub struct UnexpectedEvent {
+    pub(crate) event: &'static str,
+    pub(crate) state: &'static str,
+}
+
+impl fmt::Display for UnexpectedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unexpected {} event while the transaction is {}",
+            self.event, self.state
+        )
+    }
+}
+
+impl std::error::Error for UnexpectedEvent {}
+
+/// Error type for stored entries that can't be read back, e.g. because they were damaged on
+/// disk or written by an incompatible version. Managers return it from
+/// [`CacheManager::get`](crate::CacheManager::get), and the cache treats the entry as a miss
+/// and deletes it, see [`CacheEvent::CorruptEntry`](crate::CacheEvent::CorruptEntry).
+#[derive(Debug)]
+pub struct CorruptEntry(BoxError);
+
+impl CorruptEntry {
+    /// Wraps the error the entry failed to be read with
+    pub fn new(error: impl Into<BoxError>) -> Self {
+        Self(error.into())
+    }
+}
+
+impl fmt::Display for CorruptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Corrupt cache entry: {}", self.0)
+    }
+}
+
+impl std::error::Error for CorruptEntry {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
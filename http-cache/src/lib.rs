@@ -20,48 +20,186 @@
 //! The following features are available. By default `manager-cacache` and `cacache-async-std` are enabled.
 //!
 //! - `manager-cacache` (default): enable [cacache](https://github.com/zkat/cacache-rs),
-//! a high-performance disk cache, backend manager.
+//!   a high-performance disk cache, backend manager.
 //! - `cacache-async-std` (default): enable [async-std](https://github.com/async-rs/async-std) runtime support for cacache.
 //! - `cacache-tokio` (disabled): enable [tokio](https://github.com/tokio-rs/tokio) runtime support for cacache.
 //! - `manager-moka` (disabled): enable [moka](https://github.com/moka-rs/moka),
-//! a high-performance in-memory cache, backend manager.
+//!   a high-performance in-memory cache, backend manager.
+//! - `manager-moka-arc` (disabled): enable the `MokaArcManager`, a [moka](https://github.com/moka-rs/moka)
+//!   backend manager keeping entries without serializing them, for the fastest hits.
+//! - `manager-foyer` (disabled): enable [foyer](https://github.com/foyer-rs/foyer),
+//!   a hybrid memory and disk cache, backend manager. foyer requires Rust 1.81 or newer.
+//! - `manager-postgres` (disabled): enable the [PostgreSQL](https://www.postgresql.org)
+//!   backend manager, built on [tokio-postgres](https://github.com/sfackler/rust-postgres).
+//! - `manager-sled` (disabled): enable the [sled](https://github.com/spacejam/sled) embedded database
+//!   backend manager, keeping the cache in a single crash-safe store.
+//! - `manager-object-store` (disabled): enable the backend manager storing entries with
+//!   [object_store](https://github.com/apache/arrow-rs/tree/master/object_store), in Amazon S3,
+//!   Google Cloud Storage or Azure Blob Storage.
+//! - `manager-cache-api` (disabled): enable the backend manager storing entries with the browser's
+//!   [Cache API](https://developer.mozilla.org/en-US/docs/Web/API/Cache), for `wasm32` builds.
+//! - `manager-indexed-db` (disabled): enable the backend manager storing entries in the browser's
+//!   [IndexedDB](https://developer.mozilla.org/en-US/docs/Web/API/IndexedDB_API), for `wasm32` builds.
 //! - `with-http-types` (disabled): enable [http-types](https://github.com/http-rs/http-types)
-//! type conversion support
+//!   type conversion support
+//! - `encryption` (disabled): enable the `EncryptedManager` wrapper that encrypts entries with AES-256-GCM.
+//! - `compression-zstd` (disabled): enable the `CompressedManager` wrapper that compresses stored bodies,
+//!   with [Zstandard](https://facebook.github.io/zstd/).
+//! - `compression-brotli` (disabled): enable the `CompressedManager` wrapper that compresses stored bodies,
+//!   with [Brotli](https://github.com/google/brotli).
+//! - `metrics` (disabled): enable the `MeteredManager` wrapper that records the operations of a manager
+//!   with the [metrics](https://github.com/metrics-rs/metrics) facade.
+//! - `write-behind` (disabled): enable the `WriteBehindManager` wrapper that applies writes in the background.
+//! - `write-retry` (disabled): enable the `WriteRetryManager` wrapper that retries failed writes in the background.
+//! - `serde` (enabled by the managers): derive `Serialize` and `Deserialize` for [`HttpResponse`],
+//!   [`HttpVersion`] and the cache modes. Builds with only non-serializing managers, like an embedded
+//!   or wasm client, can disable the default features to leave out `serde`.
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+mod config;
 mod error;
 mod managers;
+mod transaction;
 
 use std::{
-    collections::HashMap,
+    collections::{hash_map::DefaultHasher, HashMap},
     convert::TryFrom,
     fmt::{self, Debug},
-    str::FromStr,
-    sync::Arc,
-    time::SystemTime,
+    future::{poll_fn, Future},
+    hash::{Hash, Hasher},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::Poll,
+    time::{Duration, Instant, SystemTime},
 };
 
-use http::{header::CACHE_CONTROL, request, response, StatusCode};
+use http::{
+    header::{
+        HeaderName, HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL,
+        CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, COOKIE, ETAG, EXPIRES,
+        IF_MODIFIED_SINCE, IF_NONE_MATCH, VARY, WARNING,
+    },
+    request, response, HeaderMap, StatusCode,
+};
 use http_cache_semantics::{AfterResponse, BeforeRequest, CachePolicy};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-pub use error::{BadHeader, BadVersion, BoxError, Result};
+pub use error::{
+    BadHeader, BadMetadata, BadVersion, BoxError, ClearUnsupported,
+    CorruptEntry, ListUnsupported, Result, StatsUnsupported, UnexpectedEvent,
+};
+pub use transaction::{CacheTransaction, Event, Step};
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+pub use config::{CacheConfig, ConfiguredManager, ManagerConfig};
 
 #[cfg(feature = "manager-cacache")]
-pub use managers::cacache::CACacheManager;
+pub use managers::cacache::{
+    CACacheManager, CACacheManagerBuilder, EntryMetadata, MaintenanceReport,
+};
+
+#[cfg(all(feature = "manager-cache-api", target_arch = "wasm32"))]
+pub use managers::cache_api::{CacheApiError, CacheApiManager};
+
+#[cfg(any(feature = "compression-zstd", feature = "compression-brotli"))]
+pub use managers::compressed::{CompressedManager, Compression};
+
+pub use managers::distributed::DistributedManager;
+
+#[cfg(all(feature = "manager-indexed-db", target_arch = "wasm32"))]
+pub use managers::indexed_db::{IndexedDbError, IndexedDbManager};
+
+pub use managers::invalidation::{InvalidatingManager, InvalidationBus};
+
+#[cfg(feature = "encryption")]
+pub use managers::encrypted::EncryptedManager;
+
+#[cfg(feature = "manager-foyer")]
+pub use managers::foyer::{FoyerCache, FoyerHasher, FoyerManager};
+
+#[cfg(feature = "metrics")]
+pub use managers::metered::{
+    MeteredManager, DURATION_METRIC, OPERATIONS_METRIC,
+};
+
+pub use managers::mock::{MockCall, MockManager};
 
 #[cfg(feature = "manager-moka")]
-pub use managers::moka::MokaManager;
+pub use managers::moka::{MokaManager, MokaManagerBuilder};
+
+#[cfg(feature = "manager-moka-arc")]
+pub use managers::moka_arc::{MokaArcManager, MokaArcStore};
+
+pub use managers::namespaced::NamespacedManager;
+
+pub use managers::negative::NegativeLookupManager;
+
+pub use managers::null::NullManager;
+
+#[cfg(feature = "manager-object-store")]
+pub use managers::object_store::ObjectStoreManager;
+
+#[cfg(feature = "manager-postgres")]
+pub use managers::postgres::PostgresManager;
+
+pub use managers::replicated::{ReplicatedManager, ReplicationPolicy};
+
+#[cfg(feature = "manager-sled")]
+pub use managers::sled::SledManager;
+
+pub use managers::tiered::TieredManager;
+
+#[cfg(feature = "write-behind")]
+pub use managers::write_behind::{
+    WriteBehindClosed, WriteBehindManager, WriteBehindWorker,
+};
+
+#[cfg(feature = "write-retry")]
+pub use managers::write_retry::{
+    Transient, WriteRetry, WriteRetryManager, WriteRetryWorker,
+};
 
 // Exposing the moka cache for convenience, renaming to avoid naming conflicts
-#[cfg(feature = "manager-moka")]
-#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+#[cfg(any(feature = "manager-moka", feature = "manager-moka-arc"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(feature = "manager-moka", feature = "manager-moka-arc")))
+)]
 pub use moka::future::{Cache as MokaCache, CacheBuilder as MokaCacheBuilder};
 
 // Custom headers used to indicate cache status (hit or miss)
-/// `x-cache` header: Value will be HIT if the response was served from cache, MISS if not
+/// `x-cache` header: Value will be HIT if the response was served from cache, MISS if not,
+/// or the [`CacheState`] if [`HttpCacheOptions::cache_state_header`] is set
 pub const XCACHE: &str = "x-cache";
 /// `x-cache-lookup` header: Value will be HIT if a response existed in cache, MISS if not
 pub const XCACHELOOKUP: &str = "x-cache-lookup";
+/// `x-cache-reason` header: Value will be the [`CacheDecision`] made for the response,
+/// only added when [`HttpCacheOptions::cache_reason_header`] is enabled
+pub const XCACHEREASON: &str = "x-cache-reason";
+
+// Carries the metadata of an entry inside its headers, so every manager stores it without
+// changing the stored format. It's removed from fetched responses, so the origin can't set
+// it, and before the response is served.
+const METADATA_HEADER: &str = "x-http-cache-metadata";
+
+// How long the upstream fetch of an entry took in milliseconds, kept and removed the same
+// way.
+const FETCH_LATENCY_HEADER: &str = "x-http-cache-fetch-latency";
+
+// The byte ranges of a partial entry, like `0-99,200-299`, whose bodies are concatenated
+// in that order.
+const PARTIAL_RANGES_HEADER: &str = "x-http-cache-ranges";
+
+// The variants stored next to an entry with `store_variants`, as comma separated hashes of
+// the request headers they vary on. It's removed before the response is served.
+const VARIANTS_HEADER: &str = "x-http-cache-variants";
+
+// How many variants of an entry are kept, the oldest are dropped
+const MAX_VARIANTS: usize = 32;
 
 /// Represents a basic cache status
 /// Used in the custom headers `x-cache` and `x-cache-lookup`
@@ -82,24 +220,153 @@ impl fmt::Display for HitOrMiss {
     }
 }
 
+impl From<CacheState> for HitOrMiss {
+    fn from(state: CacheState) -> Self {
+        if state.is_hit() {
+            Self::HIT
+        } else {
+            Self::MISS
+        }
+    }
+}
+
+/// Represents the detailed cache status of a response, which [`HitOrMiss`] only tells apart
+/// as served from the cache or not. Used in the custom header `x-cache` when
+/// [`HttpCacheOptions::cache_state_header`] is set, see also [`CacheEvent::Served`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheState {
+    /// A fresh stored response was served
+    Hit,
+    /// No stored response could be used, the response is from the network
+    Miss,
+    /// A stale stored response was served without revalidating it, e.g. because
+    /// revalidating it failed
+    Stale,
+    /// A stale stored response was revalidated with the origin and served
+    Revalidated,
+    /// A stale stored response was replaced by a new response from the network
+    Expired,
+    /// The cache wasn't consulted for the request
+    Bypass,
+}
+
+impl CacheState {
+    /// Returns whether the response was served from the cache
+    pub fn is_hit(&self) -> bool {
+        matches!(self, Self::Hit | Self::Stale | Self::Revalidated)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "HIT" => Self::Hit,
+            "MISS" => Self::Miss,
+            "STALE" => Self::Stale,
+            "REVALIDATED" => Self::Revalidated,
+            "EXPIRED" => Self::Expired,
+            "BYPASS" => Self::Bypass,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for CacheState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Hit => write!(f, "HIT"),
+            Self::Miss => write!(f, "MISS"),
+            Self::Stale => write!(f, "STALE"),
+            Self::Revalidated => write!(f, "REVALIDATED"),
+            Self::Expired => write!(f, "EXPIRED"),
+            Self::Bypass => write!(f, "BYPASS"),
+        }
+    }
+}
+
+/// Describes why the cache made, or would make, a particular decision for a request.
+/// Returned by [`HttpCache::explain`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheDecision {
+    /// The cache mode for the request bypasses the cache entirely
+    Bypass(CacheMode),
+    /// The request method is not GET or HEAD
+    MethodNotCacheable,
+    /// The cache will be consulted for the request, no response was provided to evaluate
+    Lookup,
+    /// The response status code is not cacheable
+    StatusNotCacheable(u16),
+    /// The response headers forbid storing it (e.g. `no-store`, or `private` in a shared cache)
+    NotStorable,
+    /// The response will be stored and can be served without revalidation
+    Fresh,
+    /// The response will be stored but must be revalidated before it is served
+    Stale,
+    /// The stored response was revalidated with the origin and served
+    Revalidated,
+    /// Revalidating the stored response failed, so the stale response was served
+    RevalidationFailed,
+    /// Revalidating the stored response took longer than the timeout of
+    /// [`StaleOnTimeout`], so the stale response was served
+    RevalidationDeadlineExceeded,
+    /// The origin was revalidated more often than the [`RevalidationRateLimit`]
+    /// allows, so the stale response was served
+    RevalidationRateLimited,
+    /// The stored response was stale but within the [`MaxStale`] of the request, or
+    /// [`HttpCacheOptions::read_only_stale`] is set, so it was served without revalidation
+    StaleAccepted,
+    /// No response was stored and the cache mode forbids going to the network
+    OnlyIfCachedMiss,
+    /// The cache mode is [`CacheMode::ReadOnly`], so the response won't be stored
+    ReadOnly,
+}
+
+impl fmt::Display for CacheDecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Bypass(mode) => write!(f, "bypass; mode={mode:?}"),
+            Self::MethodNotCacheable => write!(f, "method-not-cacheable"),
+            Self::Lookup => write!(f, "lookup"),
+            Self::StatusNotCacheable(status) => {
+                write!(f, "status-not-cacheable; status={status}")
+            }
+            Self::NotStorable => write!(f, "not-storable"),
+            Self::Fresh => write!(f, "fresh"),
+            Self::Stale => write!(f, "stale"),
+            Self::Revalidated => write!(f, "revalidated"),
+            Self::RevalidationFailed => write!(f, "revalidation-failed"),
+            Self::RevalidationDeadlineExceeded => {
+                write!(f, "revalidation-deadline-exceeded")
+            }
+            Self::RevalidationRateLimited => {
+                write!(f, "revalidation-rate-limited")
+            }
+            Self::StaleAccepted => write!(f, "stale-accepted"),
+            Self::OnlyIfCachedMiss => write!(f, "only-if-cached-miss"),
+            Self::ReadOnly => write!(f, "read-only"),
+        }
+    }
+}
+
 /// Represents an HTTP version
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 #[non_exhaustive]
 pub enum HttpVersion {
     /// HTTP Version 0.9
-    #[serde(rename = "HTTP/0.9")]
+    #[cfg_attr(feature = "serde", serde(rename = "HTTP/0.9"))]
     Http09,
     /// HTTP Version 1.0
-    #[serde(rename = "HTTP/1.0")]
+    #[cfg_attr(feature = "serde", serde(rename = "HTTP/1.0"))]
     Http10,
     /// HTTP Version 1.1
-    #[serde(rename = "HTTP/1.1")]
+    #[cfg_attr(feature = "serde", serde(rename = "HTTP/1.1"))]
     Http11,
     /// HTTP Version 2.0
-    #[serde(rename = "HTTP/2.0")]
+    #[cfg_attr(feature = "serde", serde(rename = "HTTP/2.0"))]
     H2,
     /// HTTP Version 3.0
-    #[serde(rename = "HTTP/3.0")]
+    #[cfg_attr(feature = "serde", serde(rename = "HTTP/3.0"))]
     H3,
 }
 
@@ -115,13 +382,40 @@ impl fmt::Display for HttpVersion {
     }
 }
 
+/// A record in cache, as listed by [`CacheManager::list`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct CachedEntry {
+    /// The key the record is stored under, to pass to [`CacheManager::delete`]
+    pub cache_key: String,
+    /// The url of the response
+    pub url: Url,
+    /// The method of the request the response answered
+    #[cfg_attr(feature = "serde", serde(with = "method"))]
+    pub method: http::Method,
+    /// When the response was received
+    pub stored_at: SystemTime,
+}
+
+/// The size of a cache, as measured by [`CacheManager::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct CacheStats {
+    /// The number of records
+    pub entries: u64,
+    /// The space the records take in bytes, as the manager stores them
+    pub bytes: u64,
+}
+
 /// A basic generic type that represents an HTTP response
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct HttpResponse {
     /// HTTP response body
-    pub body: Vec<u8>,
+    pub body: Bytes,
     /// HTTP response headers
-    pub headers: HashMap<String, String>,
+    #[cfg_attr(feature = "serde", serde(with = "header_map"))]
+    pub headers: HeaderMap,
     /// HTTP response status code
     pub status: u16,
     /// HTTP response url
@@ -130,28 +424,83 @@ pub struct HttpResponse {
     pub version: HttpVersion,
 }
 
+// Stores the headers as a map of names to values, like the `HashMap<String, String>` they
+// were kept in before, so stored entries stay readable. The values of a header that
+// occurs more than once are joined by newlines, which no value contains.
+#[cfg(feature = "serde")]
+pub(crate) mod header_map {
+    use std::collections::HashMap;
+
+    use http::{HeaderMap, HeaderName, HeaderValue};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        headers: &HeaderMap,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(headers.keys().map(|name| {
+            let values: Vec<_> = headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                .collect();
+            (name.as_str(), values.join("\n"))
+        }))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HeaderMap, D::Error> {
+        let map = HashMap::<String, String>::deserialize(deserializer)?;
+        let mut headers = HeaderMap::with_capacity(map.len());
+        for (name, values) in map {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(D::Error::custom)?;
+            for value in values.split('\n') {
+                headers.append(
+                    &name,
+                    HeaderValue::from_str(value).map_err(D::Error::custom)?,
+                );
+            }
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) mod method {
+    use http::Method;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        method: &Method,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(method.as_str())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Method, D::Error> {
+        let method = String::deserialize(deserializer)?;
+        Method::from_bytes(method.as_bytes()).map_err(D::Error::custom)
+    }
+}
+
 impl HttpResponse {
     /// Returns `http::response::Parts`
     pub fn parts(&self) -> Result<response::Parts> {
         let mut converted =
             response::Builder::new().status(self.status).body(())?;
-        {
-            let headers = converted.headers_mut();
-            for header in &self.headers {
-                headers.insert(
-                    http::header::HeaderName::from_str(header.0.as_str())?,
-                    http::HeaderValue::from_str(header.1.as_str())?,
-                );
-            }
-        }
+        *converted.headers_mut() = self.headers.clone();
         Ok(converted.into_parts().0)
     }
 
     /// Returns the status code of the warning header if present
     #[must_use]
     pub fn warning_code(&self) -> Option<usize> {
-        self.headers.get("warning").and_then(|hdr| {
-            hdr.as_str().chars().take(3).collect::<String>().parse().ok()
+        self.header(WARNING.as_str()).and_then(|hdr| {
+            hdr.chars().take(3).collect::<String>().parse().ok()
         })
     }
 
@@ -166,9 +515,9 @@ impl HttpResponse {
         // warn-text  = quoted-string
         // warn-date  = <"> HTTP-date <">
         // (https://tools.ietf.org/html/rfc2616#section-14.46)
-        self.headers.insert(
-            "warning".to_string(),
-            format!(
+        self.set_header(
+            WARNING.as_str(),
+            &format!(
                 "{} {} {:?} \"{}\"",
                 code,
                 url.host().expect("Invalid URL"),
@@ -180,16 +529,87 @@ impl HttpResponse {
 
     /// Removes a warning header from a response
     pub fn remove_warning(&mut self) {
-        self.headers.remove("warning");
+        self.headers.remove(WARNING);
+    }
+
+    // The value of a header, if it is text
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    // Sets a header added by the cache, leaving it out if the value is invalid
+    fn set_header(&mut self, name: &'static str, value: &str) {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            self.headers.insert(name, value);
+        }
+    }
+
+    // The `Content-Encoding` of a response whose body was already decoded by the
+    // client, detected by a `Content-Length` or gzip header that doesn't match the body.
+    fn decoded_encoding(&self) -> Option<String> {
+        let coding = self.header(CONTENT_ENCODING.as_str())?;
+        if self.body.is_empty() || coding.eq_ignore_ascii_case("identity") {
+            return None;
+        }
+        let length_differs = self
+            .header(CONTENT_LENGTH.as_str())
+            .and_then(|length| length.parse::<usize>().ok())
+            .map_or(false, |length| length != self.body.len());
+        let missing_gzip_header = (coding.eq_ignore_ascii_case("gzip")
+            || coding.eq_ignore_ascii_case("x-gzip"))
+            && !self.body.starts_with(&[0x1f, 0x8b]);
+        (length_differs || missing_gzip_header).then(|| coding.to_string())
+    }
+
+    fn set_body_encoding(&mut self, body: Bytes, coding: Option<&str>) {
+        self.headers.remove(CONTENT_ENCODING);
+        if let Some(coding) = coding {
+            self.set_header(CONTENT_ENCODING.as_str(), coding);
+        }
+        self.headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+        self.body = body;
+    }
+
+    /// Returns the metadata stored with the entry, see [`HttpCacheOptions::store_metadata`].
+    #[must_use]
+    pub fn metadata(&self) -> Option<&str> {
+        self.header(METADATA_HEADER)
+    }
+
+    // Sets the metadata stored with the entry, failing rather than dropping a value that
+    // can't be read back as text from a header
+    fn set_metadata(&mut self, metadata: &str) -> Result<()> {
+        if !metadata.bytes().all(|b| b == b'\t' || (b' '..=b'~').contains(&b)) {
+            return Err(BadMetadata.into());
+        }
+        self.headers.insert(METADATA_HEADER, HeaderValue::from_str(metadata)?);
+        Ok(())
+    }
+
+    /// Removes and returns the metadata stored with the entry, client implementations
+    /// call this before converting the response so it isn't sent as a header.
+    pub fn take_metadata(&mut self) -> Option<String> {
+        self.headers
+            .remove(METADATA_HEADER)
+            .and_then(|value| value.to_str().ok().map(str::to_string))
+    }
+
+    /// Returns how long the upstream fetch of the entry took, if it was recorded when the
+    /// entry was stored.
+    #[must_use]
+    pub fn fetch_latency(&self) -> Option<Duration> {
+        self.header(FETCH_LATENCY_HEADER)
+            .and_then(|millis| millis.parse().ok())
+            .map(Duration::from_millis)
     }
 
     /// Update the headers from `http::response::Parts`
     pub fn update_headers(&mut self, parts: &response::Parts) -> Result<()> {
-        for header in parts.headers.iter() {
-            self.headers.insert(
-                header.0.as_str().to_string(),
-                header.1.to_str()?.to_string(),
-            );
+        for name in parts.headers.keys() {
+            self.headers.remove(name);
+            for value in parts.headers.get_all(name) {
+                self.headers.append(name, value.clone());
+            }
         }
         Ok(())
     }
@@ -197,44 +617,286 @@ impl HttpResponse {
     /// Checks if the Cache-Control header contains the must-revalidate directive
     #[must_use]
     pub fn must_revalidate(&self) -> bool {
-        self.headers.get(CACHE_CONTROL.as_str()).map_or(false, |val| {
-            val.as_str().to_lowercase().contains("must-revalidate")
+        self.headers.get_all(CACHE_CONTROL).iter().any(|val| {
+            val.to_str().map_or(false, |val| {
+                val.to_lowercase().contains("must-revalidate")
+            })
         })
     }
 
     /// Adds the custom `x-cache` header to the response
     pub fn cache_status(&mut self, hit_or_miss: HitOrMiss) {
-        self.headers.insert(XCACHE.to_string(), hit_or_miss.to_string());
+        self.set_header(XCACHE, &hit_or_miss.to_string());
+    }
+
+    /// Adds the custom `x-cache` header to the response with the detailed cache state
+    pub fn cache_state(&mut self, state: CacheState) {
+        self.set_header(XCACHE, &state.to_string());
     }
 
     /// Adds the custom `x-cache-lookup` header to the response
     pub fn cache_lookup_status(&mut self, hit_or_miss: HitOrMiss) {
-        self.headers.insert(XCACHELOOKUP.to_string(), hit_or_miss.to_string());
+        self.set_header(XCACHELOOKUP, &hit_or_miss.to_string());
+    }
+
+    /// Adds the custom `x-cache-reason` header to the response
+    pub fn cache_reason(&mut self, decision: CacheDecision) {
+        self.set_header(XCACHEREASON, &decision.to_string());
     }
 }
 
+/// Marker trait that requires [`Send`] everywhere except on `wasm32` targets,
+/// where single-threaded executors and JS handles are not `Send`.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// Marker trait that requires [`Send`] everywhere except on `wasm32` targets,
+/// where single-threaded executors and JS handles are not `Send`.
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// Marker trait that requires [`Sync`] everywhere except on `wasm32` targets,
+/// where single-threaded executors and JS handles are not `Sync`.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSync: Sync {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+
+/// Marker trait that requires [`Sync`] everywhere except on `wasm32` targets,
+/// where single-threaded executors and JS handles are not `Sync`.
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSync {}
+#[cfg(target_arch = "wasm32")]
+impl<T: ?Sized> MaybeSync for T {}
+
 /// A trait providing methods for storing, reading, and removing cache records.
-#[async_trait::async_trait]
-pub trait CacheManager: Send + Sync + 'static {
+///
+/// It's implemented for `Arc<T>`, `Box<T>` and `&'static T` of any manager, so one manager
+/// can be shared between several caches.
+///
+/// On `wasm32` targets the trait does not require `Send` or `Sync` and its futures
+/// are not `Send`, implementations there should use `#[async_trait(?Send)]`.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+pub trait CacheManager: MaybeSend + MaybeSync + 'static {
+    /// The error type returned by the manager. Managers that don't need to report
+    /// specific failures can use [`BoxError`].
+    type Error: Into<BoxError>;
     /// Attempts to pull a cached response and related policy from cache.
     async fn get(
         &self,
         cache_key: &str,
-    ) -> Result<Option<(HttpResponse, CachePolicy)>>;
+    ) -> std::result::Result<Option<(HttpResponse, CachePolicy)>, Self::Error>;
     /// Attempts to cache a response and related policy.
     async fn put(
         &self,
         cache_key: String,
         res: HttpResponse,
         policy: CachePolicy,
-    ) -> Result<HttpResponse>;
+    ) -> std::result::Result<HttpResponse, Self::Error>;
+    /// Attempts to remove a record from cache.
+    async fn delete(
+        &self,
+        cache_key: &str,
+    ) -> std::result::Result<(), Self::Error>;
+    /// Attempts to pull the cached responses and related policies of several keys, in the
+    /// order of `cache_keys`, e.g. to prefetch a page's resources. The default gets them one
+    /// after the other; managers that can read several records at once override it.
+    async fn get_many(
+        &self,
+        cache_keys: &[&str],
+    ) -> std::result::Result<
+        Vec<Option<(HttpResponse, CachePolicy)>>,
+        Self::Error,
+    > {
+        let mut entries = Vec::with_capacity(cache_keys.len());
+        for cache_key in cache_keys {
+            entries.push(self.get(cache_key).await?);
+        }
+        Ok(entries)
+    }
+    /// Attempts to remove the records of several keys from cache, e.g. to invalidate every
+    /// page of a changed resource. The default deletes them one after the other and stops at
+    /// the first failure; managers that can remove several records at once override it.
+    async fn delete_many(
+        &self,
+        cache_keys: &[&str],
+    ) -> std::result::Result<(), Self::Error> {
+        for cache_key in cache_keys {
+            self.delete(cache_key).await?;
+        }
+        Ok(())
+    }
+    /// Applies any writes that are still pending and stops background work, so nothing is
+    /// lost when the process exits. Called by [`HttpCache::shutdown`], the default does nothing.
+    async fn shutdown(&self) -> std::result::Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Removes every record from cache, e.g. when a user logs out or the format of the
+    /// responses changes. The default fails with [`ClearUnsupported`], which is reported as
+    /// a [`BoxError`] so managers with their own error type don't have to represent it.
+    async fn clear(&self) -> Result<()> {
+        Err(ClearUnsupported.into())
+    }
+    /// Lists the records in cache, e.g. to show what is cached and delete some of them. The
+    /// default fails with [`ListUnsupported`], reported as a [`BoxError`] like for
+    /// [`clear`](Self::clear).
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        Err(ListUnsupported.into())
+    }
+    /// Measures the records in cache, e.g. to report the memory or disk the cache takes and
+    /// run maintenance once it grows past a limit. The default fails with
+    /// [`StatsUnsupported`], reported as a [`BoxError`] like for [`clear`](Self::clear).
+    async fn stats(&self) -> Result<CacheStats> {
+        Err(StatsUnsupported.into())
+    }
+}
+
+/// A [`CacheManager`] that can also store, read and remove records blocking the current
+/// thread, for synchronous clients that don't run an async runtime. Drive a
+/// [`CacheTransaction`] with these instead of the async methods, or let
+/// [`HttpCache::run_blocking`] drive it.
+pub trait BlockingCacheManager: CacheManager {
+    /// Attempts to pull a cached response and related policy from cache.
+    fn get_blocking(
+        &self,
+        cache_key: &str,
+    ) -> std::result::Result<Option<(HttpResponse, CachePolicy)>, Self::Error>;
+    /// Attempts to cache a response and related policy.
+    fn put_blocking(
+        &self,
+        cache_key: String,
+        res: HttpResponse,
+        policy: CachePolicy,
+    ) -> std::result::Result<HttpResponse, Self::Error>;
     /// Attempts to remove a record from cache.
-    async fn delete(&self, cache_key: &str) -> Result<()>;
+    fn delete_blocking(
+        &self,
+        cache_key: &str,
+    ) -> std::result::Result<(), Self::Error>;
+}
+
+/// A type-erased [`CacheManager`], for selecting the manager at runtime without its type
+/// appearing in every signature. Any manager reporting [`BoxError`]s can be used as one,
+/// e.g. `let manager: DynCacheManager = Arc::new(MokaManager::default());`.
+pub type DynCacheManager = Arc<dyn CacheManager<Error = BoxError>>;
+
+/// An [`HttpCache`] using a [`DynCacheManager`], see [`HttpCache::into_dyn`].
+pub type DynHttpCache = HttpCache<DynCacheManager>;
+
+// Forwards to the manager behind a pointer, so one manager can be shared between caches.
+macro_rules! forward_cache_manager {
+    ($($pointer:ty),*) => {$(
+        #[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+        #[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+        impl<T: CacheManager + ?Sized> CacheManager for $pointer {
+            type Error = T::Error;
+
+            async fn get(
+                &self,
+                cache_key: &str,
+            ) -> std::result::Result<Option<(HttpResponse, CachePolicy)>, Self::Error>
+            {
+                (**self).get(cache_key).await
+            }
+
+            async fn put(
+                &self,
+                cache_key: String,
+                res: HttpResponse,
+                policy: CachePolicy,
+            ) -> std::result::Result<HttpResponse, Self::Error> {
+                (**self).put(cache_key, res, policy).await
+            }
+
+            async fn delete(
+                &self,
+                cache_key: &str,
+            ) -> std::result::Result<(), Self::Error> {
+                (**self).delete(cache_key).await
+            }
+
+            async fn get_many(
+                &self,
+                cache_keys: &[&str],
+            ) -> std::result::Result<
+                Vec<Option<(HttpResponse, CachePolicy)>>,
+                Self::Error,
+            > {
+                (**self).get_many(cache_keys).await
+            }
+
+            async fn delete_many(
+                &self,
+                cache_keys: &[&str],
+            ) -> std::result::Result<(), Self::Error> {
+                (**self).delete_many(cache_keys).await
+            }
+
+            async fn shutdown(&self) -> std::result::Result<(), Self::Error> {
+                (**self).shutdown().await
+            }
+
+            async fn clear(&self) -> Result<()> {
+                (**self).clear().await
+            }
+
+            async fn list(&self) -> Result<Vec<CachedEntry>> {
+                (**self).list().await
+            }
+
+            async fn stats(&self) -> Result<CacheStats> {
+                (**self).stats().await
+            }
+        }
+    )*};
+}
+
+forward_cache_manager!(Arc<T>, Box<T>, &'static T);
+
+// Forwards the blocking methods as well, see `forward_cache_manager`
+macro_rules! forward_blocking_cache_manager {
+    ($($pointer:ty),*) => {$(
+        impl<T: BlockingCacheManager + ?Sized> BlockingCacheManager for $pointer {
+            fn get_blocking(
+                &self,
+                cache_key: &str,
+            ) -> std::result::Result<Option<(HttpResponse, CachePolicy)>, Self::Error>
+            {
+                (**self).get_blocking(cache_key)
+            }
+
+            fn put_blocking(
+                &self,
+                cache_key: String,
+                res: HttpResponse,
+                policy: CachePolicy,
+            ) -> std::result::Result<HttpResponse, Self::Error> {
+                (**self).put_blocking(cache_key, res, policy)
+            }
+
+            fn delete_blocking(
+                &self,
+                cache_key: &str,
+            ) -> std::result::Result<(), Self::Error> {
+                (**self).delete_blocking(cache_key)
+            }
+        }
+    )*};
 }
 
+forward_blocking_cache_manager!(Arc<T>, Box<T>, &'static T);
+
 /// Describes the functionality required for interfacing with HTTP client middleware
-#[async_trait::async_trait]
-pub trait Middleware: Send {
+///
+/// As with [`CacheManager`], `Send` is not required on `wasm32` targets.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+pub trait Middleware: MaybeSend {
     /// Determines if the request method is either GET or HEAD
     fn is_method_get_head(&self) -> bool;
     /// Returns a new cache policy with default options
@@ -249,6 +911,14 @@ pub trait Middleware: Send {
     fn update_headers(&mut self, parts: &request::Parts) -> Result<()>;
     /// Attempts to force the "no-cache" directive on the request
     fn force_no_cache(&mut self) -> Result<()>;
+    /// Marks the request as sent by the cache to revalidate a stored response, by adding
+    /// the [`CacheRevalidationRequest`] to its extensions. Does nothing by default.
+    fn mark_revalidation(
+        &mut self,
+        _revalidation: CacheRevalidationRequest,
+    ) -> Result<()> {
+        Ok(())
+    }
     /// Attempts to construct `http::request::Parts` from the request
     fn parts(&self) -> Result<request::Parts>;
     /// Attempts to determine the requested url
@@ -257,11 +927,27 @@ pub trait Middleware: Send {
     fn method(&self) -> Result<String>;
     /// Attempts to fetch an upstream resource and return an [`HttpResponse`]
     async fn remote_fetch(&mut self) -> Result<HttpResponse>;
+    /// Takes the request to fetch it like [`remote_fetch`](Self::remote_fetch), with a future
+    /// that doesn't borrow the middleware so it can finish in the background, see
+    /// [`BackgroundRevalidation`]. The middleware isn't used to fetch afterwards. Clients that
+    /// can only send the request while the middleware runs return `None`, the default.
+    fn detached_fetch(&mut self) -> Result<Option<DetachedFetch>> {
+        Ok(None)
+    }
 }
 
 /// Similar to [make-fetch-happen cache options](https://github.com/npm/make-fetch-happen#--optscache).
 /// Passed in when the [`HttpCache`] struct is being built.
+///
+/// Inserted into the extensions of a request, e.g. with `with_extension` in
+/// reqwest-middleware or `set_ext` in surf, it overrides the mode of the cache for that request alone, so one
+/// client can mix cached and uncached calls.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
 pub enum CacheMode {
     /// Will inspect the HTTP cache on the way to the network.
     /// If there is a fresh response it will be used.
@@ -286,12 +972,29 @@ pub enum CacheMode {
     /// Uses any response in the HTTP cache matching the request,
     /// not paying attention to staleness. If there was no response,
     /// it returns a network error.
+    /// Requests with `Cache-Control: only-if-cached` are made in this mode whatever the
+    /// mode of the cache, unless another is set in their extensions.
     OnlyIfCached,
     /// Overrides the check that determines if a response can be cached to always return true on 200.
     /// Uses any response in the HTTP cache matching the request,
     /// not paying attention to staleness. If there was no response,
     /// it creates a normal request and updates the HTTP cache with the response.
     IgnoreRules,
+    /// Behaves as if there is no HTTP cache on the way to the network, but if a stored
+    /// response would have been served it is compared with the response from the network,
+    /// see [`CacheEvent::ShadowCompared`]. It then updates the HTTP cache with the response.
+    Shadow,
+    /// Behaves as if there is no HTTP cache on the way to the network, but looks up the
+    /// stored response and reports whether it would have been served, see
+    /// [`CacheEvent::Lookup`]. It then updates the HTTP cache with the response.
+    /// This can be used to measure the hit ratio before enabling caching.
+    DryRun,
+    /// Serves fresh responses from the HTTP cache but never changes it: responses from the
+    /// network aren't stored, stored responses aren't updated and nothing is deleted, not
+    /// even by unsafe requests. Stale responses are fetched anew unless
+    /// [`HttpCacheOptions::read_only_stale`] is set.
+    /// This can be used to share a pre-populated cache, e.g. baked into an image.
+    ReadOnly,
 }
 
 impl TryFrom<http::Version> for HttpVersion {
@@ -350,12 +1053,43 @@ impl From<HttpVersion> for http_types::Version {
     }
 }
 
+/// The body of an [`HttpResponse`], provided by [`bytes`](https://docs.rs/bytes). Clones
+/// share the buffer instead of copying it.
+pub use bytes::Bytes;
+
 /// Options struct provided by
 /// [`http-cache-semantics`](https://github.com/kornelski/rusty-http-cache-semantics).
 pub use http_cache_semantics::CacheOptions;
 
 /// A closure that takes [`http::request::Parts`] and returns a [`String`].
 /// By default, the cache key is a combination of the request method and uri with a colon in between.
+///
+/// For example, to store responses separately for each tenant and share them between
+/// sessions:
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use http_cache::{CacheKey, HttpCacheOptions};
+/// let cache_key: CacheKey = Arc::new(|parts: &http::request::Parts| {
+///     let tenant = parts
+///         .headers
+///         .get("x-tenant-id")
+///         .and_then(|value| value.to_str().ok())
+///         .unwrap_or_default();
+///     let mut url = url::Url::parse(&parts.uri.to_string()).unwrap();
+///     let query: Vec<(String, String)> = url
+///         .query_pairs()
+///         .filter(|(name, _)| name != "session")
+///         .map(|(name, value)| (name.into_owned(), value.into_owned()))
+///         .collect();
+///     url.set_query(None);
+///     if !query.is_empty() {
+///         url.query_pairs_mut().extend_pairs(query);
+///     }
+///     format!("{tenant}:{}:{url}", parts.method)
+/// });
+/// let options = HttpCacheOptions { cache_key: Some(cache_key), ..Default::default() };
+/// ```
 pub type CacheKey = Arc<dyn Fn(&request::Parts) -> String + Send + Sync>;
 
 /// A closure that takes [`http::request::Parts`] and returns a [`CacheMode`]
@@ -369,74 +1103,1721 @@ pub type CacheBust = Arc<
         + Sync,
 >;
 
-/// Can be used to override the default [`CacheOptions`] and cache key.
-/// The cache key is a closure that takes [`http::request::Parts`] and returns a [`String`].
-#[derive(Default, Clone)]
-pub struct HttpCacheOptions {
-    /// Override the default cache options.
-    pub cache_options: Option<CacheOptions>,
-    /// Override the default cache key generator.
-    pub cache_key: Option<CacheKey>,
-    /// Override the default cache mode.
-    pub cache_mode_fn: Option<CacheModeFn>,
-    /// Bust the caches of the returned keys.
-    pub cache_bust: Option<CacheBust>,
-}
+/// A closure that takes [`http::request::Parts`] and a mutable [`HttpResponse`] fetched from upstream.
+/// It runs before the cache policy is computed, so any header rewrites (for example adding
+/// `Cache-Control: public, max-age=300` or stripping `Set-Cookie`) affect whether and how long
+/// the response is stored as well as what is served.
+pub type ModifyResponse =
+    Arc<dyn Fn(&request::Parts, &mut HttpResponse) + Send + Sync>;
 
-impl Debug for HttpCacheOptions {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("HttpCacheOptions")
-            .field("cache_options", &self.cache_options)
-            .field("cache_key", &"Fn(&request::Parts) -> String")
-            .field("cache_mode_fn", &"Fn(&request::Parts) -> CacheMode")
-            .field("cache_bust", &"Fn(&request::Parts) -> Vec<String>")
-            .finish()
-    }
+/// A closure that takes [`http::request::Parts`] and returns the time to live to assume for a
+/// response that carries no freshness information at all (neither `Cache-Control` nor `Expires`).
+/// Returning [`None`] leaves the response as it is.
+pub type AssumeCacheable =
+    Arc<dyn Fn(&request::Parts) -> Option<Duration> + Send + Sync>;
+
+/// A closure that takes the [`http::request::Parts`] of a request and the [`HttpResponse`]
+/// about to be stored for it, and returns metadata to store with the entry, such as the
+/// mirror the response came from. Structured values can be serialized, e.g. as JSON, but
+/// the text must be visible ASCII on a single line.
+pub type StoreMetadata =
+    Arc<dyn Fn(&request::Parts, &HttpResponse) -> Option<String> + Send + Sync>;
+
+/// The metadata stored with an entry, added to the extensions of responses by client
+/// implementations. See [`HttpCacheOptions::store_metadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheMetadata(pub String);
+
+/// What a stored entry cost to obtain, passed to an [`EntryWeigher`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryCost {
+    /// The size of the stored entry in bytes
+    pub size: usize,
+    /// How long the upstream fetch took, see [`HttpResponse::fetch_latency`]
+    pub fetch_latency: Option<Duration>,
 }
 
-impl HttpCacheOptions {
-    fn create_cache_key(
-        &self,
-        parts: &request::Parts,
-        override_method: Option<&str>,
-    ) -> String {
-        if let Some(cache_key) = &self.cache_key {
-            cache_key(parts)
-        } else {
-            format!(
-                "{}:{}",
-                override_method.unwrap_or_else(|| parts.method.as_str()),
-                parts.uri
-            )
-        }
+impl EntryCost {
+    /// Returns the size of the entry discounted by how expensive it was to fetch, so an
+    /// entry fetched in `reference` weighs half its size and slower ones even less.
+    /// Entries without a recorded latency weigh their full size.
+    #[must_use]
+    pub fn latency_discounted(&self, reference: Duration) -> u32 {
+        let size = u32::try_from(self.size).unwrap_or(u32::MAX);
+        let Some(latency) = self.fetch_latency else {
+            return size;
+        };
+        let reference = reference.as_millis().max(1);
+        let discounted =
+            u128::from(size) * reference / (reference + latency.as_millis());
+        u32::try_from(discounted).unwrap_or(u32::MAX).max(1)
     }
 }
 
-/// Caches requests according to http spec.
-#[derive(Debug, Clone)]
-pub struct HttpCache<T: CacheManager> {
-    /// Determines the manager behavior.
-    pub mode: CacheMode,
-    /// Manager instance that implements the [`CacheManager`] trait.
-    /// By default, a manager implementation with [`cacache`](https://github.com/zkat/cacache-rs)
-    /// as the backend has been provided, see [`CACacheManager`].
-    pub manager: T,
-    /// Override the default cache options.
-    pub options: HttpCacheOptions,
+/// A closure that takes the [`EntryCost`] of an entry and returns its weight against the
+/// capacity of a manager, such as the `weigher` of a `MokaManagerBuilder`. Entries that are cheap
+/// to fetch again can be given a larger weight so capacity is spent on expensive ones.
+pub type EntryWeigher = Arc<dyn Fn(&EntryCost) -> u32 + Send + Sync>;
+
+/// Lets a single request accept a stored response that has been stale for up to the given
+/// duration without revalidating it, even in [`CacheMode::Default`], for latency-critical
+/// paths that prefer slightly old data. Insert it into the extensions of the request, e.g.
+/// with `with_extension` in reqwest-middleware or `set_ext` in surf.
+///
+/// The duration is rounded down to whole seconds, and responses with `must-revalidate`
+/// are still revalidated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxStale(pub Duration);
+
+/// Added by the client implementations to the extensions of requests the cache sends to
+/// revalidate a stored response, so middlewares further down, e.g. for retries or tracing,
+/// can tell them apart from the requests made by the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheRevalidationRequest {
+    /// The request was made conditional on the validators of a stale stored response
+    Conditional,
+    /// The request was sent with `Cache-Control: no-cache` by [`CacheMode::NoCache`]
+    NoCache,
 }
 
-#[allow(dead_code)]
-impl<T: CacheManager> HttpCache<T> {
-    /// Determines if the request should be cached
+/// A closure that takes an [`HttpResponse`] fetched from upstream and returns whether it may be
+/// stored, for origins that signal cacheability with custom headers rather than `Cache-Control`.
+/// It runs after the cache policy, so it can only prevent responses from being stored.
+pub type StorePredicate = Arc<dyn Fn(&HttpResponse) -> bool + Send + Sync>;
+
+/// Something that happened in the cache, reported to the [`Observer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheEvent {
+    /// The manager was consulted for a request.
+    Lookup {
+        /// Key that was looked up
+        cache_key: String,
+        /// What was found
+        outcome: LookupOutcome,
+    },
+    /// The [`Admission`] policy decided whether a new response is stored.
+    Admission {
+        /// Key the response would be stored under
+        cache_key: String,
+        /// Whether the response is stored
+        admitted: bool,
+    },
+    /// A response was written to the manager.
+    Stored {
+        /// Key the response was stored under
+        cache_key: String,
+        /// Size of the response body in bytes
+        body_size: usize,
+    },
+    /// A response wasn't stored because its headers exceed the [`HeaderLimits`].
+    HeadersRejected {
+        /// Key the response would be stored under
+        cache_key: String,
+        /// The limit that was exceeded
+        limit: HeaderLimit,
+    },
+    /// A write to the manager failed for good, see `WriteRetryManager`.
+    WriteFailed {
+        /// Key the response would have been stored under
+        cache_key: String,
+        /// How many times the write was attempted
+        attempts: u32,
+    },
+    /// A response was served for a request the cache was consulted for.
+    Served {
+        /// Key of the response
+        cache_key: String,
+        /// How the response was served
+        state: CacheState,
+    },
+    /// A stored entry couldn't be read back, see [`CorruptEntry`]. It was treated as a miss
+    /// and deleted, unless the cache mode is [`CacheMode::ReadOnly`].
+    CorruptEntry {
+        /// Key of the entry
+        cache_key: String,
+        /// Why the entry couldn't be read
+        error: String,
+    },
+    /// [`CacheMode::Shadow`] compared a stored response that would have been served with
+    /// the response from the network.
+    ShadowCompared {
+        /// Key of the stored response
+        cache_key: String,
+        /// How the responses differ, empty if they match
+        divergences: Vec<Divergence>,
+    },
+}
+
+/// Limits on the headers of stored responses, so an origin can't bloat the cache with
+/// header data. Names and values are measured in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// The most headers a stored response may have
+    pub max_count: usize,
+    /// The most bytes a single header may have
+    pub max_header_size: usize,
+    /// The most bytes all headers of a stored response may have together
+    pub max_total_size: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self { max_count: 100, max_header_size: 8192, max_total_size: 65536 }
+    }
+}
+
+impl HeaderLimits {
+    fn exceeded(&self, response: &HttpResponse) -> Option<HeaderLimit> {
+        if response.headers.len() > self.max_count {
+            return Some(HeaderLimit::Count);
+        }
+        let mut total = 0;
+        for (name, value) in &response.headers {
+            let size = name.as_str().len() + value.len();
+            if size > self.max_header_size {
+                return Some(HeaderLimit::HeaderSize);
+            }
+            total += size;
+        }
+        (total > self.max_total_size).then_some(HeaderLimit::TotalSize)
+    }
+}
+
+/// Which of the [`HeaderLimits`] a response exceeded, see [`CacheEvent::HeadersRejected`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HeaderLimit {
+    /// It has more than `max_count` headers
+    Count,
+    /// One of its headers is larger than `max_header_size`
+    HeaderSize,
+    /// Its headers are larger than `max_total_size` together
+    TotalSize,
+}
+
+/// What a lookup found, see [`CacheEvent::Lookup`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LookupOutcome {
+    /// Nothing was stored for the request
+    Miss,
+    /// A stored response can be served without going to the network
+    Fresh,
+    /// A stored response was found but must be revalidated before it is served
+    Stale,
+}
+
+/// A difference between a stored response and the response from the network,
+/// see [`CacheEvent::ShadowCompared`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Divergence {
+    /// The status codes differ
+    Status {
+        /// Status of the stored response
+        cached: u16,
+        /// Status of the response from the network
+        fresh: u16,
+    },
+    /// One of the compared headers differs or is missing from one of the responses.
+    /// The compared headers are `content-type`, `content-encoding`, `content-language`,
+    /// `etag`, `last-modified`, `location` and `vary`.
+    Header {
+        /// Name of the header
+        name: &'static str,
+        /// Value in the stored response
+        cached: Option<String>,
+        /// Value in the response from the network
+        fresh: Option<String>,
+    },
+    /// The bodies differ
+    Body {
+        /// Hash of the stored body, only comparable within the same process
+        cached: u64,
+        /// Hash of the body from the network
+        fresh: u64,
+    },
+}
+
+const SHADOW_HEADERS: &[&str] = &[
+    "content-type",
+    "content-encoding",
+    "content-language",
+    "etag",
+    "last-modified",
+    "location",
+    "vary",
+];
+
+impl Divergence {
+    fn compare(cached: &HttpResponse, fresh: &HttpResponse) -> Vec<Self> {
+        let mut divergences = Vec::new();
+        if cached.status != fresh.status {
+            divergences.push(Self::Status {
+                cached: cached.status,
+                fresh: fresh.status,
+            });
+        }
+        for name in SHADOW_HEADERS {
+            let cached = cached.header(name);
+            let fresh = fresh.header(name);
+            if cached != fresh {
+                divergences.push(Self::Header {
+                    name,
+                    cached: cached.map(str::to_string),
+                    fresh: fresh.map(str::to_string),
+                });
+            }
+        }
+        if cached.body != fresh.body {
+            divergences.push(Self::Body {
+                cached: hash_body(&cached.body),
+                fresh: hash_body(&fresh.body),
+            });
+        }
+        divergences
+    }
+}
+
+// 64-bit FNV-1a. Unlike `DefaultHasher` it is the same in every process and Rust version,
+// for decisions every instance of a fleet must agree on.
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn hash_body(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A closure that takes a [`CacheEvent`] as it happens, for example to record metrics.
+/// It is called inline, so it should return quickly.
+pub type Observer = Arc<dyn Fn(&CacheEvent) + Send + Sync>;
+
+/// A future completing once a duration has passed, returned by a [`Sleep`] closure.
+#[cfg(not(target_arch = "wasm32"))]
+pub type SleepFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A future completing once a duration has passed, returned by a [`Sleep`] closure.
+#[cfg(target_arch = "wasm32")]
+pub type SleepFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A closure that takes a [`Duration`] and returns a future completing once it has passed,
+/// for example `Arc::new(|d| Box::pin(tokio::time::sleep(d)))`. This keeps the cache
+/// independent of the async runtime in use.
+pub type Sleep = Arc<dyn Fn(Duration) -> SleepFuture + Send + Sync>;
+
+/// A closure that takes the [`http::request::Parts`] of a request that was served a stale
+/// response because revalidating it exceeded the timeout of [`StaleOnTimeout`].
+pub type Refresh = Arc<dyn Fn(&request::Parts) + Send + Sync>;
+
+/// A future to run in the background, passed to a [`Spawn`] closure.
+#[cfg(not(target_arch = "wasm32"))]
+pub type SpawnFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A future to run in the background, passed to a [`Spawn`] closure.
+#[cfg(target_arch = "wasm32")]
+pub type SpawnFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A closure that runs a [`SpawnFuture`] to completion in the background, for example
+/// `Arc::new(|future| drop(tokio::spawn(future)))`. Like [`Sleep`], this keeps the cache
+/// independent of the async runtime in use.
+pub type Spawn = Arc<dyn Fn(SpawnFuture) + Send + Sync>;
+
+/// A fetch of a request that doesn't borrow its [`Middleware`], returned by
+/// [`Middleware::detached_fetch`].
+#[cfg(not(target_arch = "wasm32"))]
+pub type DetachedFetch =
+    Pin<Box<dyn Future<Output = Result<HttpResponse>> + Send>>;
+
+/// A fetch of a request that doesn't borrow its [`Middleware`], returned by
+/// [`Middleware::detached_fetch`].
+#[cfg(target_arch = "wasm32")]
+pub type DetachedFetch = Pin<Box<dyn Future<Output = Result<HttpResponse>>>>;
+
+/// Stops waiting for revalidating a stale response that takes longer than a timeout and
+/// serves the stale response instead.
+///
+/// With `background`, the revalidation keeps running once the timeout passes and its response
+/// refreshes the entry, if the client can send it without the request it belongs to, see
+/// [`Middleware::detached_fetch`]. Otherwise it's cancelled and the entry stays stale; to
+/// refresh it, spawn a request for it from `refresh`, made with [`CacheMode::NoCache`] so the
+/// timeout doesn't apply to it. Responses with `must-revalidate` are never served stale and
+/// always wait for the origin.
+#[derive(Clone)]
+pub struct StaleOnTimeout {
+    /// How long to wait for the origin before serving the stale response.
+    pub timeout: Duration,
+    /// Used to wait for the timeout.
+    pub sleep: Sleep,
+    /// Called after serving the stale response, e.g. to refresh the entry in the background.
+    /// It's given the request as it was made, without the validators added to revalidate.
+    pub refresh: Option<Refresh>,
+    /// Lets the revalidation finish in the background after serving the stale response.
+    pub background: Option<BackgroundRevalidation>,
+}
+
+impl Debug for StaleOnTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaleOnTimeout")
+            .field("timeout", &self.timeout)
+            .field("sleep", &"Fn(Duration) -> SleepFuture")
+            .field(
+                "refresh",
+                &self.refresh.as_ref().map(|_| "Fn(&request::Parts)"),
+            )
+            .field("background", &self.background)
+            .finish()
+    }
+}
+
+/// Runs the revalidations that [`StaleOnTimeout`] stopped waiting for to completion, so
+/// their responses refresh the entries.
+///
+/// The revalidations are run by `spawn`. The manager belongs to the cache, so the response
+/// of a finished revalidation is stored when the cache handles its next request, before
+/// looking it up, or when it shuts down.
+#[derive(Clone)]
+pub struct BackgroundRevalidation {
+    spawn: Spawn,
+    finished: Arc<Mutex<Vec<FinishedRevalidation>>>,
+}
+
+// A revalidation that finished in the background, with what is needed to store its response
+#[derive(Debug)]
+struct FinishedRevalidation {
+    parts: request::Parts,
+    url: Url,
+    cached: HttpResponse,
+    policy: CachePolicy,
+    fetched: Result<HttpResponse>,
+    fetch_latency: Duration,
+}
+
+impl Debug for BackgroundRevalidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackgroundRevalidation")
+            .field("spawn", &"Fn(SpawnFuture)")
+            .finish_non_exhaustive()
+    }
+}
+
+impl BackgroundRevalidation {
+    /// Runs the revalidations with `spawn`.
+    pub fn new(spawn: Spawn) -> Self {
+        Self { spawn, finished: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    // Runs the fetch in the background, keeping its response to store
+    fn spawn(
+        &self,
+        fetch: DetachedFetch,
+        parts: request::Parts,
+        url: Url,
+        cached: HttpResponse,
+        policy: CachePolicy,
+        started: Instant,
+    ) {
+        let finished = self.finished.clone();
+        (self.spawn)(Box::pin(async move {
+            let fetched = fetch.await;
+            let fetch_latency = started.elapsed();
+            finished.lock().unwrap().push(FinishedRevalidation {
+                parts,
+                url,
+                cached,
+                policy,
+                fetched,
+                fetch_latency,
+            });
+        }));
+    }
+
+    fn take_finished(&self) -> Vec<FinishedRevalidation> {
+        std::mem::take(&mut *self.finished.lock().unwrap())
+    }
+}
+
+/// Refreshes entries that are about to go stale when they are served, so frequently
+/// requested entries never have to be revalidated while a request waits.
+///
+/// Once an entry has less than `fraction` of its freshness lifetime left, `refresh` is called
+/// the next time it is served, at most once for each stored response. The cache can't make
+/// requests of its own, so `refresh` should spawn a request for it made with
+/// [`CacheMode::NoCache`], which stores the new response.
+#[derive(Clone)]
+pub struct RefreshAhead {
+    fraction: f64,
+    refresh: Refresh,
+    // Expiry of the stored response a refresh was last requested for, by cache key
+    requested: Arc<Mutex<HashMap<String, SystemTime>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Debug for RefreshAhead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefreshAhead")
+            .field("fraction", &self.fraction)
+            .field("refresh", &"Fn(&request::Parts)")
+            .finish_non_exhaustive()
+    }
+}
+
+impl RefreshAhead {
+    /// Calls `refresh` once less than `fraction` (between 0 and 1) of an entry's freshness
+    /// lifetime is left, e.g. `0.1` for the last 10%.
+    pub fn new(fraction: f64, refresh: Refresh) -> Self {
+        Self {
+            fraction: fraction.clamp(0.0, 1.0),
+            refresh,
+            requested: Arc::new(Mutex::new(HashMap::new())),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Stops requesting refreshes, e.g. on shutdown. Shared by every clone.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    fn on_hit(
+        &self,
+        cache_key: &str,
+        parts: &request::Parts,
+        policy: &CachePolicy,
+    ) {
+        if self.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let now = SystemTime::now();
+        let ttl = policy.time_to_live(now);
+        let lifetime = policy.age(now) + ttl;
+        if ttl.as_secs_f64() > lifetime.as_secs_f64() * self.fraction {
+            return;
+        }
+        let expires = now + ttl;
+        {
+            let mut requested = self.requested.lock().unwrap();
+            // The expiry is the same on every hit until the response is replaced,
+            // allow for it being computed with a little drift.
+            let already = requested.get(cache_key).map_or(false, |previous| {
+                let drift = match previous.duration_since(expires) {
+                    Ok(drift) => drift,
+                    Err(e) => e.duration(),
+                };
+                drift < Duration::from_secs(1)
+            });
+            if already {
+                return;
+            }
+            requested.retain(|_, expiry| *expiry > now);
+            requested.insert(cache_key.to_string(), expires);
+        }
+        (self.refresh)(parts);
+    }
+}
+
+/// Limits how often stale responses from each origin are revalidated, serving them stale
+/// beyond that. This keeps many entries going stale at once from sending a burst of
+/// requests to a small origin.
+///
+/// Each origin has a token bucket holding up to `burst` revalidations and refilling at
+/// `per_second`. Responses with `must-revalidate` are never served stale and are not limited.
+#[derive(Clone)]
+pub struct RevalidationRateLimit {
+    per_second: f64,
+    burst: f64,
+    buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Debug for RevalidationRateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RevalidationRateLimit")
+            .field("per_second", &self.per_second)
+            .field("burst", &self.burst)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RevalidationRateLimit {
+    /// Allows `per_second` revalidations per origin on average, and up to `burst` at once.
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst: f64::from(burst),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn try_acquire(&self, origin: String) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full buckets behave like new ones, so they can be dropped
+        if buckets.len() > 1024 {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+        let bucket = buckets
+            .entry(origin)
+            .or_insert(TokenBucket { tokens: self.burst, updated: now });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refill(&self, bucket: &TokenBucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+}
+
+/// Only stores a response once it has been requested a number of times recently, so that
+/// one-off requests, as in a crawl, don't evict entries that are requested often.
+///
+/// Requests are counted approximately in a fixed amount of memory, in the manner of
+/// TinyLFU, and the counts are halved periodically so that only recent requests matter.
+/// Responses refreshing an already stored entry are always stored.
+#[derive(Clone)]
+pub struct Admission {
+    min_requests: u8,
+    sketch: Arc<Mutex<FrequencySketch>>,
+}
+
+impl Debug for Admission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Admission")
+            .field("min_requests", &self.min_requests)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Admission {
+    /// Stores a response on its `min_requests`th request. `counters` bounds the memory used,
+    /// it should be a few times the number of entries the cache is expected to hold.
+    pub fn new(min_requests: u8, counters: usize) -> Self {
+        Self {
+            min_requests,
+            sketch: Arc::new(Mutex::new(FrequencySketch::new(counters))),
+        }
+    }
+
+    fn admit(&self, cache_key: &str) -> bool {
+        self.sketch.lock().unwrap().increment(cache_key) >= self.min_requests
+    }
+}
+
+// A count-min sketch of saturating counters.
+#[derive(Debug)]
+struct FrequencySketch {
+    rows: [Vec<u8>; 4],
+    additions: usize,
+    reset_at: usize,
+}
+
+impl FrequencySketch {
+    fn new(counters: usize) -> Self {
+        let width = counters.max(16);
+        Self {
+            rows: [
+                vec![0; width],
+                vec![0; width],
+                vec![0; width],
+                vec![0; width],
+            ],
+            additions: 0,
+            reset_at: width * 10,
+        }
+    }
+
+    // Counts a request for the key and returns the estimated number of requests.
+    fn increment(&mut self, key: &str) -> u8 {
+        let indexes: Vec<usize> = (0..self.rows.len())
+            .map(|seed| {
+                let mut hasher = DefaultHasher::new();
+                seed.hash(&mut hasher);
+                key.hash(&mut hasher);
+                (hasher.finish() % self.rows[seed].len() as u64) as usize
+            })
+            .collect();
+        let estimate = indexes
+            .iter()
+            .enumerate()
+            .map(|(row, &index)| self.rows[row][index])
+            .min()
+            .unwrap_or_default()
+            .saturating_add(1);
+        // Conservative update, only raise the counters that are below the new estimate
+        for (row, &index) in indexes.iter().enumerate() {
+            let counter = &mut self.rows[row][index];
+            *counter = (*counter).max(estimate);
+        }
+        self.additions += 1;
+        if self.additions >= self.reset_at {
+            for row in &mut self.rows {
+                row.iter_mut().for_each(|counter| *counter /= 2);
+            }
+            self.additions /= 2;
+        }
+        estimate
+    }
+}
+
+/// Pauses the cache at runtime, for example while cached responses are suspected of being
+/// wrong. While paused, requests are made as with [`CacheMode::NoStore`] but the stored
+/// entries are kept, except those invalidated by unsafe requests, and served again once
+/// resumed.
+///
+/// Clones share the same state, so one can be kept to control the cache after it's built.
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle {
+    paused: Arc<AtomicBool>,
+}
+
+impl PauseHandle {
+    /// Creates a new handle, not paused.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bypasses the cache until [`resume`](Self::resume) is called.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Uses the cache again.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns whether the cache is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the responses served from the cache and from the network for each origin host,
+/// to show which upstreams benefit from the cache. Requests that bypass the cache, such
+/// as those with [`CacheMode::NoStore`], aren't counted.
+///
+/// At most `max_hosts` hosts are counted on their own, the responses of any further
+/// hosts are counted together under [`HostStats::OTHER`]. Clones share the counters.
+#[derive(Debug, Clone)]
+pub struct HostStats {
+    max_hosts: usize,
+    counters: Arc<Mutex<HashMap<String, HostCounters>>>,
+}
+
+/// The responses counted for a host by [`HostStats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HostCounters {
+    /// Responses served from the cache
+    pub hits: u64,
+    /// Responses fetched from the network
+    pub misses: u64,
+    /// Of the hits, stale responses served without revalidation
+    pub stale: u64,
+    /// Of the hits, stale responses revalidated with the origin
+    pub revalidated: u64,
+    /// Of the misses, stale responses replaced from the network
+    pub expired: u64,
+}
+
+impl HostCounters {
+    /// The fraction of responses served from the cache, zero if none were counted.
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+impl HostStats {
+    /// The bucket counting the hosts beyond `max_hosts`.
+    pub const OTHER: &'static str = "(other)";
+
+    /// Counts up to `max_hosts` hosts on their own.
+    pub fn new(max_hosts: usize) -> Self {
+        Self { max_hosts, counters: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Returns the counters of every host counted so far.
+    pub fn snapshot(&self) -> HashMap<String, HostCounters> {
+        self.counters.lock().unwrap().clone()
+    }
+
+    /// Clears the counters.
+    pub fn reset(&self) {
+        self.counters.lock().unwrap().clear();
+    }
+
+    fn record(&self, response: &HttpResponse) {
+        let state = match response.header(XCACHE) {
+            Some(value) => match CacheState::parse(value) {
+                Some(CacheState::Bypass) | None => return,
+                Some(state) => state,
+            },
+            None => return,
+        };
+        let host = response.url.host_str().unwrap_or_default();
+        let mut counters = self.counters.lock().unwrap();
+        let bucket =
+            if counters.contains_key(host) || counters.len() < self.max_hosts {
+                host
+            } else {
+                Self::OTHER
+            };
+        let counter = counters.entry(bucket.to_string()).or_default();
+        if state.is_hit() {
+            counter.hits += 1;
+        } else {
+            counter.misses += 1;
+        }
+        match state {
+            CacheState::Stale => counter.stale += 1,
+            CacheState::Revalidated => counter.revalidated += 1,
+            CacheState::Expired => counter.expired += 1,
+            _ => {}
+        }
+    }
+}
+
+/// A closure that takes the [`http::request::Parts`] of a request and returns the namespace
+/// its entry belongs to, if any.
+pub type Namespace =
+    Arc<dyn Fn(&request::Parts) -> Option<String> + Send + Sync>;
+
+/// Mixes a generation number for each namespace into the cache keys of its entries, so
+/// [`bump_generation`](Self::bump_generation) invalidates every entry of a namespace at
+/// once without enumerating or deleting them. Entries of earlier generations are no
+/// longer looked up and are left for the manager to evict.
+///
+/// Generations are kept in memory, starting at zero. Instances sharing a manager must
+/// agree on them, for example by bumping the same namespaces or restoring them with
+/// [`set_generation`](Self::set_generation) when they start.
+#[derive(Clone)]
+pub struct Generations {
+    namespace: Namespace,
+    generations: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl Debug for Generations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Generations")
+            .field("namespace", &"Fn(&request::Parts) -> Option<String>")
+            .field("generations", &self.generations.lock().unwrap())
+            .finish()
+    }
+}
+
+impl Generations {
+    /// Groups entries into the namespaces returned by `namespace`, e.g. by host.
+    pub fn new(namespace: Namespace) -> Self {
+        Self { namespace, generations: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Returns the current generation of `namespace`.
+    pub fn generation(&self, namespace: &str) -> u64 {
+        self.generations
+            .lock()
+            .unwrap()
+            .get(namespace)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Invalidates every entry of `namespace` and returns its new generation.
+    pub fn bump_generation(&self, namespace: &str) -> u64 {
+        let mut generations = self.generations.lock().unwrap();
+        let generation = generations.entry(namespace.to_string()).or_default();
+        *generation += 1;
+        *generation
+    }
+
+    /// Sets the generation of `namespace`, e.g. to one persisted by another instance.
+    pub fn set_generation(&self, namespace: &str, generation: u64) {
+        self.generations
+            .lock()
+            .unwrap()
+            .insert(namespace.to_string(), generation);
+    }
+
+    fn apply(&self, parts: &request::Parts, cache_key: String) -> String {
+        match (self.namespace)(parts) {
+            Some(namespace) => {
+                let generation = self.generation(&namespace);
+                format!("{namespace}@{generation}:{cache_key}")
+            }
+            None => cache_key,
+        }
+    }
+}
+
+/// A closure that takes a content coding, such as `gzip`, and a body and returns the body
+/// encoded with it, or [`None`] if the coding isn't supported.
+pub type Encode = Arc<dyn Fn(&str, &[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+/// What to do with a response whose body the client already decoded, such as with
+/// reqwest's `gzip` or `brotli` features, while its `Content-Encoding` and
+/// `Content-Length` headers still describe the encoded body.
+#[derive(Clone, Default)]
+pub enum DecodedBodies {
+    /// Store and serve the response as identity, removing `Content-Encoding` and
+    /// correcting `Content-Length`
+    #[default]
+    Identity,
+    /// Store the decoded body and encode it again whenever it is served, falling back
+    /// to identity for codings the closure doesn't support
+    Reencode(Encode),
+}
+
+impl Debug for DecodedBodies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identity => write!(f, "Identity"),
+            Self::Reencode(_) => {
+                f.debug_tuple("Reencode").field(&"Fn(&str, &[u8])").finish()
+            }
+        }
+    }
+}
+
+/// Selects the validators sent when revalidating a stale response that has both an `ETag`
+/// and a `Last-Modified` header, as some origins mishandle requests carrying both
+/// `If-None-Match` and `If-Modified-Since`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum RevalidationHeaders {
+    /// Send both `If-None-Match` and `If-Modified-Since`
+    #[default]
+    Both,
+    /// Send only `If-None-Match`
+    #[cfg_attr(feature = "serde", serde(rename = "etag-only"))]
+    ETagOnly,
+    /// Send only `If-Modified-Since`
+    LastModifiedOnly,
+}
+
+/// Can be used to override the default [`CacheOptions`] and cache key.
+/// The cache key is a closure that takes [`http::request::Parts`] and returns a [`String`].
+#[derive(Default, Clone)]
+pub struct HttpCacheOptions {
+    /// Override the default cache options.
+    pub cache_options: Option<CacheOptions>,
+    /// Override the default cache key generator.
+    pub cache_key: Option<CacheKey>,
+    /// Override the default cache mode.
+    ///
+    /// The closure chooses the mode of each request, e.g. [`CacheMode::NoStore`] for
+    /// `/auth/*` and [`CacheMode::ForceCache`] for static assets, unless the request carries
+    /// a [`CacheMode`] in its extensions.
+    pub cache_mode_fn: Option<CacheModeFn>,
+    /// Bust the caches of the returned keys.
+    pub cache_bust: Option<CacheBust>,
+    /// Rewrite upstream responses before they are evaluated and stored.
+    pub modify_response: Option<ModifyResponse>,
+    /// Add the `x-cache-reason` header describing the [`CacheDecision`] to responses.
+    pub cache_reason_header: bool,
+    /// Mixed into every cache key, including the keys returned by `cache_bust`,
+    /// so that clients sharing a manager each get a private view of it.
+    pub isolation_key: Option<String>,
+    /// Store responses without any caching headers as if they were fresh for the returned duration.
+    pub assume_cacheable: Option<AssumeCacheable>,
+    /// Which validators to send when revalidating a response that has both.
+    pub revalidation_headers: RevalidationHeaders,
+    /// Cancel revalidating a stale response that takes too long and serve it instead.
+    pub stale_on_timeout: Option<StaleOnTimeout>,
+    /// Called with each [`CacheEvent`].
+    pub observer: Option<Observer>,
+    /// Refresh entries that are about to go stale when they are served.
+    pub refresh_ahead: Option<RefreshAhead>,
+    /// Only store new responses the admission policy lets in.
+    pub admission: Option<Admission>,
+    /// Limit how often each origin is revalidated, serving stale responses beyond that.
+    pub revalidation_rate_limit: Option<RevalidationRateLimit>,
+    /// Only store responses this predicate accepts.
+    pub store_predicate: Option<StorePredicate>,
+    /// Requests carrying this header are made with [`CacheMode::Reload`], dropping the
+    /// stored response, e.g. for support tooling. The header is removed before the
+    /// request is sent upstream.
+    pub refresh_header: Option<HeaderName>,
+    /// Only these cookies tell variants apart. They are added to the cache key, and a
+    /// `Vary: Cookie` only compares them, so other cookies such as session IDs don't
+    /// create a variant for each client. By default the whole `Cookie` header is compared.
+    pub variant_cookies: Option<Vec<String>>,
+    /// Compare `Accept-Encoding` for a `Vary: Accept-Encoding` by which of `gzip` and `br`
+    /// it accepts, so clients sending trivially different values share a stored response.
+    /// The request sent upstream is unchanged.
+    pub normalize_accept_encoding: bool,
+    /// How to handle responses whose body was decoded by the client.
+    pub decoded_bodies: DecodedBodies,
+    /// Mix a generation number for each namespace into cache keys, so a namespace can be
+    /// invalidated at once, including the keys returned by `cache_bust`.
+    pub generations: Option<Generations>,
+    /// Bypass the cache while the handle is paused.
+    pub pause: Option<PauseHandle>,
+    /// Count hits and misses by origin host.
+    pub host_stats: Option<HostStats>,
+    /// Only store new responses for this fraction of cache keys, between 0 and 1, chosen by
+    /// hashing the key so every instance stores the same keys.
+    pub store_sample_rate: Option<f64>,
+    /// Store the returned metadata with new entries. It's returned with the response as a
+    /// [`CacheMetadata`] extension, both when stored and when served from the cache.
+    /// Metadata that isn't visible ASCII on a single line fails the request with
+    /// [`BadMetadata`].
+    pub store_metadata: Option<StoreMetadata>,
+    /// Add the values of these request headers, such as `Accept` or `X-Api-Version`, to the
+    /// cache key, so responses that depend on them are stored separately without a custom
+    /// `cache_key`. Headers the request doesn't have are left out.
+    pub key_headers: Option<Vec<HeaderName>>,
+    /// Store the ranges of `206 Partial Content` responses, e.g. of resumed downloads, and
+    /// once they cover the whole object store it as a complete `200` response. Ranges are
+    /// only combined while they share a strong validator.
+    pub assemble_partial: bool,
+    /// Don't store responses whose headers exceed these limits, reporting them to the
+    /// `observer` instead.
+    pub header_limits: Option<HeaderLimits>,
+    /// In [`CacheMode::ReadOnly`], serve stale stored responses as well, with a
+    /// `110 Response is stale` warning, instead of fetching them from the network.
+    pub read_only_stale: bool,
+    /// Report the detailed [`CacheState`] in the `x-cache` header, e.g. `REVALIDATED` or
+    /// `STALE`, instead of only `HIT` or `MISS`.
+    pub cache_state_header: bool,
+    /// Store each variant of a response with a `Vary` header under its own key, made of
+    /// the cache key and the values of the request headers it varies on, so responses
+    /// negotiated on e.g. `Accept-Language` don't replace each other. The entry under the
+    /// cache key holds the latest variant and lists the others, which are deleted with it
+    /// when the cache invalidates the entry, e.g. on an unsafe request. Up to 32 variants
+    /// are kept for each entry, the oldest are deleted. Deleting the entry directly from
+    /// the manager leaves its variants behind.
+    pub store_variants: bool,
+}
+
+impl Debug for HttpCacheOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpCacheOptions")
+            .field("cache_options", &self.cache_options)
+            .field("cache_key", &"Fn(&request::Parts) -> String")
+            .field("cache_mode_fn", &"Fn(&request::Parts) -> CacheMode")
+            .field("cache_bust", &"Fn(&request::Parts) -> Vec<String>")
+            .field("modify_response", &"Fn(&request::Parts, &mut HttpResponse)")
+            .field("cache_reason_header", &self.cache_reason_header)
+            .field("isolation_key", &self.isolation_key)
+            .field(
+                "assume_cacheable",
+                &"Fn(&request::Parts) -> Option<Duration>",
+            )
+            .field("revalidation_headers", &self.revalidation_headers)
+            .field("stale_on_timeout", &self.stale_on_timeout)
+            .field("observer", &"Fn(&CacheEvent)")
+            .field("refresh_ahead", &self.refresh_ahead)
+            .field("admission", &self.admission)
+            .field("revalidation_rate_limit", &self.revalidation_rate_limit)
+            .field("store_predicate", &"Fn(&HttpResponse) -> bool")
+            .field("refresh_header", &self.refresh_header)
+            .field("variant_cookies", &self.variant_cookies)
+            .field("normalize_accept_encoding", &self.normalize_accept_encoding)
+            .field("decoded_bodies", &self.decoded_bodies)
+            .field("generations", &self.generations)
+            .field("pause", &self.pause)
+            .field("host_stats", &self.host_stats)
+            .field("store_sample_rate", &self.store_sample_rate)
+            .field(
+                "store_metadata",
+                &"Fn(&request::Parts, &HttpResponse) -> Option<String>",
+            )
+            .field("key_headers", &self.key_headers)
+            .field("assemble_partial", &self.assemble_partial)
+            .field("header_limits", &self.header_limits)
+            .field("read_only_stale", &self.read_only_stale)
+            .field("cache_state_header", &self.cache_state_header)
+            .field("store_variants", &self.store_variants)
+            .finish()
+    }
+}
+
+// Parses a `Content-Range` value like `bytes 0-99/1000` into the first and last byte of
+// the range and the complete length, which must be known.
+fn content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, complete) =
+        value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let first = first.trim().parse().ok()?;
+    let last = last.trim().parse().ok()?;
+    let complete = complete.trim().parse().ok()?;
+    (first <= last && last < complete).then_some((first, last, complete))
+}
+
+// Ranges of a representation can only be combined when it has a strong entity tag
+fn strong_etag(response: &HttpResponse) -> Option<&str> {
+    response.header(ETAG.as_str()).filter(|etag| !etag.starts_with("W/"))
+}
+
+// Splits the body of a partial entry into its ranges, each with its first byte
+fn partial_segments(partial: HttpResponse) -> Vec<(u64, Vec<u8>)> {
+    let Some(ranges) = partial.header(PARTIAL_RANGES_HEADER) else {
+        return Vec::new();
+    };
+    let mut segments = Vec::new();
+    let mut offset = 0;
+    for range in ranges.split(',') {
+        let Some((first, last)) = range.split_once('-') else {
+            return Vec::new();
+        };
+        let (Ok(first), Ok(last)) = (first.parse::<u64>(), last.parse::<u64>())
+        else {
+            return Vec::new();
+        };
+        let end = offset + (last - first) as usize + 1;
+        let Some(body) = partial.body.get(offset..end) else {
+            return Vec::new();
+        };
+        segments.push((first, body.to_vec()));
+        offset = end;
+    }
+    segments
+}
+
+// Sorts the segments and merges the ones that overlap or touch
+fn coalesce(mut segments: Vec<(u64, Vec<u8>)>) -> Vec<(u64, Vec<u8>)> {
+    segments.sort_by_key(|(first, _)| *first);
+    let mut coalesced: Vec<(u64, Vec<u8>)> = Vec::new();
+    for (first, body) in segments {
+        if let Some((previous, merged)) = coalesced.last_mut() {
+            let end = *previous + merged.len() as u64;
+            if first <= end {
+                let overlap = (end - first) as usize;
+                if overlap < body.len() {
+                    merged.extend_from_slice(&body[overlap..]);
+                }
+                continue;
+            }
+        }
+        coalesced.push((first, body));
+    }
+    coalesced
+}
+
+// Splits a `Cache-Control` value into its directives, keeping quoted commas
+fn split_directives(value: &str) -> Vec<&str> {
+    let mut directives = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                directives.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    directives.push(value[start..].trim());
+    directives.retain(|directive| !directive.is_empty());
+    directives
+}
+
+// Whether the request carries `Cache-Control: only-if-cached`
+fn only_if_cached(parts: &request::Parts) -> bool {
+    parts
+        .headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(split_directives)
+        .any(|directive| directive.eq_ignore_ascii_case("only-if-cached"))
+}
+
+impl HttpCacheOptions {
+    fn store_allowed(&self, response: &HttpResponse) -> bool {
+        self.store_predicate.as_ref().map_or(true, |allow| allow(response))
+    }
+
+    /// Rewrites the field-qualified forms of `private` (shared caches only) and
+    /// `no-cache`, such as `private="set-cookie"`, into the response to store: the
+    /// directive is dropped and the listed fields are stripped, so the rest of the
+    /// response can be reused. Returns `None` if there are no such directives.
+    fn qualified_directives(
+        &self,
+        response: &HttpResponse,
+    ) -> Option<HttpResponse> {
+        let shared = self.cache_options.map_or(true, |options| options.shared);
+        // Every field line is read, since they're all replaced below
+        let values = response
+            .headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .map(|value| value.to_str().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let mut kept = Vec::new();
+        let mut fields = Vec::new();
+        for directive in values.into_iter().flat_map(split_directives) {
+            let qualified = directive.split_once('=').and_then(|(d, v)| {
+                let d = d.trim();
+                let qualifies = d.eq_ignore_ascii_case("no-cache")
+                    || (shared && d.eq_ignore_ascii_case("private"));
+                qualifies.then(|| v.trim().trim_matches('"'))
+            });
+            match qualified {
+                Some(listed) => fields.extend(
+                    listed
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|field| !field.is_empty()),
+                ),
+                None => kept.push(directive),
+            }
+        }
+        if fields.is_empty() {
+            return None;
+        }
+        let mut stored = response.clone();
+        for field in fields {
+            stored.headers.remove(field);
+        }
+        stored.headers.remove(CACHE_CONTROL);
+        if !kept.is_empty() {
+            stored.set_header(CACHE_CONTROL.as_str(), &kept.join(", "));
+        }
+        Some(stored)
+    }
+
+    // Whether the headers of the response are within the limits, reporting it otherwise
+    fn headers_allowed(
+        &self,
+        cache_key: &str,
+        response: &HttpResponse,
+    ) -> bool {
+        let Some(limit) =
+            self.header_limits.and_then(|limits| limits.exceeded(response))
+        else {
+            return true;
+        };
+        self.observe(|| CacheEvent::HeadersRejected {
+            cache_key: cache_key.to_string(),
+            limit,
+        });
+        false
+    }
+
+    fn sampled(&self, cache_key: &str) -> bool {
+        let Some(rate) = self.store_sample_rate else {
+            return true;
+        };
+        // The low bits of FNV-1a are poorly mixed for short inputs, use the high ones
+        let position = (stable_hash(cache_key.as_bytes()) >> 11) as f64
+            / (1u64 << 53) as f64;
+        position < rate
+    }
+
+    fn create_cache_key(
+        &self,
+        parts: &request::Parts,
+        override_method: Option<&str>,
+    ) -> String {
+        let cache_key = self.isolate_cache_key(self.generation_cache_key(
+            parts,
+            self.base_cache_key(parts, override_method),
+        ));
+        let cache_key = match self.key_header_values(parts) {
+            Some(values) => format!("{cache_key}:headers:{values}"),
+            None => cache_key,
+        };
+        match self.variant_cookie_header(parts) {
+            Some(cookies) => format!("{cache_key}:cookie:{cookies}"),
+            None => cache_key,
+        }
+    }
+
+    // The values of the key headers the request has, in the configured order.
+    fn key_header_values(&self, parts: &request::Parts) -> Option<String> {
+        let values: Vec<String> = self
+            .key_headers
+            .as_ref()?
+            .iter()
+            .filter(|name| parts.headers.contains_key(*name))
+            .map(|name| {
+                let value: Vec<&str> = parts
+                    .headers
+                    .get_all(name)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .collect();
+                format!("{name}={}", value.join(", "))
+            })
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.join("&"))
+    }
+
+    // The allow-listed cookies of the request, sorted by name.
+    fn variant_cookie_header(&self, parts: &request::Parts) -> Option<String> {
+        let allowed = self.variant_cookies.as_ref()?;
+        let mut cookies: Vec<&str> = parts
+            .headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .map(str::trim)
+            .filter(|cookie| {
+                let name = cookie.split_once('=').map_or(*cookie, |(n, _)| n);
+                allowed.iter().any(|allowed| allowed == name)
+            })
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_unstable();
+        Some(cookies.join("; "))
+    }
+
+    // Which of gzip and br the request accepts, or nothing if it accepts neither.
+    fn normalized_accept_encoding(
+        parts: &request::Parts,
+    ) -> Option<&'static str> {
+        let mut gzip = false;
+        let mut br = false;
+        let codings = parts
+            .headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for coding in codings {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default().to_ascii_lowercase();
+            let rejected = params.any(|param| {
+                param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok())
+                    == Some(0.0)
+            });
+            if rejected {
+                continue;
+            }
+            match name.as_str() {
+                "gzip" | "x-gzip" => gzip = true,
+                "br" => br = true,
+                "*" => {
+                    gzip = true;
+                    br = true;
+                }
+                _ => {}
+            }
+        }
+        match (gzip, br) {
+            (true, true) => Some("gzip, br"),
+            (true, false) => Some("gzip"),
+            (false, true) => Some("br"),
+            (false, false) => None,
+        }
+    }
+
+    // Identifies the variant of the response the request selects, by the values of the
+    // request headers named by its `Vary`. Responses that don't vary, or vary on `*`,
+    // have none.
+    fn variant_hash(
+        &self,
+        parts: &request::Parts,
+        response: &HttpResponse,
+    ) -> Result<Option<u64>> {
+        let mut names: Vec<String> = response
+            .headers
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() || names.iter().any(|name| name == "*") {
+            return Ok(None);
+        }
+        names.sort_unstable();
+        names.dedup();
+        let parts = self.variant_parts(parts)?;
+        let mut selected = String::new();
+        for name in names {
+            let values: Vec<&str> = parts
+                .headers
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            selected.push_str(&format!("{name}={}\n", values.join(", ")));
+        }
+        Ok(Some(stable_hash(selected.as_bytes())))
+    }
+
+    fn normalizes_variants(&self) -> bool {
+        self.variant_cookies.is_some() || self.normalize_accept_encoding
+    }
+
+    // The request as seen by the cache policy, with the headers that tell variants
+    // apart normalized.
+    fn variant_parts(&self, parts: &request::Parts) -> Result<request::Parts> {
+        let mut variant = request::Builder::new()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .version(parts.version)
+            .body(())?
+            .into_parts()
+            .0;
+        variant.headers = parts.headers.clone();
+        if self.variant_cookies.is_some() {
+            variant.headers.remove(COOKIE);
+            if let Some(cookies) = self.variant_cookie_header(parts) {
+                variant
+                    .headers
+                    .insert(COOKIE, HeaderValue::from_str(&cookies)?);
+            }
+        }
+        if self.normalize_accept_encoding {
+            variant.headers.remove(ACCEPT_ENCODING);
+            if let Some(codings) = Self::normalized_accept_encoding(parts) {
+                variant
+                    .headers
+                    .insert(ACCEPT_ENCODING, HeaderValue::from_static(codings));
+            }
+        }
+        Ok(variant)
+    }
+
+    // Puts back the headers normalized by `variant_parts`, so the request sent
+    // upstream is unchanged.
+    fn restore_variant_headers(
+        &self,
+        original: &request::Parts,
+        parts: &mut request::Parts,
+    ) {
+        let mut normalized = Vec::new();
+        if self.variant_cookies.is_some() {
+            normalized.push(COOKIE);
+        }
+        if self.normalize_accept_encoding {
+            normalized.push(ACCEPT_ENCODING);
+        }
+        for name in normalized {
+            parts.headers.remove(&name);
+            for value in original.headers.get_all(&name) {
+                parts.headers.append(&name, value.clone());
+            }
+        }
+    }
+
+    fn base_cache_key(
+        &self,
+        parts: &request::Parts,
+        override_method: Option<&str>,
+    ) -> String {
+        if let Some(cache_key) = &self.cache_key {
+            cache_key(parts)
+        } else {
+            format!(
+                "{}:{}",
+                override_method.unwrap_or_else(|| parts.method.as_str()),
+                parts.uri
+            )
+        }
+    }
+
+    fn generation_cache_key(
+        &self,
+        parts: &request::Parts,
+        cache_key: String,
+    ) -> String {
+        match &self.generations {
+            Some(generations) => generations.apply(parts, cache_key),
+            None => cache_key,
+        }
+    }
+
+    fn isolate_cache_key(&self, cache_key: String) -> String {
+        match &self.isolation_key {
+            Some(isolation_key) => format!("{isolation_key}:{cache_key}"),
+            None => cache_key,
+        }
+    }
+
+    fn reencode(&self, response: &mut HttpResponse) {
+        let DecodedBodies::Reencode(encode) = &self.decoded_bodies else {
+            return;
+        };
+        if let Some(coding) = response.decoded_encoding() {
+            match encode(&coding, &response.body) {
+                Some(body) => {
+                    response.set_body_encoding(body.into(), Some(&coding))
+                }
+                None => {
+                    let body = std::mem::take(&mut response.body);
+                    response.set_body_encoding(body, None);
+                }
+            }
+        }
+    }
+
+    // Counts the response and reports its cache state, which is then collapsed to `HIT` or
+    // `MISS` unless `cache_state_header` is set
+    fn record_stats(
+        &self,
+        parts: &request::Parts,
+        response: &mut HttpResponse,
+    ) {
+        if let Some(host_stats) = &self.host_stats {
+            host_stats.record(response);
+        }
+        let Some(state) = response.header(XCACHE).and_then(CacheState::parse)
+        else {
+            return;
+        };
+        self.observe(|| CacheEvent::Served {
+            cache_key: self.create_cache_key(parts, None),
+            state,
+        });
+        if !self.cache_state_header {
+            response.cache_status(state.into());
+        }
+    }
+
+    fn observe(&self, event: impl FnOnce() -> CacheEvent) {
+        if let Some(observer) = &self.observer {
+            observer(&event());
+        }
+    }
+
+    fn select_validators(&self, parts: &mut request::Parts) {
+        let headers = &mut parts.headers;
+        if !headers.contains_key(IF_NONE_MATCH)
+            || !headers.contains_key(IF_MODIFIED_SINCE)
+        {
+            return;
+        }
+        match self.revalidation_headers {
+            RevalidationHeaders::Both => {}
+            RevalidationHeaders::ETagOnly => {
+                headers.remove(IF_MODIFIED_SINCE);
+            }
+            RevalidationHeaders::LastModifiedOnly => {
+                headers.remove(IF_NONE_MATCH);
+            }
+        }
+    }
+
+    fn keys_to_cache_bust(&self, parts: &request::Parts) -> Vec<String> {
+        match &self.cache_bust {
+            Some(cache_bust) => cache_bust(
+                parts,
+                &self.cache_key,
+                &self.base_cache_key(parts, None),
+            )
+            .into_iter()
+            .map(|key| {
+                self.isolate_cache_key(self.generation_cache_key(parts, key))
+            })
+            .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn prepare_response(
+        &self,
+        parts: &request::Parts,
+        response: &mut HttpResponse,
+    ) {
+        // Only the cache sets the headers it keeps its own data in
+        response.headers.remove(METADATA_HEADER);
+        response.headers.remove(FETCH_LATENCY_HEADER);
+        if let Some(modify_response) = &self.modify_response {
+            modify_response(parts, response);
+        }
+        if let DecodedBodies::Identity = self.decoded_bodies {
+            if response.decoded_encoding().is_some() {
+                let body = std::mem::take(&mut response.body);
+                response.set_body_encoding(body, None);
+            }
+        }
+        if let Some(assume_cacheable) = &self.assume_cacheable {
+            let has_freshness = response.headers.contains_key(CACHE_CONTROL)
+                || response.headers.contains_key(EXPIRES);
+            if !has_freshness {
+                if let Some(ttl) = assume_cacheable(parts) {
+                    response.set_header(
+                        CACHE_CONTROL.as_str(),
+                        &format!("max-age={}", ttl.as_secs()),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Prepares a stored response selected for the request
+fn stored_hit(res: &mut HttpResponse) {
+    res.cache_lookup_status(HitOrMiss::HIT);
+    if let Some(warning_code) = res.warning_code() {
+        // https://tools.ietf.org/html/rfc7234#section-4.3.4
+        //
+        // If a stored response is selected for update, the cache MUST:
+        //
+        // * delete any warning header fields in the stored response with
+        //   warn-code 1xx (see Section 5.5);
+        //
+        // * retain any warning header fields in the stored response with
+        //   warn-code 2xx;
+        //
+        if (100..200).contains(&warning_code) {
+            res.remove_warning();
+        }
+    }
+}
+
+// The hashes of the variants a stored response lists with `store_variants`
+fn stored_variants(stored: &HttpResponse) -> Vec<String> {
+    stored
+        .header(VARIANTS_HEADER)
+        .map(|variants| {
+            variants
+                .split(',')
+                .filter(|hash| !hash.is_empty())
+                .map(Into::into)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn variant_key(cache_key: &str, hash: &str) -> String {
+    format!("{cache_key}:vary:{hash}")
+}
+
+// Waits for the fetch, or gives up with `None` once the timeout passes
+async fn before_timeout<F: Future + ?Sized, T: Future + ?Sized>(
+    mut fetch: Pin<&mut F>,
+    mut timeout: Pin<&mut T>,
+) -> Option<F::Output> {
+    poll_fn(|cx| {
+        if let Poll::Ready(res) = fetch.as_mut().poll(cx) {
+            return Poll::Ready(Some(res));
+        }
+        timeout.as_mut().poll(cx).map(|_| None)
+    })
+    .await
+}
+
+// Serves a stored response without checking its freshness
+fn disconnected_hit(mut res: HttpResponse) -> HttpResponse {
+    //   112 Disconnected operation
+    // SHOULD be included if the cache is intentionally disconnected from
+    // the rest of the network for a period of time.
+    // (https://tools.ietf.org/html/rfc2616#section-14.46)
+    res.add_warning(&res.url.clone(), 112, "Disconnected operation");
+    res.cache_status(HitOrMiss::HIT);
+    res
+}
+
+// What to do with a stored response that was looked up in the default mode. `run` and
+// `CacheTransaction` decide it the same way, then do it their own way.
+#[allow(clippy::large_enum_variant)]
+enum Revalidation {
+    // Serve the stored response without the origin
+    Serve(HttpResponse),
+    // Send the request to revalidate the stored response, with the headers of `parts`
+    // if the stored response has validators matching the request
+    Fetch { cached: HttpResponse, parts: Option<request::Parts> },
+}
+
+// What to do after the origin answered a revalidation
+#[allow(clippy::large_enum_variant)]
+enum Revalidated {
+    // Serve the stored response without changing it
+    Serve(HttpResponse),
+    // Store the stored response with the updated headers and policy
+    NotModified(HttpResponse, CachePolicy),
+    // Store the new response in place of the stored one
+    Modified(HttpResponse),
+}
+
+/// Caches requests according to http spec.
+#[derive(Debug, Clone)]
+pub struct HttpCache<T: CacheManager> {
+    /// Determines the manager behavior.
+    pub mode: CacheMode,
+    /// Manager instance that implements the [`CacheManager`] trait.
+    /// By default, a manager implementation with [`cacache`](https://github.com/zkat/cacache-rs)
+    /// as the backend has been provided, see [`CACacheManager`].
+    pub manager: T,
+    /// Override the default cache options.
+    pub options: HttpCacheOptions,
+}
+
+impl<T: CacheManager<Error = BoxError>> HttpCache<T> {
+    /// Erases the type of the manager, so caches with different managers have the same type.
+    pub fn into_dyn(self) -> DynHttpCache {
+        HttpCache {
+            mode: self.mode,
+            manager: Arc::new(self.manager),
+            options: self.options,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<T: CacheManager> HttpCache<T> {
+    fn cache_mode(&self, parts: &request::Parts) -> CacheMode {
+        if let Some(pause) = &self.options.pause {
+            if pause.is_paused() {
+                return CacheMode::NoStore;
+            }
+        }
+        if let Some(refresh_header) = &self.options.refresh_header {
+            if parts.headers.contains_key(refresh_header) {
+                return CacheMode::Reload;
+            }
+        }
+        self.request_mode(parts)
+    }
+
+    // The mode set in the extensions of the request, or else `OnlyIfCached` for a request
+    // with `Cache-Control: only-if-cached`, as fetch does, or else the one `cache_mode_fn`
+    // chooses for it, or else the mode of the cache
+    fn request_mode(&self, parts: &request::Parts) -> CacheMode {
+        if let Some(mode) = parts.extensions.get::<CacheMode>() {
+            *mode
+        } else if only_if_cached(parts) {
+            CacheMode::OnlyIfCached
+        } else if let Some(cache_mode_fn) = &self.options.cache_mode_fn {
+            cache_mode_fn(parts)
+        } else {
+            self.mode
+        }
+    }
+
+    /// Determines if the request should be cached
     pub fn can_cache_request(
         &self,
         middleware: &impl Middleware,
     ) -> Result<bool> {
-        let mode = if let Some(cache_mode_fn) = &self.options.cache_mode_fn {
-            cache_mode_fn(&middleware.parts()?)
-        } else {
-            self.mode
-        };
+        let mode = self.cache_mode(&middleware.parts()?);
 
         Ok(mode == CacheMode::IgnoreRules
             || middleware.is_method_get_head()
@@ -444,119 +2825,278 @@ impl<T: CacheManager> HttpCache<T> {
                 && mode != CacheMode::Reload)
     }
 
+    /// Explains how the cache treats the request and, if provided, the response.
+    /// This has no side effects, the manager is never consulted.
+    pub fn explain(
+        &self,
+        parts: &request::Parts,
+        response: Option<&HttpResponse>,
+    ) -> Result<CacheDecision> {
+        let mode = self.cache_mode(parts);
+        if mode == CacheMode::NoStore || mode == CacheMode::Reload {
+            return Ok(CacheDecision::Bypass(mode));
+        }
+        let is_get_head = parts.method == http::Method::GET
+            || parts.method == http::Method::HEAD;
+        if !is_get_head && mode != CacheMode::IgnoreRules {
+            return Ok(CacheDecision::MethodNotCacheable);
+        }
+        let response = match response {
+            Some(response) => response,
+            None => return Ok(CacheDecision::Lookup),
+        };
+        if mode == CacheMode::ReadOnly {
+            return Ok(CacheDecision::ReadOnly);
+        }
+        if response.status != 200 {
+            return Ok(CacheDecision::StatusNotCacheable(response.status));
+        }
+        if !self.options.store_allowed(response)
+            || !self
+                .options
+                .sampled(&self.options.create_cache_key(parts, None))
+        {
+            return Ok(CacheDecision::NotStorable);
+        }
+        let qualified = self.options.qualified_directives(response);
+        let response = qualified.as_ref().unwrap_or(response);
+        let parts = &self.options.variant_parts(parts)?;
+        let policy = CachePolicy::new_options(
+            parts,
+            &response.parts()?,
+            SystemTime::now(),
+            self.options.cache_options.unwrap_or_default(),
+        );
+        if mode == CacheMode::IgnoreRules {
+            return Ok(CacheDecision::Fresh);
+        }
+        if !policy.is_storable() {
+            return Ok(CacheDecision::NotStorable);
+        }
+        Ok(match mode {
+            CacheMode::NoCache => CacheDecision::Stale,
+            CacheMode::ForceCache | CacheMode::OnlyIfCached => {
+                CacheDecision::Fresh
+            }
+            _ => match policy.before_request(parts, SystemTime::now()) {
+                BeforeRequest::Fresh(_) => CacheDecision::Fresh,
+                BeforeRequest::Stale { .. } => CacheDecision::Stale,
+            },
+        })
+    }
+
+    /// Prepares the cache for the process to exit: entries are no longer refreshed ahead of
+    /// time, the revalidations that finished in the background are stored, see
+    /// [`BackgroundRevalidation`], and the manager applies its pending writes, e.g. the queue
+    /// of a write-behind manager, see [`CacheManager::shutdown`]. Requests still running may
+    /// store entries afterwards, so call this once they have finished.
+    pub async fn shutdown(&self) -> Result<()> {
+        if let Some(refresh_ahead) = &self.options.refresh_ahead {
+            refresh_ahead.stop();
+        }
+        self.store_finished_revalidations().await;
+        self.manager.shutdown().await.map_err(Into::into)
+    }
+
+    /// Removes every entry of the cache, see [`CacheManager::clear`].
+    pub async fn clear(&self) -> Result<()> {
+        self.manager.clear().await
+    }
+
     /// Runs the actions to preform when the client middleware is running without the cache
     pub async fn run_no_cache(
         &self,
-        middleware: &mut impl Middleware,
+        middleware: &mut impl Middleware,
+    ) -> Result<()> {
+        self.store_finished_revalidations().await;
+        let keys = self.keys_to_delete_bypassing(&middleware.parts()?);
+        let Some((get_key, keys_to_cache_bust)) = keys.split_first() else {
+            return Ok(());
+        };
+        self.delete_entry(get_key).await.ok();
+        for key_to_cache_bust in keys_to_cache_bust {
+            self.delete_entry(key_to_cache_bust).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Attempts to run the passed middleware along with the cache
+    pub async fn run(
+        &self,
+        middleware: impl Middleware,
+    ) -> Result<HttpResponse> {
+        let parts = middleware.parts()?;
+        self.store_finished_revalidations().await;
+        let res = self.lookup_or_fetch(middleware).await?;
+        self.finish(&parts, res)
+    }
+
+    // Gets the entry from the manager, recovering from a corrupt entry by deleting it unless
+    // the request is made read-only
+    async fn lookup(
+        &self,
+        cache_key: &str,
+        parts: &request::Parts,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let e: BoxError = match self.manager.get(cache_key).await {
+            Ok(stored) => return Ok(stored),
+            Err(e) => e.into(),
+        };
+        let mut source: Option<&(dyn std::error::Error + 'static)> =
+            Some(e.as_ref());
+        while let Some(error) = source {
+            if let Some(corrupt) = error.downcast_ref::<CorruptEntry>() {
+                self.options.observe(|| CacheEvent::CorruptEntry {
+                    cache_key: cache_key.to_string(),
+                    error: corrupt.to_string(),
+                });
+                if self.cache_mode(parts) != CacheMode::ReadOnly {
+                    self.manager.delete(cache_key).await.ok();
+                }
+                return Ok(None);
+            }
+            source = error.source();
+        }
+        Err(e)
+    }
+
+    // Deletes the entry under the cache key, and with `store_variants` the variants it lists
+    async fn delete_entry(&self, cache_key: &str) -> Result<()> {
+        // An entry that can't be read is deleted on its own
+        let stored = match self.options.store_variants {
+            true => self.manager.get(cache_key).await.ok().flatten(),
+            false => None,
+        };
+        if let Some((stored, _)) = stored {
+            self.delete_variants(cache_key, &stored_variants(&stored)).await?;
+        }
+        self.manager.delete(cache_key).await.map_err(Into::into)
+    }
+
+    async fn delete_variants(
+        &self,
+        cache_key: &str,
+        hashes: &[String],
     ) -> Result<()> {
-        self.manager
-            .delete(
-                &self
-                    .options
-                    .create_cache_key(&middleware.parts()?, Some("GET")),
-            )
-            .await
-            .ok();
-
-        let cache_key =
-            self.options.create_cache_key(&middleware.parts()?, None);
+        if hashes.is_empty() {
+            return Ok(());
+        }
+        let keys: Vec<String> =
+            hashes.iter().map(|hash| variant_key(cache_key, hash)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.manager.delete_many(&keys).await.map_err(Into::into)
+    }
 
-        if let Some(cache_bust) = &self.options.cache_bust {
-            for key_to_cache_bust in cache_bust(
-                &middleware.parts()?,
-                &self.options.cache_key,
-                &cache_key,
-            ) {
-                self.manager.delete(&key_to_cache_bust).await?;
-            }
+    // With `store_variants`, selects the stored variant of the entry the request matches.
+    // There is none if the request selects a variant that isn't listed by the entry.
+    async fn lookup_variant(
+        &self,
+        cache_key: &str,
+        parts: &request::Parts,
+        stored: (HttpResponse, CachePolicy),
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let (mut res, policy) = stored;
+        let Some(variants) = res.headers.remove(VARIANTS_HEADER) else {
+            return Ok(Some((res, policy)));
+        };
+        let variants = variants.to_str().unwrap_or_default().to_string();
+        let matches = match policy.before_request(
+            &self.options.variant_parts(parts)?,
+            SystemTime::now(),
+        ) {
+            BeforeRequest::Fresh(_) => true,
+            BeforeRequest::Stale { matches, .. } => matches,
+        };
+        if matches {
+            return Ok(Some((res, policy)));
+        }
+        let Some(hash) = self.options.variant_hash(parts, &res)? else {
+            return Ok(None);
+        };
+        let hash = format!("{hash:016x}");
+        if !variants.split(',').any(|variant| variant == hash) {
+            return Ok(None);
         }
+        self.lookup(&variant_key(cache_key, &hash), parts).await
+    }
 
-        Ok(())
+    fn set_cache_reason(
+        &self,
+        res: &mut HttpResponse,
+        decision: CacheDecision,
+    ) {
+        if self.options.cache_reason_header {
+            res.cache_reason(decision);
+        }
     }
 
-    /// Attempts to run the passed middleware along with the cache
-    pub async fn run(
+    async fn lookup_or_fetch(
         &self,
         mut middleware: impl Middleware,
     ) -> Result<HttpResponse> {
         let is_cacheable = self.can_cache_request(&middleware)?;
         if !is_cacheable {
-            return self.remote_fetch(&mut middleware).await;
+            let mut res = self.remote_fetch(&mut middleware).await?;
+            res.cache_state(CacheState::Bypass);
+            return Ok(res);
         }
 
-        let cache_key =
-            self.options.create_cache_key(&middleware.parts()?, None);
+        let parts = middleware.parts()?;
+        let cache_key = self.options.create_cache_key(&parts, None);
+        let mode = self.request_mode(&parts);
 
-        if let Some(cache_bust) = &self.options.cache_bust {
-            for key_to_cache_bust in cache_bust(
-                &middleware.parts()?,
-                &self.options.cache_key,
-                &cache_key,
-            ) {
-                self.manager.delete(&key_to_cache_bust).await?;
-            }
+        for key_to_cache_bust in self.keys_to_delete(&parts) {
+            self.delete_entry(&key_to_cache_bust).await?;
         }
 
-        if let Some(store) = self.manager.get(&cache_key).await? {
-            let (mut res, policy) = store;
-            res.cache_lookup_status(HitOrMiss::HIT);
-            if let Some(warning_code) = res.warning_code() {
-                // https://tools.ietf.org/html/rfc7234#section-4.3.4
-                //
-                // If a stored response is selected for update, the cache MUST:
-                //
-                // * delete any warning header fields in the stored response with
-                //   warn-code 1xx (see Section 5.5);
-                //
-                // * retain any warning header fields in the stored response with
-                //   warn-code 2xx;
-                //
-                if (100..200).contains(&warning_code) {
-                    res.remove_warning();
-                }
+        let stored = match self.lookup(&cache_key, &parts).await? {
+            Some(stored) if self.options.store_variants => {
+                self.lookup_variant(&cache_key, &parts, stored).await?
             }
+            stored => stored,
+        };
+        self.observe_lookup(&cache_key, &parts, stored.as_ref());
+        if let Some(store) = stored {
+            let (mut res, policy) = store;
+            stored_hit(&mut res);
 
-            match self.mode {
+            match mode {
                 CacheMode::Default => {
                     self.conditional_fetch(middleware, res, policy).await
                 }
+                CacheMode::Shadow => {
+                    self.shadow_fetch(middleware, cache_key, res, policy).await
+                }
+                CacheMode::DryRun => self.remote_fetch(&mut middleware).await,
                 CacheMode::NoCache => {
                     middleware.force_no_cache()?;
+                    middleware
+                        .mark_revalidation(CacheRevalidationRequest::NoCache)?;
                     let mut res = self.remote_fetch(&mut middleware).await?;
                     res.cache_lookup_status(HitOrMiss::HIT);
                     Ok(res)
                 }
                 CacheMode::ForceCache
                 | CacheMode::OnlyIfCached
-                | CacheMode::IgnoreRules => {
-                    //   112 Disconnected operation
-                    // SHOULD be included if the cache is intentionally disconnected from
-                    // the rest of the network for a period of time.
-                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                    res.add_warning(
-                        &res.url.clone(),
-                        112,
-                        "Disconnected operation",
-                    );
-                    res.cache_status(HitOrMiss::HIT);
+                | CacheMode::IgnoreRules => Ok(disconnected_hit(res)),
+                CacheMode::ReadOnly => {
+                    if let Some(res) =
+                        self.read_only_hit(&parts, res, &policy)?
+                    {
+                        return Ok(res);
+                    }
+                    let mut res = self.remote_fetch(&mut middleware).await?;
+                    res.cache_state(CacheState::Expired);
+                    res.cache_lookup_status(HitOrMiss::HIT);
                     Ok(res)
                 }
                 _ => self.remote_fetch(&mut middleware).await,
             }
         } else {
-            match self.mode {
+            match mode {
                 CacheMode::OnlyIfCached => {
-                    // ENOTCACHED
-                    let mut res = HttpResponse {
-                        body: b"GatewayTimeout".to_vec(),
-                        headers: HashMap::default(),
-                        status: 504,
-                        url: middleware.url()?,
-                        version: HttpVersion::Http11,
-                    };
-                    res.cache_status(HitOrMiss::MISS);
-                    res.cache_lookup_status(HitOrMiss::MISS);
-                    Ok(res)
+                    Ok(self.not_cached(middleware.url()?))
                 }
                 _ => self.remote_fetch(&mut middleware).await,
             }
@@ -567,149 +3107,781 @@ impl<T: CacheManager> HttpCache<T> {
         &self,
         middleware: &mut impl Middleware,
     ) -> Result<HttpResponse> {
+        let started = Instant::now();
         let mut res = middleware.remote_fetch().await?;
+        let fetch_latency = started.elapsed();
+        self.options.prepare_response(&middleware.parts()?, &mut res);
         res.cache_status(HitOrMiss::MISS);
         res.cache_lookup_status(HitOrMiss::MISS);
-        let policy = match self.options.cache_options {
-            Some(options) => middleware.policy_with_options(&res, options)?,
-            None => middleware.policy(&res)?,
-        };
+        let qualified = self.options.qualified_directives(&res);
+        let policy =
+            self.policy(middleware, qualified.as_ref().unwrap_or(&res))?;
         let is_get_head = middleware.is_method_get_head();
-        let mut is_cacheable = is_get_head
-            && self.mode != CacheMode::NoStore
-            && self.mode != CacheMode::Reload
-            && res.status == 200
-            && policy.is_storable();
-        if self.mode == CacheMode::IgnoreRules && res.status == 200 {
-            is_cacheable = true;
-        }
-        if is_cacheable {
-            Ok(self
-                .manager
-                .put(
-                    self.options.create_cache_key(&middleware.parts()?, None),
-                    res,
-                    policy,
-                )
-                .await?)
-        } else if !is_get_head {
-            self.manager
-                .delete(
-                    &self
-                        .options
-                        .create_cache_key(&middleware.parts()?, Some("GET")),
-                )
-                .await
-                .ok();
+        let parts = middleware.parts()?;
+        let mode = self.request_mode(&parts);
+        if self.storable(mode, &parts, &res, &policy) {
+            let cache_key = self.options.create_cache_key(&parts, None);
+            if !self.admit(&parts, &cache_key, &mut res, fetch_latency)? {
+                return Ok(res);
+            }
+            self.store_qualified(&parts, cache_key, res, policy).await
+        } else if res.status == 206
+            && self.options.assemble_partial
+            && mode != CacheMode::NoStore
+            && mode != CacheMode::Reload
+            && mode != CacheMode::ReadOnly
+        {
+            if parts.method == http::Method::GET {
+                self.store_partial(&parts, &res).await?;
+            }
+            Ok(res)
+        } else if !is_get_head && mode != CacheMode::ReadOnly {
+            self.delete_entry(
+                &self.options.create_cache_key(&parts, Some("GET")),
+            )
+            .await
+            .ok();
             Ok(res)
         } else {
             Ok(res)
         }
     }
 
-    async fn conditional_fetch(
+    // Adds the range of a `206` response to the stored ranges of the object, and stores the
+    // object as a complete response once they cover it.
+    async fn store_partial(
+        &self,
+        parts: &request::Parts,
+        res: &HttpResponse,
+    ) -> Result<()> {
+        let Some((first, last, complete)) =
+            res.header(CONTENT_RANGE.as_str()).and_then(content_range)
+        else {
+            return Ok(());
+        };
+        let Some(etag) = strong_etag(res) else {
+            return Ok(());
+        };
+        let cache_key = self.options.create_cache_key(parts, None);
+        if res.body.len() as u64 != last - first + 1
+            || !self.options.sampled(&cache_key)
+        {
+            return Ok(());
+        }
+        let partial_key = format!("{cache_key}:partial");
+        let mut segments = vec![(first, res.body.to_vec())];
+        if let Some((stored, _)) = self.lookup(&partial_key, parts).await? {
+            // Ranges of another representation are dropped
+            let stored_complete = stored
+                .header(CONTENT_RANGE.as_str())
+                .and_then(content_range)
+                .map(|(_, _, complete)| complete);
+            if strong_etag(&stored) == Some(etag)
+                && stored_complete == Some(complete)
+            {
+                segments.extend(partial_segments(stored));
+            }
+        }
+        let mut segments = coalesce(segments);
+        let now = SystemTime::now();
+        let variant_parts = self.options.variant_parts(parts)?;
+        let cache_options = self.options.cache_options.unwrap_or_default();
+        if let [(0, body)] = segments.as_mut_slice() {
+            if body.len() as u64 == complete {
+                let mut whole = res.clone();
+                whole.status = 200;
+                whole.body = std::mem::take(body).into();
+                whole.headers.remove(CONTENT_RANGE);
+                whole
+                    .headers
+                    .insert(CONTENT_LENGTH, HeaderValue::from(complete));
+                self.manager.delete(&partial_key).await.map_err(Into::into)?;
+                let policy = CachePolicy::new_options(
+                    &variant_parts,
+                    &whole.parts()?,
+                    now,
+                    cache_options,
+                );
+                if policy.is_storable() && self.options.store_allowed(&whole) {
+                    self.store_qualified(parts, cache_key, whole, policy)
+                        .await?;
+                }
+                return Ok(());
+            }
+        }
+        let mut partial = res.clone();
+        let ranges: Vec<String> = segments
+            .iter()
+            .map(|(first, body)| {
+                format!("{first}-{}", first + body.len() as u64 - 1)
+            })
+            .collect();
+        partial.set_header(PARTIAL_RANGES_HEADER, &ranges.join(","));
+        partial.body =
+            segments.into_iter().flat_map(|(_, body)| body).collect();
+        if !self.options.headers_allowed(&partial_key, &partial) {
+            return Ok(());
+        }
+        let policy = CachePolicy::new_options(
+            &variant_parts,
+            &partial.parts()?,
+            now,
+            cache_options,
+        );
+        self.manager
+            .put(partial_key, partial, policy)
+            .await
+            .map_err(Into::into)?;
+        Ok(())
+    }
+
+    fn policy(
+        &self,
+        middleware: &impl Middleware,
+        res: &HttpResponse,
+    ) -> Result<CachePolicy> {
+        if !self.options.normalizes_variants() {
+            return match self.options.cache_options {
+                Some(options) => middleware.policy_with_options(res, options),
+                None => middleware.policy(res),
+            };
+        }
+        Ok(CachePolicy::new_options(
+            &self.options.variant_parts(&middleware.parts()?)?,
+            &res.parts()?,
+            SystemTime::now(),
+            self.options.cache_options.unwrap_or_default(),
+        ))
+    }
+
+    async fn store(
+        &self,
+        parts: &request::Parts,
+        cache_key: String,
+        mut res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        if !self.options.headers_allowed(&cache_key, &res) {
+            return Ok(res);
+        }
+        self.options.observe(|| CacheEvent::Stored {
+            cache_key: cache_key.clone(),
+            body_size: res.body.len(),
+        });
+        let hash = match self.options.store_variants {
+            true => self.options.variant_hash(parts, &res)?,
+            false => None,
+        };
+        // The variants listed by the entry this replaces
+        let mut variants = match self.options.store_variants {
+            true => match self.lookup(&cache_key, parts).await? {
+                Some((stored, _)) => stored_variants(&stored),
+                None => Vec::new(),
+            },
+            false => Vec::new(),
+        };
+        let Some(hash) = hash else {
+            // A response that doesn't vary anymore replaces all of them
+            let stored = self
+                .manager
+                .put(cache_key.clone(), res, policy)
+                .await
+                .map_err(Into::into)?;
+            self.delete_variants(&cache_key, &variants).await?;
+            return Ok(stored);
+        };
+        // The variant is stored under its own key, and as the latest one under the cache key
+        let hash = format!("{hash:016x}");
+        variants.retain(|variant| *variant != hash);
+        variants.push(hash.clone());
+        let dropped: Vec<String> = variants
+            .drain(..variants.len().saturating_sub(MAX_VARIANTS))
+            .collect();
+        let stored = self
+            .manager
+            .put(variant_key(&cache_key, &hash), res.clone(), policy.clone())
+            .await
+            .map_err(Into::into)?;
+        res.set_header(VARIANTS_HEADER, &variants.join(","));
+        self.manager
+            .put(cache_key.clone(), res, policy)
+            .await
+            .map_err(Into::into)?;
+        self.delete_variants(&cache_key, &dropped).await?;
+        Ok(stored)
+    }
+
+    // Stores the response without the fields listed by qualified `private` and
+    // `no-cache` directives, but returns them to this request.
+    async fn store_qualified(
+        &self,
+        parts: &request::Parts,
+        cache_key: String,
+        res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        match self.options.qualified_directives(&res) {
+            Some(stored) => {
+                self.store(parts, cache_key, stored, policy).await?;
+                Ok(res)
+            }
+            None => self.store(parts, cache_key, res, policy).await,
+        }
+    }
+
+    async fn shadow_fetch(
         &self,
         mut middleware: impl Middleware,
-        mut cached_res: HttpResponse,
-        mut policy: CachePolicy,
+        cache_key: String,
+        cached_res: HttpResponse,
+        policy: CachePolicy,
     ) -> Result<HttpResponse> {
+        let parts = self.options.variant_parts(&middleware.parts()?)?;
+        let res = self.remote_fetch(&mut middleware).await?;
+        // Only compare responses that would have been served without the network
+        if let BeforeRequest::Fresh(_) =
+            policy.before_request(&parts, SystemTime::now())
+        {
+            self.options.observe(|| CacheEvent::ShadowCompared {
+                cache_key,
+                divergences: Divergence::compare(&cached_res, &res),
+            });
+        }
+        Ok(res)
+    }
+
+    // Evaluates the stale response again with the `MaxStale` of the request, if any, as a
+    // `max-stale` directive. Returns the headers to serve it with if that's acceptable.
+    fn accepted_stale(
+        &self,
+        original: &request::Parts,
+        policy: &CachePolicy,
+        now: SystemTime,
+    ) -> Result<Option<response::Parts>> {
+        let Some(MaxStale(max_stale)) = original.extensions.get::<MaxStale>()
+        else {
+            return Ok(None);
+        };
+        let mut parts = self.options.variant_parts(original)?;
+        let directive = format!("max-stale={}", max_stale.as_secs());
+        let cache_control = match parts.headers.get(CACHE_CONTROL) {
+            Some(value) => format!("{}, {directive}", value.to_str()?),
+            None => directive,
+        };
+        parts
+            .headers
+            .insert(CACHE_CONTROL, HeaderValue::from_str(&cache_control)?);
+        Ok(match policy.before_request(&parts, now) {
+            BeforeRequest::Fresh(parts) => Some(parts),
+            BeforeRequest::Stale { .. } => None,
+        })
+    }
+
+    // The keys to delete before looking up the entry of a request the cache handles
+    fn keys_to_delete(&self, parts: &request::Parts) -> Vec<String> {
+        match self.request_mode(parts) {
+            CacheMode::ReadOnly => Vec::new(),
+            _ => self.options.keys_to_cache_bust(parts),
+        }
+    }
+
+    // The keys to delete for a request that bypasses the cache, starting with the stored
+    // response of a `GET` for the same URL
+    fn keys_to_delete_bypassing(&self, parts: &request::Parts) -> Vec<String> {
+        // A paused cache keeps its entries, unsafe requests still invalidate them
+        let paused =
+            self.options.pause.as_ref().map_or(false, PauseHandle::is_paused);
+        let is_get_head = parts.method == http::Method::GET
+            || parts.method == http::Method::HEAD;
+        if paused && is_get_head
+            || self.cache_mode(parts) == CacheMode::ReadOnly
+        {
+            return Vec::new();
+        }
+        let mut keys = vec![self.options.create_cache_key(parts, Some("GET"))];
+        keys.extend(self.options.keys_to_cache_bust(parts));
+        keys
+    }
+
+    fn observe_lookup(
+        &self,
+        cache_key: &str,
+        parts: &request::Parts,
+        stored: Option<&(HttpResponse, CachePolicy)>,
+    ) {
+        self.options.observe(|| CacheEvent::Lookup {
+            cache_key: cache_key.to_string(),
+            outcome: match stored {
+                None => LookupOutcome::Miss,
+                Some((_, policy)) => {
+                    match self.options.variant_parts(parts).map(|parts| {
+                        policy.before_request(&parts, SystemTime::now())
+                    }) {
+                        Ok(BeforeRequest::Fresh(_)) => LookupOutcome::Fresh,
+                        _ => LookupOutcome::Stale,
+                    }
+                }
+            },
+        });
+    }
+
+    // The answer to a request made with `only-if-cached` that has no stored response
+    fn not_cached(&self, url: Url) -> HttpResponse {
+        // ENOTCACHED
+        let mut res = HttpResponse {
+            body: Bytes::from_static(b"GatewayTimeout"),
+            headers: HeaderMap::new(),
+            status: 504,
+            url,
+            version: HttpVersion::Http11,
+        };
+        res.cache_status(HitOrMiss::MISS);
+        res.cache_lookup_status(HitOrMiss::MISS);
+        self.set_cache_reason(&mut res, CacheDecision::OnlyIfCachedMiss);
+        res
+    }
+
+    // Serves the stored response of a request in a read-only mode, unless it's stale and
+    // has to be fetched anew
+    fn read_only_hit(
+        &self,
+        parts: &request::Parts,
+        mut res: HttpResponse,
+        policy: &CachePolicy,
+    ) -> Result<Option<HttpResponse>> {
+        let parts = self.options.variant_parts(parts)?;
+        match policy.before_request(&parts, SystemTime::now()) {
+            BeforeRequest::Fresh(parts) => {
+                res.update_headers(&parts)?;
+                res.cache_status(HitOrMiss::HIT);
+                self.set_cache_reason(&mut res, CacheDecision::Fresh);
+                Ok(Some(res))
+            }
+            BeforeRequest::Stale { .. } if self.options.read_only_stale => {
+                //   110 Response is stale
+                //   MUST be included whenever the returned response is stale.
+                // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                res.add_warning(&res.url.clone(), 110, "Response is stale");
+                res.cache_state(CacheState::Stale);
+                self.set_cache_reason(&mut res, CacheDecision::StaleAccepted);
+                Ok(Some(res))
+            }
+            BeforeRequest::Stale { .. } => Ok(None),
+        }
+    }
+
+    // Decides whether the stored response of a request in the default mode can be served
+    // as it is, or has to be revalidated first
+    fn plan_revalidation(
+        &self,
+        original: &request::Parts,
+        url: &Url,
+        mut cached_res: HttpResponse,
+        policy: &CachePolicy,
+    ) -> Result<Revalidation> {
+        let now = SystemTime::now();
         let before_req =
-            policy.before_request(&middleware.parts()?, SystemTime::now());
-        match before_req {
+            policy.before_request(&self.options.variant_parts(original)?, now);
+        let (mut parts, matches) = match before_req {
             BeforeRequest::Fresh(parts) => {
+                if let Some(refresh_ahead) = &self.options.refresh_ahead {
+                    refresh_ahead.on_hit(
+                        &self.options.create_cache_key(original, None),
+                        original,
+                        policy,
+                    );
+                }
                 cached_res.update_headers(&parts)?;
                 cached_res.cache_status(HitOrMiss::HIT);
                 cached_res.cache_lookup_status(HitOrMiss::HIT);
-                return Ok(cached_res);
+                return Ok(Revalidation::Serve(cached_res));
             }
-            BeforeRequest::Stale { request: parts, matches } => {
-                if matches {
-                    middleware.update_headers(&parts)?;
-                }
+            BeforeRequest::Stale { request, matches } => (request, matches),
+        };
+        if let Some(parts) = self.accepted_stale(original, policy, now)? {
+            cached_res.update_headers(&parts)?;
+            //   110 Response is stale
+            //   MUST be included whenever the returned response is stale.
+            // (https://tools.ietf.org/html/rfc2616#section-14.46)
+            cached_res.add_warning(url, 110, "Response is stale");
+            cached_res.cache_state(CacheState::Stale);
+            cached_res.cache_lookup_status(HitOrMiss::HIT);
+            self.set_cache_reason(
+                &mut cached_res,
+                CacheDecision::StaleAccepted,
+            );
+            return Ok(Revalidation::Serve(cached_res));
+        }
+        if let Some(rate_limit) = &self.options.revalidation_rate_limit {
+            if !cached_res.must_revalidate()
+                && !rate_limit.try_acquire(url.origin().ascii_serialization())
+            {
+                cached_res.add_warning(url, 110, "Response is stale");
+                cached_res.cache_state(CacheState::Stale);
+                self.set_cache_reason(
+                    &mut cached_res,
+                    CacheDecision::RevalidationRateLimited,
+                );
+                return Ok(Revalidation::Serve(cached_res));
+            }
+        }
+        if !matches {
+            return Ok(Revalidation::Fetch { cached: cached_res, parts: None });
+        }
+        self.options.select_validators(&mut parts);
+        self.options.restore_variant_headers(original, &mut parts);
+        Ok(Revalidation::Fetch { cached: cached_res, parts: Some(parts) })
+    }
+
+    // Decides what the response of the origin to a revalidation means for the stored
+    // response
+    fn revalidated(
+        &self,
+        parts: &request::Parts,
+        url: &Url,
+        mut cached_res: HttpResponse,
+        policy: CachePolicy,
+        mut cond_res: HttpResponse,
+    ) -> Result<Revalidated> {
+        self.options.prepare_response(parts, &mut cond_res);
+        let status = StatusCode::from_u16(cond_res.status)?;
+        if status.is_server_error() && cached_res.must_revalidate() {
+            //   111 Revalidation failed
+            //   MUST be included if a cache returns a stale response
+            //   because an attempt to revalidate the response failed,
+            //   due to an inability to reach the server.
+            // (https://tools.ietf.org/html/rfc2616#section-14.46)
+            cached_res.add_warning(url, 111, "Revalidation failed");
+            cached_res.cache_state(CacheState::Stale);
+            self.set_cache_reason(
+                &mut cached_res,
+                CacheDecision::RevalidationFailed,
+            );
+            Ok(Revalidated::Serve(cached_res))
+        } else if cond_res.status == 304 {
+            let (AfterResponse::Modified(policy, parts)
+            | AfterResponse::NotModified(policy, parts)) = policy
+                .after_response(
+                    &self.options.variant_parts(parts)?,
+                    &cond_res.parts()?,
+                    SystemTime::now(),
+                );
+            cached_res.update_headers(&parts)?;
+            cached_res.cache_state(CacheState::Revalidated);
+            cached_res.cache_lookup_status(HitOrMiss::HIT);
+            Ok(Revalidated::NotModified(cached_res, policy))
+        } else if cond_res.status == 200 {
+            cond_res.cache_state(CacheState::Expired);
+            cond_res.cache_lookup_status(HitOrMiss::HIT);
+            Ok(Revalidated::Modified(cond_res))
+        } else {
+            cached_res.cache_state(CacheState::Stale);
+            self.set_cache_reason(
+                &mut cached_res,
+                CacheDecision::RevalidationFailed,
+            );
+            Ok(Revalidated::Serve(cached_res))
+        }
+    }
+
+    // Serves the stored response when the request revalidating it failed, unless it must
+    // be revalidated
+    fn revalidation_failed(
+        &self,
+        url: &Url,
+        mut cached_res: HttpResponse,
+        e: BoxError,
+    ) -> Result<HttpResponse> {
+        if cached_res.must_revalidate() {
+            return Err(e);
+        }
+        //   111 Revalidation failed
+        //   MUST be included if a cache returns a stale response
+        //   because an attempt to revalidate the response failed,
+        //   due to an inability to reach the server.
+        // (https://tools.ietf.org/html/rfc2616#section-14.46)
+        cached_res.add_warning(url, 111, "Revalidation failed");
+        cached_res.cache_state(CacheState::Stale);
+        self.set_cache_reason(
+            &mut cached_res,
+            CacheDecision::RevalidationFailed,
+        );
+        Ok(cached_res)
+    }
+
+    // Whether a response fetched in `mode` is stored, given its policy
+    fn storable(
+        &self,
+        mode: CacheMode,
+        parts: &request::Parts,
+        res: &HttpResponse,
+        policy: &CachePolicy,
+    ) -> bool {
+        let is_get_head = parts.method == http::Method::GET
+            || parts.method == http::Method::HEAD;
+        let is_cacheable = is_get_head
+            && mode != CacheMode::NoStore
+            && mode != CacheMode::Reload
+            && mode != CacheMode::ReadOnly
+            && res.status == 200
+            && policy.is_storable();
+        (is_cacheable || mode == CacheMode::IgnoreRules && res.status == 200)
+            && self.options.store_allowed(res)
+    }
+
+    // Applies the options deciding whether a storable response is stored, adding what is
+    // stored along with it
+    fn admit(
+        &self,
+        parts: &request::Parts,
+        cache_key: &str,
+        res: &mut HttpResponse,
+        fetch_latency: Duration,
+    ) -> Result<bool> {
+        if !self.options.sampled(cache_key) {
+            return Ok(false);
+        }
+        if let Some(store_metadata) = &self.options.store_metadata {
+            if let Some(metadata) = store_metadata(parts, res) {
+                res.set_metadata(&metadata)?;
             }
         }
+        res.set_header(
+            FETCH_LATENCY_HEADER,
+            &fetch_latency.as_millis().to_string(),
+        );
+        if let Some(admission) = &self.options.admission {
+            let admitted = admission.admit(cache_key);
+            self.options.observe(|| CacheEvent::Admission {
+                cache_key: cache_key.to_string(),
+                admitted,
+            });
+            return Ok(admitted);
+        }
+        Ok(true)
+    }
+
+    // Finishes the response returned to the client
+    fn finish(
+        &self,
+        parts: &request::Parts,
+        mut res: HttpResponse,
+    ) -> Result<HttpResponse> {
+        // Outcomes only known while running, like a revalidation, are set
+        // along the way, everything else is explained from the final response.
+        if self.options.cache_reason_header
+            && !res.headers.contains_key(XCACHEREASON)
+        {
+            let decision = self.explain(parts, Some(&res))?;
+            res.cache_reason(decision);
+        }
+        self.options.record_stats(parts, &mut res);
+        self.options.reencode(&mut res);
+        res.headers.remove(FETCH_LATENCY_HEADER);
+        res.headers.remove(VARIANTS_HEADER);
+        Ok(res)
+    }
+
+    async fn conditional_fetch(
+        &self,
+        mut middleware: impl Middleware,
+        cached_res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let original = middleware.parts()?;
         let req_url = middleware.url()?;
-        match middleware.remote_fetch().await {
-            Ok(mut cond_res) => {
-                let status = StatusCode::from_u16(cond_res.status)?;
-                if status.is_server_error() && cached_res.must_revalidate() {
-                    //   111 Revalidation failed
-                    //   MUST be included if a cache returns a stale response
-                    //   because an attempt to revalidate the response failed,
-                    //   due to an inability to reach the server.
-                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                    cached_res.add_warning(
-                        &req_url,
-                        111,
-                        "Revalidation failed",
-                    );
-                    cached_res.cache_status(HitOrMiss::HIT);
-                    Ok(cached_res)
-                } else if cond_res.status == 304 {
-                    let after_res = policy.after_response(
-                        &middleware.parts()?,
-                        &cond_res.parts()?,
-                        SystemTime::now(),
+        let (mut cached_res, parts) = match self
+            .plan_revalidation(&original, &req_url, cached_res, &policy)?
+        {
+            Revalidation::Serve(res) => return Ok(res),
+            Revalidation::Fetch { cached, parts } => (cached, parts),
+        };
+        if let Some(parts) = parts {
+            middleware.update_headers(&parts)?;
+        }
+        middleware.mark_revalidation(CacheRevalidationRequest::Conditional)?;
+        let stale_on_timeout = match &self.options.stale_on_timeout {
+            Some(stale_on_timeout) if !cached_res.must_revalidate() => {
+                stale_on_timeout
+            }
+            _ => return self.revalidate(middleware, cached_res, policy).await,
+        };
+        let mut timeout = (stale_on_timeout.sleep)(stale_on_timeout.timeout);
+        let started = Instant::now();
+        // To finish in the background, the revalidation mustn't borrow the request
+        let background = stale_on_timeout.background.as_ref();
+        let mut detached = match background {
+            Some(_) => middleware.detached_fetch()?,
+            None => None,
+        };
+        let fetched = match &mut detached {
+            Some(fetch) => {
+                before_timeout(fetch.as_mut(), timeout.as_mut()).await
+            }
+            None => {
+                let mut fetch = middleware.remote_fetch();
+                before_timeout(fetch.as_mut(), timeout.as_mut()).await
+            }
+        };
+        match fetched {
+            Some(fetched) => {
+                let fetch_latency = started.elapsed();
+                self.finish_revalidation(
+                    middleware,
+                    cached_res,
+                    policy,
+                    req_url,
+                    fetched,
+                    fetch_latency,
+                )
+                .await
+            }
+            None => {
+                if let (Some(background), Some(fetch)) = (background, detached)
+                {
+                    background.spawn(
+                        fetch,
+                        middleware.parts()?,
+                        req_url.clone(),
+                        cached_res.clone(),
+                        policy,
+                        started,
                     );
-                    match after_res {
-                        AfterResponse::Modified(new_policy, parts)
-                        | AfterResponse::NotModified(new_policy, parts) => {
-                            policy = new_policy;
-                            cached_res.update_headers(&parts)?;
-                        }
-                    }
-                    cached_res.cache_status(HitOrMiss::HIT);
-                    cached_res.cache_lookup_status(HitOrMiss::HIT);
-                    let res = self
-                        .manager
-                        .put(
-                            self.options
-                                .create_cache_key(&middleware.parts()?, None),
-                            cached_res,
-                            policy,
-                        )
-                        .await?;
-                    Ok(res)
-                } else if cond_res.status == 200 {
-                    let policy = match self.options.cache_options {
-                        Some(options) => middleware
-                            .policy_with_options(&cond_res, options)?,
-                        None => middleware.policy(&cond_res)?,
-                    };
-                    cond_res.cache_status(HitOrMiss::MISS);
-                    cond_res.cache_lookup_status(HitOrMiss::HIT);
-                    let res = self
-                        .manager
-                        .put(
-                            self.options
-                                .create_cache_key(&middleware.parts()?, None),
-                            cond_res,
-                            policy,
-                        )
-                        .await?;
-                    Ok(res)
-                } else {
-                    cached_res.cache_status(HitOrMiss::HIT);
-                    Ok(cached_res)
                 }
+                //   110 Response is stale
+                //   MUST be included whenever the returned response is stale.
+                // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                cached_res.add_warning(&req_url, 110, "Response is stale");
+                cached_res.cache_state(CacheState::Stale);
+                self.set_cache_reason(
+                    &mut cached_res,
+                    CacheDecision::RevalidationDeadlineExceeded,
+                );
+                if let Some(refresh) = &stale_on_timeout.refresh {
+                    refresh(&original);
+                }
+                Ok(cached_res)
             }
-            Err(e) => {
-                if cached_res.must_revalidate() {
-                    Err(e)
-                } else {
-                    //   111 Revalidation failed
-                    //   MUST be included if a cache returns a stale response
-                    //   because an attempt to revalidate the response failed,
-                    //   due to an inability to reach the server.
-                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                    cached_res.add_warning(
-                        &req_url,
-                        111,
-                        "Revalidation failed",
-                    );
-                    cached_res.cache_status(HitOrMiss::HIT);
-                    Ok(cached_res)
+        }
+    }
+
+    async fn revalidate(
+        &self,
+        mut middleware: impl Middleware,
+        cached_res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let req_url = middleware.url()?;
+        let started = Instant::now();
+        let fetched = middleware.remote_fetch().await;
+        let fetch_latency = started.elapsed();
+        self.finish_revalidation(
+            middleware,
+            cached_res,
+            policy,
+            req_url,
+            fetched,
+            fetch_latency,
+        )
+        .await
+    }
+
+    async fn finish_revalidation(
+        &self,
+        middleware: impl Middleware,
+        cached_res: HttpResponse,
+        policy: CachePolicy,
+        req_url: Url,
+        fetched: Result<HttpResponse>,
+        fetch_latency: Duration,
+    ) -> Result<HttpResponse> {
+        let parts = middleware.parts()?;
+        self.revalidation_finished(
+            &parts,
+            cached_res,
+            policy,
+            req_url,
+            fetched,
+            fetch_latency,
+            move |res| self.policy(&middleware, res),
+        )
+        .await
+    }
+
+    // Stores the responses of the revalidations that finished in the background
+    async fn store_finished_revalidations(&self) {
+        let Some(background) =
+            self.options.stale_on_timeout.as_ref().and_then(
+                |stale_on_timeout| stale_on_timeout.background.as_ref(),
+            )
+        else {
+            return;
+        };
+        for finished in background.take_finished() {
+            let options = &self.options;
+            let parts = finished.parts;
+            // The request isn't served, so failures are left for the next revalidation
+            self.revalidation_finished(
+                &parts,
+                finished.cached,
+                finished.policy,
+                finished.url,
+                finished.fetched,
+                finished.fetch_latency,
+                |res| {
+                    Ok(CachePolicy::new_options(
+                        &options.variant_parts(&parts)?,
+                        &res.parts()?,
+                        SystemTime::now(),
+                        options.cache_options.unwrap_or_default(),
+                    ))
+                },
+            )
+            .await
+            .ok();
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn revalidation_finished(
+        &self,
+        parts: &request::Parts,
+        cached_res: HttpResponse,
+        policy: CachePolicy,
+        req_url: Url,
+        fetched: Result<HttpResponse>,
+        fetch_latency: Duration,
+        new_policy: impl FnOnce(&HttpResponse) -> Result<CachePolicy>,
+    ) -> Result<HttpResponse> {
+        let cond_res = match fetched {
+            Ok(cond_res) => cond_res,
+            Err(e) => return self.revalidation_failed(&req_url, cached_res, e),
+        };
+        let cache_key = self.options.create_cache_key(parts, None);
+        match self.revalidated(parts, &req_url, cached_res, policy, cond_res)? {
+            Revalidated::Serve(res) => Ok(res),
+            Revalidated::NotModified(cached_res, policy) => {
+                let mut res =
+                    self.store(parts, cache_key, cached_res, policy).await?;
+                self.set_cache_reason(&mut res, CacheDecision::Revalidated);
+                Ok(res)
+            }
+            Revalidated::Modified(mut cond_res) => {
+                let qualified = self.options.qualified_directives(&cond_res);
+                let policy =
+                    new_policy(qualified.as_ref().unwrap_or(&cond_res))?;
+                let mode = self.request_mode(parts);
+                if !self.storable(mode, parts, &cond_res, &policy)
+                    || !self.admit(
+                        parts,
+                        &cache_key,
+                        &mut cond_res,
+                        fetch_latency,
+                    )?
+                {
+                    return Ok(cond_res);
                 }
+                self.store_qualified(parts, cache_key, cond_res, policy).await
             }
         }
     }
@@ -1,21 +1,181 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::{CacheManager, HttpResponse, Result};
+use super::{compact::CompactPolicy, format};
+use crate::{
+    BlockingCacheManager, BoxError, Bytes, CacheEvent, CacheManager,
+    CacheStats, CachedEntry, CorruptEntry, HttpResponse, HttpVersion, Observer,
+    Result, Sleep,
+};
 
-use http_cache_semantics::CachePolicy;
+use futures_util::future::join_all;
+use http::{request, HeaderMap, HeaderName, HeaderValue};
+use http_cache_semantics::{CacheOptions, CachePolicy};
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use url::Url;
+
+const NPM_KEY_PREFIX: &str = "make-fetch-happen:request-cache:";
+
+// What the puts of this process added to each cache directory with limits since its
+// entries were last counted, by path. Overwritten entries and shared content are counted
+// again, so it only tells when the limits may have been reached. A directory that isn't
+// listed hasn't been counted yet.
+static USAGE: Mutex<Option<HashMap<PathBuf, Usage>>> = Mutex::new(None);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Usage {
+    entries: u64,
+    bytes: u64,
+    // Whether the entries are being counted, the puts meanwhile are added afterwards
+    evicting: bool,
+}
+
+// Headers make-fetch-happen keeps from responses
+const NPM_RESPONSE_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-encoding",
+    "content-language",
+    "content-type",
+    "date",
+    "etag",
+    "expires",
+    "last-modified",
+    "link",
+    "location",
+    "pragma",
+    "vary",
+];
 
 /// Implements [`CacheManager`] with [`cacache`](https://github.com/zkat/cacache-rs) as the backend.
+///
+/// It also implements [`BlockingCacheManager`] with the synchronous API of cacache, which
+/// doesn't run the async runtime enabled by the `cacache-async-std` or `cacache-tokio`
+/// feature. cacache needs one of them to build all the same.
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct CACacheManager {
     /// Directory where the cache will be stored.
     pub path: PathBuf,
+    /// When set, bodies up to this many bytes are stored inside the index entry so a hit
+    /// takes a single read, larger bodies are stored on their own as content.
+    /// When unset, only empty bodies are stored inside the index entry.
+    pub inline_threshold: Option<usize>,
+    /// When set, entries are written the way [make-fetch-happen](https://github.com/npm/make-fetch-happen)
+    /// writes them so Node tooling can share the cache, see [`CACacheManager::npm_cache_key`].
+    /// Bodies are always stored as content and `inline_threshold` is ignored.
+    /// Entries written by make-fetch-happen are read either way.
+    pub npm_compat: bool,
+    /// The algorithm the checksums of bodies stored as content are computed with. When
+    /// unset, SHA-256 is used, or SHA-512 with `npm_compat` as make-fetch-happen does.
+    /// Entries written with any algorithm are read.
+    pub algorithm: Option<cacache::Algorithm>,
+    /// When set, `put` flushes the index entry and content it wrote to disk before
+    /// returning, so stored entries survive a crash or power loss at the cost of slower
+    /// writes. The flush blocks the calling thread.
+    pub fsync: bool,
+    /// When set, the oldest entries are evicted after a `put` that takes the cache past
+    /// this many bytes, counted as by [`CacheManager::stats`], until it's below 90% of the
+    /// limit. An entry larger than the limit is evicted right away.
+    pub max_bytes: Option<u64>,
+    /// When set, the oldest entries are evicted after a `put` that takes the cache past
+    /// this many entries, until it's below 90% of the limit.
+    pub max_entries: Option<u64>,
+    /// Called with a [`CacheEvent::CorruptEntry`] when an entry can't be read back. The
+    /// entry is removed and `get` reports a miss rather than an error.
+    pub observer: Option<Observer>,
+}
+
+impl fmt::Debug for CACacheManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CACacheManager")
+            .field("path", &self.path)
+            .field("inline_threshold", &self.inline_threshold)
+            .field("npm_compat", &self.npm_compat)
+            .field("algorithm", &self.algorithm)
+            .field("fsync", &self.fsync)
+            .field("max_bytes", &self.max_bytes)
+            .field("max_entries", &self.max_entries)
+            .field(
+                "observer",
+                &self.observer.as_ref().map(|_| "Fn(&CacheEvent)"),
+            )
+            .finish()
+    }
 }
 
 impl Default for CACacheManager {
     fn default() -> Self {
-        Self { path: "./http-cacache".into() }
+        Self {
+            path: "./http-cacache".into(),
+            inline_threshold: None,
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        }
+    }
+}
+
+/// Builds a [`CACacheManager`], starting from the defaults of [`CACacheManager::default`].
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+#[derive(Debug, Default, Clone)]
+pub struct CACacheManagerBuilder {
+    manager: CACacheManager,
+}
+
+impl CACacheManagerBuilder {
+    /// Sets the directory where the cache will be stored, see [`CACacheManager::path`].
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manager.path = path.into();
+        self
+    }
+    /// Stores bodies up to `bytes` inside the index entry, see
+    /// [`CACacheManager::inline_threshold`].
+    pub fn inline_threshold(mut self, bytes: usize) -> Self {
+        self.manager.inline_threshold = Some(bytes);
+        self
+    }
+    /// Writes entries the way make-fetch-happen does, see [`CACacheManager::npm_compat`].
+    pub fn npm_compat(mut self, npm_compat: bool) -> Self {
+        self.manager.npm_compat = npm_compat;
+        self
+    }
+    /// Sets the algorithm of the checksums of bodies, see [`CACacheManager::algorithm`].
+    pub fn algorithm(mut self, algorithm: cacache::Algorithm) -> Self {
+        self.manager.algorithm = Some(algorithm);
+        self
+    }
+    /// Flushes every entry to disk as it's stored, see [`CACacheManager::fsync`].
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        self.manager.fsync = fsync;
+        self
+    }
+    /// Bounds the total size of the cache, see [`CACacheManager::max_bytes`].
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.manager.max_bytes = Some(bytes);
+        self
+    }
+    /// Bounds the number of entries, see [`CACacheManager::max_entries`].
+    pub fn max_entries(mut self, entries: u64) -> Self {
+        self.manager.max_entries = Some(entries);
+        self
+    }
+    /// Reports entries that can't be read back, see [`CACacheManager::observer`].
+    pub fn observer(mut self, observer: Observer) -> Self {
+        self.manager.observer = Some(observer);
+        self
+    }
+    /// Builds the manager.
+    pub fn build(self) -> CACacheManager {
+        self.manager
     }
 }
 
@@ -25,28 +185,891 @@ struct Store {
     policy: CachePolicy,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+struct CompactStore {
+    response: HttpResponse,
+    policy: CompactPolicy,
+}
+
+impl CompactStore {
+    fn into_store(self) -> Result<Store> {
+        Ok(Store {
+            response: self.response,
+            policy: self.policy.into_policy()?,
+        })
+    }
+}
+
+// Kept in the raw metadata of the index entry. Entries written before this
+// have no raw metadata and hold the whole `Store` as content instead.
+#[derive(Debug, Deserialize, Serialize)]
+enum Record {
+    // The whole entry, nothing is written as content.
+    Inline(Store),
+    // The entry without its body, the content is the body.
+    External(Store),
+    // As `Inline` with the compact policy, written since.
+    InlineCompact(CompactStore),
+    // As `External` with the compact policy, written since.
+    ExternalCompact(CompactStore),
+}
+
+// How an index entry holds its response
+enum Entry {
+    Inline(Box<Store>),
+    External(Box<Store>),
+    // Written by make-fetch-happen, the content is the body.
+    Npm(NpmMetadata),
+    // Written by older versions, the content is the whole `Store`.
+    Legacy,
+    // Written in another version of the format, read as a miss.
+    OtherVersion,
+}
+
+impl Entry {
+    fn parse(metadata: &cacache::Metadata) -> Result<Self> {
+        if let Some(raw) = &metadata.raw_metadata {
+            let record = match format::decode(raw, format::unversioned)? {
+                Some(record) => record,
+                None => return Ok(Self::OtherVersion),
+            };
+            return Ok(match record {
+                Record::Inline(store) => Self::Inline(Box::new(store)),
+                Record::External(store) => Self::External(Box::new(store)),
+                Record::InlineCompact(store) => {
+                    Self::Inline(Box::new(store.into_store()?))
+                }
+                Record::ExternalCompact(store) => {
+                    Self::External(Box::new(store.into_store()?))
+                }
+            });
+        }
+        if metadata.metadata.is_null() {
+            return Ok(Self::Legacy);
+        }
+        Ok(Self::Npm(NpmMetadata::deserialize(&metadata.metadata)?))
+    }
+
+    // Rebuilds the stored entry, `content` being what the index entry points to
+    fn into_store(self, content: Vec<u8>) -> Result<Store> {
+        Ok(match self {
+            Self::Inline(store) => *store,
+            Self::External(mut store) => {
+                store.response.body = content.into();
+                *store
+            }
+            Self::Npm(npm) => {
+                npm.into_store(content).map_err(CorruptEntry::new)?
+            }
+            Self::Legacy => bincode::deserialize::<Store>(&content)
+                .map_err(CorruptEntry::new)?,
+            Self::OtherVersion => return Err(format::OTHER_VERSION.into()),
+        })
+    }
+}
+
+// The JSON metadata make-fetch-happen keeps in the index entry
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NpmMetadata {
+    time: u64,
+    url: String,
+    #[serde(default)]
+    req_headers: HashMap<String, String>,
+    #[serde(default)]
+    res_headers: HashMap<String, String>,
+    #[serde(default)]
+    options: NpmOptions,
+    // Only present when not 200 or 304
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct NpmOptions {
+    compress: bool,
+}
+
+impl Default for NpmOptions {
+    fn default() -> Self {
+        Self { compress: true }
+    }
+}
+
+impl NpmMetadata {
+    fn new(response: &HttpResponse) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let res_headers = NPM_RESPONSE_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let values: Vec<&str> = response
+                    .headers
+                    .get_all(*name)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .collect();
+                (!values.is_empty())
+                    .then(|| (name.to_string(), values.join(", ")))
+            })
+            .collect();
+        Self {
+            time,
+            url: response.url.to_string(),
+            // The request headers aren't available here, so make-fetch-happen
+            // won't reuse responses that vary on a header it sends.
+            req_headers: HashMap::new(),
+            res_headers,
+            options: NpmOptions::default(),
+            status: match response.status {
+                200 | 304 => None,
+                status => Some(status),
+            },
+        }
+    }
+
+    // Describes the record stored under `cache_key`, make-fetch-happen only stores `GET`s
+    fn entry(&self, cache_key: &str) -> Result<CachedEntry> {
+        Ok(CachedEntry {
+            cache_key: cache_key.to_string(),
+            url: Url::parse(&self.url)?,
+            method: http::Method::GET,
+            stored_at: UNIX_EPOCH + Duration::from_millis(self.time),
+        })
+    }
+
+    // Rebuilds the entry, with the policy make-fetch-happen would use for it.
+    fn into_store(self, body: Vec<u8>) -> Result<Store> {
+        let mut req = http::Request::get(self.url.as_str());
+        for (name, value) in &self.req_headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        let req = req.body(())?.into_parts().0;
+        let mut headers = HeaderMap::with_capacity(self.res_headers.len());
+        for (name, value) in &self.res_headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        let response = HttpResponse {
+            body: body.into(),
+            headers,
+            status: self.status.unwrap_or(200),
+            url: Url::parse(&self.url)?,
+            version: HttpVersion::Http11,
+        };
+        let policy = CachePolicy::new_options(
+            &req,
+            &response.parts()?,
+            UNIX_EPOCH + Duration::from_millis(self.time),
+            CacheOptions {
+                shared: false,
+                ignore_cargo_cult: true,
+                ..Default::default()
+            },
+        );
+        Ok(Store { response, policy })
+    }
+}
+
+/// Metadata of a stored entry, see [`CACacheManager::metadata`].
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// Subresource integrity string of the response body, e.g. `sha256-…`.
+    /// `None` for entries written by versions that didn't record it.
+    pub integrity: Option<String>,
+    /// Size of the response body in bytes, if known without reading it.
+    pub body_size: Option<usize>,
+}
+
+/// Outcome of [`CACacheManager::maintenance`] and [`CACacheManager::verify`], which leaves
+/// the orphans alone.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Number of entries that passed verification.
+    pub entries: usize,
+    /// Number of entries stored inline in the index, see
+    /// [`CACacheManager::inline_threshold`].
+    pub inline_entries: usize,
+    /// Number of entries that failed verification and were removed.
+    pub corrupted_removed: usize,
+    /// Total size in bytes of the content referenced by the entries, counting
+    /// shared content once per entry.
+    pub content_bytes: u64,
+    /// Total size in bytes of the distinct content referenced by the entries.
+    /// The difference to `content_bytes` is what deduplication saves.
+    pub unique_content_bytes: u64,
+    /// Number of content files no entry referenced that were removed.
+    pub orphans_removed: usize,
+    /// Total size in bytes of the removed content files.
+    pub orphan_bytes_removed: u64,
+}
+
 #[allow(dead_code)]
 impl CACacheManager {
+    /// Creates a new manager storing the cache in `path`, e.g. a directory under the
+    /// platform's cache directory, with the other settings left at their defaults.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), ..Self::default() }
+    }
+
+    /// Returns a [`CACacheManagerBuilder`] to configure the manager
+    pub fn builder() -> CACacheManagerBuilder {
+        CACacheManagerBuilder::default()
+    }
+
     /// Clears out the entire cache.
     pub async fn clear(&self) -> Result<()> {
         cacache::clear(&self.path).await?;
         Ok(())
     }
+
+    /// Builds cache keys the way make-fetch-happen does, for use as
+    /// [`HttpCacheOptions::cache_key`](crate::HttpCacheOptions::cache_key) with `npm_compat`.
+    ///
+    /// The method is not part of the key, as make-fetch-happen only caches `GET` requests.
+    pub fn npm_cache_key(parts: &request::Parts) -> String {
+        match Url::parse(&parts.uri.to_string()) {
+            Ok(mut url) => {
+                url.set_fragment(None);
+                url.set_username("").ok();
+                url.set_password(None).ok();
+                format!("{}{}", NPM_KEY_PREFIX, url)
+            }
+            Err(_) => format!("{}{}", NPM_KEY_PREFIX, parts.uri),
+        }
+    }
+
+    /// Returns the metadata of the entry stored under `cache_key` without reading its body.
+    ///
+    /// The integrity string can be used to derive a strong `ETag` or to verify a download
+    /// without hashing the body again.
+    pub async fn metadata(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<EntryMetadata>> {
+        let metadata = match cacache::metadata(&self.path, cache_key).await? {
+            Some(metadata) => metadata,
+            None => return Ok(None),
+        };
+        let body_size = match Entry::parse(&metadata)? {
+            Entry::Inline(store) => store.response.body.len(),
+            Entry::External(_) | Entry::Npm(_) => metadata.size,
+            Entry::Legacy => {
+                return Ok(Some(EntryMetadata {
+                    integrity: None,
+                    body_size: None,
+                }))
+            }
+            Entry::OtherVersion => return Ok(None),
+        };
+        Ok(Some(EntryMetadata {
+            integrity: Some(metadata.integrity.to_string()),
+            body_size: Some(body_size),
+        }))
+    }
+
+    /// Verifies every entry and compacts the store, returning what was found.
+    ///
+    /// Entries whose content no longer matches its checksum or that can't be decoded are
+    /// removed, as are content files no remaining entry refers to, such as those left behind
+    /// by deleted or overwritten entries. Listing the entries and removing content is
+    /// blocking. Content written while this runs may be removed before its entry is
+    /// recorded, so run it while the cache is not being written to.
+    pub async fn maintenance(&self) -> Result<MaintenanceReport> {
+        let (mut report, referenced) = self.verify_entries().await?;
+        self.remove_orphans(&referenced, &mut report)?;
+        Ok(report)
+    }
+
+    /// Verifies every entry, returning what was found.
+    ///
+    /// Entries whose content no longer matches its checksum or that can't be decoded are
+    /// removed and reported to the [`observer`](Self::observer), as `get` does when it
+    /// comes across one. Unlike [`maintenance`](Self::maintenance) content no entry refers
+    /// to is kept, so it can run while the cache is in use. Listing the entries is blocking.
+    pub async fn verify(&self) -> Result<MaintenanceReport> {
+        Ok(self.verify_entries().await?.0)
+    }
+
+    // Verifies every entry, returning the content the remaining ones refer to
+    async fn verify_entries(
+        &self,
+    ) -> Result<(MaintenanceReport, HashSet<String>)> {
+        let mut report = MaintenanceReport::default();
+        let mut referenced = HashSet::new();
+        let entries = cacache::list_sync(&self.path)
+            .collect::<cacache::Result<Vec<_>>>()?;
+        for metadata in entries {
+            let entry = match Entry::parse(&metadata) {
+                Ok(Entry::Inline(_)) => {
+                    report.entries += 1;
+                    report.inline_entries += 1;
+                    continue;
+                }
+                // Left alone along with its content, it isn't corrupt
+                Ok(Entry::OtherVersion) => {
+                    let (algorithm, hex) = metadata.integrity.to_hex();
+                    referenced.insert(format!("{}-{}", algorithm, hex));
+                    continue;
+                }
+                Ok(entry) => entry,
+                Err(e) => {
+                    cacache::remove(&self.path, &metadata.key).await?;
+                    self.report(&metadata.key, e);
+                    report.corrupted_removed += 1;
+                    continue;
+                }
+            };
+            let integrity = &metadata.integrity;
+            let error = match cacache::read_hash(&self.path, integrity).await {
+                // Legacy entries hold the whole entry as content
+                Ok(d) if matches!(entry, Entry::Legacy) => {
+                    bincode::deserialize::<Store>(&d).err().map(Into::into)
+                }
+                Ok(_) => None,
+                Err(e) => Some(e.into()),
+            };
+            if let Some(error) = error {
+                self.remove_corrupted(&metadata.key).await?;
+                self.report(&metadata.key, error);
+                report.corrupted_removed += 1;
+                continue;
+            }
+            report.entries += 1;
+            report.content_bytes += metadata.size as u64;
+            let (algorithm, hex) = integrity.to_hex();
+            if referenced.insert(format!("{}-{}", algorithm, hex)) {
+                report.unique_content_bytes += metadata.size as u64;
+            }
+        }
+        Ok((report, referenced))
+    }
+
+    /// Removes the entries that have been stale for longer than `grace` and returns how
+    /// many were removed, along with the content no remaining entry refers to. A stale
+    /// entry can still be revalidated if the response has an `ETag` or `Last-Modified`
+    /// header, `grace` should cover how long that is worthwhile.
+    ///
+    /// Unlike [`maintenance`](Self::maintenance) it can run while the cache is in use. The
+    /// content of an entry stored again while it runs may be removed, which makes the
+    /// entry a miss. Listing the entries is blocking.
+    pub async fn prune_expired(&self, grace: Duration) -> Result<u64> {
+        let cutoff = SystemTime::now().checked_sub(grace).unwrap_or(UNIX_EPOCH);
+        let entries = cacache::list_sync(&self.path)
+            .collect::<cacache::Result<Vec<_>>>()?;
+        let mut referenced = HashSet::new();
+        let mut expired = Vec::new();
+        for metadata in entries {
+            match self.stored_policy(&metadata).await {
+                Ok((policy, content)) if stale_at(&policy, cutoff) => {
+                    expired.push((metadata, content))
+                }
+                // Entries that can't be decoded are left to `get` and `maintenance`
+                _ => {
+                    referenced.insert(metadata.integrity.to_string());
+                }
+            }
+        }
+        let mut removed = 0;
+        for (metadata, content) in expired {
+            cacache::remove(&self.path, &metadata.key).await?;
+            removed += 1;
+            // Content shared with a remaining entry is kept, and removed only once
+            if content && referenced.insert(metadata.integrity.to_string()) {
+                // It may already be gone, which is what's wanted anyway
+                cacache::remove_hash(&self.path, &metadata.integrity)
+                    .await
+                    .ok();
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Runs [`prune_expired`](Self::prune_expired) with `grace` every `period`, waiting with
+    /// `sleep`, until the returned future is dropped. It must be spawned on the runtime in
+    /// use, e.g. `tokio::spawn(manager.clone().prune_expired_every(period, grace, sleep))`.
+    /// A pass that fails is tried again a period later.
+    pub async fn prune_expired_every(
+        self,
+        period: Duration,
+        grace: Duration,
+        sleep: Sleep,
+    ) {
+        loop {
+            sleep(period).await;
+            self.prune_expired(grace).await.ok();
+        }
+    }
+
+    // The policy of an index entry, and whether its body is stored as content, reading the
+    // content only for legacy entries
+    async fn stored_policy(
+        &self,
+        metadata: &cacache::Metadata,
+    ) -> Result<(CachePolicy, bool)> {
+        Ok(match Entry::parse(metadata)? {
+            Entry::Inline(store) => (store.policy, false),
+            Entry::External(store) => (store.policy, true),
+            Entry::Npm(npm) => (npm.into_store(Vec::new())?.policy, true),
+            Entry::Legacy => {
+                let content =
+                    cacache::read_hash(&self.path, &metadata.integrity).await?;
+                (bincode::deserialize::<Store>(&content)?.policy, true)
+            }
+            Entry::OtherVersion => return Err(format::OTHER_VERSION.into()),
+        })
+    }
+
+    /// Evicts the oldest entries, by when they were stored, until the cache is within
+    /// [`max_bytes`](Self::max_bytes) and [`max_entries`](Self::max_entries), and returns
+    /// how many were evicted. `put` keeps a count of what it writes and evicts once the
+    /// count reaches a limit, so this only needs to be called after lowering a limit, or
+    /// when other processes write to the cache.
+    ///
+    /// Listing the entries is blocking and takes longer the more entries there are.
+    pub fn evict(&self) -> Result<u64> {
+        if self.max_bytes.is_none() && self.max_entries.is_none() {
+            return Ok(0);
+        }
+        self.usage(|usages| {
+            usages.insert(
+                self.path.clone(),
+                Usage { evicting: true, ..Default::default() },
+            );
+        });
+        self.evict_to(self.max_entries, self.max_bytes)
+    }
+
+    fn usage<T>(&self, f: impl FnOnce(&mut HashMap<PathBuf, Usage>) -> T) -> T {
+        let mut usage = USAGE.lock().unwrap_or_else(PoisonError::into_inner);
+        f(usage.get_or_insert_with(HashMap::new))
+    }
+
+    // Adds an entry of `bytes` written by a put to the usage of the directory, and returns
+    // whether it may have reached a limit, or wasn't counted yet, and has to be evicted
+    // from. Only one put evicts at a time.
+    fn track(&self, bytes: u64) -> bool {
+        if self.max_bytes.is_none() && self.max_entries.is_none() {
+            return false;
+        }
+        self.usage(|usages| {
+            let Some(usage) = usages.get_mut(&self.path) else {
+                usages.insert(
+                    self.path.clone(),
+                    Usage { evicting: true, ..Default::default() },
+                );
+                return true;
+            };
+            usage.entries += 1;
+            usage.bytes += bytes;
+            if usage.evicting {
+                return false;
+            }
+            let reached =
+                self.max_entries.map_or(false, |max| usage.entries > max)
+                    || self.max_bytes.map_or(false, |max| usage.bytes > max);
+            if reached {
+                *usage = Usage { evicting: true, ..Default::default() };
+            }
+            reached
+        })
+    }
+
+    // Evicts entries until the cache is below 90% of the limits, leaving room for the puts
+    // that follow
+    fn evict_tracked(&self) -> Result<u64> {
+        let low_water = |max: u64| max - max / 10;
+        self.evict_to(
+            self.max_entries.map(low_water),
+            self.max_bytes.map(low_water),
+        )
+    }
+
+    // Records what's left after the entries were counted, on top of what the puts added
+    // meanwhile. The directory is counted again on the next put if that failed.
+    fn counted(&self, remaining: Option<Usage>) {
+        self.usage(|usages| match remaining {
+            Some(remaining) => {
+                let usage = usages.entry(self.path.clone()).or_default();
+                usage.entries += remaining.entries;
+                usage.bytes += remaining.bytes;
+                usage.evicting = false;
+            }
+            None => {
+                usages.remove(&self.path);
+            }
+        })
+    }
+
+    fn evict_to(
+        &self,
+        max_entries: Option<u64>,
+        max_bytes: Option<u64>,
+    ) -> Result<u64> {
+        match self.list_and_evict(max_entries, max_bytes) {
+            Ok((evicted, remaining)) => {
+                self.counted(Some(remaining));
+                Ok(evicted)
+            }
+            Err(e) => {
+                self.counted(None);
+                Err(e)
+            }
+        }
+    }
+
+    fn list_and_evict(
+        &self,
+        max_entries: Option<u64>,
+        max_bytes: Option<u64>,
+    ) -> Result<(u64, Usage)> {
+        let mut entries = cacache::list_sync(&self.path)
+            .collect::<cacache::Result<Vec<_>>>()?;
+        entries.sort_by_key(|metadata| metadata.time);
+        // Counted as in `stats`, with content shared by several entries counted once
+        let mut entry_count = entries.len() as u64;
+        let mut bytes = 0;
+        let mut references = HashMap::<String, usize>::new();
+        for metadata in &entries {
+            bytes += raw_len(metadata);
+            let count =
+                references.entry(metadata.integrity.to_string()).or_default();
+            if *count == 0 {
+                bytes += metadata.size as u64;
+            }
+            *count += 1;
+        }
+        let mut evicted = 0;
+        for metadata in entries {
+            if max_entries.map_or(true, |max| entry_count <= max)
+                && max_bytes.map_or(true, |max| bytes <= max)
+            {
+                break;
+            }
+            cacache::remove_sync(&self.path, &metadata.key)?;
+            evicted += 1;
+            entry_count -= 1;
+            bytes -= raw_len(&metadata);
+            let count =
+                references.entry(metadata.integrity.to_string()).or_default();
+            *count -= 1;
+            if *count == 0 {
+                bytes -= metadata.size as u64;
+                // Inline entries have no content, and it may already be gone otherwise
+                if !matches!(Entry::parse(&metadata), Ok(Entry::Inline(_))) {
+                    cacache::remove_hash_sync(&self.path, &metadata.integrity)
+                        .ok();
+                }
+            }
+        }
+        Ok((evicted, Usage { entries: entry_count, bytes, evicting: false }))
+    }
+
+    // Content is laid out by cacache as `content-v2/<algorithm>/<hex[0..2]>/<hex[2..4]>/<hex[4..]>`.
+    fn remove_orphans(
+        &self,
+        referenced: &HashSet<String>,
+        report: &mut MaintenanceReport,
+    ) -> Result<()> {
+        let root = self.path.join("content-v2");
+        if !root.is_dir() {
+            return Ok(());
+        }
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let id = match content_id(&root, &path) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                if !referenced.contains(&id) {
+                    report.orphan_bytes_removed += entry.metadata()?.len();
+                    fs::remove_file(&path)?;
+                    report.orphans_removed += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Describes an index entry, reading its content only for legacy entries
+    async fn describe(
+        &self,
+        metadata: &cacache::Metadata,
+    ) -> Result<CachedEntry> {
+        let store = match Entry::parse(metadata)? {
+            Entry::Inline(store) | Entry::External(store) => *store,
+            Entry::Npm(npm) => return npm.entry(&metadata.key),
+            Entry::Legacy => bincode::deserialize(
+                &cacache::read_hash(&self.path, &metadata.integrity).await?,
+            )?,
+            Entry::OtherVersion => return Err(format::OTHER_VERSION.into()),
+        };
+        Ok(CompactPolicy::new(&store.policy)?
+            .entry(&metadata.key, &store.response))
+    }
+
+    // Reads the content an index entry points to, dropping the entry if the
+    // content no longer matches its checksum.
+    async fn read_content(
+        &self,
+        cache_key: &str,
+        integrity: &cacache::Integrity,
+    ) -> Result<Option<Vec<u8>>> {
+        match cacache::read_hash(&self.path, integrity).await {
+            Ok(d) => Ok(Some(d)),
+            Err(
+                e @ (cacache::Error::IntegrityError(_)
+                | cacache::Error::SizeMismatch(..)),
+            ) => {
+                // The stored content no longer matches the checksum recorded
+                // in the index, remove it so it will be fetched again.
+                self.remove_corrupted(cache_key).await?;
+                self.report(cache_key, e.into());
+                Ok(None)
+            }
+            Err(_e) => Ok(None),
+        }
+    }
+
+    // Reports an entry that can't be read back to the observer
+    fn report(&self, cache_key: &str, error: BoxError) {
+        if let Some(observer) = &self.observer {
+            let error = match error.downcast::<CorruptEntry>() {
+                Ok(corrupt) => *corrupt,
+                Err(e) => CorruptEntry::new(e),
+            };
+            observer(&CacheEvent::CorruptEntry {
+                cache_key: cache_key.to_string(),
+                error: error.to_string(),
+            });
+        }
+    }
+
+    async fn remove_corrupted(&self, cache_key: &str) -> Result<()> {
+        if let Some(metadata) = cacache::metadata(&self.path, cache_key).await?
+        {
+            cacache::remove_hash(&self.path, &metadata.integrity).await?;
+        }
+        cacache::remove(&self.path, cache_key).await?;
+        Ok(())
+    }
+
+    // The index entry `put` writes, and the body to write as content unless it's stored
+    // inline. The index entry records the checksum of the body either way, the caller
+    // adds it for content.
+    fn prepare(
+        &self,
+        response: &HttpResponse,
+        policy: &CachePolicy,
+    ) -> Result<(cacache::WriteOpts, Option<Bytes>, u64)> {
+        let inline = response.body.is_empty()
+            || !self.npm_compat
+                && self.inline_threshold.map_or(false, |threshold| {
+                    response.body.len() <= threshold
+                });
+        let policy = CompactPolicy::new(policy)?;
+        let opts = cacache::WriteOpts::new();
+        if inline {
+            let record = Record::InlineCompact(CompactStore {
+                response: response.clone(),
+                policy,
+            });
+            let raw_metadata = format::encode(&record)?;
+            let bytes = raw_metadata.len() as u64;
+            let opts = opts
+                .integrity(cacache::Integrity::from(&response.body))
+                .size(0)
+                .raw_metadata(raw_metadata);
+            return Ok((opts, None, bytes));
+        }
+        let opts = if self.npm_compat {
+            opts.metadata(serde_json::to_value(NpmMetadata::new(response))?)
+        } else {
+            opts
+        };
+        let mut head = response.clone();
+        let body = std::mem::take(&mut head.body);
+        let record =
+            Record::ExternalCompact(CompactStore { response: head, policy });
+        let raw_metadata = format::encode(&record)?;
+        let bytes = (raw_metadata.len() + body.len()) as u64;
+        let opts = opts.size(body.len()).raw_metadata(raw_metadata);
+        Ok((opts, Some(body), bytes))
+    }
+
+    // make-fetch-happen hashes its content with SHA-512
+    fn algorithm(&self) -> cacache::Algorithm {
+        match self.algorithm {
+            Some(algorithm) => algorithm,
+            None if self.npm_compat => cacache::Algorithm::Sha512,
+            None => cacache::Algorithm::Sha256,
+        }
+    }
+
+    // Flushes the index entry of `cache_key`, and the content it points to, to disk with
+    // the directories listing them. cacache keeps the index of a key in
+    // `index-v5/<sha1 of the key>` and content in `content-v2/<algorithm>/<hex>`, both
+    // split into directories by the first two pairs of hex digits.
+    fn flush(
+        &self,
+        cache_key: &str,
+        content: Option<&cacache::Integrity>,
+    ) -> Result<()> {
+        let split = |root: PathBuf, hex: &str| {
+            root.join(&hex[0..2]).join(&hex[2..4]).join(&hex[4..])
+        };
+        let hashed = format!("{:x}", Sha1::digest(cache_key));
+        let mut files = vec![split(self.path.join("index-v5"), &hashed)];
+        if let Some(integrity) = content {
+            let (algorithm, hex) = integrity.to_hex();
+            let root = self.path.join("content-v2").join(algorithm.to_string());
+            files.push(split(root, &hex));
+        }
+        for file in files {
+            fs::File::open(&file)?.sync_all()?;
+            // Directories can't be opened to be flushed on Windows
+            #[cfg(unix)]
+            if let Some(dir) = file.parent() {
+                fs::File::open(dir)?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+
+    // `read_content` for `BlockingCacheManager`
+    fn read_content_blocking(
+        &self,
+        cache_key: &str,
+        integrity: &cacache::Integrity,
+    ) -> Result<Option<Vec<u8>>> {
+        match cacache::read_hash_sync(&self.path, integrity) {
+            Ok(d) => Ok(Some(d)),
+            Err(
+                e @ (cacache::Error::IntegrityError(_)
+                | cacache::Error::SizeMismatch(..)),
+            ) => {
+                self.remove_corrupted_blocking(cache_key)?;
+                self.report(cache_key, e.into());
+                Ok(None)
+            }
+            Err(_e) => Ok(None),
+        }
+    }
+
+    fn remove_corrupted_blocking(&self, cache_key: &str) -> Result<()> {
+        if let Some(metadata) = cacache::metadata_sync(&self.path, cache_key)? {
+            cacache::remove_hash_sync(&self.path, &metadata.integrity)?;
+        }
+        cacache::remove_sync(&self.path, cache_key)?;
+        Ok(())
+    }
+}
+
+// The size of the metadata cacache stores for an entry
+// Runs blocking work, like listing the index, on the blocking threads of the runtime
+// cacache runs on
+#[cfg(feature = "cacache-tokio")]
+async fn unblock<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[cfg(not(feature = "cacache-tokio"))]
+async fn unblock<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> T {
+    async_std::task::spawn_blocking(f).await
+}
+
+fn raw_len(metadata: &cacache::Metadata) -> u64 {
+    metadata.raw_metadata.as_ref().map_or(0, |raw| raw.len() as u64)
+}
+
+// Whether the entry was already stale at `cutoff`. The policy can only tell from the time
+// the response was received on, entries received after are not.
+fn stale_at(policy: &CachePolicy, cutoff: SystemTime) -> bool {
+    let received = match CompactPolicy::new(policy) {
+        Ok(compact) => compact.response_time(),
+        Err(_) => return false,
+    };
+    received <= cutoff && policy.time_to_live(cutoff).is_zero()
+}
+
+// Turns a content file path back into `<algorithm>-<hex>`
+fn content_id(root: &Path, path: &Path) -> Option<String> {
+    let parts = path
+        .strip_prefix(root)
+        .ok()?
+        .iter()
+        .map(|part| part.to_str())
+        .collect::<Option<Vec<_>>>()?;
+    match parts.as_slice() {
+        [algorithm, a, b, rest] => {
+            Some(format!("{}-{}{}{}", algorithm, a, b, rest))
+        }
+        _ => None,
+    }
 }
 
 #[async_trait::async_trait]
 impl CacheManager for CACacheManager {
+    type Error = BoxError;
+
     async fn get(
         &self,
         cache_key: &str,
     ) -> Result<Option<(HttpResponse, CachePolicy)>> {
-        let store: Store = match cacache::read(&self.path, cache_key).await {
-            Ok(d) => bincode::deserialize(&d)?,
-            Err(_e) => {
+        let metadata = match cacache::metadata(&self.path, cache_key).await {
+            Ok(Some(metadata)) => metadata,
+            _ => return Ok(None),
+        };
+        // An entry that can't be decoded is removed, its content may be shared
+        let entry = match Entry::parse(&metadata) {
+            Ok(entry) => entry,
+            Err(e) => {
+                cacache::remove(&self.path, cache_key).await?;
+                self.report(cache_key, e);
                 return Ok(None);
             }
         };
-        Ok(Some((store.response, store.policy)))
+        match entry {
+            Entry::Inline(store) => {
+                return Ok(Some((store.response, store.policy)))
+            }
+            // Replaced when the response is stored again
+            Entry::OtherVersion => return Ok(None),
+            _ => {}
+        }
+        let content =
+            match self.read_content(cache_key, &metadata.integrity).await? {
+                Some(content) => content,
+                None => return Ok(None),
+            };
+        match entry.into_store(content) {
+            Ok(store) => Ok(Some((store.response, store.policy))),
+            Err(e) => {
+                cacache::remove(&self.path, cache_key).await?;
+                self.report(cache_key, e);
+                Ok(None)
+            }
+        }
     }
 
     async fn put(
@@ -55,13 +1078,157 @@ impl CacheManager for CACacheManager {
         response: HttpResponse,
         policy: CachePolicy,
     ) -> Result<HttpResponse> {
-        let data = Store { response: response.clone(), policy };
-        let bytes = bincode::serialize(&data)?;
-        cacache::write(&self.path, cache_key, bytes).await?;
+        let (mut opts, body, bytes) = self.prepare(&response, &policy)?;
+        let mut content = None;
+        if let Some(body) = body {
+            let integrity = cacache::write_hash_with_algo(
+                self.algorithm(),
+                &self.path,
+                body,
+            )
+            .await?;
+            opts = opts.integrity(integrity.clone());
+            content = Some(integrity);
+        }
+        cacache::index::insert_async(&self.path, &cache_key, opts).await?;
+        if self.fsync {
+            self.flush(&cache_key, content.as_ref())?;
+        }
+        if self.track(bytes) {
+            let manager = self.clone();
+            unblock(move || manager.evict_tracked()).await?;
+        }
         Ok(response)
     }
 
     async fn delete(&self, cache_key: &str) -> Result<()> {
         Ok(cacache::remove(&self.path, cache_key).await?)
     }
+
+    async fn get_many(
+        &self,
+        cache_keys: &[&str],
+    ) -> Result<Vec<Option<(HttpResponse, CachePolicy)>>> {
+        // Each entry is in its own files, so they're read concurrently
+        join_all(cache_keys.iter().map(|cache_key| self.get(cache_key)))
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    async fn delete_many(&self, cache_keys: &[&str]) -> Result<()> {
+        // Every key is attempted, the first failure is returned
+        let results =
+            join_all(cache_keys.iter().map(|cache_key| self.delete(cache_key)))
+                .await;
+        results.into_iter().collect()
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        // Listing the entries is blocking, as in `maintenance`
+        let entries = cacache::list_sync(&self.path)
+            .collect::<cacache::Result<Vec<_>>>()?;
+        let mut listed = Vec::with_capacity(entries.len());
+        for metadata in entries {
+            // Entries that can't be decoded are left out, a `get` removes them
+            if let Ok(entry) = self.describe(&metadata).await {
+                listed.push(entry);
+            }
+        }
+        Ok(listed)
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        // Listing the entries is blocking, as in `maintenance`
+        let mut stats = CacheStats::default();
+        let mut counted = HashSet::new();
+        for metadata in cacache::list_sync(&self.path) {
+            let metadata = metadata?;
+            stats.entries += 1;
+            stats.bytes += raw_len(&metadata);
+            // Content shared by several entries is only stored once
+            if counted.insert(metadata.integrity.to_string()) {
+                stats.bytes += metadata.size as u64;
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method
+        Self::clear(self).await
+    }
+}
+
+impl BlockingCacheManager for CACacheManager {
+    fn get_blocking(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let metadata = match cacache::metadata_sync(&self.path, cache_key) {
+            Ok(Some(metadata)) => metadata,
+            _ => return Ok(None),
+        };
+        // An entry that can't be decoded is removed, its content may be shared
+        let entry = match Entry::parse(&metadata) {
+            Ok(entry) => entry,
+            Err(e) => {
+                cacache::remove_sync(&self.path, cache_key)?;
+                self.report(cache_key, e);
+                return Ok(None);
+            }
+        };
+        match entry {
+            Entry::Inline(store) => {
+                return Ok(Some((store.response, store.policy)))
+            }
+            // Replaced when the response is stored again
+            Entry::OtherVersion => return Ok(None),
+            _ => {}
+        }
+        let content =
+            match self.read_content_blocking(cache_key, &metadata.integrity)? {
+                Some(content) => content,
+                None => return Ok(None),
+            };
+        match entry.into_store(content) {
+            Ok(store) => Ok(Some((store.response, store.policy))),
+            Err(e) => {
+                cacache::remove_sync(&self.path, cache_key)?;
+                self.report(cache_key, e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn put_blocking(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let (mut opts, body, bytes) = self.prepare(&response, &policy)?;
+        let mut content = None;
+        if let Some(body) = body {
+            let integrity = cacache::write_hash_sync_with_algo(
+                self.algorithm(),
+                &self.path,
+                body,
+            )?;
+            opts = opts.integrity(integrity.clone());
+            content = Some(integrity);
+        }
+        cacache::index::insert(&self.path, &cache_key, opts)?;
+        if self.fsync {
+            self.flush(&cache_key, content.as_ref())?;
+        }
+        if self.track(bytes) {
+            self.evict_tracked()?;
+        }
+        Ok(response)
+    }
+
+    fn delete_blocking(&self, cache_key: &str) -> Result<()> {
+        Ok(cacache::remove_sync(&self.path, cache_key)?)
+    }
 }
@@ -0,0 +1,214 @@
+use crate::{
+    BoxError, CacheManager, CorruptEntry, HttpResponse, HttpVersion, Result,
+};
+
+use std::fmt;
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+use http_cache_semantics::CachePolicy;
+use js_sys::{Array, Reflect, Uint8Array};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Cache, CacheStorage, Headers, Response, ResponseInit};
+
+// Holds the `Entry` of a stored response as JSON
+const ENTRY_HEADER: &str = "x-http-cache-entry";
+
+// The Cache API only stores requests for http(s) URLs, so the cache key is put in one
+const KEY_URL: &str = "https://http-cache.invalid/";
+
+/// Implements [`CacheManager`] with the browser's
+/// [Cache API](https://developer.mozilla.org/en-US/docs/Web/API/Cache) as the backend,
+/// for `wasm32` builds running in a window or a worker.
+///
+/// Entries count against the storage quota of the origin and are evicted by the browser
+/// with it. They are stored as regular responses in the named cache, under the URL
+/// `https://http-cache.invalid/?key=<cache key>`, so a service worker opening the same
+/// cache can read them. The cache policy is stored in the `x-http-cache-entry` header.
+/// Headers the browser doesn't allow in a constructed response, such as `Set-Cookie`,
+/// are not stored.
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "manager-cache-api", target_arch = "wasm32")))
+)]
+#[derive(Debug, Clone)]
+pub struct CacheApiManager {
+    name: String,
+}
+
+/// Error type for failures reported by the Cache API
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "manager-cache-api", target_arch = "wasm32")))
+)]
+#[derive(Debug, Clone)]
+pub struct CacheApiError(String);
+
+impl fmt::Display for CacheApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cache API error: {}", self.0)
+    }
+}
+
+impl std::error::Error for CacheApiError {}
+
+fn js_error(value: JsValue) -> BoxError {
+    let message = match value.dyn_ref::<js_sys::Error>() {
+        Some(error) => String::from(error.message()),
+        None => value.as_string().unwrap_or_else(|| format!("{value:?}")),
+    };
+    Box::new(CacheApiError(message))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    url: Url,
+    version: HttpVersion,
+    policy: CachePolicy,
+}
+
+fn key_url(cache_key: &str) -> String {
+    let mut url = Url::parse(KEY_URL).unwrap();
+    url.query_pairs_mut().append_pair("key", cache_key);
+    url.into()
+}
+
+impl CacheApiManager {
+    /// Creates a new manager storing entries in the cache called `name`, which is
+    /// created when it is first written to.
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string() }
+    }
+
+    /// Returns the name of the cache
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Clears out the entire cache by deleting it.
+    pub async fn clear(&self) -> Result<()> {
+        JsFuture::from(Self::storage()?.delete(&self.name))
+            .await
+            .map_err(js_error)?;
+        Ok(())
+    }
+
+    // `caches` is a property of both `Window` and `WorkerGlobalScope`
+    fn storage() -> Result<CacheStorage> {
+        let caches =
+            Reflect::get(&js_sys::global(), &JsValue::from_str("caches"))
+                .map_err(js_error)?;
+        match caches.dyn_into() {
+            Ok(storage) => Ok(storage),
+            Err(_) => Err(Box::new(CacheApiError(
+                "the Cache API is not available".into(),
+            ))),
+        }
+    }
+
+    async fn open(&self) -> Result<Cache> {
+        let cache = JsFuture::from(Self::storage()?.open(&self.name))
+            .await
+            .map_err(js_error)?;
+        Ok(cache.unchecked_into())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl CacheManager for CacheApiManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let cache = self.open().await?;
+        let found = JsFuture::from(cache.match_with_str(&key_url(cache_key)))
+            .await
+            .map_err(js_error)?;
+        if found.is_undefined() {
+            return Ok(None);
+        }
+        let response: Response = found.dyn_into().map_err(js_error)?;
+        let stored = response.headers();
+        // Responses stored by anything else are left alone
+        let Some(entry) = stored.get(ENTRY_HEADER).map_err(js_error)? else {
+            return Ok(None);
+        };
+        let entry: Entry =
+            serde_json::from_str(&entry).map_err(CorruptEntry::new)?;
+        let mut headers = HeaderMap::new();
+        for pair in stored.entries() {
+            let pair: Array = pair.map_err(js_error)?.unchecked_into();
+            let (Some(name), Some(value)) =
+                (pair.get(0).as_string(), pair.get(1).as_string())
+            else {
+                continue;
+            };
+            if name != ENTRY_HEADER {
+                headers.append(
+                    HeaderName::from_bytes(name.as_bytes())?,
+                    HeaderValue::from_str(&value)?,
+                );
+            }
+        }
+        let body = JsFuture::from(response.array_buffer().map_err(js_error)?)
+            .await
+            .map_err(js_error)?;
+        Ok(Some((
+            HttpResponse {
+                body: Uint8Array::new(&body).to_vec().into(),
+                headers,
+                status: response.status(),
+                url: entry.url,
+                version: entry.version,
+            },
+            entry.policy,
+        )))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let entry = Entry {
+            url: response.url.clone(),
+            version: response.version,
+            policy,
+        };
+        let headers = Headers::new().map_err(js_error)?;
+        for (name, value) in &response.headers {
+            if let Ok(value) = value.to_str() {
+                headers.append(name.as_str(), value).map_err(js_error)?;
+            }
+        }
+        headers
+            .set(ENTRY_HEADER, &serde_json::to_string(&entry)?)
+            .map_err(js_error)?;
+        let init = ResponseInit::new();
+        init.set_status(response.status);
+        init.set_headers(&headers);
+        // Responses like `204 No Content` can't be constructed with a body at all
+        let mut body = response.body.to_vec();
+        let body = (!body.is_empty()).then_some(body.as_mut_slice());
+        let stored = Response::new_with_opt_u8_array_and_init(body, &init)
+            .map_err(js_error)?;
+        let cache = self.open().await?;
+        JsFuture::from(cache.put_with_str(&key_url(&cache_key), &stored))
+            .await
+            .map_err(js_error)?;
+        Ok(response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        let cache = self.open().await?;
+        JsFuture::from(cache.delete_with_str(&key_url(cache_key)))
+            .await
+            .map_err(js_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{CachedEntry, HttpResponse, Result};
+
+use std::{collections::HashMap, time::SystemTime};
+
+use http::{
+    header::{AUTHORIZATION, CACHE_CONTROL, HOST, VARY},
+    HeaderMap, HeaderValue, Method, StatusCode, Uri,
+};
+use http_cache_semantics::{CacheOptions, CachePolicy};
+use serde::{Deserialize, Serialize};
+
+type CacheControl = HashMap<Box<str>, Option<Box<str>>>;
+
+// Mirrors how `CachePolicy` serializes its private fields, to read them back
+#[derive(Deserialize)]
+struct PolicyFields {
+    #[serde(with = "http_serde::header_map")]
+    req: HeaderMap,
+    #[serde(with = "http_serde::header_map")]
+    res: HeaderMap,
+    #[serde(with = "http_serde::uri")]
+    uri: Uri,
+    #[serde(with = "http_serde::status_code")]
+    status: StatusCode,
+    #[serde(with = "http_serde::method")]
+    method: Method,
+    opts: CacheOptions,
+    #[serde(rename = "res_cc")]
+    _res_cc: CacheControl,
+    #[serde(rename = "req_cc")]
+    _req_cc: CacheControl,
+    response_time: SystemTime,
+}
+
+// What a `CachePolicy` is rebuilt from: the parsed `Cache-Control` directives are left out,
+// as are the request headers the policy never consults. Of the `Authorization` header only
+// its presence matters, so its value isn't kept.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct CompactPolicy {
+    #[serde(with = "http_serde::header_map")]
+    req: HeaderMap,
+    #[serde(with = "http_serde::header_map")]
+    res: HeaderMap,
+    #[serde(with = "http_serde::uri")]
+    uri: Uri,
+    #[serde(with = "http_serde::status_code")]
+    status: StatusCode,
+    #[serde(with = "http_serde::method")]
+    method: Method,
+    opts: CacheOptions,
+    response_time: SystemTime,
+}
+
+impl CompactPolicy {
+    pub(crate) fn new(policy: &CachePolicy) -> Result<Self> {
+        let fields: PolicyFields =
+            bincode::deserialize(&bincode::serialize(policy)?)?;
+        let mut req = HeaderMap::new();
+        for name in [HOST, CACHE_CONTROL] {
+            for value in fields.req.get_all(&name) {
+                req.append(name.clone(), value.clone());
+            }
+        }
+        if fields.req.contains_key(AUTHORIZATION) {
+            req.insert(AUTHORIZATION, HeaderValue::from_static(""));
+        }
+        // Stored responses are only served to requests matching these
+        for value in fields.res.get_all(VARY) {
+            for name in value.to_str().unwrap_or_default().split(',') {
+                let name = name.trim().to_ascii_lowercase();
+                if req.contains_key(&name) {
+                    continue;
+                }
+                if let Some(value) = fields.req.get(&name) {
+                    req.insert(
+                        http::HeaderName::try_from(name)?,
+                        value.clone(),
+                    );
+                }
+            }
+        }
+        Ok(Self {
+            req,
+            res: fields.res,
+            uri: fields.uri,
+            status: fields.status,
+            method: fields.method,
+            opts: fields.opts,
+            response_time: fields.response_time,
+        })
+    }
+
+    // Describes the record stored under `cache_key` for `CacheManager::list`
+    pub(crate) fn entry(
+        &self,
+        cache_key: &str,
+        response: &HttpResponse,
+    ) -> CachedEntry {
+        CachedEntry {
+            cache_key: cache_key.to_string(),
+            url: response.url.clone(),
+            method: self.method.clone(),
+            stored_at: self.response_time,
+        }
+    }
+
+    // When the response was received, which the policy doesn't expose
+    #[cfg(feature = "manager-cacache")]
+    pub(crate) fn response_time(&self) -> SystemTime {
+        self.response_time
+    }
+
+    pub(crate) fn into_policy(self) -> Result<CachePolicy> {
+        let mut req = http::Request::builder()
+            .method(self.method)
+            .uri(self.uri)
+            .body(())?;
+        *req.headers_mut() = self.req;
+        let mut res = http::Response::builder().status(self.status).body(())?;
+        *res.headers_mut() = self.res;
+        Ok(CachePolicy::new_options(
+            &req.into_parts().0,
+            &res.into_parts().0,
+            self.response_time,
+            self.opts,
+        ))
+    }
+}
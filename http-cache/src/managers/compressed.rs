@@ -0,0 +1,203 @@
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, CorruptEntry,
+    HttpResponse, Result,
+};
+
+use std::io::Read;
+
+use bytes::Bytes;
+use http_cache_semantics::CachePolicy;
+
+// The algorithm a stored body was compressed with. It's removed before the response is
+// returned, and a body without it is stored as it is.
+const COMPRESSION_HEADER: &str = "x-http-cache-compression";
+
+// Bodies smaller than this are stored as they are by default
+const DEFAULT_THRESHOLD: usize = 1024;
+
+/// The algorithm a [`CompressedManager`] compresses bodies with.
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(
+        feature = "compression-zstd",
+        feature = "compression-brotli"
+    )))
+)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// [Zstandard](https://facebook.github.io/zstd/) at the given level, from 1 to 22, or 0
+    /// for the default level of 3.
+    #[cfg(feature = "compression-zstd")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "compression-zstd")))]
+    Zstd(i32),
+    /// [Brotli](https://github.com/google/brotli) at the given quality, from 0 to 11.
+    #[cfg(feature = "compression-brotli")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "compression-brotli")))]
+    Brotli(u32),
+}
+
+impl Compression {
+    // The value of the header marking a body compressed with the algorithm
+    fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "compression-zstd")]
+            Self::Zstd(_) => "zstd",
+            #[cfg(feature = "compression-brotli")]
+            Self::Brotli(_) => "br",
+        }
+    }
+
+    fn compress(&self, body: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            #[cfg(feature = "compression-zstd")]
+            Self::Zstd(level) => Ok(zstd::bulk::compress(body, level)?),
+            #[cfg(feature = "compression-brotli")]
+            Self::Brotli(quality) => {
+                let mut compressed = Vec::new();
+                let mut encoder =
+                    brotli::CompressorReader::new(body, 4096, quality, 22);
+                encoder.read_to_end(&mut compressed)?;
+                Ok(compressed)
+            }
+        }
+    }
+}
+
+// Decompresses a body marked with `name`, which may have been written with an algorithm
+// other than the one the manager is configured with
+fn decompress(name: &str, body: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    match name {
+        #[cfg(feature = "compression-zstd")]
+        "zstd" => {
+            zstd::stream::read::Decoder::new(body)?
+                .read_to_end(&mut decompressed)?;
+        }
+        #[cfg(feature = "compression-brotli")]
+        "br" => {
+            brotli::Decompressor::new(body, 4096)
+                .read_to_end(&mut decompressed)?;
+        }
+        name => {
+            return Err(BoxError::from(format!(
+                "the body is compressed with {name}, which is not enabled"
+            )))
+        }
+    }
+    Ok(decompressed)
+}
+
+/// Wraps a [`CacheManager`] to compress the bodies of the responses it stores, cutting the
+/// space taken by large text responses such as JSON or HTML.
+///
+/// Bodies at least as large as the threshold, 1 KiB by default, are compressed on `put` and
+/// decompressed on `get`. A body that doesn't get smaller is stored as it is. Compressed
+/// bodies are marked with a header, so entries stored without compression, or with another
+/// algorithm that is enabled, can still be read. A body that can't be decompressed is
+/// reported as a [`CorruptEntry`].
+#[cfg_attr(
+    docsrs,
+    doc(cfg(any(
+        feature = "compression-zstd",
+        feature = "compression-brotli"
+    )))
+)]
+#[derive(Debug, Clone)]
+pub struct CompressedManager<T: CacheManager> {
+    inner: T,
+    compression: Compression,
+    threshold: usize,
+}
+
+impl<T: CacheManager> CompressedManager<T> {
+    /// Creates a new manager compressing the bodies stored in `inner` with `compression`.
+    pub fn new(inner: T, compression: Compression) -> Self {
+        Self { inner, compression, threshold: DEFAULT_THRESHOLD }
+    }
+
+    /// Sets the size in bytes from which bodies are compressed.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Returns the inner manager.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<T: CacheManager> CacheManager for CompressedManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let (mut response, policy) =
+            match self.inner.get(cache_key).await.map_err(Into::into)? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+        if let Some(name) = response.headers.remove(COMPRESSION_HEADER) {
+            let name = name.to_str().map_err(CorruptEntry::new)?;
+            let body =
+                decompress(name, &response.body).map_err(CorruptEntry::new)?;
+            response.body = Bytes::from(body);
+        }
+        Ok(Some((response, policy)))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let compressed = if response.body.len() < self.threshold {
+            None
+        } else {
+            Some(self.compression.compress(&response.body)?)
+                .filter(|compressed| compressed.len() < response.body.len())
+        };
+        let Some(compressed) = compressed else {
+            return self
+                .inner
+                .put(cache_key, response, policy)
+                .await
+                .map_err(Into::into);
+        };
+        let mut stored = HttpResponse {
+            body: Bytes::from(compressed),
+            headers: response.headers.clone(),
+            status: response.status,
+            url: response.url.clone(),
+            version: response.version,
+        };
+        stored.set_header(COMPRESSION_HEADER, self.compression.name());
+        self.inner.put(cache_key, stored, policy).await.map_err(Into::into)?;
+        Ok(response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.inner.delete(cache_key).await.map_err(Into::into)
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        self.inner.list().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        self.inner.stats().await
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{
+    stable_hash, BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse,
+    Result,
+};
+
+use std::{collections::HashSet, fmt, sync::Arc};
+
+use http_cache_semantics::CachePolicy;
+
+// Points each node gets on the ring, more spread the keys more evenly.
+const POINTS_PER_NODE: usize = 100;
+
+/// Shards entries across a set of named [`CacheManager`]s, usually remote ones, with
+/// consistent hashing, so a fleet of instances can pool the capacity of the backends
+/// instead of each keeping its own copy.
+///
+/// Every entry is written to `replication` distinct nodes. Reads try them in turn and
+/// return the first entry found, skipping nodes that fail, so an entry stays available
+/// while one of its nodes is down. Nodes are placed on the ring by name, so instances
+/// given the same names agree on where each key lives regardless of the order, and
+/// adding or removing a node only moves the keys next to it.
+pub struct DistributedManager<T: CacheManager> {
+    nodes: Arc<Vec<(String, T)>>,
+    ring: Arc<Vec<(u64, usize)>>,
+    replication: usize,
+}
+
+impl<T: CacheManager> Clone for DistributedManager<T> {
+    fn clone(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+            ring: self.ring.clone(),
+            replication: self.replication,
+        }
+    }
+}
+
+impl<T: CacheManager> fmt::Debug for DistributedManager<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DistributedManager")
+            .field(
+                "nodes",
+                &self.nodes.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .field("replication", &self.replication)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: CacheManager> DistributedManager<T> {
+    /// Creates a new manager over the named `nodes`, writing every entry to `replication`
+    /// of them. `replication` is clamped between one and the number of nodes.
+    pub fn new(
+        nodes: impl IntoIterator<Item = (String, T)>,
+        replication: usize,
+    ) -> Self {
+        let nodes: Vec<(String, T)> = nodes.into_iter().collect();
+        let mut ring: Vec<(u64, usize)> = nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, (name, _))| {
+                (0..POINTS_PER_NODE).map(move |point| {
+                    (stable_hash(format!("{name}#{point}").as_bytes()), index)
+                })
+            })
+            .collect();
+        ring.sort_unstable();
+        Self {
+            replication: replication.clamp(1, nodes.len().max(1)),
+            nodes: Arc::new(nodes),
+            ring: Arc::new(ring),
+        }
+    }
+
+    /// Returns the names of the nodes that hold `cache_key`, in the order they are read.
+    pub fn nodes_for(&self, cache_key: &str) -> Vec<&str> {
+        self.replicas(cache_key)
+            .into_iter()
+            .map(|index| self.nodes[index].0.as_str())
+            .collect()
+    }
+
+    fn replicas(&self, cache_key: &str) -> Vec<usize> {
+        let hash = stable_hash(cache_key.as_bytes());
+        let start = self.ring.partition_point(|(point, _)| *point < hash);
+        let mut replicas = Vec::with_capacity(self.replication);
+        for (_, index) in
+            self.ring.iter().cycle().skip(start).take(self.ring.len())
+        {
+            if !replicas.contains(index) {
+                replicas.push(*index);
+                if replicas.len() == self.replication {
+                    break;
+                }
+            }
+        }
+        replicas
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<T: CacheManager> CacheManager for DistributedManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let mut error = None;
+        for index in self.replicas(cache_key) {
+            match self.nodes[index].1.get(cache_key).await {
+                Ok(Some(entry)) => return Ok(Some(entry)),
+                Ok(None) => {}
+                Err(e) => error = Some(e.into()),
+            }
+        }
+        // A miss is only certain if every replica answered
+        match error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let mut stored = false;
+        let mut error = None;
+        for index in self.replicas(&cache_key) {
+            match self.nodes[index]
+                .1
+                .put(cache_key.clone(), response.clone(), policy.clone())
+                .await
+            {
+                Ok(_) => stored = true,
+                Err(e) => error = Some(e.into()),
+            }
+        }
+        match error {
+            Some(e) if !stored => Err(e),
+            _ => Ok(response),
+        }
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        // Every replica is attempted, so a failing node doesn't keep the others stale
+        let mut error = None;
+        for index in self.replicas(cache_key) {
+            if let Err(e) = self.nodes[index].1.delete(cache_key).await {
+                error = Some(e.into());
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        let mut error = None;
+        for (_, node) in self.nodes.iter() {
+            if let Err(e) = node.shutdown().await {
+                error = Some(e.into());
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // Every node is attempted, like for a delete
+        let mut error = None;
+        for (_, node) in self.nodes.iter() {
+            if let Err(e) = node.clear().await {
+                error = Some(e);
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        // Each entry is listed once, from the first node holding it
+        let mut listed = HashSet::new();
+        let mut entries = Vec::new();
+        for (_, node) in self.nodes.iter() {
+            for entry in node.list().await? {
+                if listed.insert(entry.cache_key.clone()) {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        // Replicas are counted on every node holding them, as they take space on each
+        let mut stats = CacheStats::default();
+        for (_, node) in self.nodes.iter() {
+            let node = node.stats().await?;
+            stats.entries += node.entries;
+            stats.bytes += node.bytes;
+        }
+        Ok(stats)
+    }
+}
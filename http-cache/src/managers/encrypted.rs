@@ -0,0 +1,155 @@
+use crate::{BoxError, CacheManager, CorruptEntry, HttpResponse, Result};
+
+use std::{fmt, time::SystemTime};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use bytes::Bytes;
+use http::{header::CACHE_CONTROL, HeaderMap};
+use http_cache_semantics::CachePolicy;
+use serde::{Deserialize, Serialize};
+
+// The length of the nonce stored in front of each ciphertext
+const NONCE_LEN: usize = 12;
+
+/// Wraps a [`CacheManager`] to encrypt entries with AES-256-GCM before they reach it, for
+/// caching responses containing personal data on disks or in stores shared with others.
+///
+/// The headers and body of the response are encrypted together with the cache policy,
+/// which holds the headers of the request and response too, and the cache key is
+/// authenticated with them, so an entry can't be read back under another key. The inner
+/// manager stores the ciphertext as the body of a response without headers, next to a
+/// policy that only keeps the time the response goes stale. The url, status and version of
+/// the response are left readable, as the cache key already contains the url.
+///
+/// An entry that can't be decrypted, because it was tampered with or encrypted with another
+/// key, is reported as a [`CorruptEntry`].
+#[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+#[derive(Clone)]
+pub struct EncryptedManager<T: CacheManager> {
+    inner: T,
+    cipher: Aes256Gcm,
+}
+
+impl<T: CacheManager + fmt::Debug> fmt::Debug for EncryptedManager<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedManager")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Store {
+    response: HttpResponse,
+    policy: CachePolicy,
+}
+
+// A policy for `response` that goes stale when `policy` does and holds nothing else
+fn stub_policy(
+    response: &HttpResponse,
+    policy: &CachePolicy,
+) -> Result<CachePolicy> {
+    let ttl = policy.time_to_live(SystemTime::now()).as_secs();
+    let req = http::Request::get(response.url.as_str()).body(())?;
+    let res = http::Response::builder()
+        .header(CACHE_CONTROL, format!("max-age={ttl}"))
+        .body(())?;
+    Ok(CachePolicy::new(&req, &res))
+}
+
+impl<T: CacheManager> EncryptedManager<T> {
+    /// Creates a new manager encrypting the entries stored in `inner` with `key`. Entries
+    /// stored with one key can't be read with another, so keep the key for as long as the
+    /// entries are worth keeping.
+    pub fn new(inner: T, key: &[u8; 32]) -> Self {
+        Self {
+            inner,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    /// Returns the inner manager.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn encrypt(&self, cache_key: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: plaintext, aad: cache_key.as_bytes() };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| BoxError::from("the entry could not be encrypted"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn decrypt(&self, cache_key: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(Box::new(CorruptEntry::new("the entry is truncated")));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload { msg: ciphertext, aad: cache_key.as_bytes() };
+        self.cipher.decrypt(Nonce::from_slice(nonce), payload).map_err(|_| {
+            CorruptEntry::new("the entry could not be decrypted").into()
+        })
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<T: CacheManager> CacheManager for EncryptedManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let sealed =
+            match self.inner.get(cache_key).await.map_err(Into::into)? {
+                Some((sealed, _)) => sealed,
+                None => return Ok(None),
+            };
+        let plaintext = self.decrypt(cache_key, &sealed.body)?;
+        let store: Store =
+            bincode::deserialize(&plaintext).map_err(CorruptEntry::new)?;
+        Ok(Some((store.response, store.policy)))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let stub = stub_policy(&response, &policy)?;
+        let store = Store { response, policy };
+        let sealed = HttpResponse {
+            body: Bytes::from(
+                self.encrypt(&cache_key, &bincode::serialize(&store)?)?,
+            ),
+            headers: HeaderMap::new(),
+            status: store.response.status,
+            url: store.response.url.clone(),
+            version: store.response.version,
+        };
+        self.inner.put(cache_key, sealed, stub).await.map_err(Into::into)?;
+        Ok(store.response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.inner.delete(cache_key).await.map_err(Into::into)
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+}
@@ -0,0 +1,60 @@
+use crate::Result;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+// The version of the format entries are persisted in. Bump it when the encoding of an entry
+// changes in a way the previous version can't read, including when an upgrade of
+// http-cache-semantics changes how `CachePolicy` is serialized. Entries written in another
+// version are read as misses and replaced when the response is stored again.
+pub(crate) const FORMAT_VERSION: u32 = 1;
+
+// Why an entry written in another version of the format can't be read
+#[cfg(feature = "manager-cacache")]
+pub(crate) const OTHER_VERSION: &str =
+    "the entry was written in another version of the format";
+
+// Starts every versioned entry, followed by the version. Entries written before the format
+// was versioned start with the length of a response body or a small enum tag, which are
+// never this large.
+const VERSIONED: [u8; 8] = (u64::MAX - 1).to_le_bytes();
+
+// Encodes an entry in the current version of the format
+pub(crate) fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = VERSIONED.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, value)?;
+    Ok(bytes)
+}
+
+// Decodes an entry written by `encode`, or by `unversioned` when it was written before the
+// format was versioned. `None` if the entry was written in another version.
+pub(crate) fn decode<T: DeserializeOwned>(
+    bytes: &[u8],
+    unversioned: impl FnOnce(&[u8]) -> Result<T>,
+) -> Result<Option<T>> {
+    let Some(rest) = bytes.strip_prefix(&VERSIONED[..]) else {
+        return unversioned(bytes).map(Some);
+    };
+    let (version, value) = match rest {
+        [a, b, c, d, value @ ..] => {
+            (u32::from_le_bytes([*a, *b, *c, *d]), value)
+        }
+        _ => return Err("the entry is truncated".into()),
+    };
+    if version != FORMAT_VERSION {
+        return Ok(None);
+    }
+    Ok(Some(bincode::deserialize(value)?))
+}
+
+// Decodes an entry that was written as plain bincode before the format was versioned
+#[cfg(any(
+    feature = "manager-cacache",
+    feature = "manager-foyer",
+    feature = "manager-object-store",
+    feature = "manager-postgres",
+    feature = "manager-sled"
+))]
+pub(crate) fn unversioned<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(bincode::deserialize(bytes)?)
+}
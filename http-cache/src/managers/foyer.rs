@@ -0,0 +1,148 @@
+use super::format;
+use crate::{BoxError, CacheManager, CorruptEntry, HttpResponse, Result};
+
+use std::{
+    collections::hash_map::DefaultHasher, fmt, fs, hash::BuildHasherDefault,
+    path::Path,
+};
+
+use foyer::{
+    DirectFsDeviceOptions, Engine, HybridCache, HybridCacheBuilder,
+    LargeEngineOptions, TombstoneLogConfigBuilder,
+};
+use http_cache_semantics::CachePolicy;
+use serde::{Deserialize, Serialize};
+
+/// Implements [`CacheManager`] with a [`foyer`](https://github.com/foyer-rs/foyer) hybrid
+/// cache as the backend, which keeps hot entries in memory and moves the ones it evicts
+/// from memory to disk, so the cache can grow past the memory it is given.
+///
+/// Each entry is stored under its cache key as the versioned bincode encoding of the
+/// response and its policy. foyer runs on tokio, and writes reach the disk in the
+/// background, so call [`HttpCache::shutdown`](crate::HttpCache::shutdown) before the
+/// process exits to keep the entries that are still being written.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-foyer")))]
+#[derive(Clone)]
+pub struct FoyerManager {
+    /// The instance of `foyer::HybridCache`
+    pub cache: FoyerCache,
+}
+
+/// Hashes the keys of a [`FoyerCache`]. Entries on disk are found by the hash of their key,
+/// so unlike foyer's default, which is seeded randomly, it hashes a key the same way in
+/// every process.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-foyer")))]
+pub type FoyerHasher = BuildHasherDefault<DefaultHasher>;
+
+/// The hybrid cache used by a [`FoyerManager`], built with
+/// `with_hash_builder(FoyerHasher::default())`.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-foyer")))]
+pub type FoyerCache = HybridCache<String, Vec<u8>, FoyerHasher>;
+
+impl fmt::Debug for FoyerManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FoyerManager").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Store {
+    response: HttpResponse,
+    policy: CachePolicy,
+}
+
+impl FoyerManager {
+    /// Create a new manager from a pre-configured HybridCache
+    pub fn new(cache: FoyerCache) -> Self {
+        Self { cache }
+    }
+
+    /// Creates a new manager keeping up to `memory_bytes` of entries in memory and up to
+    /// `disk_bytes` in files in `dir`, recovering the entries already stored there.
+    /// Entries are weighed by the size of their key and encoded value. Deletes are
+    /// recorded in a tombstone log in `dir`, so deleted entries stay deleted on reopening.
+    pub async fn open(
+        dir: impl AsRef<Path>,
+        memory_bytes: usize,
+        disk_bytes: usize,
+    ) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let tombstones =
+            TombstoneLogConfigBuilder::new(dir.join("tombstone-log")).build();
+        let cache = HybridCacheBuilder::new()
+            .with_name("http-cache")
+            .memory(memory_bytes)
+            .with_hash_builder(FoyerHasher::default())
+            .with_weighter(|key: &String, value: &Vec<u8>| {
+                key.len() + value.len()
+            })
+            .storage(Engine::Large)
+            .with_device_options(
+                DirectFsDeviceOptions::new(dir).with_capacity(disk_bytes),
+            )
+            .with_large_object_disk_cache_options(
+                LargeEngineOptions::new().with_tombstone_log_config(tombstones),
+            )
+            .build()
+            .await?;
+        Ok(Self::new(cache))
+    }
+
+    /// Clears out the entire cache, in memory and on disk.
+    pub async fn clear(&self) -> Result<()> {
+        self.cache.clear().await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheManager for FoyerManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        // Lookups that reach the disk need an owned key
+        let entry = match self.cache.get(&cache_key.to_string()).await? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let store: Store =
+            match format::decode(entry.value(), format::unversioned)
+                .map_err(CorruptEntry::new)?
+            {
+                Some(store) => store,
+                None => return Ok(None),
+            };
+        Ok(Some((store.response, store.policy)))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let store = Store { response, policy };
+        self.cache.insert(cache_key, format::encode(&store)?);
+        Ok(store.response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.cache.remove(cache_key);
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        // Waits for the entries still being written to disk
+        self.cache.close().await?;
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method
+        Self::clear(self).await
+    }
+}
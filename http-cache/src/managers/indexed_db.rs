@@ -0,0 +1,214 @@
+use crate::{BoxError, CacheManager, CorruptEntry, HttpResponse, Result};
+
+use std::fmt;
+
+use http_cache_semantics::CachePolicy;
+use js_sys::{Promise, Reflect, Uint8Array};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    Event, IdbDatabase, IdbFactory, IdbObjectStore, IdbOpenDbRequest,
+    IdbRequest, IdbTransactionMode,
+};
+
+// The object store holding the entries, created with the database
+const STORE: &str = "entries";
+
+/// Implements [`CacheManager`] with the browser's
+/// [IndexedDB](https://developer.mozilla.org/en-US/docs/Web/API/IndexedDB_API) as the
+/// backend, for `wasm32` builds running in a window or a worker.
+///
+/// Entries persist across page reloads and count against the storage quota of the
+/// origin. They are stored in the `entries` object store of the named database, keyed by
+/// the cache key, each as the bincode encoding of the response and its policy.
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "manager-indexed-db", target_arch = "wasm32")))
+)]
+#[derive(Debug, Clone)]
+pub struct IndexedDbManager {
+    name: String,
+}
+
+/// Error type for failures reported by IndexedDB
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "manager-indexed-db", target_arch = "wasm32")))
+)]
+#[derive(Debug, Clone)]
+pub struct IndexedDbError(String);
+
+impl fmt::Display for IndexedDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IndexedDB error: {}", self.0)
+    }
+}
+
+impl std::error::Error for IndexedDbError {}
+
+fn js_error(value: JsValue) -> BoxError {
+    let message = match value.dyn_ref::<js_sys::Error>() {
+        Some(error) => String::from(error.message()),
+        None => value.as_string().unwrap_or_else(|| format!("{value:?}")),
+    };
+    Box::new(IndexedDbError(message))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Store {
+    response: HttpResponse,
+    policy: CachePolicy,
+}
+
+// Waits for `request` to finish, returning its result
+async fn settle(request: &IdbRequest) -> Result<JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+    let settled = JsFuture::from(promise).await;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+    if let Err(e) = settled {
+        // The event rejecting the promise doesn't say what went wrong, the request does
+        return Err(match request.error() {
+            Ok(Some(error)) => Box::new(IndexedDbError(error.message())),
+            _ => js_error(e),
+        });
+    }
+    request.result().map_err(js_error)
+}
+
+impl IndexedDbManager {
+    /// Creates a new manager storing entries in the database called `name`, which is
+    /// created when it is first opened.
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string() }
+    }
+
+    /// Returns the name of the database
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Clears out the entire cache.
+    pub async fn clear(&self) -> Result<()> {
+        let (db, store) = self.store(IdbTransactionMode::Readwrite).await?;
+        let cleared = settle(&store.clear().map_err(js_error)?).await;
+        db.close();
+        cleared?;
+        Ok(())
+    }
+
+    // `indexedDB` is a property of both `Window` and `WorkerGlobalScope`
+    fn factory() -> Result<IdbFactory> {
+        let factory =
+            Reflect::get(&js_sys::global(), &JsValue::from_str("indexedDB"))
+                .map_err(js_error)?;
+        match factory.dyn_into() {
+            Ok(factory) => Ok(factory),
+            Err(_) => Err(Box::new(IndexedDbError(
+                "IndexedDB is not available".into(),
+            ))),
+        }
+    }
+
+    async fn open(&self) -> Result<IdbDatabase> {
+        let request =
+            Self::factory()?.open_with_u32(&self.name, 1).map_err(js_error)?;
+        // Creates the object store when the database is new
+        let upgrade = Closure::<dyn FnMut(Event)>::new(|event: Event| {
+            let db = event
+                .target()
+                .and_then(|target| target.dyn_into::<IdbOpenDbRequest>().ok())
+                .and_then(|request| request.result().ok())
+                .and_then(|db| db.dyn_into::<IdbDatabase>().ok());
+            if let Some(db) = db {
+                // A failure aborts the upgrade, which fails the open request
+                let _ = db.create_object_store(STORE);
+            }
+        });
+        request.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+        let opened = settle(&request).await;
+        request.set_onupgradeneeded(None);
+        Ok(opened?.unchecked_into())
+    }
+
+    // The database is returned too, to be closed once the request on the store settles
+    async fn store(
+        &self,
+        mode: IdbTransactionMode,
+    ) -> Result<(IdbDatabase, IdbObjectStore)> {
+        let db = self.open().await?;
+        let store = db
+            .transaction_with_str_and_mode(STORE, mode)
+            .and_then(|transaction| transaction.object_store(STORE));
+        match store {
+            Ok(store) => Ok((db, store)),
+            Err(e) => {
+                db.close();
+                Err(js_error(e))
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl CacheManager for IndexedDbManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let (db, store) = self.store(IdbTransactionMode::Readonly).await?;
+        let found = match store.get(&JsValue::from_str(cache_key)) {
+            Ok(request) => settle(&request).await,
+            Err(e) => Err(js_error(e)),
+        };
+        db.close();
+        let found = found?;
+        if found.is_undefined() {
+            return Ok(None);
+        }
+        let bytes = found
+            .dyn_into::<Uint8Array>()
+            .map_err(|_| CorruptEntry::new("the entry is not a byte array"))?
+            .to_vec();
+        let store: Store =
+            bincode::deserialize(&bytes).map_err(CorruptEntry::new)?;
+        Ok(Some((store.response, store.policy)))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let bytes =
+            bincode::serialize(&Store { response: response.clone(), policy })?;
+        let value = Uint8Array::from(bytes.as_slice());
+        let (db, store) = self.store(IdbTransactionMode::Readwrite).await?;
+        let stored =
+            match store.put_with_key(&value, &JsValue::from_str(&cache_key)) {
+                Ok(request) => settle(&request).await,
+                Err(e) => Err(js_error(e)),
+            };
+        db.close();
+        stored?;
+        Ok(response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        let (db, store) = self.store(IdbTransactionMode::Readwrite).await?;
+        let deleted = match store.delete(&JsValue::from_str(cache_key)) {
+            Ok(request) => settle(&request).await,
+            Err(e) => Err(js_error(e)),
+        };
+        db.close();
+        deleted?;
+        Ok(())
+    }
+}
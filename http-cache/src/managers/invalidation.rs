@@ -0,0 +1,123 @@
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse, MaybeSend,
+    MaybeSync, Result,
+};
+
+use std::{fmt, sync::Arc};
+
+use http_cache_semantics::CachePolicy;
+
+/// Carries invalidated cache keys between the instances sharing a cache, so each of them
+/// can evict the keys from its own local tier. See [`InvalidatingManager`].
+///
+/// Delivery is best effort: a key published while an instance isn't subscribed, for
+/// example while it reconnects, is not seen by that instance.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+pub trait InvalidationBus: MaybeSend + MaybeSync + 'static {
+    /// Announces to every subscriber that `cache_key` was invalidated.
+    async fn publish(&self, cache_key: &str) -> Result<()>;
+    /// Waits for the next invalidated key, returns `None` once the bus is closed.
+    /// Keys published by this instance may be received as well.
+    async fn receive(&self) -> Result<Option<String>>;
+}
+
+/// Wraps the local tier of a cache, such as a [`MokaManager`](crate::MokaManager) in front
+/// of a shared backend, so a purge on one instance evicts the key on every instance.
+///
+/// Deleting a key deletes it from the wrapped manager and publishes it on the bus.
+/// [`listen`](Self::listen) deletes the keys published by other instances, it must be
+/// spawned on the runtime in use for them to be evicted.
+pub struct InvalidatingManager<T: CacheManager, B: InvalidationBus> {
+    inner: Arc<T>,
+    bus: Arc<B>,
+}
+
+impl<T: CacheManager, B: InvalidationBus> Clone for InvalidatingManager<T, B> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), bus: self.bus.clone() }
+    }
+}
+
+impl<T: CacheManager, B: InvalidationBus> fmt::Debug
+    for InvalidatingManager<T, B>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InvalidatingManager").finish_non_exhaustive()
+    }
+}
+
+impl<T: CacheManager, B: InvalidationBus> InvalidatingManager<T, B> {
+    /// Creates a new manager evicting keys from `inner` when they are published on `bus`.
+    pub fn new(inner: T, bus: B) -> Self {
+        Self { inner: Arc::new(inner), bus: Arc::new(bus) }
+    }
+
+    /// Returns the wrapped manager
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the bus
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    /// Deletes every key received from the bus from the wrapped manager until the bus is
+    /// closed or fails, e.g. `tokio::spawn(manager.clone().listen())`. Failing to delete a
+    /// key doesn't stop listening.
+    pub async fn listen(self) -> Result<()> {
+        while let Some(cache_key) = self.bus.receive().await? {
+            self.inner.delete(&cache_key).await.ok();
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<T: CacheManager, B: InvalidationBus> CacheManager
+    for InvalidatingManager<T, B>
+{
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        self.inner.get(cache_key).await.map_err(Into::into)
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        self.inner.put(cache_key, response, policy).await.map_err(Into::into)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        // The other instances are told even if the key wasn't cached here
+        let deleted = self.inner.delete(cache_key).await.map_err(Into::into);
+        self.bus.publish(cache_key).await?;
+        deleted
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // Only the keys of deletes are published, the other instances keep their entries
+        self.inner.clear().await
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        self.inner.list().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        self.inner.stats().await
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse, Result,
+};
+
+use std::time::Instant;
+
+use http_cache_semantics::CachePolicy;
+use metrics::{counter, histogram, SharedString};
+
+/// The counter of the operations on a [`MeteredManager`].
+pub const OPERATIONS_METRIC: &str = "http_cache_manager_operations_total";
+
+/// The histogram of how long the operations on a [`MeteredManager`] took, in seconds.
+pub const DURATION_METRIC: &str = "http_cache_manager_duration_seconds";
+
+/// Wraps a [`CacheManager`] to record its operations with the
+/// [`metrics`](https://docs.rs/metrics) facade, so any backend reports to the recorder the
+/// application installed.
+///
+/// Each `get`, `put` and `delete` increments the [`OPERATIONS_METRIC`] counter and records
+/// its duration in the [`DURATION_METRIC`] histogram. Both are labeled with the `manager`
+/// name given to [`new`](Self::new) and the `operation`, and the counter also with the
+/// `outcome`: `hit` or `miss` for a `get` that succeeded, `ok` for a `put` or `delete` that
+/// did, and `error` for any failure. The hit ratio of a manager is the rate of its `hit`
+/// outcomes over the rate of its `hit` and `miss` outcomes.
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+#[derive(Debug, Clone)]
+pub struct MeteredManager<T: CacheManager> {
+    inner: T,
+    name: SharedString,
+}
+
+impl<T: CacheManager> MeteredManager<T> {
+    /// Creates a new manager recording the operations on `inner` under the `manager` label
+    /// `name`, telling it apart from other managers of the application.
+    pub fn new(inner: T, name: impl Into<SharedString>) -> Self {
+        Self { inner, name: name.into() }
+    }
+
+    /// Returns the inner manager.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn record(
+        &self,
+        operation: &'static str,
+        outcome: &'static str,
+        start: Instant,
+    ) {
+        let manager = self.name.clone();
+        counter!(
+            OPERATIONS_METRIC,
+            "manager" => manager.clone(),
+            "operation" => operation,
+            "outcome" => outcome,
+        )
+        .increment(1);
+        histogram!(DURATION_METRIC, "manager" => manager, "operation" => operation)
+            .record(start.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<T: CacheManager> CacheManager for MeteredManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let start = Instant::now();
+        let result = self.inner.get(cache_key).await.map_err(Into::into);
+        let outcome = match &result {
+            Ok(Some(_)) => "hit",
+            Ok(None) => "miss",
+            Err(_) => "error",
+        };
+        self.record("get", outcome, start);
+        result
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .put(cache_key, response, policy)
+            .await
+            .map_err(Into::into);
+        self.record("put", if result.is_ok() { "ok" } else { "error" }, start);
+        result
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.delete(cache_key).await.map_err(Into::into);
+        self.record(
+            "delete",
+            if result.is_ok() { "ok" } else { "error" },
+            start,
+        );
+        result
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        self.inner.list().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        self.inner.stats().await
+    }
+}
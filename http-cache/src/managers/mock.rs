@@ -0,0 +1,186 @@
+use crate::{
+    BlockingCacheManager, BoxError, CacheManager, HttpResponse, Result,
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use http_cache_semantics::CachePolicy;
+
+/// A call made to a [`MockManager`].
+// Calls are only kept for the test to read, boxing the response would only get in the way
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum MockCall {
+    /// A `get` of the key
+    Get(String),
+    /// A `put` of the response under the key
+    Put(String, HttpResponse),
+    /// A `delete` of the key
+    Delete(String),
+}
+
+impl MockCall {
+    /// Returns the cache key the call was made with.
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Get(key) | Self::Put(key, _) | Self::Delete(key) => key,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, (HttpResponse, CachePolicy)>,
+    calls: Vec<MockCall>,
+}
+
+/// Implements [`CacheManager`] in memory and records every call made to it, for testing
+/// code built on the cache.
+///
+/// Entries can be stored up front with [`with_entry`](Self::with_entry) or
+/// [`insert`](Self::insert), and the calls made since can be checked with
+/// [`calls`](Self::calls). Clones share their entries and calls, so a clone kept by the
+/// test sees what the middleware did with the other.
+#[derive(Clone, Default)]
+pub struct MockManager {
+    state: Arc<Mutex<State>>,
+}
+
+impl fmt::Debug for MockManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MockManager")
+            .field("entries", &state.entries.len())
+            .field("calls", &state.calls.len())
+            .finish()
+    }
+}
+
+impl MockManager {
+    /// Creates a new manager without entries.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores an entry under `cache_key`, without recording a call.
+    pub fn with_entry(
+        self,
+        cache_key: impl Into<String>,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Self {
+        self.insert(cache_key, response, policy);
+        self
+    }
+
+    /// Stores an entry under `cache_key`, replacing the one already there, without
+    /// recording a call.
+    pub fn insert(
+        &self,
+        cache_key: impl Into<String>,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.insert(cache_key.into(), (response, policy));
+    }
+
+    /// Returns the entry stored under `cache_key`, without recording a call.
+    pub fn entry(
+        &self,
+        cache_key: &str,
+    ) -> Option<(HttpResponse, CachePolicy)> {
+        self.state.lock().unwrap().entries.get(cache_key).cloned()
+    }
+
+    /// Returns the keys of the stored entries, in no particular order.
+    pub fn keys(&self) -> Vec<String> {
+        self.state.lock().unwrap().entries.keys().cloned().collect()
+    }
+
+    /// Returns the calls made so far, oldest first.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Returns the calls made so far, oldest first, and forgets them.
+    pub fn take_calls(&self) -> Vec<MockCall> {
+        std::mem::take(&mut self.state.lock().unwrap().calls)
+    }
+
+    /// Returns the responses stored with `put` so far with their keys, oldest first.
+    pub fn puts(&self) -> Vec<(String, HttpResponse)> {
+        let state = self.state.lock().unwrap();
+        state
+            .calls
+            .iter()
+            .filter_map(|call| match call {
+                MockCall::Put(key, response) => {
+                    Some((key.clone(), response.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl CacheManager for MockManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        self.get_blocking(cache_key)
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        self.put_blocking(cache_key, response, policy)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.delete_blocking(cache_key)
+    }
+}
+
+// The calls are recorded the same way whether they're made blocking or not
+impl BlockingCacheManager for MockManager {
+    fn get_blocking(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(MockCall::Get(cache_key.to_string()));
+        Ok(state.entries.get(cache_key).cloned())
+    }
+
+    fn put_blocking(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(MockCall::Put(cache_key.clone(), response.clone()));
+        state.entries.insert(cache_key, (response.clone(), policy));
+        Ok(response)
+    }
+
+    fn delete_blocking(&self, cache_key: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(MockCall::Delete(cache_key.to_string()));
+        state.entries.remove(cache_key);
+        Ok(())
+    }
+}
@@ -1,5 +1,70 @@
 #[cfg(feature = "manager-cacache")]
 pub mod cacache;
 
+#[cfg(all(feature = "manager-cache-api", target_arch = "wasm32"))]
+pub mod cache_api;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+pub(crate) mod compact;
+
+#[cfg(any(feature = "compression-zstd", feature = "compression-brotli"))]
+pub mod compressed;
+
+pub mod distributed;
+
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+
+#[cfg(feature = "manager-foyer")]
+pub mod foyer;
+
+#[cfg(any(
+    feature = "manager-cacache",
+    feature = "manager-foyer",
+    feature = "manager-moka",
+    feature = "manager-object-store",
+    feature = "manager-postgres",
+    feature = "manager-sled"
+))]
+pub(crate) mod format;
+
+#[cfg(all(feature = "manager-indexed-db", target_arch = "wasm32"))]
+pub mod indexed_db;
+
+pub mod invalidation;
+
+#[cfg(feature = "metrics")]
+pub mod metered;
+
+pub mod mock;
+
 #[cfg(feature = "manager-moka")]
 pub mod moka;
+
+#[cfg(feature = "manager-moka-arc")]
+pub mod moka_arc;
+
+pub mod namespaced;
+
+pub mod negative;
+
+pub mod null;
+
+#[cfg(feature = "manager-object-store")]
+pub mod object_store;
+
+#[cfg(feature = "manager-postgres")]
+pub mod postgres;
+
+pub mod replicated;
+
+#[cfg(feature = "manager-sled")]
+pub mod sled;
+
+pub mod tiered;
+
+#[cfg(feature = "write-behind")]
+pub mod write_behind;
+
+#[cfg(feature = "write-retry")]
+pub mod write_retry;
@@ -1,9 +1,19 @@
-use crate::{CacheManager, HttpResponse, Result};
+use super::{compact::CompactPolicy, format};
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, CorruptEntry, EntryCost,
+    EntryWeigher, HttpResponse, Result,
+};
 
-use std::{fmt, sync::Arc};
+use std::{
+    convert::TryFrom,
+    fmt, fs, io,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use http_cache_semantics::CachePolicy;
-use moka::future::Cache;
+use moka::{future::Cache, Expiry};
 use serde::{Deserialize, Serialize};
 
 /// Implements [`CacheManager`] with [`moka`](https://github.com/moka-rs/moka) as the backend.
@@ -27,36 +37,307 @@ impl Default for MokaManager {
     }
 }
 
+// Entries written before the compact format, which are still read
 #[derive(Debug, Deserialize, Serialize)]
 struct Store {
     response: HttpResponse,
     policy: CachePolicy,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+struct CompactStore {
+    response: HttpResponse,
+    policy: CompactPolicy,
+}
+
+// Started entries in the compact format before the format was versioned. An entry in the
+// full format starts with the length of the response body, which is never this large.
+const COMPACT: [u8; 8] = u64::MAX.to_le_bytes();
+
+fn encode(response: HttpResponse, policy: &CachePolicy) -> Result<Vec<u8>> {
+    let store = CompactStore { response, policy: CompactPolicy::new(policy)? };
+    format::encode(&store)
+}
+
+// Reads an entry in any format it has been written in, `None` if it was written in another
+// version of the format. Entries in the full format are read into the compact one.
+fn read(value: &[u8]) -> Result<Option<CompactStore>> {
+    format::decode(value, |value| {
+        Ok(match value.strip_prefix(&COMPACT[..]) {
+            Some(value) => bincode::deserialize(value)?,
+            None => {
+                let store: Store = bincode::deserialize(value)?;
+                CompactStore {
+                    response: store.response,
+                    policy: CompactPolicy::new(&store.policy)?,
+                }
+            }
+        })
+    })
+}
+
+fn decode(value: &[u8]) -> Result<Option<(HttpResponse, CachePolicy)>> {
+    match read(value)? {
+        Some(store) => Ok(Some((store.response, store.policy.into_policy()?))),
+        None => Ok(None),
+    }
+}
+
+// Describes the record stored under `cache_key`, without rebuilding its policy
+fn describe(cache_key: &str, value: &[u8]) -> Result<Option<CachedEntry>> {
+    Ok(read(value)?.map(|store| store.policy.entry(cache_key, &store.response)))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Snapshot {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
 impl MokaManager {
     /// Create a new manager from a pre-configured Cache
     pub fn new(cache: Cache<String, Arc<Vec<u8>>>) -> Self {
         Self { cache: Arc::new(cache) }
     }
+    /// Returns a [`MokaManagerBuilder`] to configure the underlying cache
+    pub fn builder() -> MokaManagerBuilder {
+        MokaManagerBuilder::default()
+    }
     /// Clears out the entire cache.
     pub async fn clear(&self) -> Result<()> {
         self.cache.invalidate_all();
         self.cache.run_pending_tasks().await;
         Ok(())
     }
+    /// Writes the current entries to a snapshot file at `path`, e.g. on shutdown.
+    /// The file is written next to `path` first and then moved into place, so an
+    /// interrupted save leaves any previous snapshot intact. File access is blocking.
+    pub async fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        self.cache.run_pending_tasks().await;
+        let snapshot = Snapshot {
+            entries: self
+                .cache
+                .iter()
+                .map(|(key, value)| (key.as_ref().clone(), value.to_vec()))
+                .collect(),
+        };
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, bincode::serialize(&snapshot)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+    /// Inserts the entries from a snapshot file written by [`save_to`](Self::save_to),
+    /// e.g. on startup, and returns how many were loaded. A missing file loads nothing.
+    /// Entries already in the cache with the same key are replaced. File access is blocking.
+    pub async fn load_from(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot: Snapshot = bincode::deserialize(&bytes)?;
+        let count = snapshot.entries.len();
+        for (key, value) in snapshot.entries {
+            self.cache.insert(key, Arc::new(value)).await;
+        }
+        self.cache.run_pending_tasks().await;
+        Ok(count)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Capacity {
+    Entries(u64),
+    Bytes(u64),
+}
+
+/// Builds a [`MokaManager`] without having to construct a [`MokaCache`](crate::MokaCache) directly.
+///
+/// Freshness is still decided by the stored cache policy, the time to live and time to idle
+/// settings only bound how long an entry may be kept. An entry evicted before it goes stale is
+/// simply fetched again, but an entry evicted while stale can no longer be revalidated, so keep
+/// these generous relative to the lifetimes of the responses being cached.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+#[derive(Default, Clone)]
+pub struct MokaManagerBuilder {
+    capacity: Option<Capacity>,
+    weigher: Option<EntryWeigher>,
+    time_to_live: Option<Duration>,
+    time_to_idle: Option<Duration>,
+    policy_grace: Option<Duration>,
+    name: Option<String>,
+}
+
+impl fmt::Debug for MokaManagerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MokaManagerBuilder")
+            .field("capacity", &self.capacity)
+            .field(
+                "weigher",
+                &self.weigher.as_ref().map(|_| "Fn(&EntryCost) -> u32"),
+            )
+            .field("time_to_live", &self.time_to_live)
+            .field("time_to_idle", &self.time_to_idle)
+            .field("policy_grace", &self.policy_grace)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+// Weighs an entry by the size of its key and value, or with the configured weigher.
+fn weigh(weigher: Option<&EntryWeigher>, key: &str, value: &[u8]) -> u32 {
+    let size = key.len() + value.len();
+    let Some(weigher) = weigher else {
+        return u32::try_from(size).unwrap_or(u32::MAX);
+    };
+    // Entries that can't be read are weighed without a latency, `get` reports the error.
+    let fetch_latency = read(value)
+        .ok()
+        .flatten()
+        .and_then(|store| store.response.fetch_latency());
+    weigher(&EntryCost { size, fetch_latency })
+}
+
+// Expires entries once they have been stale for longer than the grace period.
+#[derive(Debug, Clone, Copy)]
+struct PolicyExpiry {
+    grace: Duration,
+}
+
+impl PolicyExpiry {
+    fn expire_after(&self, value: &[u8]) -> Option<Duration> {
+        // Entries that can't be read are left to the other eviction settings,
+        // `get` reports the error.
+        let (_, policy) = decode(value).ok()??;
+        let ttl = policy.time_to_live(SystemTime::now());
+        Some(ttl.saturating_add(self.grace))
+    }
+}
+
+impl Expiry<String, Arc<Vec<u8>>> for PolicyExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &Arc<Vec<u8>>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        self.expire_after(value)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        value: &Arc<Vec<u8>>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.expire_after(value)
+    }
+}
+
+impl MokaManagerBuilder {
+    /// Sets the maximum number of entries the cache may hold.
+    /// Replaces any previously set [`max_bytes`](Self::max_bytes).
+    pub fn max_capacity(mut self, entries: u64) -> Self {
+        self.capacity = Some(Capacity::Entries(entries));
+        self
+    }
+    /// Sets the maximum total size in bytes of the serialized entries.
+    /// Replaces any previously set [`max_capacity`](Self::max_capacity).
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.capacity = Some(Capacity::Bytes(bytes));
+        self
+    }
+    /// Sets how entries are weighed against [`max_bytes`](Self::max_bytes) instead of by
+    /// their size, e.g. to evict entries that are cheap to fetch again first:
+    ///
+    /// ```
+    /// # use std::{sync::Arc, time::Duration};
+    /// # use http_cache::{EntryCost, MokaManager};
+    /// let manager = MokaManager::builder()
+    ///     .max_bytes(64 * 1024 * 1024)
+    ///     .weigher(Arc::new(|cost: &EntryCost| {
+    ///         cost.latency_discounted(Duration::from_millis(100))
+    ///     }))
+    ///     .build();
+    /// ```
+    ///
+    /// Has no effect with [`max_capacity`](Self::max_capacity).
+    pub fn weigher(mut self, weigher: EntryWeigher) -> Self {
+        self.weigher = Some(weigher);
+        self
+    }
+    /// Sets how long an entry may be kept after it was stored.
+    pub fn time_to_live(mut self, duration: Duration) -> Self {
+        self.time_to_live = Some(duration);
+        self
+    }
+    /// Sets how long an entry may be kept after it was last read or stored.
+    pub fn time_to_idle(mut self, duration: Duration) -> Self {
+        self.time_to_idle = Some(duration);
+        self
+    }
+    /// Expires each entry once it has been stale for longer than `grace`, based on the
+    /// freshness lifetime of its stored cache policy. Without this a stale entry is kept
+    /// until it is evicted by capacity or the time to live and time to idle settings.
+    ///
+    /// A stale entry can still be revalidated if the response has an `ETag` or
+    /// `Last-Modified` header, `grace` should cover how long that is worthwhile.
+    pub fn expire_by_policy(mut self, grace: Duration) -> Self {
+        self.policy_grace = Some(grace);
+        self
+    }
+    /// Sets the name of the underlying cache.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+    /// Builds the [`MokaManager`]. If no capacity was set the cache is unbounded.
+    pub fn build(self) -> MokaManager {
+        let mut builder = Cache::builder();
+        match self.capacity {
+            Some(Capacity::Entries(entries)) => {
+                builder = builder.max_capacity(entries);
+            }
+            Some(Capacity::Bytes(bytes)) => {
+                let weigher = self.weigher.clone();
+                builder = builder.max_capacity(bytes).weigher(
+                    move |key: &String, value: &Arc<Vec<u8>>| {
+                        weigh(weigher.as_ref(), key, value)
+                    },
+                );
+            }
+            None => {}
+        }
+        if let Some(duration) = self.time_to_live {
+            builder = builder.time_to_live(duration);
+        }
+        if let Some(duration) = self.time_to_idle {
+            builder = builder.time_to_idle(duration);
+        }
+        if let Some(grace) = self.policy_grace {
+            builder = builder.expire_after(PolicyExpiry { grace });
+        }
+        if let Some(name) = &self.name {
+            builder = builder.name(name);
+        }
+        MokaManager::new(builder.build())
+    }
 }
 
 #[async_trait::async_trait]
 impl CacheManager for MokaManager {
+    type Error = BoxError;
+
     async fn get(
         &self,
         cache_key: &str,
     ) -> Result<Option<(HttpResponse, CachePolicy)>> {
-        let store: Store = match self.cache.get(cache_key).await {
-            Some(d) => bincode::deserialize(&d)?,
-            None => return Ok(None),
-        };
-        Ok(Some((store.response, store.policy)))
+        match self.cache.get(cache_key).await {
+            Some(d) => Ok(decode(&d).map_err(CorruptEntry::new)?),
+            None => Ok(None),
+        }
     }
 
     async fn put(
@@ -65,8 +346,7 @@ impl CacheManager for MokaManager {
         response: HttpResponse,
         policy: CachePolicy,
     ) -> Result<HttpResponse> {
-        let data = Store { response: response.clone(), policy };
-        let bytes = bincode::serialize(&data)?;
+        let bytes = encode(response.clone(), &policy)?;
         self.cache.insert(cache_key, Arc::new(bytes)).await;
         self.cache.run_pending_tasks().await;
         Ok(response)
@@ -77,4 +357,33 @@ impl CacheManager for MokaManager {
         self.cache.run_pending_tasks().await;
         Ok(())
     }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.cache.run_pending_tasks().await;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        // Entries that can't be decoded are left out, a `get` removes them
+        Ok(self
+            .cache
+            .iter()
+            .filter_map(|(key, value)| describe(&key, &value).ok().flatten())
+            .collect())
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        // The weighted size is only in bytes without a custom weigher, so it's summed here
+        let mut stats = CacheStats::default();
+        for (key, value) in self.cache.iter() {
+            stats.entries += 1;
+            stats.bytes += (key.len() + value.len()) as u64;
+        }
+        Ok(stats)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method
+        Self::clear(self).await
+    }
 }
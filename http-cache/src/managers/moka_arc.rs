@@ -0,0 +1,120 @@
+use crate::{BoxError, CacheManager, CacheStats, HttpResponse, Result};
+
+use std::{fmt, sync::Arc};
+
+use http_cache_semantics::CachePolicy;
+use moka::future::Cache;
+
+/// Implements [`CacheManager`] with [`moka`](https://github.com/moka-rs/moka) as the backend,
+/// keeping entries as they are instead of serializing them.
+///
+/// Entries are stored behind an [`Arc`], so moka hands them out without copying and a hit
+/// only clones the response and policy, which is much faster than the round-trip through
+/// bincode [`MokaManager`](crate::MokaManager) makes. The entries can't be saved to disk
+/// or weighed by their encoded size.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka-arc")))]
+#[derive(Clone)]
+pub struct MokaArcManager {
+    /// The instance of `moka::future::Cache`
+    pub cache: Arc<Cache<String, MokaArcStore>>,
+}
+
+impl fmt::Debug for MokaArcManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MokaArcManager").finish_non_exhaustive()
+    }
+}
+
+impl Default for MokaArcManager {
+    fn default() -> Self {
+        Self::new(Cache::new(42))
+    }
+}
+
+/// An entry stored by a [`MokaArcManager`].
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka-arc")))]
+#[derive(Clone, Debug)]
+pub struct MokaArcStore {
+    response: Arc<HttpResponse>,
+    policy: Arc<CachePolicy>,
+}
+
+impl MokaArcStore {
+    /// Returns the stored response.
+    pub fn response(&self) -> &HttpResponse {
+        &self.response
+    }
+
+    /// Returns the policy of the stored response.
+    pub fn policy(&self) -> &CachePolicy {
+        &self.policy
+    }
+}
+
+impl MokaArcManager {
+    /// Create a new manager from a pre-configured Cache
+    pub fn new(cache: Cache<String, MokaArcStore>) -> Self {
+        Self { cache: Arc::new(cache) }
+    }
+    /// Clears out the entire cache.
+    pub async fn clear(&self) -> Result<()> {
+        self.cache.invalidate_all();
+        self.cache.run_pending_tasks().await;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheManager for MokaArcManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        Ok(self.cache.get(cache_key).await.map(|store| {
+            (store.response.as_ref().clone(), store.policy.as_ref().clone())
+        }))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let store = MokaArcStore {
+            response: Arc::new(response.clone()),
+            policy: Arc::new(policy),
+        };
+        self.cache.insert(cache_key, store).await;
+        self.cache.run_pending_tasks().await;
+        Ok(response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.cache.invalidate(cache_key).await;
+        self.cache.run_pending_tasks().await;
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.cache.run_pending_tasks().await;
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        // Only the keys and bodies are counted, the rest isn't stored as bytes
+        let mut stats = CacheStats::default();
+        for (key, store) in self.cache.iter() {
+            stats.entries += 1;
+            stats.bytes += (key.len() + store.response.body.len()) as u64;
+        }
+        Ok(stats)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method
+        Self::clear(self).await
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{BoxError, CacheManager, CachedEntry, HttpResponse, Result};
+
+use http_cache_semantics::CachePolicy;
+
+/// Wraps a [`CacheManager`] to prefix every key with a namespace, so several logical caches,
+/// e.g. one per environment or per API client, can share one backend without their entries
+/// colliding.
+///
+/// A key is stored as the namespace, a `:` and the key itself. Namespaces that don't contain
+/// a `:` can't collide with each other; the entries of every namespace still share the
+/// capacity of the backend.
+#[derive(Debug, Clone)]
+pub struct NamespacedManager<T: CacheManager> {
+    inner: T,
+    namespace: String,
+}
+
+impl<T: CacheManager> NamespacedManager<T> {
+    /// Creates a new manager storing its entries in `inner` under `namespace`.
+    pub fn new(inner: T, namespace: impl Into<String>) -> Self {
+        Self { inner, namespace: namespace.into() }
+    }
+
+    /// Returns the namespace of the entries.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns the inner manager.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn key(&self, cache_key: &str) -> String {
+        format!("{}:{}", self.namespace, cache_key)
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<T: CacheManager> CacheManager for NamespacedManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        self.inner.get(&self.key(cache_key)).await.map_err(Into::into)
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        self.inner
+            .put(self.key(&cache_key), response, policy)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.inner.delete(&self.key(cache_key)).await.map_err(Into::into)
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        // Only the entries of the namespace, under the keys they were stored with
+        let prefix = self.key("");
+        let mut entries = self.inner.list().await?;
+        entries.retain_mut(|entry| {
+            match entry.cache_key.strip_prefix(&prefix) {
+                Some(key) => {
+                    entry.cache_key = key.to_string();
+                    true
+                }
+                None => false,
+            }
+        });
+        Ok(entries)
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse, Result,
+};
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use http_cache_semantics::CachePolicy;
+
+#[derive(Default)]
+struct Misses {
+    keys: HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+/// Wraps a [`CacheManager`] and remembers which keys were recently found to be absent,
+/// so looking them up again doesn't reach the inner manager.
+///
+/// This is useful when most requests are for URLs that have never been cached, such as
+/// during a crawl, and the inner manager is on disk or across the network. Up to
+/// `capacity` keys are remembered, the oldest are forgotten first. A `put` through this
+/// manager forgets the key again.
+///
+/// Keys are remembered by their hash, so on a collision a cached entry may be reported
+/// as absent and fetched again. Entries written to the inner manager by anything other
+/// than this manager are not seen until the key is forgotten.
+pub struct NegativeLookupManager<T: CacheManager> {
+    inner: Arc<T>,
+    capacity: usize,
+    misses: Arc<Mutex<Misses>>,
+}
+
+impl<T: CacheManager> Clone for NegativeLookupManager<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            capacity: self.capacity,
+            misses: self.misses.clone(),
+        }
+    }
+}
+
+impl<T: CacheManager> fmt::Debug for NegativeLookupManager<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NegativeLookupManager")
+            .field("remembered", &self.misses.lock().unwrap().keys.len())
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: CacheManager> NegativeLookupManager<T> {
+    /// Creates a new manager remembering up to `capacity` absent keys of `inner`.
+    pub fn new(inner: T, capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            capacity,
+            misses: Arc::new(Mutex::new(Misses::default())),
+        }
+    }
+
+    /// Returns the wrapped manager
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Forgets every remembered absent key.
+    pub fn clear(&self) {
+        let mut misses = self.misses.lock().unwrap();
+        misses.keys.clear();
+        misses.order.clear();
+    }
+
+    fn hash(cache_key: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        cache_key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn is_known_miss(&self, cache_key: &str) -> bool {
+        self.misses.lock().unwrap().keys.contains(&Self::hash(cache_key))
+    }
+
+    fn remember(&self, cache_key: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let hash = Self::hash(cache_key);
+        let mut misses = self.misses.lock().unwrap();
+        if !misses.keys.insert(hash) {
+            return;
+        }
+        misses.order.push_back(hash);
+        while misses.order.len() > self.capacity {
+            if let Some(oldest) = misses.order.pop_front() {
+                misses.keys.remove(&oldest);
+            }
+        }
+    }
+
+    fn forget(&self, cache_key: &str) {
+        // The key stays in `order` and is skipped once it is evicted from there.
+        self.misses.lock().unwrap().keys.remove(&Self::hash(cache_key));
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<T: CacheManager> CacheManager for NegativeLookupManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        if self.is_known_miss(cache_key) {
+            return Ok(None);
+        }
+        let entry = self.inner.get(cache_key).await.map_err(Into::into)?;
+        if entry.is_none() {
+            self.remember(cache_key);
+        }
+        Ok(entry)
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        self.forget(&cache_key);
+        self.inner.put(cache_key, response, policy).await.map_err(Into::into)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.inner.delete(cache_key).await.map_err(Into::into)?;
+        self.remember(cache_key);
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await?;
+        // The inherent method, forgetting the absent keys
+        NegativeLookupManager::clear(self);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        self.inner.list().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        self.inner.stats().await
+    }
+}
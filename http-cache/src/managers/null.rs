@@ -0,0 +1,75 @@
+use crate::{
+    BlockingCacheManager, BoxError, CacheManager, CacheStats, CachedEntry,
+    HttpResponse, Result,
+};
+
+use http_cache_semantics::CachePolicy;
+
+/// Implements [`CacheManager`] without storing anything: every `get` misses, and `put` and
+/// `delete` succeed without doing anything.
+///
+/// It disables caching while keeping the type of the middleware, e.g. when the manager is
+/// chosen from the configuration, see [`ManagerConfig`](crate::ManagerConfig), or in
+/// tests. Responses still pass through the cache, so they get its headers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullManager;
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl CacheManager for NullManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        _cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        Ok(None)
+    }
+
+    async fn put(
+        &self,
+        _cache_key: String,
+        response: HttpResponse,
+        _policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        Ok(response)
+    }
+
+    async fn delete(&self, _cache_key: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        Ok(Vec::new())
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        Ok(CacheStats::default())
+    }
+}
+
+impl BlockingCacheManager for NullManager {
+    fn get_blocking(
+        &self,
+        _cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        Ok(None)
+    }
+
+    fn put_blocking(
+        &self,
+        _cache_key: String,
+        response: HttpResponse,
+        _policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        Ok(response)
+    }
+
+    fn delete_blocking(&self, _cache_key: &str) -> Result<()> {
+        Ok(())
+    }
+}
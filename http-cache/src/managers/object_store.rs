@@ -0,0 +1,118 @@
+use super::format;
+use crate::{BoxError, CacheManager, CorruptEntry, HttpResponse, Result};
+
+use std::{fmt, sync::Arc};
+
+use futures_util::{StreamExt, TryStreamExt};
+use http_cache_semantics::CachePolicy;
+use object_store::{path::Path, ObjectStore, PutPayload};
+use serde::{Deserialize, Serialize};
+
+/// Implements [`CacheManager`] with an [`ObjectStore`] as the backend, such as Amazon S3
+/// or an S3-compatible service, Google Cloud Storage or Azure Blob Storage, so that many
+/// stateless workers can share one cache.
+///
+/// Each entry is an object under the prefix, named after its cache key, holding the
+/// versioned bincode encoding of the response and its policy. The cache key is
+/// percent-encoded into a single path segment. The stores for the cloud providers are enabled with the features of the
+/// `object_store` crate, and they need a tokio runtime.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-object-store")))]
+#[derive(Clone)]
+pub struct ObjectStoreManager {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl fmt::Debug for ObjectStoreManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ObjectStoreManager")
+            .field("store", &self.store.to_string())
+            .field("prefix", &self.prefix.as_ref())
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Store {
+    response: HttpResponse,
+    policy: CachePolicy,
+}
+
+impl ObjectStoreManager {
+    /// Creates a new manager storing entries in `store` under `prefix`, which is split into
+    /// segments at each `/`. Other managers can share the store with a different prefix.
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str) -> Self {
+        Self { store, prefix: Path::from(prefix) }
+    }
+
+    /// Returns the prefix the entries are stored under
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    fn path(&self, cache_key: &str) -> Path {
+        self.prefix.child(cache_key)
+    }
+
+    /// Clears out the entire cache, deleting every object under the prefix.
+    pub async fn clear(&self) -> Result<()> {
+        let locations = self
+            .store
+            .list(Some(&self.prefix))
+            .map_ok(|meta| meta.location)
+            .boxed();
+        self.store
+            .delete_stream(locations)
+            .try_for_each(|_| async { Ok(()) })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheManager for ObjectStoreManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let bytes = match self.store.get(&self.path(cache_key)).await {
+            Ok(result) => result.bytes().await?,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(Box::new(e)),
+        };
+        let store: Store = match format::decode(&bytes, format::unversioned)
+            .map_err(CorruptEntry::new)?
+        {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+        Ok(Some((store.response, store.policy)))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let store = Store { response, policy };
+        let payload = PutPayload::from(format::encode(&store)?);
+        self.store.put(&self.path(&cache_key), payload).await?;
+        Ok(store.response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        // Some stores report a missing object, others don't
+        match self.store.delete(&self.path(cache_key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method
+        Self::clear(self).await
+    }
+}
@@ -0,0 +1,261 @@
+use super::format;
+use crate::{
+    header_map, BoxError, CacheManager, CorruptEntry, HttpResponse,
+    HttpVersion, Result,
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use http_cache_semantics::CachePolicy;
+use tokio_postgres::{Client, Row};
+use url::Url;
+
+/// Implements [`CacheManager`] with a [PostgreSQL](https://www.postgresql.org) table as the
+/// backend, using [`tokio-postgres`](https://github.com/sfackler/rust-postgres).
+///
+/// Each entry is a row holding the cache key, the response status, url, version, headers
+/// as JSON, the body as `bytea`, the versioned bincode encoding of the cache policy and the
+/// time the response goes stale. Rows whose policy was written in another version of the
+/// format are read as misses.
+/// [`create_table`](Self::create_table) creates the table. Stale rows are kept, as they can
+/// still be revalidated, until they are removed with [`delete_expired`](Self::delete_expired).
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-postgres")))]
+#[derive(Clone)]
+pub struct PostgresManager {
+    client: Arc<Client>,
+    table: String,
+}
+
+impl fmt::Debug for PostgresManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PostgresManager")
+            .field("table", &self.table)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PostgresManager {
+    /// Creates a new manager storing entries in `table`. The connection of `client` must be
+    /// driven separately, as described in the `tokio-postgres` documentation. The table
+    /// name is quoted, so it is used exactly as given.
+    pub fn new(client: Client, table: &str) -> Self {
+        Self { client: Arc::new(client), table: table.to_string() }
+    }
+
+    fn table(&self) -> String {
+        quote(&self.table)
+    }
+
+    /// Creates the table and an index on the expiry column if they don't exist yet.
+    pub async fn create_table(&self) -> Result<()> {
+        let table = self.table();
+        let index = quote(&format!("{}_expires_at", self.table));
+        self.client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    cache_key TEXT PRIMARY KEY,
+                    status INTEGER NOT NULL,
+                    url TEXT NOT NULL,
+                    version TEXT NOT NULL,
+                    headers TEXT NOT NULL,
+                    body BYTEA NOT NULL,
+                    policy BYTEA NOT NULL,
+                    expires_at TIMESTAMPTZ NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS {index} ON {table} (expires_at);"
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Removes the entries that have been stale for longer than `grace` and returns how
+    /// many were removed. A stale entry can still be revalidated if the response has an
+    /// `ETag` or `Last-Modified` header, `grace` should cover how long that is worthwhile.
+    pub async fn delete_expired(&self, grace: Duration) -> Result<u64> {
+        let cutoff = SystemTime::now() - grace;
+        let removed = self
+            .client
+            .execute(
+                &format!("DELETE FROM {} WHERE expires_at < $1", self.table()),
+                &[&cutoff],
+            )
+            .await?;
+        Ok(removed)
+    }
+
+    /// Removes every entry.
+    pub async fn clear(&self) -> Result<()> {
+        self.client
+            .batch_execute(&format!("DELETE FROM {}", self.table()))
+            .await?;
+        Ok(())
+    }
+}
+
+// Reads an entry selected with its status, url, version, headers, body and policy. `None` if
+// the policy was written in another version of the format.
+fn parse_row(row: &Row) -> Result<Option<(HttpResponse, CachePolicy)>> {
+    let status: i32 = row.try_get("status")?;
+    let url: &str = row.try_get("url")?;
+    let version: String = row.try_get("version")?;
+    let headers: &str = row.try_get("headers")?;
+    let policy: &[u8] = row.try_get("policy")?;
+    let body: Vec<u8> = row.try_get("body")?;
+    let parse = || -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let policy = match format::decode(policy, format::unversioned)? {
+            Some(policy) => policy,
+            None => return Ok(None),
+        };
+        let response = HttpResponse {
+            body: body.into(),
+            headers: header_map::deserialize(
+                &mut serde_json::Deserializer::from_str(headers),
+            )?,
+            status: u16::try_from(status)?,
+            url: Url::parse(url)?,
+            version: serde_json::from_value::<HttpVersion>(
+                serde_json::Value::String(version),
+            )?,
+        };
+        Ok(Some((response, policy)))
+    };
+    parse().map_err(|e| CorruptEntry::new(e).into())
+}
+
+// Quotes an identifier, so it can't be mistaken for SQL.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[async_trait::async_trait]
+impl CacheManager for PostgresManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let row = self
+            .client
+            .query_opt(
+                &format!(
+                    "SELECT status, url, version, headers, body, policy
+                    FROM {} WHERE cache_key = $1",
+                    self.table()
+                ),
+                &[&cache_key],
+            )
+            .await?;
+        match row {
+            Some(row) => parse_row(&row),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_many(
+        &self,
+        cache_keys: &[&str],
+    ) -> Result<Vec<Option<(HttpResponse, CachePolicy)>>> {
+        // One query for every key, the rows are matched back to the keys
+        let rows = self
+            .client
+            .query(
+                &format!(
+                    "SELECT cache_key, status, url, version, headers, body, policy
+                    FROM {} WHERE cache_key = ANY($1)",
+                    self.table()
+                ),
+                &[&cache_keys],
+            )
+            .await?;
+        let mut found = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let cache_key: String = row.try_get("cache_key")?;
+            found.insert(cache_key, row);
+        }
+        cache_keys
+            .iter()
+            .map(|cache_key| match found.get(*cache_key) {
+                Some(row) => parse_row(row),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let now = SystemTime::now();
+        let expires_at = now + policy.time_to_live(now);
+        let mut headers = Vec::new();
+        header_map::serialize(
+            &response.headers,
+            &mut serde_json::Serializer::new(&mut headers),
+        )?;
+        let headers = String::from_utf8(headers)?;
+        self.client
+            .execute(
+                &format!(
+                    "INSERT INTO {} (cache_key, status, url, version, headers, body, policy, expires_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                    ON CONFLICT (cache_key) DO UPDATE SET
+                        status = EXCLUDED.status,
+                        url = EXCLUDED.url,
+                        version = EXCLUDED.version,
+                        headers = EXCLUDED.headers,
+                        body = EXCLUDED.body,
+                        policy = EXCLUDED.policy,
+                        expires_at = EXCLUDED.expires_at",
+                    self.table()
+                ),
+                &[
+                    &cache_key,
+                    &i32::from(response.status),
+                    &response.url.as_str(),
+                    &response.version.to_string(),
+                    &headers,
+                    &response.body.as_ref(),
+                    &format::encode(&policy)?,
+                    &expires_at,
+                ],
+            )
+            .await?;
+        Ok(response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.client
+            .execute(
+                &format!("DELETE FROM {} WHERE cache_key = $1", self.table()),
+                &[&cache_key],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_many(&self, cache_keys: &[&str]) -> Result<()> {
+        self.client
+            .execute(
+                &format!(
+                    "DELETE FROM {} WHERE cache_key = ANY($1)",
+                    self.table()
+                ),
+                &[&cache_keys],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method
+        Self::clear(self).await
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse, Result,
+};
+
+use std::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::Poll,
+};
+
+use http_cache_semantics::CachePolicy;
+
+// Runs both futures concurrently until `done` accepts the output of one of them or both
+// finish. Returns the outputs that were available at that point.
+async fn race<F, G, T>(
+    mut first: Pin<Box<F>>,
+    mut second: Pin<Box<G>>,
+    done: impl Fn(&T) -> bool,
+) -> (Option<T>, Option<T>)
+where
+    F: Future<Output = T>,
+    G: Future<Output = T>,
+{
+    let mut outputs = (None, None);
+    poll_fn(|cx| {
+        if outputs.0.is_none() {
+            if let Poll::Ready(output) = first.as_mut().poll(cx) {
+                let finished = done(&output);
+                outputs.0 = Some(output);
+                if finished {
+                    return Poll::Ready(());
+                }
+            }
+        }
+        if outputs.1.is_none() {
+            if let Poll::Ready(output) = second.as_mut().poll(cx) {
+                let finished = done(&output);
+                outputs.1 = Some(output);
+                if finished {
+                    return Poll::Ready(());
+                }
+            }
+        }
+        if outputs.0.is_some() && outputs.1.is_some() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    outputs
+}
+
+/// Decides whether a write or delete that only some managers of a [`ReplicatedManager`]
+/// completed succeeds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplicationPolicy {
+    /// Succeeds if either manager does, so the cache keeps working while one is down.
+    #[default]
+    Either,
+    /// Succeeds only if both managers do, so a failure to replicate is reported.
+    Both,
+    /// Succeeds if the primary manager does, whatever happens to the secondary, e.g. for a
+    /// local cache that must work, replicated to a shared one on a best-effort basis.
+    Primary,
+}
+
+/// Keeps every entry in two [`CacheManager`]s, such as a local disk cache and a backend
+/// shared by a fleet, so entries reach every instance while the cache keeps working if
+/// either backend is down.
+///
+/// Writes and deletes go to both managers at the same time, and whether they succeed when
+/// only one manager does is decided by the [`ReplicationPolicy`], by default if either of
+/// them does. Reads ask both at the same time and return the first entry found, so a slow
+/// or unavailable manager doesn't hold up a read the other can answer. An error is only
+/// returned if both managers fail.
+///
+/// To replicate to more managers, nest them, e.g.
+/// `ReplicatedManager::new(local, ReplicatedManager::new(first, second))`.
+#[derive(Debug, Clone)]
+pub struct ReplicatedManager<A: CacheManager, B: CacheManager> {
+    primary: A,
+    secondary: B,
+    policy: ReplicationPolicy,
+}
+
+impl<A: CacheManager, B: CacheManager> ReplicatedManager<A, B> {
+    /// Creates a new manager keeping entries in both `primary` and `secondary`. When both
+    /// have an entry ready at once, the one from `primary` is used.
+    pub fn new(primary: A, secondary: B) -> Self {
+        Self { primary, secondary, policy: ReplicationPolicy::default() }
+    }
+
+    /// Sets how writes and deletes that fail on one of the managers are reported.
+    pub fn with_policy(mut self, policy: ReplicationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the policy for writes and deletes that fail on one of the managers.
+    pub fn policy(&self) -> ReplicationPolicy {
+        self.policy
+    }
+
+    /// Returns the primary manager.
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// Returns the secondary manager.
+    pub fn secondary(&self) -> &B {
+        &self.secondary
+    }
+}
+
+// Keeps the first success, otherwise the first error.
+fn either<T>(outputs: (Option<Result<T>>, Option<Result<T>>)) -> Result<T> {
+    match outputs {
+        (Some(Ok(value)), _) | (_, Some(Ok(value))) => Ok(value),
+        (Some(Err(e)), _) | (_, Some(Err(e))) => Err(e),
+        (None, None) => unreachable!("race returns an output"),
+    }
+}
+
+// Combines the outputs of a write or delete, which both managers have finished.
+fn settle<T>(
+    policy: ReplicationPolicy,
+    outputs: (Option<Result<T>>, Option<Result<T>>),
+) -> Result<T> {
+    match (policy, outputs) {
+        (ReplicationPolicy::Either, outputs) => either(outputs),
+        (ReplicationPolicy::Both, (Some(primary), Some(secondary))) => {
+            let value = primary?;
+            secondary.map(|_| value)
+        }
+        (ReplicationPolicy::Primary, (Some(primary), _)) => primary,
+        _ => unreachable!("both managers finish"),
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<A: CacheManager, B: CacheManager> CacheManager
+    for ReplicatedManager<A, B>
+{
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let primary = async {
+            self.primary.get(cache_key).await.map_err(Into::<BoxError>::into)
+        };
+        let secondary = async {
+            self.secondary.get(cache_key).await.map_err(Into::<BoxError>::into)
+        };
+        let outputs = race(Box::pin(primary), Box::pin(secondary), |output| {
+            matches!(output, Ok(Some(_)))
+        })
+        .await;
+        match outputs {
+            (Some(Ok(Some(entry))), _) | (_, Some(Ok(Some(entry)))) => {
+                Ok(Some(entry))
+            }
+            // A miss from either manager is trusted over an error from the other
+            (Some(Ok(None)), _) | (_, Some(Ok(None))) => Ok(None),
+            outputs => either(outputs),
+        }
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let primary = async {
+            self.primary
+                .put(cache_key.clone(), response.clone(), policy.clone())
+                .await
+                .map_err(Into::<BoxError>::into)
+        };
+        let secondary = async {
+            self.secondary
+                .put(cache_key.clone(), response.clone(), policy.clone())
+                .await
+                .map_err(Into::<BoxError>::into)
+        };
+        let outputs =
+            race(Box::pin(primary), Box::pin(secondary), |_| false).await;
+        settle(self.policy, outputs)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        let primary = async {
+            self.primary.delete(cache_key).await.map_err(Into::<BoxError>::into)
+        };
+        let secondary = async {
+            self.secondary
+                .delete(cache_key)
+                .await
+                .map_err(Into::<BoxError>::into)
+        };
+        let outputs =
+            race(Box::pin(primary), Box::pin(secondary), |_| false).await;
+        settle(self.policy, outputs)
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        // Both are shut down even if one fails
+        let primary = self.primary.shutdown().await.map_err(Into::into);
+        let secondary = self.secondary.shutdown().await.map_err(Into::into);
+        primary.and(secondary)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        let outputs = race(
+            Box::pin(self.primary.clear()),
+            Box::pin(self.secondary.clear()),
+            |_| false,
+        )
+        .await;
+        settle(self.policy, outputs)
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        // Both keep every entry, the secondary is only listed if the primary can't be
+        match self.primary.list().await {
+            Ok(entries) => Ok(entries),
+            Err(e) => self.secondary.list().await.map_err(|_| e),
+        }
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        match self.primary.stats().await {
+            Ok(stats) => Ok(stats),
+            Err(e) => self.secondary.stats().await.map_err(|_| e),
+        }
+    }
+}
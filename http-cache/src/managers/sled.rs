@@ -0,0 +1,99 @@
+use super::format;
+use crate::{BoxError, CacheManager, CorruptEntry, HttpResponse, Result};
+
+use std::path::Path;
+
+use http_cache_semantics::CachePolicy;
+use serde::{Deserialize, Serialize};
+
+// The tree `SledManager::open` stores entries in
+const TREE: &str = "http-cache";
+
+/// Implements [`CacheManager`] with a [sled](https://github.com/spacejam/sled) tree as the
+/// backend, an embedded database keeping the cache in a single directory.
+///
+/// Each entry is stored under its cache key as the versioned bincode encoding of the
+/// response and its policy. sled recovers to a consistent state after a crash, but writes
+/// only become durable once the tree is flushed, which sled does periodically (every 500ms
+/// by default) or when [`flush`](Self::flush) is called. sled's calls block the current
+/// thread, which is usually brief as it works from its page cache.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-sled")))]
+#[derive(Debug, Clone)]
+pub struct SledManager {
+    /// The tree the entries are stored in
+    pub tree: sled::Tree,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Store {
+    response: HttpResponse,
+    policy: CachePolicy,
+}
+
+impl SledManager {
+    /// Creates a new manager storing entries in `tree`, which can belong to a database
+    /// shared with other data.
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Opens the database at `path`, creating it if it doesn't exist, and stores entries
+    /// in its `http-cache` tree.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(sled::open(path)?.open_tree(TREE)?))
+    }
+
+    /// Writes the entries stored so far to disk, returning how many bytes were written.
+    pub async fn flush(&self) -> Result<usize> {
+        Ok(self.tree.flush_async().await?)
+    }
+
+    /// Clears out the entire cache.
+    pub async fn clear(&self) -> Result<()> {
+        self.tree.clear()?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheManager for SledManager {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let value = match self.tree.get(cache_key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let store: Store = match format::decode(&value, format::unversioned)
+            .map_err(CorruptEntry::new)?
+        {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+        Ok(Some((store.response, store.policy)))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let store = Store { response, policy };
+        self.tree.insert(cache_key, format::encode(&store)?)?;
+        Ok(store.response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.tree.remove(cache_key)?;
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method
+        Self::clear(self).await
+    }
+}
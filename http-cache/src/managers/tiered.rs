@@ -0,0 +1,109 @@
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse, Result,
+};
+
+use http_cache_semantics::CachePolicy;
+
+/// Layers a fast [`CacheManager`] in front of a durable one, typically a
+/// [`MokaManager`](crate::MokaManager) in front of a
+/// [`CACacheManager`](crate::CACacheManager), so hot entries are served from memory while
+/// every entry survives a restart.
+///
+/// Reads ask the front first. A miss, or an error, falls through to the back, and an entry
+/// found there is copied into the front for the next read. Writes go to the back first and
+/// only reach the front once the back has stored them, and deletes go to both. The front can
+/// evict entries on its own, which only costs a read from the back.
+#[derive(Debug, Clone)]
+pub struct TieredManager<F: CacheManager, B: CacheManager> {
+    front: F,
+    back: B,
+}
+
+impl<F: CacheManager, B: CacheManager> TieredManager<F, B> {
+    /// Creates a new manager reading through `front` to `back` and writing to both.
+    pub fn new(front: F, back: B) -> Self {
+        Self { front, back }
+    }
+
+    /// Returns the front manager.
+    pub fn front(&self) -> &F {
+        &self.front
+    }
+
+    /// Returns the back manager.
+    pub fn back(&self) -> &B {
+        &self.back
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<F: CacheManager, B: CacheManager> CacheManager for TieredManager<F, B> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        // A front that can't answer, e.g. because its entry is corrupt, is skipped
+        if let Ok(Some(entry)) = self.front.get(cache_key).await {
+            return Ok(Some(entry));
+        }
+        let entry = match self.back.get(cache_key).await.map_err(Into::into)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        // The entry is served even if it can't be copied to the front
+        let (response, policy) = entry;
+        let response = self
+            .front
+            .put(cache_key.to_string(), response.clone(), policy.clone())
+            .await
+            .unwrap_or(response);
+        Ok(Some((response, policy)))
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let response = self
+            .back
+            .put(cache_key.clone(), response, policy.clone())
+            .await
+            .map_err(Into::into)?;
+        self.front.put(cache_key, response, policy).await.map_err(Into::into)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        // Both are deleted from even if one fails
+        let front = self.front.delete(cache_key).await.map_err(Into::into);
+        let back = self.back.delete(cache_key).await.map_err(Into::into);
+        front.and(back)
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        // Both are shut down even if one fails
+        let front = self.front.shutdown().await.map_err(Into::into);
+        let back = self.back.shutdown().await.map_err(Into::into);
+        front.and(back)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The back is cleared first, so the front can't be refilled from it
+        self.back.clear().await?;
+        self.front.clear().await
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        // Every entry of the front is also in the back
+        self.back.list().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        // The entries of the back, the front only holds copies
+        self.back.stats().await
+    }
+}
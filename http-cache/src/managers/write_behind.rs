@@ -0,0 +1,283 @@
+use crate::{
+    BoxError, CacheManager, CacheStats, CachedEntry, HttpResponse, Result,
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use async_channel::{Receiver, Sender};
+use http_cache_semantics::CachePolicy;
+
+type Pending = HashMap<String, (u64, Option<(HttpResponse, CachePolicy)>)>;
+
+enum Op {
+    Put { id: u64, cache_key: String, entry: Box<(HttpResponse, CachePolicy)> },
+    Delete { id: u64, cache_key: String },
+    Flush(Sender<()>),
+}
+
+struct Shared {
+    pending: Mutex<Pending>,
+    next_id: Mutex<u64>,
+    // Writes with an id up to this one were queued before the cache was cleared
+    cleared: Mutex<u64>,
+}
+
+/// Wraps a [`CacheManager`] so that writes are queued and applied in the background
+/// instead of being awaited while the response is returned to the caller.
+///
+/// The queue is bounded, once it is full `put` and `delete` wait for room, which keeps a
+/// slow backend from accumulating unbounded memory. Reads see queued writes immediately.
+/// Writes are applied by the [`WriteBehindWorker`] returned alongside the manager, which
+/// must be spawned on the runtime in use. Errors from the inner manager while applying
+/// queued writes are discarded, the entry is simply not cached.
+#[cfg_attr(docsrs, doc(cfg(feature = "write-behind")))]
+pub struct WriteBehindManager<T: CacheManager> {
+    inner: Arc<T>,
+    sender: Sender<Op>,
+    shared: Arc<Shared>,
+}
+
+impl<T: CacheManager> Clone for WriteBehindManager<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            sender: self.sender.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: CacheManager> fmt::Debug for WriteBehindManager<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteBehindManager")
+            .field("queued", &self.sender.len())
+            .field("capacity", &self.sender.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Applies the writes queued by a [`WriteBehindManager`], see [`WriteBehindManager::new`].
+#[cfg_attr(docsrs, doc(cfg(feature = "write-behind")))]
+pub struct WriteBehindWorker<T: CacheManager> {
+    inner: Arc<T>,
+    receiver: Receiver<Op>,
+    shared: Arc<Shared>,
+}
+
+impl<T: CacheManager> fmt::Debug for WriteBehindWorker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteBehindWorker").finish_non_exhaustive()
+    }
+}
+
+/// Error returned when writing to a [`WriteBehindManager`] that has been shut down
+#[cfg_attr(docsrs, doc(cfg(feature = "write-behind")))]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct WriteBehindClosed;
+
+impl fmt::Display for WriteBehindClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Write-behind queue has been shut down")
+    }
+}
+
+impl std::error::Error for WriteBehindClosed {}
+
+impl<T: CacheManager> WriteBehindManager<T> {
+    /// Creates a new manager queuing up to `capacity` writes for `inner`.
+    /// The returned worker must be spawned for the writes to be applied, e.g.
+    /// `tokio::spawn(worker.run())`.
+    pub fn new(inner: T, capacity: usize) -> (Self, WriteBehindWorker<T>) {
+        let (sender, receiver) = async_channel::bounded(capacity.max(1));
+        let inner = Arc::new(inner);
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(HashMap::new()),
+            next_id: Mutex::new(0),
+            cleared: Mutex::new(0),
+        });
+        (
+            Self { inner: inner.clone(), sender, shared: shared.clone() },
+            WriteBehindWorker { inner, receiver, shared },
+        )
+    }
+
+    /// Returns the wrapped manager
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Waits until every write queued before this call has been applied.
+    pub async fn flush(&self) -> Result<()> {
+        let (done, wait) = async_channel::bounded(1);
+        self.sender
+            .send(Op::Flush(done))
+            .await
+            .map_err(|_| WriteBehindClosed)?;
+        // The worker drops the sender once the flush marker is reached.
+        wait.recv().await.ok();
+        Ok(())
+    }
+
+    /// Applies every queued write and stops accepting new ones, the worker then exits.
+    pub async fn shutdown(&self) -> Result<()> {
+        if !self.sender.is_closed() {
+            self.flush().await?;
+            self.sender.close();
+        }
+        Ok(())
+    }
+
+    /// Drops the queued writes and removes every entry of the inner manager.
+    pub async fn clear(&self) -> Result<()> {
+        {
+            let next_id = self.shared.next_id.lock().unwrap();
+            *self.shared.cleared.lock().unwrap() = *next_id;
+            self.shared.pending.lock().unwrap().clear();
+        }
+        // Waits for the write being applied, if any, so it can't refill the inner manager
+        if !self.sender.is_closed() {
+            self.flush().await?;
+        }
+        self.inner.clear().await
+    }
+
+    fn track(
+        &self,
+        cache_key: &str,
+        entry: Option<(HttpResponse, CachePolicy)>,
+    ) -> u64 {
+        let mut next_id = self.shared.next_id.lock().unwrap();
+        *next_id += 1;
+        let id = *next_id;
+        self.shared
+            .pending
+            .lock()
+            .unwrap()
+            .insert(cache_key.to_string(), (id, entry));
+        id
+    }
+
+    async fn enqueue(&self, cache_key: &str, op: Op) -> Result<()> {
+        if self.sender.send(op).await.is_err() {
+            self.shared.pending.lock().unwrap().remove(cache_key);
+            return Err(Box::new(WriteBehindClosed));
+        }
+        Ok(())
+    }
+}
+
+impl<T: CacheManager> WriteBehindWorker<T> {
+    /// Applies queued writes until the manager is shut down or every copy of it is dropped.
+    pub async fn run(self) {
+        while let Ok(op) = self.receiver.recv().await {
+            let cleared = *self.shared.cleared.lock().unwrap();
+            let (id, cache_key) = match op {
+                Op::Put { id, cache_key, .. }
+                | Op::Delete { id, cache_key }
+                    if id <= cleared =>
+                {
+                    (id, cache_key)
+                }
+                Op::Put { id, cache_key, entry } => {
+                    let (response, policy) = *entry;
+                    self.inner
+                        .put(cache_key.clone(), response, policy)
+                        .await
+                        .ok();
+                    (id, cache_key)
+                }
+                Op::Delete { id, cache_key } => {
+                    self.inner.delete(&cache_key).await.ok();
+                    (id, cache_key)
+                }
+                Op::Flush(done) => {
+                    drop(done);
+                    continue;
+                }
+            };
+            let mut pending = self.shared.pending.lock().unwrap();
+            // Only forget the entry if no newer write for the key is queued.
+            if pending
+                .get(&cache_key)
+                .map_or(false, |(latest, _)| *latest == id)
+            {
+                pending.remove(&cache_key);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: CacheManager> CacheManager for WriteBehindManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let queued = self
+            .shared
+            .pending
+            .lock()
+            .unwrap()
+            .get(cache_key)
+            .map(|(_, entry)| entry.clone());
+        match queued {
+            Some(entry) => Ok(entry),
+            None => self.inner.get(cache_key).await.map_err(Into::into),
+        }
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let id =
+            self.track(&cache_key, Some((response.clone(), policy.clone())));
+        self.enqueue(
+            &cache_key,
+            Op::Put {
+                id,
+                cache_key: cache_key.clone(),
+                entry: Box::new((response.clone(), policy)),
+            },
+        )
+        .await?;
+        Ok(response)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        let id = self.track(cache_key, None);
+        self.enqueue(
+            cache_key,
+            Op::Delete { id, cache_key: cache_key.to_string() },
+        )
+        .await
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        // The inherent method, applying the queue before the inner manager shuts down
+        WriteBehindManager::shutdown(self).await?;
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // The inherent method, dropping the queue as well
+        WriteBehindManager::clear(self).await
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        // Queued writes aren't listed until they are applied
+        self.inner.list().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        self.inner.stats().await
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{
+    BoxError, CacheEvent, CacheManager, CacheStats, CachedEntry, HttpResponse,
+    Observer, Result, Sleep,
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_channel::{Receiver, Sender};
+use http_cache_semantics::CachePolicy;
+
+/// A closure that takes an error returned by the inner manager of a [`WriteRetryManager`]
+/// and returns whether it is transient, so the write is worth retrying.
+pub type Transient = Arc<dyn Fn(&BoxError) -> bool + Send + Sync>;
+
+/// How a [`WriteRetryManager`] retries failed writes.
+#[cfg_attr(docsrs, doc(cfg(feature = "write-retry")))]
+#[derive(Clone)]
+pub struct WriteRetry {
+    /// How many times a failed write is retried.
+    pub retries: u32,
+    /// How long to wait before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+    /// Used to wait between retries.
+    pub sleep: Sleep,
+    /// Which errors are retried. By default I/O errors that are usually temporary, such as
+    /// timeouts and reset connections, are retried.
+    pub transient: Option<Transient>,
+    /// Called with a [`CacheEvent::WriteFailed`] when a write fails for good.
+    pub observer: Option<Observer>,
+}
+
+impl fmt::Debug for WriteRetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteRetry")
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .field("sleep", &"Fn(Duration) -> SleepFuture")
+            .field(
+                "transient",
+                &self.transient.as_ref().map(|_| "Fn(&BoxError) -> bool"),
+            )
+            .field(
+                "observer",
+                &self.observer.as_ref().map(|_| "Fn(&CacheEvent)"),
+            )
+            .finish()
+    }
+}
+
+impl WriteRetry {
+    fn is_transient(&self, error: &BoxError) -> bool {
+        if let Some(transient) = &self.transient {
+            return transient(error);
+        }
+        let mut source: Option<&(dyn std::error::Error + 'static)> =
+            Some(error.as_ref());
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                use std::io::ErrorKind::*;
+                return matches!(
+                    error.kind(),
+                    Interrupted
+                        | WouldBlock
+                        | TimedOut
+                        | ConnectionReset
+                        | ConnectionAborted
+                        | ConnectionRefused
+                        | BrokenPipe
+                );
+            }
+            source = error.source();
+        }
+        false
+    }
+
+    fn failed(&self, cache_key: &str, attempts: u32) {
+        if let Some(observer) = &self.observer {
+            observer(&CacheEvent::WriteFailed {
+                cache_key: cache_key.to_string(),
+                attempts,
+            });
+        }
+    }
+}
+
+struct Retry {
+    id: u64,
+    cache_key: String,
+    entry: Box<(HttpResponse, CachePolicy)>,
+}
+
+struct Shared {
+    retry: WriteRetry,
+    // The id of the retry queued for each key, dropped once the key is written again
+    pending: Mutex<HashMap<String, u64>>,
+    next_id: Mutex<u64>,
+}
+
+impl Shared {
+    fn is_pending(&self, cache_key: &str, id: u64) -> bool {
+        self.pending.lock().unwrap().get(cache_key) == Some(&id)
+    }
+
+    fn forget(&self, cache_key: &str, id: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.get(cache_key) == Some(&id) {
+            pending.remove(cache_key);
+        }
+    }
+}
+
+/// Wraps a [`CacheManager`] so that writes failing with a transient error, such as a
+/// network blip to a remote store or a busy disk, are retried in the background instead
+/// of failing the request.
+///
+/// The response is returned as soon as the write is queued for retrying. Up to `capacity`
+/// writes wait to be retried, beyond that and for errors that aren't transient the error
+/// is returned as it would be without this manager. Writes that fail for good are reported
+/// to the observer of the [`WriteRetry`]. Retries are made by the [`WriteRetryWorker`]
+/// returned alongside the manager, which must be spawned on the runtime in use. It retries
+/// one write at a time, so an outage of the store doesn't turn into a burst of retries. A
+/// retry is dropped if the key is written or deleted again in the meantime.
+#[cfg_attr(docsrs, doc(cfg(feature = "write-retry")))]
+pub struct WriteRetryManager<T: CacheManager> {
+    inner: Arc<T>,
+    sender: Sender<Retry>,
+    shared: Arc<Shared>,
+}
+
+impl<T: CacheManager> Clone for WriteRetryManager<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            sender: self.sender.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: CacheManager> fmt::Debug for WriteRetryManager<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteRetryManager")
+            .field("retry", &self.shared.retry)
+            .field("queued", &self.sender.len())
+            .field("capacity", &self.sender.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Retries the writes queued by a [`WriteRetryManager`], see [`WriteRetryManager::new`].
+#[cfg_attr(docsrs, doc(cfg(feature = "write-retry")))]
+pub struct WriteRetryWorker<T: CacheManager> {
+    inner: Arc<T>,
+    receiver: Receiver<Retry>,
+    shared: Arc<Shared>,
+}
+
+impl<T: CacheManager> fmt::Debug for WriteRetryWorker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteRetryWorker").finish_non_exhaustive()
+    }
+}
+
+impl<T: CacheManager> WriteRetryManager<T> {
+    /// Creates a new manager retrying up to `capacity` failed writes to `inner` at a time.
+    /// The returned worker must be spawned for the writes to be retried, e.g.
+    /// `tokio::spawn(worker.run())`.
+    pub fn new(
+        inner: T,
+        retry: WriteRetry,
+        capacity: usize,
+    ) -> (Self, WriteRetryWorker<T>) {
+        let (sender, receiver) = async_channel::bounded(capacity.max(1));
+        let inner = Arc::new(inner);
+        let shared = Arc::new(Shared {
+            retry,
+            pending: Mutex::new(HashMap::new()),
+            next_id: Mutex::new(0),
+        });
+        (
+            Self { inner: inner.clone(), sender, shared: shared.clone() },
+            WriteRetryWorker { inner, receiver, shared },
+        )
+    }
+
+    /// Returns the wrapped manager
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    // Queues the write to be retried, returning false if the queue is full or closed
+    fn queue(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> bool {
+        let id = {
+            let mut next_id = self.shared.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        self.shared.pending.lock().unwrap().insert(cache_key.clone(), id);
+        let retry = Retry {
+            id,
+            cache_key: cache_key.clone(),
+            entry: Box::new((response, policy)),
+        };
+        if self.sender.try_send(retry).is_err() {
+            self.shared.forget(&cache_key, id);
+            return false;
+        }
+        true
+    }
+}
+
+impl<T: CacheManager> WriteRetryWorker<T> {
+    /// Retries queued writes until the manager is shut down or every copy of it is dropped.
+    pub async fn run(self) {
+        let retry = &self.shared.retry;
+        while let Ok(Retry { id, cache_key, entry }) =
+            self.receiver.recv().await
+        {
+            let (response, policy) = *entry;
+            let mut backoff = retry.backoff;
+            let mut attempts = 1;
+            loop {
+                if attempts > retry.retries {
+                    retry.failed(&cache_key, attempts);
+                    break;
+                }
+                (retry.sleep)(backoff).await;
+                backoff = backoff.saturating_mul(2);
+                if self.receiver.is_closed()
+                    || !self.shared.is_pending(&cache_key, id)
+                {
+                    break;
+                }
+                attempts += 1;
+                let written = self
+                    .inner
+                    .put(cache_key.clone(), response.clone(), policy.clone())
+                    .await;
+                match written {
+                    Ok(_) => break,
+                    Err(e) => {
+                        if !retry.is_transient(&e.into()) {
+                            retry.failed(&cache_key, attempts);
+                            break;
+                        }
+                    }
+                }
+            }
+            self.shared.forget(&cache_key, id);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: CacheManager> CacheManager for WriteRetryManager<T> {
+    type Error = BoxError;
+
+    async fn get(
+        &self,
+        cache_key: &str,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        self.inner.get(cache_key).await.map_err(Into::into)
+    }
+
+    async fn put(
+        &self,
+        cache_key: String,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        self.shared.pending.lock().unwrap().remove(&cache_key);
+        let e = match self
+            .inner
+            .put(cache_key.clone(), response.clone(), policy.clone())
+            .await
+        {
+            Ok(response) => return Ok(response),
+            Err(e) => e.into(),
+        };
+        if self.shared.retry.is_transient(&e)
+            && self.queue(cache_key.clone(), response.clone(), policy)
+        {
+            return Ok(response);
+        }
+        self.shared.retry.failed(&cache_key, 1);
+        Err(e)
+    }
+
+    async fn delete(&self, cache_key: &str) -> Result<()> {
+        self.shared.pending.lock().unwrap().remove(cache_key);
+        self.inner.delete(cache_key).await.map_err(Into::into)
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        // Writes still waiting to be retried are dropped
+        self.sender.close();
+        self.inner.shutdown().await.map_err(Into::into)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        // Writes still waiting to be retried are dropped, like when the key is written again
+        self.shared.pending.lock().unwrap().clear();
+        self.inner.clear().await
+    }
+
+    async fn list(&self) -> Result<Vec<CachedEntry>> {
+        self.inner.list().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats> {
+        self.inner.stats().await
+    }
+}
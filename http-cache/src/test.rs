@@ -1,16 +1,83 @@
 use crate::{
-    error, CacheMode, HitOrMiss, HttpCacheOptions, HttpResponse, HttpVersion,
-    Result,
+    error, Bytes, CacheMode, HitOrMiss, HttpCacheOptions, HttpResponse,
+    HttpVersion, Result,
+};
+use http::{
+    header::{CACHE_CONTROL, ETAG, SERVER, VIA},
+    HeaderMap, HeaderName, HeaderValue, StatusCode,
 };
-use http::{header::CACHE_CONTROL, StatusCode};
 use http_cache_semantics::CacheOptions;
 use url::Url;
 
-use std::{collections::HashMap, str::FromStr};
+use std::str::FromStr;
 
 const GET: &str = "GET";
 const TEST_BODY: &[u8] = b"test";
 
+#[test]
+fn partial_ranges() {
+    use crate::{coalesce, content_range};
+
+    assert_eq!(content_range("bytes 0-99/1000"), Some((0, 99, 1000)));
+    assert_eq!(content_range("bytes 0-99/*"), None);
+    assert_eq!(content_range("bytes 99-0/1000"), None);
+    assert_eq!(content_range("bytes 0-1000/1000"), None);
+
+    let segments = vec![
+        (4, b"ef".to_vec()),
+        (0, b"abc".to_vec()),
+        (2, b"cd".to_vec()),
+        (8, b"i".to_vec()),
+    ];
+    assert_eq!(
+        coalesce(segments),
+        vec![(0, b"abcdef".to_vec()), (8, b"i".to_vec())]
+    );
+}
+
+#[test]
+fn header_limits() -> Result<()> {
+    use crate::{HeaderLimit, HeaderLimits};
+
+    let mut response = HttpResponse {
+        body: Bytes::from_static(TEST_BODY),
+        headers: HeaderMap::from_iter([
+            (ETAG, HeaderValue::from_static("\"abc\"")),
+            (SERVER, HeaderValue::from_static("test")),
+        ]),
+        status: 200,
+        url: Url::parse("http://example.com")?,
+        version: HttpVersion::Http11,
+    };
+    let limits =
+        HeaderLimits { max_count: 2, max_header_size: 10, max_total_size: 20 };
+    assert_eq!(limits.exceeded(&response), None);
+    response.headers.insert(VIA, HeaderValue::from_static("proxy"));
+    assert_eq!(limits.exceeded(&response), Some(HeaderLimit::Count));
+    response.headers.remove(VIA);
+    response.headers.insert(SERVER, HeaderValue::from_static("test-server"));
+    assert_eq!(limits.exceeded(&response), Some(HeaderLimit::HeaderSize));
+    let limits = HeaderLimits { max_header_size: 20, ..limits };
+    assert_eq!(limits.exceeded(&response), Some(HeaderLimit::TotalSize));
+    Ok(())
+}
+
+#[test]
+fn latency_discounted() {
+    use crate::EntryCost;
+    use std::time::Duration;
+
+    let reference = Duration::from_millis(100);
+    let cost = |fetch_latency| EntryCost { size: 1000, fetch_latency };
+    assert_eq!(cost(None).latency_discounted(reference), 1000);
+    assert_eq!(cost(Some(Duration::ZERO)).latency_discounted(reference), 1000);
+    assert_eq!(cost(Some(reference)).latency_discounted(reference), 500);
+    assert_eq!(
+        cost(Some(Duration::from_secs(3600))).latency_discounted(reference),
+        1
+    );
+}
+
 #[test]
 fn hit_miss() -> Result<()> {
     // Testing the Debug, Display, and Clone traits for the HitOrMiss ebnum
@@ -23,6 +90,18 @@ fn hit_miss() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn cache_state() {
+    use crate::CacheState;
+
+    assert_eq!(CacheState::Revalidated.to_string(), "REVALIDATED");
+    assert_eq!(CacheState::parse("STALE"), Some(CacheState::Stale));
+    assert_eq!(CacheState::parse("hit"), None);
+    assert_eq!(HitOrMiss::from(CacheState::Stale).to_string(), "HIT");
+    assert_eq!(HitOrMiss::from(CacheState::Expired).to_string(), "MISS");
+    assert_eq!(HitOrMiss::from(CacheState::Bypass).to_string(), "MISS");
+}
+
 #[test]
 fn cache_mode() -> Result<()> {
     // Testing the Debug and Clone traits for the CacheMode enum
@@ -36,17 +115,34 @@ fn cache_mode() -> Result<()> {
 fn cache_options() -> Result<()> {
     // Testing the Debug, Default and Clone traits for the HttpCacheOptions struct
     let mut opts = HttpCacheOptions::default();
-    assert_eq!(format!("{:?}", opts.clone()), "HttpCacheOptions { cache_options: None, cache_key: \"Fn(&request::Parts) -> String\", cache_mode_fn: \"Fn(&request::Parts) -> CacheMode\", cache_bust: \"Fn(&request::Parts) -> Vec<String>\" }");
+    assert_eq!(format!("{:?}", opts.clone()), "HttpCacheOptions { cache_options: None, cache_key: \"Fn(&request::Parts) -> String\", cache_mode_fn: \"Fn(&request::Parts) -> CacheMode\", cache_bust: \"Fn(&request::Parts) -> Vec<String>\", modify_response: \"Fn(&request::Parts, &mut HttpResponse)\", cache_reason_header: false, isolation_key: None, assume_cacheable: \"Fn(&request::Parts) -> Option<Duration>\", revalidation_headers: Both, stale_on_timeout: None, observer: \"Fn(&CacheEvent)\", refresh_ahead: None, admission: None, revalidation_rate_limit: None, store_predicate: \"Fn(&HttpResponse) -> bool\", refresh_header: None, variant_cookies: None, normalize_accept_encoding: false, decoded_bodies: Identity, generations: None, pause: None, host_stats: None, store_sample_rate: None, store_metadata: \"Fn(&request::Parts, &HttpResponse) -> Option<String>\", key_headers: None, assemble_partial: false, header_limits: None, read_only_stale: false, cache_state_header: false, store_variants: false }");
     opts.cache_options = Some(CacheOptions::default());
-    assert_eq!(format!("{:?}", opts.clone()), "HttpCacheOptions { cache_options: Some(CacheOptions { shared: true, cache_heuristic: 0.1, immutable_min_time_to_live: 86400s, ignore_cargo_cult: false }), cache_key: \"Fn(&request::Parts) -> String\", cache_mode_fn: \"Fn(&request::Parts) -> CacheMode\", cache_bust: \"Fn(&request::Parts) -> Vec<String>\" }");
+    assert_eq!(format!("{:?}", opts.clone()), "HttpCacheOptions { cache_options: Some(CacheOptions { shared: true, cache_heuristic: 0.1, immutable_min_time_to_live: 86400s, ignore_cargo_cult: false }), cache_key: \"Fn(&request::Parts) -> String\", cache_mode_fn: \"Fn(&request::Parts) -> CacheMode\", cache_bust: \"Fn(&request::Parts) -> Vec<String>\", modify_response: \"Fn(&request::Parts, &mut HttpResponse)\", cache_reason_header: false, isolation_key: None, assume_cacheable: \"Fn(&request::Parts) -> Option<Duration>\", revalidation_headers: Both, stale_on_timeout: None, observer: \"Fn(&CacheEvent)\", refresh_ahead: None, admission: None, revalidation_rate_limit: None, store_predicate: \"Fn(&HttpResponse) -> bool\", refresh_header: None, variant_cookies: None, normalize_accept_encoding: false, decoded_bodies: Identity, generations: None, pause: None, host_stats: None, store_sample_rate: None, store_metadata: \"Fn(&request::Parts, &HttpResponse) -> Option<String>\", key_headers: None, assemble_partial: false, header_limits: None, read_only_stale: false, cache_state_header: false, store_variants: false }");
     opts.cache_options = None;
     opts.cache_key = Some(std::sync::Arc::new(|req: &http::request::Parts| {
         format!("{}:{}:{:?}:test", req.method, req.uri, req.version)
     }));
-    assert_eq!(format!("{:?}", opts), "HttpCacheOptions { cache_options: None, cache_key: \"Fn(&request::Parts) -> String\", cache_mode_fn: \"Fn(&request::Parts) -> CacheMode\", cache_bust: \"Fn(&request::Parts) -> Vec<String>\" }");
+    assert_eq!(format!("{:?}", opts), "HttpCacheOptions { cache_options: None, cache_key: \"Fn(&request::Parts) -> String\", cache_mode_fn: \"Fn(&request::Parts) -> CacheMode\", cache_bust: \"Fn(&request::Parts) -> Vec<String>\", modify_response: \"Fn(&request::Parts, &mut HttpResponse)\", cache_reason_header: false, isolation_key: None, assume_cacheable: \"Fn(&request::Parts) -> Option<Duration>\", revalidation_headers: Both, stale_on_timeout: None, observer: \"Fn(&CacheEvent)\", refresh_ahead: None, admission: None, revalidation_rate_limit: None, store_predicate: \"Fn(&HttpResponse) -> bool\", refresh_header: None, variant_cookies: None, normalize_accept_encoding: false, decoded_bodies: Identity, generations: None, pause: None, host_stats: None, store_sample_rate: None, store_metadata: \"Fn(&request::Parts, &HttpResponse) -> Option<String>\", key_headers: None, assemble_partial: false, header_limits: None, read_only_stale: false, cache_state_header: false, store_variants: false }");
     Ok(())
 }
 
+#[test]
+fn store_sample_rate() {
+    let opts = HttpCacheOptions {
+        store_sample_rate: Some(0.25),
+        ..Default::default()
+    };
+    let keys: Vec<String> =
+        (0..1000).map(|i| format!("GET:http://example.com/{i}")).collect();
+    let sampled = keys.iter().filter(|key| opts.sampled(key)).count();
+    assert!((200..300).contains(&sampled), "{sampled} keys sampled");
+    // The same keys are chosen every time
+    assert_eq!(keys.iter().filter(|key| opts.sampled(key)).count(), sampled);
+    let none =
+        HttpCacheOptions { store_sample_rate: Some(0.0), ..Default::default() };
+    assert!(!keys.iter().any(|key| none.sampled(key)));
+}
+
 #[test]
 #[allow(clippy::default_constructed_unit_structs)]
 fn test_errors() -> Result<()> {
@@ -57,6 +153,12 @@ fn test_errors() -> Result<()> {
     let bh = error::BadHeader::default();
     assert_eq!(format!("{:?}", bh.clone()), "BadHeader",);
     assert_eq!(bh.to_string(), "Error parsing header value".to_string(),);
+    let bm = error::BadMetadata::default();
+    assert_eq!(format!("{:?}", bm.clone()), "BadMetadata",);
+    assert_eq!(
+        bm.to_string(),
+        "Metadata must be visible ASCII on a single line".to_string(),
+    );
     Ok(())
 }
 
@@ -64,13 +166,13 @@ fn test_errors() -> Result<()> {
 fn response_methods_work() -> Result<()> {
     let url = Url::from_str("http://example.com")?;
     let mut res = HttpResponse {
-        body: TEST_BODY.to_vec(),
-        headers: HashMap::default(),
+        body: Bytes::from_static(TEST_BODY),
+        headers: HeaderMap::new(),
         status: 200,
         url: url.clone(),
         version: HttpVersion::Http11,
     };
-    assert_eq!(format!("{:?}", res.clone()), "HttpResponse { body: [116, 101, 115, 116], headers: {}, status: 200, url: Url { scheme: \"http\", cannot_be_a_base: false, username: \"\", password: None, host: Some(Domain(\"example.com\")), port: None, path: \"/\", query: None, fragment: None }, version: Http11 }");
+    assert_eq!(format!("{:?}", res.clone()), "HttpResponse { body: b\"test\", headers: {}, status: 200, url: Url { scheme: \"http\", cannot_be_a_base: false, username: \"\", password: None, host: Some(Domain(\"example.com\")), port: None, path: \"/\", query: None, fragment: None }, version: Http11 }");
     res.add_warning(&url, 112, "Test Warning");
     let code = res.warning_code();
     assert!(code.is_some());
@@ -174,9 +276,16 @@ mod with_http_types {
 mod with_cacache {
 
     use super::*;
-    use crate::{CACacheManager, CacheManager};
+    use crate::{
+        CACacheManager, CacheDecision, CacheEvent, CacheManager, EntryMetadata,
+        HttpCache, MaintenanceReport,
+    };
 
     use http_cache_semantics::CachePolicy;
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
 
     #[cfg(feature = "cacache-async-std")]
     use async_attributes::test as async_test;
@@ -186,13 +295,22 @@ mod with_cacache {
     #[async_test]
     async fn cacache() -> Result<()> {
         let url = Url::parse("http://example.com")?;
-        let manager = CACacheManager { path: "./http-cacache-test".into() };
+        let manager = CACacheManager {
+            path: "./http-cacache-test".into(),
+            inline_threshold: None,
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
         assert_eq!(
             &format!("{:?}", manager),
-            "CACacheManager { path: \"./http-cacache-test\" }"
+            "CACacheManager { path: \"./http-cacache-test\", inline_threshold: None, npm_compat: false, algorithm: None, fsync: false, max_bytes: None, max_entries: None, observer: None }"
         );
         let http_res = HttpResponse {
-            body: TEST_BODY.to_vec(),
+            body: Bytes::from_static(TEST_BODY),
             headers: Default::default(),
             status: 200,
             url: url.clone(),
@@ -223,15 +341,815 @@ mod with_cacache {
         std::fs::remove_dir_all("./http-cacache-test")?;
         Ok(())
     }
+
+    #[async_test]
+    async fn cacache_blocking() -> Result<()> {
+        use crate::BlockingCacheManager;
+
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        // Inline and external entries, readable by both APIs
+        for inline_threshold in [None, Some(1024)] {
+            let manager = CACacheManager {
+                path: "./http-cacache-blocking-test".into(),
+                inline_threshold,
+                npm_compat: false,
+                algorithm: None,
+                fsync: false,
+                max_bytes: None,
+                max_entries: None,
+                observer: None,
+            };
+            let http_res = HttpResponse {
+                body: Bytes::from_static(TEST_BODY),
+                headers: Default::default(),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            manager.put_blocking(key.clone(), http_res, policy.clone())?;
+            let data = manager.get_blocking(&key)?;
+            assert_eq!(data.unwrap().0.body, TEST_BODY);
+            let data = manager.get(&key).await?;
+            assert_eq!(data.unwrap().0.body, TEST_BODY);
+            manager.delete_blocking(&key)?;
+            assert!(manager.get_blocking(&key)?.is_none());
+        }
+        std::fs::remove_dir_all("./http-cacache-blocking-test")?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_inline_threshold() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let path = "./http-cacache-inline-test";
+        let manager = CACacheManager {
+            path: path.into(),
+            inline_threshold: Some(8),
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let large_body = vec![b'a'; 64];
+        for (key, body) in
+            [("small", TEST_BODY.to_vec()), ("large", large_body.clone())]
+        {
+            let http_res = HttpResponse {
+                body: body.into(),
+                headers: Default::default(),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            manager.put(key.to_string(), http_res, policy.clone()).await?;
+        }
+
+        // Small bodies live in the index, large ones are stored as content
+        let small = cacache::metadata(path, "small").await?.unwrap();
+        assert_eq!(small.size, 0);
+        assert!(small.raw_metadata.is_some());
+        let large = cacache::metadata(path, "large").await?.unwrap();
+        assert_eq!(large.size, large_body.len());
+        assert_eq!(
+            cacache::read_hash(path, &large.integrity).await?,
+            large_body
+        );
+
+        let data = manager.get("small").await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        let data = manager.get("large").await?;
+        assert_eq!(data.unwrap().0.body, large_body);
+
+        // Both record the checksum of the body
+        assert_eq!(
+            manager.metadata("small").await?,
+            Some(EntryMetadata {
+                integrity: Some(
+                    cacache::Integrity::from(TEST_BODY).to_string()
+                ),
+                body_size: Some(TEST_BODY.len()),
+            })
+        );
+        assert_eq!(
+            manager.metadata("large").await?,
+            Some(EntryMetadata {
+                integrity: Some(
+                    cacache::Integrity::from(&large_body).to_string()
+                ),
+                body_size: Some(large_body.len()),
+            })
+        );
+        assert!(manager.metadata("missing").await?.is_none());
+
+        manager.delete("large").await?;
+        assert!(manager.get("large").await?.is_none());
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_maintenance() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let path = "./http-cacache-maintenance-test";
+        let manager = CACacheManager {
+            path: path.into(),
+            inline_threshold: Some(8),
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let shared_body = vec![b'a'; 64];
+        let deleted_body = vec![b'b'; 32];
+        for (key, body) in [
+            ("small", TEST_BODY.to_vec()),
+            ("shared-1", shared_body.clone()),
+            ("shared-2", shared_body.clone()),
+            ("deleted", deleted_body.clone()),
+        ] {
+            let http_res = HttpResponse {
+                body: body.into(),
+                headers: Default::default(),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            manager.put(key.to_string(), http_res, policy.clone()).await?;
+        }
+        // Deleting only removes the entry, its content is left behind
+        manager.delete("deleted").await?;
+        cacache::write(path, "undecodable", b"not an entry").await?;
+
+        let report = manager.maintenance().await?;
+        assert_eq!(
+            report,
+            MaintenanceReport {
+                entries: 3,
+                inline_entries: 1,
+                corrupted_removed: 1,
+                content_bytes: 2 * shared_body.len() as u64,
+                unique_content_bytes: shared_body.len() as u64,
+                orphans_removed: 1,
+                orphan_bytes_removed: deleted_body.len() as u64,
+            }
+        );
+        assert!(cacache::metadata(path, "undecodable").await?.is_none());
+        let data = manager.get("shared-2").await?;
+        assert_eq!(data.unwrap().0.body, shared_body);
+
+        let report = manager.maintenance().await?;
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.corrupted_removed + report.orphans_removed, 0);
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_npm_compat() -> Result<()> {
+        let path = "./http-cacache-npm-test";
+        let manager = CACacheManager {
+            path: path.into(),
+            inline_threshold: Some(1024),
+            npm_compat: true,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
+        let parts = http::Request::get("http://user@example.com#top")
+            .body(())?
+            .into_parts()
+            .0;
+        let key = CACacheManager::npm_cache_key(&parts);
+        assert_eq!(key, "make-fetch-happen:request-cache:http://example.com/");
+
+        // Written the way make-fetch-happen writes entries
+        let url = Url::parse("http://example.com/")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: HeaderMap::from_iter([
+                (CACHE_CONTROL, HeaderValue::from_static("max-age=3600")),
+                (
+                    HeaderName::from_static("x-custom"),
+                    HeaderValue::from_static("dropped"),
+                ),
+            ]),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let res = http::Response::builder()
+            .status(200)
+            .header("cache-control", "max-age=3600")
+            .body(())?;
+        let policy = CachePolicy::new(&parts, &res);
+        manager.put(key.clone(), http_res, policy).await?;
+        let metadata = cacache::metadata(path, &key).await?.unwrap();
+        assert!(metadata.integrity.to_string().starts_with("sha512-"));
+        assert_eq!(metadata.metadata["url"], "http://example.com/");
+        assert_eq!(
+            metadata.metadata["resHeaders"],
+            serde_json::json!({ "cache-control": "max-age=3600" })
+        );
+        assert_eq!(cacache::read(path, &key).await?, TEST_BODY);
+        let data = manager.get(&key).await?.unwrap();
+        assert_eq!(data.0.body, TEST_BODY);
+        assert_eq!(data.0.headers.len(), 2);
+
+        // Entries written by make-fetch-happen are read as well
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let integrity = cacache::write_hash_with_algo(
+            cacache::Algorithm::Sha512,
+            path,
+            TEST_BODY,
+        )
+        .await?;
+        cacache::index::insert_async(
+            path.as_ref(),
+            "make-fetch-happen:request-cache:http://example.com/npm",
+            cacache::WriteOpts::new()
+                .integrity(integrity)
+                .size(TEST_BODY.len())
+                .metadata(serde_json::json!({
+                    "time": time as u64,
+                    "url": "http://example.com/npm",
+                    "reqHeaders": {},
+                    "resHeaders": { "cache-control": "max-age=3600" },
+                    "options": { "compress": true },
+                    "status": 203,
+                })),
+        )
+        .await?;
+        let (response, policy) = manager
+            .get("make-fetch-happen:request-cache:http://example.com/npm")
+            .await?
+            .unwrap();
+        assert_eq!(response.body, TEST_BODY);
+        assert_eq!(response.status, 203);
+        assert_eq!(response.url.as_str(), "http://example.com/npm");
+        assert!(!policy.is_stale(SystemTime::now()));
+        let mut urls: Vec<String> = manager
+            .list()
+            .await?
+            .into_iter()
+            .map(|entry| entry.url.to_string())
+            .collect();
+        urls.sort();
+        assert_eq!(urls, ["http://example.com/", "http://example.com/npm"]);
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_builder() -> Result<()> {
+        let path = "./http-cacache-builder-test";
+        assert_eq!(
+            CACacheManager::new(path).path,
+            CACacheManager::builder().path(path).build().path
+        );
+        let manager = CACacheManager::builder()
+            .path(path)
+            .algorithm(cacache::Algorithm::Sha512)
+            .fsync(true)
+            .build();
+        assert_eq!(manager.inline_threshold, None);
+        let url = "http://example.com/";
+        let req = http::Request::get(url).body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse(url)?,
+            version: HttpVersion::Http11,
+        };
+        manager.put("builder".into(), http_res, policy).await?;
+        let metadata = cacache::metadata(path, "builder").await?.unwrap();
+        assert!(metadata.integrity.to_string().starts_with("sha512-"));
+        assert_eq!(manager.get("builder").await?.unwrap().0.body, TEST_BODY);
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_prune_expired() -> Result<()> {
+        let path = "./http-cacache-prune-test";
+        let manager = CACacheManager::new(path);
+        let url = "http://example.com/";
+        let req = http::Request::get(url).body(())?;
+        let stored = |max_age: u64| -> Result<(HttpResponse, CachePolicy)> {
+            let res = http::Response::builder()
+                .status(200)
+                .header("cache-control", format!("max-age={max_age}"))
+                .body(())?;
+            let response = HttpResponse {
+                body: Bytes::from(format!("body {max_age}")),
+                headers: Default::default(),
+                status: 200,
+                url: Url::parse(url)?,
+                version: HttpVersion::Http11,
+            };
+            Ok((response, CachePolicy::new(&req, &res)))
+        };
+        let (response, policy) = stored(0)?;
+        manager.put("stale".into(), response, policy.clone()).await?;
+        let (response, policy_fresh) = stored(3600)?;
+        manager.put("fresh".into(), response, policy_fresh).await?;
+        let integrity =
+            cacache::metadata(path, "stale").await?.unwrap().integrity;
+
+        // Stale entries are kept for the grace period
+        assert_eq!(manager.prune_expired(Duration::from_secs(60)).await?, 0);
+        assert_eq!(manager.prune_expired(Duration::ZERO).await?, 1);
+        assert!(manager.get("stale").await?.is_none());
+        assert!(manager.get("fresh").await?.is_some());
+        assert!(cacache::read_hash(path, &integrity).await.is_err());
+
+        // Run in the background
+        let (response, _) = stored(0)?;
+        manager.put("stale".into(), response, policy).await?;
+        let sleep: crate::Sleep =
+            Arc::new(|d| Box::pin(async_std::task::sleep(d)));
+        let pruning = manager.clone().prune_expired_every(
+            Duration::from_millis(10),
+            Duration::ZERO,
+            sleep,
+        );
+        async_std::future::timeout(Duration::from_millis(200), pruning)
+            .await
+            .unwrap_err();
+        assert!(manager.get("stale").await?.is_none());
+        assert!(manager.get("fresh").await?.is_some());
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_evict() -> Result<()> {
+        let path = "./http-cacache-evict-test";
+        let manager =
+            CACacheManager::builder().path(path).max_entries(2).build();
+        let url = "http://example.com/";
+        let req = http::Request::get(url).body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        let response = |size: usize| -> Result<HttpResponse> {
+            Ok(HttpResponse {
+                body: Bytes::from(vec![size as u8; size]),
+                headers: Default::default(),
+                status: 200,
+                url: Url::parse(url)?,
+                version: HttpVersion::Http11,
+            })
+        };
+        for (key, size) in [("one", 1024), ("two", 2048), ("three", 4096)] {
+            manager.put(key.into(), response(size)?, policy.clone()).await?;
+            // Entries are evicted by the millisecond they were stored
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let integrity =
+            cacache::metadata(path, "two").await?.unwrap().integrity;
+        assert!(manager.get("one").await?.is_none());
+        assert!(manager.get("two").await?.is_some());
+        assert!(manager.get("three").await?.is_some());
+
+        // The oldest entries are evicted until the cache is small enough
+        let mut manager = manager;
+        manager.max_bytes = Some(manager.stats().await?.bytes - 1);
+        assert_eq!(manager.evict()?, 1);
+        assert!(manager.get("two").await?.is_none());
+        assert!(manager.get("three").await?.is_some());
+        assert!(cacache::read_hash(path, &integrity).await.is_err());
+        assert!(manager.stats().await?.bytes <= manager.max_bytes.unwrap());
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_evict_tracked() -> Result<()> {
+        let path = "./http-cacache-evict-tracked-test";
+        let manager =
+            CACacheManager::builder().path(path).max_entries(10).build();
+        let url = "http://example.com/";
+        let req = http::Request::get(url).body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        let response = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse(url)?,
+            version: HttpVersion::Http11,
+        };
+        // The first put counts the entries, the ones after add to the count without
+        // listing the index, so entries written around the manager aren't noticed
+        manager.put("key-0".into(), response.clone(), policy.clone()).await?;
+        for i in 0..10 {
+            cacache::write(path, format!("other-{i}"), b"other").await?;
+        }
+        std::thread::sleep(Duration::from_millis(2));
+        for i in 1..10 {
+            manager
+                .put(format!("key-{i}"), response.clone(), policy.clone())
+                .await?;
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let count = || cacache::list_sync(path).count();
+        assert_eq!(count(), 20);
+
+        // Once the count is past the limit, entries are evicted below 90% of it
+        manager.put("key-10".into(), response.clone(), policy.clone()).await?;
+        assert_eq!(count(), 9);
+        assert!(manager.get("key-1").await?.is_none());
+        assert!(manager.get("key-2").await?.is_some());
+        assert!(manager.get("key-10").await?.is_some());
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_stats() -> Result<()> {
+        let path = "./http-cacache-stats-test";
+        let manager = CACacheManager {
+            path: path.into(),
+            inline_threshold: Some(1024),
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
+        let url = "http://example.com/";
+        let req = http::Request::get(url).body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        let large = HttpResponse {
+            body: Bytes::from(vec![0; 2048]),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse(url)?,
+            version: HttpVersion::Http11,
+        };
+        manager.put("large".into(), large.clone(), policy.clone()).await?;
+        let one = manager.stats().await?;
+        assert_eq!(one.entries, 1);
+        assert!(one.bytes > 2048);
+
+        // The same body stored twice takes its space once
+        manager.put("copy".into(), large, policy.clone()).await?;
+        let two = manager.stats().await?;
+        assert_eq!(two.entries, 2);
+        assert!(two.bytes < one.bytes * 2);
+        manager.delete("copy").await?;
+        manager.delete("large").await?;
+        assert_eq!(manager.stats().await?.entries, 0);
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_many() -> Result<()> {
+        let path = "./http-cacache-many-test";
+        let manager = CACacheManager {
+            path: path.into(),
+            inline_threshold: None,
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        let keys = ["GET:http://example.com/a", "GET:http://example.com/b"];
+        for key in keys {
+            let http_res = HttpResponse {
+                body: Bytes::from(key),
+                headers: Default::default(),
+                status: 200,
+                url: Url::parse("http://example.com")?,
+                version: HttpVersion::Http11,
+            };
+            manager.put(key.to_string(), http_res, policy.clone()).await?;
+        }
+
+        // In the order of the keys, missing ones included
+        let entries = manager
+            .get_many(&[keys[1], "GET:http://example.com/c", keys[0]])
+            .await?;
+        let bodies: Vec<Option<Bytes>> = entries
+            .into_iter()
+            .map(|entry| entry.map(|(response, _)| response.body))
+            .collect();
+        assert_eq!(
+            bodies,
+            [Some(Bytes::from(keys[1])), None, Some(Bytes::from(keys[0]))]
+        );
+        manager.delete_many(&keys).await?;
+        let entries = manager.get_many(&keys).await?;
+        assert!(entries.iter().all(Option::is_none));
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_list() -> Result<()> {
+        let path = "./http-cacache-list-test";
+        let manager = CACacheManager {
+            path: path.into(),
+            inline_threshold: Some(1024),
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
+        let before = SystemTime::now();
+        for (method, url, body) in [
+            ("GET", "http://example.com/small", TEST_BODY.to_vec()),
+            ("POST", "http://example.com/large", vec![0; 2048]),
+        ] {
+            let http_res = HttpResponse {
+                body: Bytes::from(body),
+                headers: Default::default(),
+                status: 200,
+                url: Url::parse(url)?,
+                version: HttpVersion::Http11,
+            };
+            let req =
+                http::Request::builder().method(method).uri(url).body(())?;
+            let res = http::Response::builder().status(200).body(())?;
+            let policy = CachePolicy::new(&req, &res);
+            manager.put(format!("{method}:{url}"), http_res, policy).await?;
+        }
+
+        // Both inline and external entries are listed
+        let mut entries = manager.list().await?;
+        entries.sort_by(|a, b| a.cache_key.cmp(&b.cache_key));
+        let keys: Vec<&str> =
+            entries.iter().map(|entry| entry.cache_key.as_str()).collect();
+        assert_eq!(
+            keys,
+            ["GET:http://example.com/small", "POST:http://example.com/large"]
+        );
+        assert_eq!(entries[0].method, http::Method::GET);
+
+        // Deleted entries are not
+        manager.delete("GET:http://example.com/small").await?;
+        let entries = manager.list().await?;
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.method, http::Method::POST);
+        assert_eq!(entry.url.as_str(), "http://example.com/large");
+        assert!(entry.stored_at >= before);
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_corrupted_content() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let path = "./http-cacache-corrupted-test";
+        let manager = CACacheManager {
+            path: path.into(),
+            inline_threshold: None,
+            npm_compat: false,
+            algorithm: None,
+            fsync: false,
+            max_bytes: None,
+            max_entries: None,
+            observer: None,
+        };
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+
+        // Flip the stored content so it no longer matches its checksum
+        let mut dirs = vec![std::path::PathBuf::from(path).join("content-v2")];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir)? {
+                let entry_path = entry?.path();
+                if entry_path.is_dir() {
+                    dirs.push(entry_path);
+                } else {
+                    let mut bytes = std::fs::read(&entry_path)?;
+                    bytes[0] ^= 0xff;
+                    std::fs::write(&entry_path, bytes)?;
+                }
+            }
+        }
+
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_none());
+        let metadata =
+            cacache::metadata(path, format!("{}:{}", GET, &url)).await?;
+        assert!(metadata.is_none());
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_verify() -> Result<()> {
+        let path = "./http-cacache-verify-test";
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let manager = CACacheManager::builder()
+            .path(path)
+            .observer(Arc::new(move |event: &CacheEvent| {
+                recorded.lock().unwrap().push(event.clone())
+            }))
+            .build();
+        // An index entry this version can't decode
+        let integrity = cacache::write_hash(path, TEST_BODY).await?;
+        let broken = || {
+            cacache::WriteOpts::new()
+                .integrity(integrity.clone())
+                .raw_metadata(vec![0xff; 4])
+        };
+        cacache::index::insert_async(path.as_ref(), "broken", broken()).await?;
+
+        // Read as a miss and removed
+        assert!(manager.get("broken").await?.is_none());
+        assert!(cacache::metadata(path, "broken").await?.is_none());
+        assert!(matches!(
+            &events.lock().unwrap()[..],
+            [CacheEvent::CorruptEntry { cache_key, .. }] if cache_key == "broken"
+        ));
+
+        // Found by verifying every entry, which keeps the content
+        cacache::index::insert_async(path.as_ref(), "broken", broken()).await?;
+        let url = "http://example.com/";
+        let req = http::Request::get(url).body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse(url)?,
+            version: HttpVersion::Http11,
+        };
+        manager
+            .put("valid".into(), http_res, CachePolicy::new(&req, &res))
+            .await?;
+        let report = manager.verify().await?;
+        assert_eq!(report.entries, 1);
+        assert_eq!(report.corrupted_removed, 1);
+        assert_eq!(events.lock().unwrap().len(), 2);
+        assert!(cacache::metadata(path, "broken").await?.is_none());
+        assert_eq!(cacache::read_hash(path, &integrity).await?, TEST_BODY);
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[async_test]
+    async fn cacache_format_version() -> Result<()> {
+        use crate::managers::format::FORMAT_VERSION;
+
+        let path = "./http-cacache-format-test";
+        let manager = CACacheManager::new(path);
+        let url = "http://example.com/";
+        let req = http::Request::get(url).body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse(url)?,
+            version: HttpVersion::Http11,
+        };
+        manager
+            .put("key".into(), http_res, CachePolicy::new(&req, &res))
+            .await?;
+        let metadata = cacache::metadata(path, "key").await?.unwrap();
+        let mut raw = metadata.raw_metadata.unwrap();
+        assert_eq!(raw[8..12], FORMAT_VERSION.to_le_bytes());
+
+        // Entries written in another version are misses, and kept with their content
+        raw[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let opts = cacache::WriteOpts::new()
+            .integrity(metadata.integrity.clone())
+            .size(metadata.size)
+            .raw_metadata(raw);
+        cacache::index::insert_async(path.as_ref(), "key", opts).await?;
+        assert!(manager.get("key").await?.is_none());
+        assert!(manager.metadata("key").await?.is_none());
+        let report = manager.maintenance().await?;
+        assert_eq!(report.corrupted_removed + report.orphans_removed, 0);
+        assert!(cacache::metadata(path, "key").await?.is_some());
+        assert_eq!(
+            cacache::read_hash(path, &metadata.integrity).await?,
+            TEST_BODY
+        );
+        std::fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn explain() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let mut cache = HttpCache {
+            mode: CacheMode::Default,
+            manager: CACacheManager::default(),
+            options: HttpCacheOptions::default(),
+        };
+        let get = http::Request::get(url.as_str()).body(())?.into_parts().0;
+        let post = http::Request::post(url.as_str()).body(())?.into_parts().0;
+        let mut res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: HeaderMap::from_iter([(
+                CACHE_CONTROL,
+                HeaderValue::from_static("max-age=86400, public"),
+            )]),
+            status: 200,
+            url,
+            version: HttpVersion::Http11,
+        };
+        assert_eq!(cache.explain(&get, None)?, CacheDecision::Lookup);
+        assert_eq!(
+            cache.explain(&post, None)?,
+            CacheDecision::MethodNotCacheable
+        );
+        assert_eq!(cache.explain(&get, Some(&res))?, CacheDecision::Fresh);
+
+        res.headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        assert_eq!(cache.explain(&get, Some(&res))?, CacheDecision::Stale);
+
+        res.headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        assert_eq!(
+            cache.explain(&get, Some(&res))?,
+            CacheDecision::NotStorable
+        );
+        assert_eq!(CacheDecision::NotStorable.to_string(), "not-storable");
+
+        res.headers.insert(CACHE_CONTROL, HeaderValue::from_static("public"));
+        cache.options.store_predicate = Some(Arc::new(|res: &HttpResponse| {
+            res.headers.contains_key("x-cache-ok")
+        }));
+        assert_eq!(
+            cache.explain(&get, Some(&res))?,
+            CacheDecision::NotStorable
+        );
+        cache.options.store_predicate = None;
+
+        res.status = 404;
+        assert_eq!(
+            cache.explain(&get, Some(&res))?,
+            CacheDecision::StatusNotCacheable(404)
+        );
+
+        cache.mode = CacheMode::NoStore;
+        assert_eq!(
+            cache.explain(&get, None)?,
+            CacheDecision::Bypass(CacheMode::NoStore)
+        );
+        assert_eq!(
+            CacheDecision::Bypass(CacheMode::NoStore).to_string(),
+            "bypass; mode=NoStore"
+        );
+        Ok(())
+    }
 }
 
 #[cfg(feature = "manager-moka")]
 mod with_moka {
     use super::*;
-    use crate::{CacheManager, MokaManager};
+    use crate::{CacheManager, EntryCost, MokaManager, FETCH_LATENCY_HEADER};
+    use http::header::{SET_COOKIE, VARY};
 
     use http_cache_semantics::CachePolicy;
-    use std::sync::Arc;
+    use std::{sync::Arc, time::Duration};
 
     #[async_attributes::test]
     async fn moka() -> Result<()> {
@@ -241,7 +1159,7 @@ mod with_moka {
         let url = Url::parse("http://example.com")?;
         let manager = Arc::new(mm);
         let http_res = HttpResponse {
-            body: TEST_BODY.to_vec(),
+            body: Bytes::from_static(TEST_BODY),
             headers: Default::default(),
             status: 200,
             url: url.clone(),
@@ -271,4 +1189,2368 @@ mod with_moka {
         assert!(data.is_none());
         Ok(())
     }
+
+    #[async_attributes::test]
+    async fn moka_clear() -> Result<()> {
+        use crate::{ClearUnsupported, HttpCache, MockManager, TieredManager};
+
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // Cleared through the trait, behind a pointer, and through the cache
+        let manager = Arc::new(MokaManager::default());
+        manager.put(key.clone(), http_res.clone(), policy.clone()).await?;
+        CacheManager::clear(&manager).await?;
+        assert!(manager.get(&key).await?.is_none());
+        let cache = HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        };
+        manager.put(key.clone(), http_res, policy).await?;
+        cache.clear().await?;
+        assert!(manager.get(&key).await?.is_none());
+
+        // Managers without support report it
+        let e = MockManager::new().clear().await.unwrap_err();
+        assert!(e.downcast_ref::<ClearUnsupported>().is_some());
+        let tiered =
+            TieredManager::new(MokaManager::default(), MockManager::new());
+        assert!(tiered.clear().await.is_err());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_list() -> Result<()> {
+        use crate::NamespacedManager;
+        use std::time::SystemTime;
+
+        let manager = MokaManager::default();
+        let before = SystemTime::now();
+        for (method, url) in [
+            ("GET", "http://example.com/"),
+            ("POST", "http://example.com/form"),
+        ] {
+            let http_res = HttpResponse {
+                body: Bytes::from_static(TEST_BODY),
+                headers: Default::default(),
+                status: 200,
+                url: Url::parse(url)?,
+                version: HttpVersion::Http11,
+            };
+            let req =
+                http::Request::builder().method(method).uri(url).body(())?;
+            let res = http::Response::builder().status(200).body(())?;
+            let policy = CachePolicy::new(&req, &res);
+            manager.put(format!("{method}:{url}"), http_res, policy).await?;
+        }
+        let mut entries = manager.list().await?;
+        entries.sort_by(|a, b| a.cache_key.cmp(&b.cache_key));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].cache_key, "GET:http://example.com/");
+        assert_eq!(entries[0].method, http::Method::GET);
+        assert_eq!(entries[1].url.as_str(), "http://example.com/form");
+        assert_eq!(entries[1].method, http::Method::POST);
+        assert!(entries.iter().all(|entry| entry.stored_at >= before));
+
+        // Listed entries can be deleted by their key
+        manager.delete(&entries[1].cache_key).await?;
+        assert_eq!(manager.list().await?.len(), 1);
+
+        // A namespace only lists its own entries, under their own keys
+        let namespaced = NamespacedManager::new(manager.clone(), "api");
+        let (response, policy) =
+            manager.get("GET:http://example.com/").await?.unwrap();
+        namespaced
+            .put("GET:http://example.com/".into(), response, policy)
+            .await?;
+        assert_eq!(manager.list().await?.len(), 2);
+        let entries = namespaced.list().await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].cache_key, "GET:http://example.com/");
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_stats() -> Result<()> {
+        use crate::CacheStats;
+
+        let manager = MokaManager::default();
+        assert_eq!(manager.stats().await?, CacheStats::default());
+        let url = Url::parse("http://example.com")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put("a".into(), http_res.clone(), policy.clone()).await?;
+        let one = manager.stats().await?;
+        assert_eq!(one.entries, 1);
+        assert!(one.bytes > TEST_BODY.len() as u64);
+        manager.put("b".into(), http_res, policy).await?;
+        let two = manager.stats().await?;
+        assert_eq!(two, CacheStats { entries: 2, bytes: one.bytes * 2 });
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_many() -> Result<()> {
+        // The default implementations, one key after the other
+        let manager = MokaManager::default();
+        let url = Url::parse("http://example.com")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put("a".into(), http_res, policy).await?;
+        let entries = manager.get_many(&["b", "a"]).await?;
+        assert!(entries[0].is_none());
+        assert_eq!(entries[1].as_ref().unwrap().0.body, TEST_BODY);
+        manager.delete_many(&["a", "b"]).await?;
+        assert!(manager.get("a").await?.is_none());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_multi_valued_headers() -> Result<()> {
+        let manager = MokaManager::default();
+        let url = Url::parse("http://example.com")?;
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, HeaderValue::from_static("a=1"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("b=2"));
+        headers.append(VARY, HeaderValue::from_static("Accept"));
+        headers.append(VARY, HeaderValue::from_static("Accept-Language"));
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: headers.clone(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+        let (stored, _) =
+            manager.get(&format!("{}:{}", GET, &url)).await?.unwrap();
+        assert_eq!(stored.headers, headers);
+        assert_eq!(stored.headers.get_all("set-cookie").iter().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn compact_policy() -> Result<()> {
+        use crate::managers::compact::CompactPolicy;
+        use http_cache_semantics::BeforeRequest;
+        use std::time::SystemTime;
+
+        let req = |language: &str| {
+            http::Request::get("http://example.com")
+                .header("accept-language", language)
+                .header("authorization", "Bearer secret")
+                .header("user-agent", "test")
+                .body(())
+        };
+        let res = http::Response::builder()
+            .header("cache-control", "public, max-age=60")
+            .header("vary", "Accept-Language")
+            .body(())?;
+        let policy = CachePolicy::new(&req("en")?, &res);
+        let compact = CompactPolicy::new(&policy)?;
+        let bytes = bincode::serialize(&compact)?;
+        assert!(bytes.len() < bincode::serialize(&policy)?.len());
+        assert!(!bytes.windows(6).any(|w| w == b"secret"));
+
+        let rebuilt: CompactPolicy = bincode::deserialize(&bytes)?;
+        let rebuilt = rebuilt.into_policy()?;
+        let now = SystemTime::now();
+        assert_eq!(rebuilt.time_to_live(now), policy.time_to_live(now));
+        assert!(matches!(
+            rebuilt.before_request(&req("en")?, now),
+            BeforeRequest::Fresh(_)
+        ));
+        assert!(matches!(
+            rebuilt.before_request(&req("de")?, now),
+            BeforeRequest::Stale { matches: false, .. }
+        ));
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_corrupt_entry() -> Result<()> {
+        let manager = MokaManager::default();
+        manager.cache.insert("corrupt".into(), Arc::new(vec![0xff; 4])).await;
+        let e = manager.get("corrupt").await.unwrap_err();
+        assert!(e.downcast_ref::<crate::CorruptEntry>().is_some());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_format_version() -> Result<()> {
+        use crate::managers::format::FORMAT_VERSION;
+
+        let manager = MokaManager::default();
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put("key".into(), http_res, policy).await?;
+        let mut stored = manager.cache.get("key").await.unwrap().to_vec();
+        assert_eq!(stored[8..12], FORMAT_VERSION.to_le_bytes());
+
+        // Entries written in another version are misses, left out of the list
+        stored[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        manager.cache.insert("key".into(), Arc::new(stored)).await;
+        assert!(manager.get("key").await?.is_none());
+        assert!(manager.list().await?.is_empty());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_builder() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let manager = MokaManager::builder()
+            .max_bytes(1024)
+            .time_to_live(Duration::from_secs(60))
+            .time_to_idle(Duration::from_secs(30))
+            .name("http-cache")
+            .build();
+        assert_eq!(manager.cache.name(), Some("http-cache"));
+        assert_eq!(manager.cache.policy().max_capacity(), Some(1024));
+        assert_eq!(
+            manager.cache.policy().time_to_live(),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            manager.cache.policy().time_to_idle(),
+            Some(Duration::from_secs(30))
+        );
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+        assert!(manager.cache.weighted_size() > 0);
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_some());
+
+        let manager = MokaManager::builder().max_capacity(10).build();
+        assert_eq!(manager.cache.policy().max_capacity(), Some(10));
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_weigher() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        // Entries fetched in under a second are cheap to fetch again
+        let manager = MokaManager::builder()
+            .max_bytes(1024)
+            .weigher(Arc::new(|cost: &EntryCost| match cost.fetch_latency {
+                Some(latency) if latency >= Duration::from_secs(1) => 1,
+                _ => 100,
+            }))
+            .build();
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        for (key, latency) in [("slow", "2000"), ("fast", "5")] {
+            let http_res = HttpResponse {
+                body: Bytes::from_static(TEST_BODY),
+                headers: HeaderMap::from_iter([(
+                    HeaderName::from_static(FETCH_LATENCY_HEADER),
+                    HeaderValue::from_static(latency),
+                )]),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            manager.put(key.to_string(), http_res, policy.clone()).await?;
+        }
+        assert_eq!(manager.cache.weighted_size(), 101);
+        let (slow, _) = manager.get("slow").await?.unwrap();
+        assert_eq!(slow.fetch_latency(), Some(Duration::from_secs(2)));
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_expire_by_policy() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let manager =
+            MokaManager::builder().expire_by_policy(Duration::ZERO).build();
+        let req = http::Request::get("http://example.com").body(())?;
+        for (key, cache_control) in
+            [("fresh", "max-age=3600"), ("stale", "max-age=0")]
+        {
+            let res = http::Response::builder()
+                .status(200)
+                .header("cache-control", cache_control)
+                .body(TEST_BODY.to_vec())?;
+            let policy = CachePolicy::new(&req, &res);
+            let http_res = HttpResponse {
+                body: Bytes::from_static(TEST_BODY),
+                headers: Default::default(),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            manager.put(key.to_string(), http_res, policy).await?;
+        }
+        assert!(manager.get("fresh").await?.is_some());
+        assert!(manager.get("stale").await?.is_none());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn moka_snapshot() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let path = "./http-cache-moka-snapshot-test";
+        let manager = MokaManager::default();
+        assert_eq!(manager.load_from(path).await?, 0);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(key.clone(), http_res, policy).await?;
+        manager.save_to(path).await?;
+
+        let restored = MokaManager::default();
+        assert_eq!(restored.load_from(path).await?, 1);
+        let data = restored.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka-arc")]
+mod with_moka_arc {
+    use super::*;
+    use crate::{CacheManager, MokaArcManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    #[async_attributes::test]
+    async fn moka_arc() -> Result<()> {
+        let manager = MokaArcManager::default();
+        assert_eq!(format!("{:?}", manager), "MokaArcManager { .. }");
+        let url = Url::parse("http://example.com")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let key = format!("{}:{}", GET, &url);
+        manager.put(key.clone(), http_res, policy).await?;
+        let data = manager.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+
+        // Stored as it is and shared by clones
+        let store = manager.clone().cache.get(&key).await.unwrap();
+        assert_eq!(store.response().body, TEST_BODY);
+        assert!(store.policy().is_storable());
+        let stats = manager.stats().await?;
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.bytes, (key.len() + TEST_BODY.len()) as u64);
+
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        manager.clear().await?;
+        assert_eq!(manager.stats().await?.entries, 0);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka")]
+mod with_dyn {
+    use super::*;
+    use crate::{CacheManager, DynHttpCache, HttpCache, MokaManager};
+
+    use http_cache_semantics::CachePolicy;
+    use std::sync::Arc;
+
+    #[async_attributes::test]
+    async fn dyn_manager() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let moka = MokaManager::default();
+
+        // Caches with different managers can have the same type
+        let caches: Vec<DynHttpCache> = vec![
+            HttpCache {
+                mode: CacheMode::Default,
+                manager: moka.clone(),
+                options: HttpCacheOptions::default(),
+            }
+            .into_dyn(),
+            HttpCache {
+                mode: CacheMode::NoStore,
+                manager: Arc::new(MokaManager::default()),
+                options: HttpCacheOptions::default(),
+            },
+        ];
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let manager = &caches[0].manager;
+        manager.put(key.clone(), http_res, policy).await?;
+        assert!(moka.get(&key).await?.is_some());
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn shared_manager() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let moka = MokaManager::default();
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // One manager behind an Arc, a Box or a static reference
+        let shared = Arc::new(moka.clone());
+        let first = HttpCache {
+            mode: CacheMode::Default,
+            manager: shared.clone(),
+            options: HttpCacheOptions::default(),
+        };
+        let second = HttpCache {
+            mode: CacheMode::Default,
+            manager: Box::new(moka.clone()),
+            options: HttpCacheOptions::default(),
+        };
+        let leaked: &'static MokaManager = Box::leak(Box::new(moka.clone()));
+        let third = HttpCache {
+            mode: CacheMode::Default,
+            manager: leaked,
+            options: HttpCacheOptions::default(),
+        };
+        first.manager.put(key.clone(), http_res, policy).await?;
+        assert!(second.manager.get(&key).await?.is_some());
+        third.manager.delete(&key).await?;
+        assert!(shared.get(&key).await?.is_none());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka")]
+mod with_transaction {
+    use super::*;
+    use crate::{
+        CacheManager, Event, HttpCache, MokaManager, Step, UnexpectedEvent,
+    };
+
+    use http::{header::IF_NONE_MATCH, request, HeaderMap};
+
+    // Drives a transaction, answering fetches with `respond`, and returns the response
+    // with the headers of every fetch.
+    async fn drive(
+        cache: &HttpCache<MokaManager>,
+        respond: impl Fn(&HeaderMap) -> HttpResponse,
+    ) -> Result<(HttpResponse, Vec<HeaderMap>)> {
+        let parts = request_parts()?;
+        let mut fetches = Vec::new();
+        let mut transaction = cache.transaction(parts);
+        let mut step = transaction.start()?;
+        loop {
+            let event = match step {
+                Step::Delete(key) => {
+                    cache.manager.delete(&key).await?;
+                    Event::Deleted
+                }
+                Step::Lookup(key) => {
+                    Event::LookedUp(cache.manager.get(&key).await?)
+                }
+                Step::Fetch(headers) => {
+                    let response = respond(&headers);
+                    fetches.push(headers);
+                    Event::Fetched(response)
+                }
+                Step::Store { cache_key, response, policy } => Event::Stored(
+                    cache.manager.put(cache_key, response, policy).await?,
+                ),
+                Step::Respond(response) => return Ok((response, fetches)),
+            };
+            step = transaction.next(event)?;
+        }
+    }
+
+    fn request_parts() -> Result<request::Parts> {
+        Ok(http::Request::get("http://example.com").body(())?.into_parts().0)
+    }
+
+    fn response(status: u16, cache_control: &str) -> Result<HttpResponse> {
+        Ok(HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: HeaderMap::from_iter([
+                (CACHE_CONTROL, HeaderValue::from_str(cache_control)?),
+                (ETAG, HeaderValue::from_static("\"v1\"")),
+            ]),
+            status,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        })
+    }
+
+    fn cache() -> HttpCache<MokaManager> {
+        HttpCache {
+            mode: CacheMode::Default,
+            manager: MokaManager::default(),
+            options: HttpCacheOptions::default(),
+        }
+    }
+
+    #[async_attributes::test]
+    async fn miss_then_hit() -> Result<()> {
+        let cache = cache();
+        let (res, fetches) =
+            drive(&cache, |_| response(200, "max-age=86400, public").unwrap())
+                .await?;
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(res.body, TEST_BODY);
+        assert_eq!(res.headers["x-cache"], HitOrMiss::MISS.to_string());
+
+        let (res, fetches) =
+            drive(&cache, |_| unreachable!("served from the cache")).await?;
+        assert!(fetches.is_empty());
+        assert_eq!(res.body, TEST_BODY);
+        assert_eq!(res.headers["x-cache"], HitOrMiss::HIT.to_string());
+        assert_eq!(res.headers["x-cache-lookup"], HitOrMiss::HIT.to_string());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn revalidates_stale() -> Result<()> {
+        let cache = cache();
+        drive(&cache, |_| response(200, "no-cache").unwrap()).await?;
+
+        let (res, fetches) = drive(&cache, |headers| {
+            assert_eq!(headers[IF_NONE_MATCH], "\"v1\"");
+            let mut res = response(304, "no-cache").unwrap();
+            res.body.clear();
+            res
+        })
+        .await?;
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, TEST_BODY);
+        assert_eq!(res.headers["x-cache"], HitOrMiss::HIT.to_string());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn read_only() -> Result<()> {
+        let mut cache = cache();
+        cache.mode = CacheMode::ReadOnly;
+        let (res, fetches) =
+            drive(&cache, |_| response(200, "max-age=86400, public").unwrap())
+                .await?;
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(res.headers["x-cache"], HitOrMiss::MISS.to_string());
+        assert!(cache.manager.get("GET:http://example.com/").await?.is_none());
+
+        cache.mode = CacheMode::Default;
+        drive(&cache, |_| response(200, "no-cache").unwrap()).await?;
+        cache.mode = CacheMode::ReadOnly;
+        cache.options.read_only_stale = true;
+        let (res, fetches) =
+            drive(&cache, |_| unreachable!("served from the cache")).await?;
+        assert!(fetches.is_empty());
+        assert_eq!(res.headers["x-cache"], HitOrMiss::HIT.to_string());
+        assert!(res.headers["warning"].to_str()?.starts_with("110"));
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn shadow() -> Result<()> {
+        use crate::{CacheEvent, Divergence, LookupOutcome};
+        use std::sync::{Arc, Mutex};
+
+        let mut cache = cache();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observed = events.clone();
+        cache.options.observer = Some(Arc::new(move |event: &CacheEvent| {
+            if let CacheEvent::Lookup { .. }
+            | CacheEvent::ShadowCompared { .. } = event
+            {
+                observed.lock().unwrap().push(event.clone());
+            }
+        }));
+        drive(&cache, |_| response(200, "max-age=86400, public").unwrap())
+            .await?;
+        let (stored, _) =
+            cache.manager.get("GET:http://example.com/").await?.unwrap();
+        assert!(stored.fetch_latency().is_some());
+
+        // Fetched although fresh, and compared with the stored response
+        cache.mode = CacheMode::Shadow;
+        let (res, fetches) = drive(&cache, |_| {
+            let mut res = response(200, "max-age=86400, public").unwrap();
+            res.body = Bytes::from_static(b"changed");
+            res
+        })
+        .await?;
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(res.body, &b"changed"[..]);
+        assert_eq!(res.headers["x-cache"], HitOrMiss::MISS.to_string());
+        let cache_key = "GET:http://example.com/".to_string();
+        let events = events.lock().unwrap().clone();
+        match &events[..] {
+            [CacheEvent::Lookup { outcome: LookupOutcome::Miss, .. }, CacheEvent::Lookup { outcome: LookupOutcome::Fresh, .. }, CacheEvent::ShadowCompared { cache_key: key, divergences }] =>
+            {
+                assert_eq!(key, &cache_key);
+                assert!(matches!(divergences[..], [Divergence::Body { .. }]));
+            }
+            _ => panic!("unexpected events {events:?}"),
+        }
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn serves_stale_when_fetch_fails() -> Result<()> {
+        let cache = cache();
+        drive(&cache, |_| response(200, "no-cache").unwrap()).await?;
+
+        let mut transaction = cache.transaction(request_parts()?);
+        let Step::Lookup(key) = transaction.start()? else {
+            panic!("expected a lookup");
+        };
+        let stored = cache.manager.get(&key).await?;
+        let Step::Fetch(_) = transaction.next(Event::LookedUp(stored))? else {
+            panic!("expected a revalidation");
+        };
+        let Step::Respond(res) =
+            transaction.next(Event::FetchFailed("unreachable".into()))?
+        else {
+            panic!("expected the stale response");
+        };
+        assert_eq!(res.body, TEST_BODY);
+        assert_eq!(res.warning_code(), Some(111));
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn unexpected_event() -> Result<()> {
+        let cache = cache();
+        let mut transaction = cache.transaction(request_parts()?);
+        let Step::Lookup(_) = transaction.start()? else {
+            panic!("expected a lookup");
+        };
+        let err = transaction.next(Event::Deleted).unwrap_err();
+        assert!(err.downcast_ref::<UnexpectedEvent>().is_some());
+        assert_eq!(
+            err.to_string(),
+            "Unexpected deleted event while the transaction is looking-up"
+        );
+        // The transaction is still waiting for the lookup
+        let step = transaction.next(Event::LookedUp(None))?;
+        assert!(matches!(step, Step::Fetch(_)));
+        assert!(transaction.start().is_err());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka")]
+mod with_config {
+    use super::*;
+    use crate::{
+        CacheConfig, ConfiguredManager, HttpCache, ManagerConfig,
+        RevalidationHeaders,
+    };
+
+    #[test]
+    fn config() -> Result<()> {
+        let config: CacheConfig = serde_json::from_str(
+            r#"{
+                "mode": "no-cache",
+                "manager": { "type": "moka", "max-capacity": 100 },
+                "shared": false,
+                "max-body-size": 4,
+                "bypass-hosts": ["internal.example.com"],
+                "revalidation-headers": "etag-only",
+                "refresh-header": "x-refresh"
+            }"#,
+        )?;
+        assert!(matches!(
+            config.manager,
+            ManagerConfig::Moka { max_capacity: Some(100), .. }
+        ));
+        let cache = HttpCache::from_config(&config)?;
+        assert!(matches!(cache.manager, ConfiguredManager::Moka(_)));
+        assert_eq!(cache.mode, CacheMode::NoCache);
+        let options = &cache.options;
+        assert!(!options.cache_options.unwrap().shared);
+        assert_eq!(options.revalidation_headers, RevalidationHeaders::ETagOnly);
+        assert_eq!(options.refresh_header.as_ref().unwrap(), "x-refresh");
+
+        // Size limits and filters
+        let mut response = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: HeaderMap::new(),
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        assert!(options.store_allowed(&response));
+        response.body = Bytes::from_static(b"test!");
+        assert!(!options.store_allowed(&response));
+        let parts = |uri: &str| -> Result<http::request::Parts> {
+            Ok(http::Request::get(uri).body(())?.into_parts().0)
+        };
+        assert_eq!(
+            cache.cache_mode(&parts("http://internal.example.com/")?),
+            CacheMode::NoStore
+        );
+        assert_eq!(
+            cache.cache_mode(&parts("http://example.com/")?),
+            CacheMode::NoCache
+        );
+
+        // Environment overrides
+        let mut config = config;
+        let env = |name: &str| match name {
+            "HTTP_CACHE_MODE" => Some("NO-STORE".to_string()),
+            "HTTP_CACHE_MAX_SIZE" => Some("1024".to_string()),
+            _ => None,
+        };
+        config.apply_overrides(env)?;
+        assert_eq!(config.mode, CacheMode::NoStore);
+        assert!(matches!(
+            config.manager,
+            ManagerConfig::Moka {
+                max_capacity: None,
+                max_bytes: Some(1024),
+                ..
+            }
+        ));
+        let env = |name: &str| {
+            (name == "HTTP_CACHE_DIR").then(|| "/tmp/http-cache".to_string())
+        };
+        assert!(config.apply_overrides(env).is_err());
+        let env = |name: &str| {
+            (name == "HTTP_CACHE_MODE").then(|| "sometimes".to_string())
+        };
+        assert!(config.apply_overrides(env).is_err());
+
+        // Unknown settings are refused
+        assert!(serde_json::from_str::<CacheConfig>(
+            r#"{ "manager": { "type": "moka" }, "max-size": 1 }"#
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn none() -> Result<()> {
+        use crate::CacheManager;
+        use http_cache_semantics::CachePolicy;
+
+        let config: CacheConfig =
+            serde_json::from_str(r#"{ "manager": { "type": "none" } }"#)?;
+        let cache = HttpCache::from_config(&config)?;
+        assert!(matches!(cache.manager, ConfiguredManager::Null(_)));
+        let url = Url::parse("http://example.com")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let key = format!("{}:{}", GET, &url);
+        let stored = cache.manager.put(key.clone(), http_res, policy).await?;
+        assert_eq!(stored.body, TEST_BODY);
+        assert!(cache.manager.get(&key).await?.is_none());
+        cache.manager.delete(&key).await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka")]
+mod with_distributed {
+    use super::*;
+    use crate::{CacheManager, DistributedManager, MokaManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    #[async_attributes::test]
+    async fn distributed() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let nodes: Vec<(String, MokaManager)> = ["a", "b", "c"]
+            .iter()
+            .map(|name| (name.to_string(), MokaManager::default()))
+            .collect();
+        let manager = DistributedManager::new(nodes.clone(), 2);
+        assert_eq!(
+            format!("{:?}", manager.clone()),
+            "DistributedManager { nodes: [\"a\", \"b\", \"c\"], replication: 2, .. }"
+        );
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // Placement only depends on the node names
+        let replicas = manager.nodes_for(&key);
+        assert_eq!(replicas.len(), 2);
+        assert_ne!(replicas[0], replicas[1]);
+        let reversed = DistributedManager::new(nodes.iter().rev().cloned(), 2);
+        assert_eq!(reversed.nodes_for(&key), replicas);
+
+        // The entry is written to exactly the replicas
+        manager.put(key.clone(), http_res, policy).await?;
+        for (name, node) in &nodes {
+            let stored = node.get(&key).await?.is_some();
+            assert_eq!(stored, replicas.contains(&name.as_str()));
+        }
+
+        // A replica losing the entry doesn't lose it for the reader
+        let first = nodes.iter().find(|(name, _)| name == replicas[0]);
+        first.unwrap().1.delete(&key).await?;
+        let data = manager.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        for (_, node) in &nodes {
+            assert!(node.get(&key).await?.is_none());
+        }
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn distributed_clear() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let nodes: Vec<(String, MokaManager)> = ["a", "b", "c"]
+            .iter()
+            .map(|name| (name.to_string(), MokaManager::default()))
+            .collect();
+        let manager = DistributedManager::new(nodes.clone(), 2);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(key.clone(), http_res, policy).await?;
+
+        // Each entry is listed once, but its replicas take space on every node
+        let entries = manager.list().await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].cache_key, key);
+        assert_eq!(manager.stats().await?.entries, 2);
+
+        manager.clear().await?;
+        assert!(manager.list().await?.is_empty());
+        for (_, node) in &nodes {
+            assert!(node.get(&key).await?.is_none());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka")]
+mod with_replicated {
+    use super::*;
+    use crate::{
+        BoxError, CacheManager, MokaManager, ReplicatedManager,
+        ReplicationPolicy,
+    };
+
+    use http_cache_semantics::CachePolicy;
+    use std::io;
+
+    // Reads nothing and fails every write and delete
+    #[derive(Debug, Clone)]
+    struct DownManager;
+
+    #[async_trait::async_trait]
+    impl CacheManager for DownManager {
+        type Error = BoxError;
+
+        async fn get(
+            &self,
+            _cache_key: &str,
+        ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+            Ok(None)
+        }
+
+        async fn put(
+            &self,
+            _cache_key: String,
+            _response: HttpResponse,
+            _policy: CachePolicy,
+        ) -> Result<HttpResponse> {
+            Err(Box::new(io::Error::from(io::ErrorKind::ConnectionRefused)))
+        }
+
+        async fn delete(&self, _cache_key: &str) -> Result<()> {
+            Err(Box::new(io::Error::from(io::ErrorKind::ConnectionRefused)))
+        }
+    }
+
+    fn entry() -> Result<(HttpResponse, CachePolicy)> {
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        Ok((http_res, CachePolicy::new(&req, &res)))
+    }
+
+    #[async_attributes::test]
+    async fn replicated_policies() -> Result<()> {
+        let key = format!("{}:{}", GET, "http://example.com/");
+        let (http_res, policy) = entry()?;
+
+        // Either manager is enough by default
+        let manager =
+            ReplicatedManager::new(MokaManager::default(), DownManager);
+        assert_eq!(manager.policy(), ReplicationPolicy::Either);
+        manager.put(key.clone(), http_res.clone(), policy.clone()).await?;
+        manager.delete(&key).await?;
+        let manager =
+            ReplicatedManager::new(DownManager, MokaManager::default());
+        manager.put(key.clone(), http_res.clone(), policy.clone()).await?;
+
+        // Both managers have to succeed
+        let manager =
+            ReplicatedManager::new(MokaManager::default(), DownManager)
+                .with_policy(ReplicationPolicy::Both);
+        assert!(manager
+            .put(key.clone(), http_res.clone(), policy.clone())
+            .await
+            .is_err());
+        assert!(manager.delete(&key).await.is_err());
+
+        // Only the primary has to succeed
+        let manager =
+            ReplicatedManager::new(MokaManager::default(), DownManager)
+                .with_policy(ReplicationPolicy::Primary);
+        manager.put(key.clone(), http_res.clone(), policy.clone()).await?;
+        manager.delete(&key).await?;
+        let manager =
+            ReplicatedManager::new(DownManager, MokaManager::default())
+                .with_policy(ReplicationPolicy::Primary);
+        assert!(manager.put(key.clone(), http_res, policy).await.is_err());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn replicated_nested() -> Result<()> {
+        let key = format!("{}:{}", GET, "http://example.com/");
+        let (http_res, policy) = entry()?;
+        let manager = ReplicatedManager::new(
+            MokaManager::default(),
+            ReplicatedManager::new(
+                MokaManager::default(),
+                MokaManager::default(),
+            )
+            .with_policy(ReplicationPolicy::Both),
+        );
+        manager.put(key.clone(), http_res, policy).await?;
+        assert!(manager.primary().get(&key).await?.is_some());
+        assert!(manager.secondary().primary().get(&key).await?.is_some());
+        assert!(manager.secondary().secondary().get(&key).await?.is_some());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn replicated() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let manager = ReplicatedManager::new(
+            MokaManager::default(),
+            MokaManager::default(),
+        );
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // Writes reach both managers
+        assert!(manager.get(&key).await?.is_none());
+        manager.put(key.clone(), http_res, policy).await?;
+        assert!(manager.primary().get(&key).await?.is_some());
+        assert!(manager.secondary().get(&key).await?.is_some());
+
+        // Either manager can answer a read on its own
+        manager.primary().delete(&key).await?;
+        let data = manager.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        assert!(manager.secondary().get(&key).await?.is_none());
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "manager-moka", feature = "manager-cacache"))]
+mod with_tiered {
+    use super::*;
+    use crate::{CACacheManager, CacheManager, MokaManager, TieredManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    #[async_attributes::test]
+    async fn tiered() -> Result<()> {
+        let url = Url::parse("http://example.com/tiered")?;
+        let key = format!("{}:{}", GET, &url);
+        let manager = TieredManager::new(
+            MokaManager::default(),
+            CACacheManager {
+                path: "./http-cacache-tiered-test".into(),
+                inline_threshold: None,
+                npm_compat: false,
+                algorithm: None,
+                fsync: false,
+                max_bytes: None,
+                max_entries: None,
+                observer: None,
+            },
+        );
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com/tiered").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // Writes reach both managers
+        assert!(manager.get(&key).await?.is_none());
+        manager.put(key.clone(), http_res, policy).await?;
+        assert!(manager.front().get(&key).await?.is_some());
+        assert!(manager.back().get(&key).await?.is_some());
+
+        // A miss in the front is answered by the back and fills the front
+        manager.front().clear().await?;
+        let data = manager.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        assert!(manager.front().get(&key).await?.is_some());
+
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        assert!(manager.front().get(&key).await?.is_none());
+        manager.back().clear().await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka")]
+mod with_invalidation {
+    use super::*;
+    use crate::{
+        CacheManager, InvalidatingManager, InvalidationBus, MokaManager,
+    };
+
+    use async_std::channel::{unbounded, Receiver, Sender};
+    use http_cache_semantics::CachePolicy;
+
+    struct ChannelBus {
+        sender: Sender<String>,
+        receiver: Receiver<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl InvalidationBus for ChannelBus {
+        async fn publish(&self, cache_key: &str) -> Result<()> {
+            self.sender.send(cache_key.to_string()).await?;
+            Ok(())
+        }
+
+        async fn receive(&self) -> Result<Option<String>> {
+            Ok(self.receiver.recv().await.ok())
+        }
+    }
+
+    #[async_attributes::test]
+    async fn invalidation() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let (to_b, from_a) = unbounded();
+        let (to_a, from_b) = unbounded();
+        let a = InvalidatingManager::new(
+            MokaManager::default(),
+            ChannelBus { sender: to_b, receiver: from_b },
+        );
+        let b = InvalidatingManager::new(
+            MokaManager::default(),
+            ChannelBus { sender: to_a, receiver: from_a },
+        );
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        a.put(key.clone(), http_res.clone(), policy.clone()).await?;
+        b.put(key.clone(), http_res, policy).await?;
+
+        // A purge on one instance evicts the key on the other once received
+        a.delete(&key).await?;
+        assert!(a.get(&key).await?.is_none());
+        assert!(b.get(&key).await?.is_some());
+        let listener = async_std::task::spawn(b.clone().listen());
+        while b.get(&key).await?.is_some() {
+            async_std::task::yield_now().await;
+        }
+
+        // Closing the bus stops the listener
+        a.bus().sender.close();
+        listener.await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-moka")]
+mod with_negative_lookup {
+    use super::*;
+    use crate::{CacheManager, MokaManager, NegativeLookupManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    #[async_attributes::test]
+    async fn negative_lookup() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let inner = MokaManager::default();
+        let manager = NegativeLookupManager::new(inner.clone(), 1);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // A remembered miss doesn't reach the inner manager
+        assert!(manager.get(&key).await?.is_none());
+        inner.put(key.clone(), http_res.clone(), policy.clone()).await?;
+        assert!(manager.get(&key).await?.is_none());
+
+        // Once the key is evicted the inner manager is consulted again
+        assert!(manager.get("other").await?.is_none());
+        assert!(manager.get(&key).await?.is_some());
+
+        // Deleting remembers the key, putting forgets it
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        manager.put(key.clone(), http_res, policy).await?;
+        let data = manager.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "write-behind", feature = "manager-moka"))]
+mod with_write_behind {
+    use super::*;
+    use crate::{CacheManager, HttpCache, MokaManager, WriteBehindManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    #[async_attributes::test]
+    async fn write_behind() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let inner = MokaManager::default();
+        let (manager, worker) = WriteBehindManager::new(inner.clone(), 8);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // Queued writes are visible before the worker has applied them
+        manager.put(key.clone(), http_res, policy).await?;
+        assert!(inner.get(&key).await?.is_none());
+        let data = manager.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+
+        let worker = async_std::task::spawn(worker.run());
+        manager.flush().await?;
+        assert!(inner.get(&key).await?.is_some());
+
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        manager.shutdown().await?;
+        worker.await;
+        assert!(inner.get(&key).await?.is_none());
+        assert!(manager.delete(&key).await.is_err());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn clear() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let inner = MokaManager::default();
+        let (manager, worker) = WriteBehindManager::new(inner.clone(), 8);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let cache = HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        };
+
+        // The queued write is dropped along with the entries of the inner manager
+        inner.put("applied".into(), http_res.clone(), policy.clone()).await?;
+        manager.put(key.clone(), http_res, policy).await?;
+        let worker = async_std::task::spawn(worker.run());
+        cache.clear().await?;
+        manager.flush().await?;
+        assert!(manager.get(&key).await?.is_none());
+        assert!(inner.get(&key).await?.is_none());
+        assert!(inner.get("applied").await?.is_none());
+        assert_eq!(manager.stats().await?.entries, 0);
+        assert!(manager.list().await?.is_empty());
+        manager.shutdown().await?;
+        worker.await;
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn shutdown() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let inner = MokaManager::default();
+        let (manager, worker) = WriteBehindManager::new(inner.clone(), 8);
+        let worker = async_std::task::spawn(worker.run());
+        let cache = HttpCache {
+            mode: CacheMode::Default,
+            manager,
+            options: HttpCacheOptions::default(),
+        };
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        cache.manager.put(key.clone(), http_res, policy).await?;
+
+        // Shutting the cache down applies the queued write and stops the worker
+        cache.shutdown().await?;
+        worker.await;
+        assert!(inner.get(&key).await?.is_some());
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "write-retry", feature = "manager-moka"))]
+mod with_write_retry {
+    use super::*;
+    use crate::{
+        BoxError, CacheEvent, CacheManager, MokaManager, WriteRetry,
+        WriteRetryManager,
+    };
+
+    use http_cache_semantics::CachePolicy;
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    // Fails the first `failures` writes with an error of `kind`
+    #[derive(Clone)]
+    struct FlakyManager {
+        inner: MokaManager,
+        failures: Arc<AtomicU32>,
+        kind: io::ErrorKind,
+    }
+
+    #[async_trait::async_trait]
+    impl CacheManager for FlakyManager {
+        type Error = BoxError;
+
+        async fn get(
+            &self,
+            cache_key: &str,
+        ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+            self.inner.get(cache_key).await
+        }
+
+        async fn put(
+            &self,
+            cache_key: String,
+            response: HttpResponse,
+            policy: CachePolicy,
+        ) -> Result<HttpResponse> {
+            if self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| {
+                    failures.checked_sub(1)
+                })
+                .is_ok()
+            {
+                return Err(Box::new(io::Error::from(self.kind)));
+            }
+            self.inner.put(cache_key, response, policy).await
+        }
+
+        async fn delete(&self, cache_key: &str) -> Result<()> {
+            self.inner.delete(cache_key).await
+        }
+    }
+
+    #[async_attributes::test]
+    async fn write_retry() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observed = events.clone();
+        let retry = WriteRetry {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            sleep: Arc::new(|d| Box::pin(async_std::task::sleep(d))),
+            transient: None,
+            observer: Some(Arc::new(move |event: &CacheEvent| {
+                observed.lock().unwrap().push(event.clone());
+            })),
+        };
+        let flaky = FlakyManager {
+            inner: MokaManager::default(),
+            failures: Arc::new(AtomicU32::new(2)),
+            kind: io::ErrorKind::TimedOut,
+        };
+        let (manager, worker) =
+            WriteRetryManager::new(flaky.clone(), retry.clone(), 8);
+        async_std::task::spawn(worker.run());
+
+        // Transient failures are retried in the background
+        manager.put(key.clone(), http_res.clone(), policy.clone()).await?;
+        for _ in 0..100 {
+            if flaky.inner.get(&key).await?.is_some() {
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(manager.get(&key).await?.is_some());
+        assert!(events.lock().unwrap().is_empty());
+
+        // Until they're out of retries
+        manager.delete(&key).await?;
+        flaky.failures.store(3, Ordering::SeqCst);
+        manager.put(key.clone(), http_res.clone(), policy.clone()).await?;
+        for _ in 0..100 {
+            if !events.lock().unwrap().is_empty() {
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [CacheEvent::WriteFailed { cache_key: key.clone(), attempts: 3 }]
+        );
+        assert!(manager.get(&key).await?.is_none());
+        manager.shutdown().await?;
+
+        // Other errors are returned
+        let flaky = FlakyManager {
+            inner: MokaManager::default(),
+            failures: Arc::new(AtomicU32::new(1)),
+            kind: io::ErrorKind::PermissionDenied,
+        };
+        let (manager, _worker) = WriteRetryManager::new(flaky, retry, 8);
+        assert!(manager.put(key.clone(), http_res, policy).await.is_err());
+        assert_eq!(
+            events.lock().unwrap()[..],
+            [CacheEvent::WriteFailed { cache_key: key, attempts: 1 }]
+        );
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-postgres")]
+mod with_postgres {
+    use super::*;
+    use crate::{CacheManager, PostgresManager};
+
+    use http_cache_semantics::CachePolicy;
+    use std::time::Duration;
+
+    async fn connect() -> Result<tokio_postgres::Client> {
+        let config = std::env::var("HTTP_CACHE_POSTGRES_URL")?;
+        let (client, connection) =
+            tokio_postgres::connect(&config, tokio_postgres::NoTls).await?;
+        tokio::spawn(connection);
+        Ok(client)
+    }
+
+    // Needs a database, run with e.g.
+    // `HTTP_CACHE_POSTGRES_URL="host=localhost user=postgres" cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn postgres() -> Result<()> {
+        let manager = PostgresManager::new(connect().await?, "http_cache_test");
+        assert_eq!(
+            format!("{:?}", manager.clone()),
+            "PostgresManager { table: \"http_cache_test\", .. }"
+        );
+        manager.create_table().await?;
+        manager.clear().await?;
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: HeaderMap::from_iter([(
+                CACHE_CONTROL,
+                HeaderValue::from_static("max-age=60"),
+            )]),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res = http::Response::builder()
+            .status(200)
+            .header(CACHE_CONTROL, "max-age=60")
+            .body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(key.clone(), http_res, policy).await?;
+        let (data, policy) = manager.get(&key).await?.unwrap();
+        assert_eq!(data.body, TEST_BODY);
+        assert_eq!(data.headers.get("cache-control").unwrap(), "max-age=60");
+        assert_eq!(data.version, HttpVersion::Http11);
+        assert!(
+            policy.time_to_live(std::time::SystemTime::now()) > Duration::ZERO
+        );
+
+        // Still fresh, so not removed
+        assert_eq!(manager.delete_expired(Duration::ZERO).await?, 0);
+
+        // Several keys in one query, in the order they were asked for
+        let other = format!("{}:{}other", GET, &url);
+        manager.put(other.clone(), data, policy).await?;
+        let entries = manager.get_many(&[&other, "missing", &key]).await?;
+        assert!(entries[0].is_some());
+        assert!(entries[1].is_none());
+        assert!(entries[2].is_some());
+        manager.delete_many(&[&key, &other]).await?;
+        assert!(manager.get(&key).await?.is_none());
+        assert!(manager.get(&other).await?.is_none());
+        Ok(())
+    }
+
+    // Needs a database, as above
+    #[tokio::test]
+    #[ignore]
+    async fn postgres_format_version() -> Result<()> {
+        use crate::managers::format::FORMAT_VERSION;
+
+        let client = connect().await?;
+        let manager =
+            PostgresManager::new(connect().await?, "http_cache_format_test");
+        manager.create_table().await?;
+        manager.clear().await?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager
+            .put("unversioned".into(), http_res.clone(), policy.clone())
+            .await?;
+        manager.put("versioned".into(), http_res, policy.clone()).await?;
+
+        // Policies written before the format was versioned are read
+        client
+            .execute(
+                "UPDATE \"http_cache_format_test\" SET policy = $1
+                WHERE cache_key = 'unversioned'",
+                &[&bincode::serialize(&policy)?],
+            )
+            .await?;
+        let (response, _) = manager.get("unversioned").await?.unwrap();
+        assert_eq!(response.body, TEST_BODY);
+
+        // Policies written in another version are misses
+        let row = client
+            .query_one(
+                "SELECT policy FROM \"http_cache_format_test\"
+                WHERE cache_key = 'versioned'",
+                &[],
+            )
+            .await?;
+        let mut stored: Vec<u8> = row.try_get("policy")?;
+        assert_eq!(stored[8..12], FORMAT_VERSION.to_le_bytes());
+        stored[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        client
+            .execute(
+                "UPDATE \"http_cache_format_test\" SET policy = $1
+                WHERE cache_key = 'versioned'",
+                &[&stored],
+            )
+            .await?;
+        assert!(manager.get("versioned").await?.is_none());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-sled")]
+mod with_sled {
+    use super::*;
+    use crate::{CacheManager, SledManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    fn manager() -> Result<SledManager> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Ok(SledManager::new(db.open_tree("http-cache")?))
+    }
+
+    #[async_attributes::test]
+    async fn sled() -> Result<()> {
+        let manager = manager()?;
+        let url = Url::parse("http://example.com")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager
+            .put(format!("{}:{}", GET, &url), http_res.clone(), policy.clone())
+            .await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_some());
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        let clone = manager.clone();
+        let clonedata = clone.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(clonedata.is_some());
+        assert_eq!(clonedata.unwrap().0.body, TEST_BODY);
+        manager.flush().await?;
+        manager.delete(&format!("{}:{}", GET, &url)).await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_none());
+
+        manager.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+        manager.clear().await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn sled_corrupt_entry() -> Result<()> {
+        let manager = manager()?;
+        manager.tree.insert("corrupt", vec![0xff; 4])?;
+        let e = manager.get("corrupt").await.unwrap_err();
+        assert!(e.downcast_ref::<crate::CorruptEntry>().is_some());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn sled_format_version() -> Result<()> {
+        use crate::managers::format::FORMAT_VERSION;
+
+        let manager = manager()?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // Entries written before the format was versioned are read
+        let unversioned = bincode::serialize(&(&http_res, &policy))?;
+        manager.tree.insert("unversioned", unversioned)?;
+        let (response, _) = manager.get("unversioned").await?.unwrap();
+        assert_eq!(response.body, TEST_BODY);
+
+        // Entries written in another version are misses
+        manager.put("versioned".into(), http_res, policy).await?;
+        let mut stored = manager.tree.get("versioned")?.unwrap().to_vec();
+        assert_eq!(stored[8..12], FORMAT_VERSION.to_le_bytes());
+        stored[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        manager.tree.insert("versioned", stored)?;
+        assert!(manager.get("versioned").await?.is_none());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-object-store")]
+mod with_object_store {
+    use super::*;
+    use crate::{CacheManager, ObjectStoreManager};
+
+    use http_cache_semantics::CachePolicy;
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+    use std::sync::Arc;
+
+    fn entry(url: &Url) -> Result<(HttpResponse, CachePolicy)> {
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get(url.as_str()).body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        Ok((http_res, CachePolicy::new(&req, &res)))
+    }
+
+    #[async_attributes::test]
+    async fn object_store() -> Result<()> {
+        let manager =
+            ObjectStoreManager::new(Arc::new(InMemory::new()), "http-cache");
+        let url = Url::parse("http://example.com")?;
+        let (http_res, policy) = entry(&url)?;
+        manager
+            .put(format!("{}:{}", GET, &url), http_res.clone(), policy.clone())
+            .await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_some());
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        let clone = manager.clone();
+        let clonedata = clone.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(clonedata.is_some());
+        assert_eq!(clonedata.unwrap().0.body, TEST_BODY);
+        manager.delete(&format!("{}:{}", GET, &url)).await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_none());
+        // Deleting a missing entry is not an error
+        manager.delete(&format!("{}:{}", GET, &url)).await?;
+
+        manager.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+        manager.clear().await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn object_store_keys_do_not_collide() -> Result<()> {
+        let manager =
+            ObjectStoreManager::new(Arc::new(InMemory::new()), "http-cache");
+        let url = Url::parse("http://example.com/a/b")?;
+        let (http_res, policy) = entry(&url)?;
+        manager.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+        let data = manager.get("GET:http://example.com/a%2Fb").await?;
+        assert!(data.is_none());
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_some());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn object_store_clear_keeps_other_prefixes() -> Result<()> {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let manager = ObjectStoreManager::new(store.clone(), "caches/one");
+        let other = ObjectStoreManager::new(store.clone(), "caches/two");
+        let url = Url::parse("http://example.com")?;
+        let (http_res, policy) = entry(&url)?;
+        manager
+            .put(format!("{}:{}", GET, &url), http_res.clone(), policy.clone())
+            .await?;
+        other.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+        manager.clear().await?;
+        assert!(manager.get(&format!("{}:{}", GET, &url)).await?.is_none());
+        assert!(other.get(&format!("{}:{}", GET, &url)).await?.is_some());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn object_store_corrupt_entry() -> Result<()> {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let manager = ObjectStoreManager::new(store.clone(), "http-cache");
+        store
+            .put(&Path::from("http-cache/corrupt"), vec![0xff; 4].into())
+            .await?;
+        let e = manager.get("corrupt").await.unwrap_err();
+        assert!(e.downcast_ref::<crate::CorruptEntry>().is_some());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "manager-foyer")]
+mod with_foyer {
+    use super::*;
+    use crate::{CacheManager, FoyerManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    const MB: usize = 1024 * 1024;
+
+    #[tokio::test]
+    async fn foyer() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let manager = FoyerManager::open(dir, 4 * MB, 64 * MB).await?;
+        let url = Url::parse("http://example.com")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        manager
+            .put(format!("{}:{}", GET, &url), http_res.clone(), policy.clone())
+            .await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_some());
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        let clone = manager.clone();
+        let clonedata = clone.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(clonedata.is_some());
+        assert_eq!(clonedata.unwrap().0.body, TEST_BODY);
+        manager.delete(&format!("{}:{}", GET, &url)).await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_none());
+
+        manager.put(format!("{}:{}", GET, &url), http_res, policy).await?;
+        manager.clear().await?;
+        let data = manager.get(&format!("{}:{}", GET, &url)).await?;
+        assert!(data.is_none());
+        manager.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn foyer_reopen() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // Entries are read back from disk
+        let manager = FoyerManager::open(dir, 4 * MB, 64 * MB).await?;
+        manager.put(key.clone(), http_res, policy).await?;
+        manager.shutdown().await?;
+        let manager = FoyerManager::open(dir, 4 * MB, 64 * MB).await?;
+        let data = manager.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+
+        // Deleted entries stay deleted
+        manager.delete(&key).await?;
+        manager.shutdown().await?;
+        let manager = FoyerManager::open(dir, 4 * MB, 64 * MB).await?;
+        assert!(manager.get(&key).await?.is_none());
+        manager.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn foyer_corrupt_entry() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let manager = FoyerManager::open(dir, 4 * MB, 64 * MB).await?;
+        manager.cache.insert("corrupt".into(), vec![0xff; 4]);
+        let e = manager.get("corrupt").await.unwrap_err();
+        assert!(e.downcast_ref::<crate::CorruptEntry>().is_some());
+        manager.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "encryption", feature = "manager-moka"))]
+mod with_encryption {
+    use super::*;
+    use crate::{CacheManager, CorruptEntry, EncryptedManager, MokaManager};
+
+    use http_cache_semantics::CachePolicy;
+    use std::time::{Duration, SystemTime};
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn entry() -> Result<(HttpResponse, CachePolicy)> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-account", HeaderValue::from_static("secret"));
+        headers.insert("cache-control", HeaderValue::from_static("max-age=60"));
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers,
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res = http::Response::builder()
+            .header("x-account", "secret")
+            .header("cache-control", "max-age=60")
+            .body(())?;
+        Ok((http_res, CachePolicy::new(&req, &res)))
+    }
+
+    #[async_attributes::test]
+    async fn encrypted() -> Result<()> {
+        let key = format!("{}:{}", GET, "http://example.com/");
+        let manager = EncryptedManager::new(MokaManager::default(), &KEY);
+        let (http_res, policy) = entry()?;
+        let stored = manager.put(key.clone(), http_res.clone(), policy).await?;
+        assert_eq!(stored.body, TEST_BODY);
+
+        // The inner manager only sees the ciphertext and when it goes stale
+        let (sealed, stub) = manager.inner().get(&key).await?.unwrap();
+        assert!(sealed.headers.is_empty());
+        assert!(!sealed.body.windows(TEST_BODY.len()).any(|w| w == TEST_BODY));
+        assert!(!sealed.body.windows(6).any(|w| w == b"secret"));
+        assert!(!serde_json::to_string(&stub)?.contains("secret"));
+        assert!(stub.time_to_live(SystemTime::now()) > Duration::ZERO);
+
+        let (res, policy) = manager.get(&key).await?.unwrap();
+        assert_eq!(res.body, TEST_BODY);
+        assert_eq!(res.headers["x-account"], "secret");
+        assert!(serde_json::to_string(&policy)?.contains("secret"));
+
+        manager.delete(&key).await?;
+        assert!(manager.get(&key).await?.is_none());
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn encrypted_rejects_other_keys() -> Result<()> {
+        let key = format!("{}:{}", GET, "http://example.com/");
+        let inner = MokaManager::default();
+        let manager = EncryptedManager::new(inner.clone(), &KEY);
+        let (http_res, policy) = entry()?;
+        manager.put(key.clone(), http_res, policy).await?;
+
+        // Encrypted with another key
+        let other = EncryptedManager::new(inner.clone(), &[8; 32]);
+        let e = other.get(&key).await.unwrap_err();
+        assert!(e.downcast_ref::<CorruptEntry>().is_some());
+
+        // Moved to another cache key
+        let (sealed, stub) = inner.get(&key).await?.unwrap();
+        inner.put("GET:http://example.com/other".into(), sealed, stub).await?;
+        let e = manager.get("GET:http://example.com/other").await.unwrap_err();
+        assert!(e.downcast_ref::<CorruptEntry>().is_some());
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    feature = "manager-moka",
+    any(feature = "compression-zstd", feature = "compression-brotli")
+))]
+mod with_compression {
+    use super::*;
+    use crate::{CacheManager, CompressedManager, Compression, MokaManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    fn entry(body: Vec<u8>) -> Result<(HttpResponse, CachePolicy)> {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/html"));
+        let http_res = HttpResponse {
+            body: Bytes::from(body),
+            headers,
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res = http::Response::builder().status(200).body(())?;
+        Ok((http_res, CachePolicy::new(&req, &res)))
+    }
+
+    fn compressions() -> Vec<Compression> {
+        vec![
+            #[cfg(feature = "compression-zstd")]
+            Compression::Zstd(0),
+            #[cfg(feature = "compression-brotli")]
+            Compression::Brotli(5),
+        ]
+    }
+
+    #[async_attributes::test]
+    async fn compressed() -> Result<()> {
+        let key = format!("{}:{}", GET, "http://example.com/");
+        let body = b"<p>hello</p>".repeat(200);
+        for compression in compressions() {
+            let manager =
+                CompressedManager::new(MokaManager::default(), compression);
+            let (http_res, policy) = entry(body.clone())?;
+            let stored = manager.put(key.clone(), http_res, policy).await?;
+            assert_eq!(stored.body, body);
+
+            // The inner manager holds the smaller body
+            let (inner, _) = manager.inner().get(&key).await?.unwrap();
+            assert!(inner.body.len() < body.len() / 4);
+
+            let (res, _) = manager.get(&key).await?.unwrap();
+            assert_eq!(res.body, body);
+            assert_eq!(res.headers["content-type"], "text/html");
+            assert_eq!(res.headers.len(), 1);
+        }
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn compressed_threshold() -> Result<()> {
+        let key = format!("{}:{}", GET, "http://example.com/");
+        for compression in compressions() {
+            let manager =
+                CompressedManager::new(MokaManager::default(), compression)
+                    .with_threshold(64 * 1024);
+            let (http_res, policy) = entry(b"<p>hello</p>".repeat(200))?;
+            manager.put(key.clone(), http_res.clone(), policy).await?;
+            let (inner, _) = manager.inner().get(&key).await?.unwrap();
+            assert_eq!(inner.body, http_res.body);
+            assert_eq!(manager.get(&key).await?.unwrap().0.body, http_res.body);
+        }
+        Ok(())
+    }
+
+    #[async_attributes::test]
+    async fn compressed_corrupt_entry() -> Result<()> {
+        let key = format!("{}:{}", GET, "http://example.com/");
+        let inner = MokaManager::default();
+        let manager = CompressedManager::new(inner.clone(), compressions()[0]);
+        let names = [
+            #[cfg(feature = "compression-zstd")]
+            "zstd",
+            #[cfg(feature = "compression-brotli")]
+            "br",
+            "lz4",
+        ];
+        for name in names {
+            let (mut http_res, policy) = entry(vec![0xff; 64])?;
+            http_res.headers.insert(
+                "x-http-cache-compression",
+                HeaderValue::from_static(name),
+            );
+            inner.put(key.clone(), http_res, policy).await?;
+            let e = manager.get(&key).await.unwrap_err();
+            assert!(e.downcast_ref::<crate::CorruptEntry>().is_some());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "metrics", feature = "manager-moka"))]
+mod with_metrics {
+    use super::*;
+    use crate::{
+        BoxError, CacheManager, MeteredManager, MokaManager, DURATION_METRIC,
+        OPERATIONS_METRIC,
+    };
+
+    use http_cache_semantics::CachePolicy;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+
+    // The value of the operations counter for `operation` and `outcome`
+    fn operations(
+        snapshotter: &Snapshotter,
+        operation: &str,
+        outcome: &str,
+    ) -> u64 {
+        snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .find_map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels: Vec<_> = key
+                    .labels()
+                    .map(|label| (label.key(), label.value()))
+                    .collect();
+                let matches = key.name() == OPERATIONS_METRIC
+                    && labels.contains(&("manager", "memory"))
+                    && labels.contains(&("operation", operation))
+                    && labels.contains(&("outcome", outcome));
+                match value {
+                    DebugValue::Counter(count) if matches => Some(count),
+                    _ => None,
+                }
+            })
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn metered() -> Result<()> {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let key = format!("{}:{}", GET, "http://example.com/");
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        metrics::with_local_recorder(&recorder, || {
+            async_std::task::block_on(async {
+                let manager =
+                    MeteredManager::new(MokaManager::default(), "memory");
+                assert!(manager.get(&key).await?.is_none());
+                manager.put(key.clone(), http_res, policy).await?;
+                assert!(manager.get(&key).await?.is_some());
+                assert!(manager.get(&key).await?.is_some());
+                manager.delete(&key).await?;
+                Ok::<_, BoxError>(())
+            })
+        })?;
+
+        // Snapshots drain the histograms, so they are checked first
+        let durations: usize = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, ..)| key.key().name() == DURATION_METRIC)
+            .map(|(.., value)| match value {
+                DebugValue::Histogram(values) => values.len(),
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(durations, 5);
+        assert_eq!(operations(&snapshotter, "get", "hit"), 2);
+        assert_eq!(operations(&snapshotter, "get", "miss"), 1);
+        assert_eq!(operations(&snapshotter, "put", "ok"), 1);
+        assert_eq!(operations(&snapshotter, "delete", "ok"), 1);
+        assert_eq!(operations(&snapshotter, "get", "error"), 0);
+        Ok(())
+    }
+}
+
+mod with_mock {
+    use super::*;
+    use crate::{CacheManager, MockCall, MockManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    #[async_attributes::test]
+    async fn mock() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+        let seeded = format!("{}:{}", GET, &url);
+        let key = format!("{}:{}/other", GET, &url);
+
+        // Seeded entries are served without recording a call
+        let manager = MockManager::new().with_entry(
+            seeded.clone(),
+            http_res.clone(),
+            policy.clone(),
+        );
+        assert!(manager.calls().is_empty());
+        let handle = manager.clone();
+        let data = manager.get(&seeded).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        assert!(manager.get(&key).await?.is_none());
+        manager.put(key.clone(), http_res, policy).await?;
+        assert!(handle.entry(&key).is_some());
+        manager.delete(&seeded).await?;
+        assert!(handle.entry(&seeded).is_none());
+        assert_eq!(handle.keys(), vec![key.clone()]);
+
+        // The calls are shared with clones, in order
+        let puts = handle.puts();
+        assert_eq!(puts.len(), 1);
+        assert_eq!(puts[0].0, key);
+        assert_eq!(puts[0].1.body, TEST_BODY);
+        let calls = handle.take_calls();
+        assert!(matches!(
+            &calls[..],
+            [
+                MockCall::Get(_),
+                MockCall::Get(_),
+                MockCall::Put(_, _),
+                MockCall::Delete(_)
+            ]
+        ));
+        let keys: Vec<&str> = calls.iter().map(MockCall::key).collect();
+        assert_eq!(keys, [&seeded, &key, &key, &seeded]);
+        assert!(manager.calls().is_empty());
+        Ok(())
+    }
+}
+
+mod with_namespaced {
+    use super::*;
+    use crate::{CacheManager, MockManager, NamespacedManager};
+
+    use http_cache_semantics::CachePolicy;
+
+    #[async_attributes::test]
+    async fn namespaced() -> Result<()> {
+        let url = Url::parse("http://example.com")?;
+        let key = format!("{}:{}", GET, &url);
+        let inner = MockManager::new();
+        let prod = NamespacedManager::new(inner.clone(), "prod");
+        let staging = NamespacedManager::new(inner.clone(), "staging");
+        assert_eq!(prod.namespace(), "prod");
+        let http_res = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: Default::default(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        let req = http::Request::get("http://example.com").body(())?;
+        let res =
+            http::Response::builder().status(200).body(TEST_BODY.to_vec())?;
+        let policy = CachePolicy::new(&req, &res);
+
+        // The same key is stored apart in each namespace
+        prod.put(key.clone(), http_res, policy).await?;
+        assert!(inner.entry(&format!("prod:{key}")).is_some());
+        assert!(inner.entry(&key).is_none());
+        let data = prod.get(&key).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        assert!(staging.get(&key).await?.is_none());
+
+        // Deleting from one namespace leaves the others alone
+        staging.delete(&key).await?;
+        assert!(prod.get(&key).await?.is_some());
+        prod.delete(&key).await?;
+        assert!(prod.get(&key).await?.is_none());
+        assert!(inner.keys().is_empty());
+        Ok(())
+    }
+}
+
+mod with_blocking {
+    use super::*;
+    use crate::{HttpCache, MockCall, MockManager};
+
+    use std::sync::Arc;
+
+    fn response() -> Result<HttpResponse> {
+        Ok(HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: HeaderMap::from_iter([(
+                CACHE_CONTROL,
+                HeaderValue::from_static("max-age=86400, public"),
+            )]),
+            status: 200,
+            url: Url::parse("http://example.com")?,
+            version: HttpVersion::Http11,
+        })
+    }
+
+    #[test]
+    fn run_blocking() -> Result<()> {
+        let manager = MockManager::new();
+        let cache = HttpCache {
+            mode: CacheMode::Default,
+            manager: Arc::new(manager.clone()),
+            options: HttpCacheOptions::default(),
+        };
+        let parts = || -> Result<http::request::Parts> {
+            Ok(http::Request::get("http://example.com")
+                .body(())?
+                .into_parts()
+                .0)
+        };
+
+        // Fetched and stored on a miss
+        let res = cache.run_blocking(parts()?, |_| response())?;
+        assert_eq!(res.body, TEST_BODY);
+        assert_eq!(res.headers["x-cache"], HitOrMiss::MISS.to_string());
+        assert_eq!(manager.puts().len(), 1);
+
+        // Served from the manager on a hit
+        let res = cache.run_blocking(parts()?, |_| {
+            unreachable!("served from the cache")
+        })?;
+        assert_eq!(res.headers["x-cache"], HitOrMiss::HIT.to_string());
+        assert!(matches!(manager.calls().last(), Some(MockCall::Get(_))));
+
+        // A failed fetch without a cached response is reported
+        let cache = HttpCache { mode: CacheMode::NoStore, ..cache };
+        let e = cache
+            .run_blocking(parts()?, |_| Err("connection refused".into()))
+            .unwrap_err();
+        assert_eq!(e.to_string(), "connection refused");
+        Ok(())
+    }
 }
@@ -0,0 +1,606 @@
+use crate::{
+    disconnected_hit, stored_hit, BlockingCacheManager, BoxError,
+    CacheDecision, CacheEvent, CacheManager, CacheMode, CacheState, Divergence,
+    HitOrMiss, HttpCache, HttpResponse, Result, Revalidated, Revalidation,
+    UnexpectedEvent,
+};
+
+use std::{
+    mem,
+    time::{Instant, SystemTime},
+};
+
+use http::{
+    header::{HeaderValue, CACHE_CONTROL},
+    request, HeaderMap, Method,
+};
+use http_cache_semantics::{BeforeRequest, CachePolicy};
+use url::Url;
+
+/// What a [`CacheTransaction`] needs done next. Perform it and pass the outcome to
+/// [`CacheTransaction::next`] as the matching [`Event`].
+// Each step is moved once, boxing the policy would only add an allocation
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+#[must_use]
+pub enum Step {
+    /// Delete the entry from the manager, then send [`Event::Deleted`].
+    Delete(String),
+    /// Look the entry up in the manager, then send [`Event::LookedUp`].
+    Lookup(String),
+    /// Send the request upstream with these headers instead of its own, then send
+    /// [`Event::Fetched`] or [`Event::FetchFailed`].
+    Fetch(HeaderMap),
+    /// Store the entry in the manager, then send [`Event::Stored`].
+    Store {
+        /// The key to store the entry under
+        cache_key: String,
+        /// The response to store
+        response: HttpResponse,
+        /// The policy to store with it
+        policy: CachePolicy,
+    },
+    /// Return this response to the client, the transaction is finished.
+    Respond(HttpResponse),
+}
+
+/// The outcome of a [`Step`], passed to [`CacheTransaction::next`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Event {
+    /// The entry of a [`Step::Delete`] was deleted
+    Deleted,
+    /// The entry of a [`Step::Lookup`], if there is one
+    LookedUp(Option<(HttpResponse, CachePolicy)>),
+    /// The response to a [`Step::Fetch`]
+    Fetched(HttpResponse),
+    /// The request of a [`Step::Fetch`] failed
+    FetchFailed(BoxError),
+    /// The response returned by the manager for a [`Step::Store`]
+    Stored(HttpResponse),
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum State {
+    Start,
+    // Deletes the keys in `deletes`, then fetches without the cache
+    Bypass,
+    // Deletes the keys in `deletes`, then looks up the entry
+    Busting,
+    // Returns the response without storing it
+    PassingThrough,
+    LookingUp,
+    Fetching {
+        lookup: HitOrMiss,
+        started: Instant,
+        // The fresh stored response to compare the fetched one with in shadow mode
+        shadowed: Option<HttpResponse>,
+    },
+    Revalidating {
+        cached: HttpResponse,
+        policy: CachePolicy,
+        started: Instant,
+    },
+    // Returns `served` once stored, or what the manager returns without it
+    Storing {
+        served: Option<HttpResponse>,
+        lookup: Option<HitOrMiss>,
+        reason: Option<CacheDecision>,
+    },
+    // Returns the response once the keys in `deletes` are deleted
+    Invalidating {
+        response: HttpResponse,
+    },
+    Done,
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Bypass => "bypass",
+            Self::Busting => "busting",
+            Self::PassingThrough => "passing-through",
+            Self::LookingUp => "looking-up",
+            Self::Fetching { .. } => "fetching",
+            Self::Revalidating { .. } => "revalidating",
+            Self::Storing { .. } => "storing",
+            Self::Invalidating { .. } => "invalidating",
+            Self::Done => "done",
+        }
+    }
+}
+
+/// The cache logic of [`HttpCache::run`] for one request as a state machine that performs
+/// no IO itself, for integrations that can't implement [`Middleware`](crate::Middleware),
+/// such as custom executors, io_uring clients or proxies. Create one with
+/// [`HttpCache::transaction`], call [`start`](Self::start) and then pass the outcome of every
+/// [`Step`] to [`next`](Self::next) until it returns [`Step::Respond`]. Responses and
+/// policies are moved through the steps, never cloned.
+///
+/// ```
+/// # use http_cache::{CacheManager, Event, HttpCache, HttpResponse, Result, Step};
+/// # async fn send(headers: http::HeaderMap) -> Result<HttpResponse> { unimplemented!() }
+/// # async fn example(cache: HttpCache<impl CacheManager>, parts: http::request::Parts) -> Result<HttpResponse> {
+/// let mut transaction = cache.transaction(parts);
+/// let mut step = transaction.start()?;
+/// loop {
+///     let event = match step {
+///         Step::Delete(key) => {
+///             cache.manager.delete(&key).await.map_err(Into::into)?;
+///             Event::Deleted
+///         }
+///         Step::Lookup(key) => {
+///             Event::LookedUp(cache.manager.get(&key).await.map_err(Into::into)?)
+///         }
+///         Step::Fetch(headers) => match send(headers).await {
+///             Ok(response) => Event::Fetched(response),
+///             Err(e) => Event::FetchFailed(e),
+///         },
+///         Step::Store { cache_key, response, policy } => Event::Stored(
+///             cache.manager.put(cache_key, response, policy).await.map_err(Into::into)?,
+///         ),
+///         Step::Respond(response) => return Ok(response),
+///     };
+///     step = transaction.next(event)?;
+/// }
+/// # }
+/// ```
+///
+/// The decisions are the ones [`HttpCache::run`] makes, except for the options that need a
+/// timer or more than one entry of their own, which aren't applied:
+/// [`StaleOnTimeout`](crate::StaleOnTimeout),
+/// [`assemble_partial`](crate::HttpCacheOptions::assemble_partial) and
+/// [`store_variants`](crate::HttpCacheOptions::store_variants).
+#[derive(Debug)]
+pub struct CacheTransaction<'a, T: CacheManager> {
+    cache: &'a HttpCache<T>,
+    mode: CacheMode,
+    parts: request::Parts,
+    cache_key: String,
+    deletes: Vec<String>,
+    state: State,
+}
+
+impl<T: CacheManager> HttpCache<T> {
+    /// Starts a [`CacheTransaction`] for the request described by `parts`.
+    pub fn transaction(
+        &self,
+        parts: request::Parts,
+    ) -> CacheTransaction<'_, T> {
+        CacheTransaction {
+            cache: self,
+            mode: self.cache_mode(&parts),
+            cache_key: self.options.create_cache_key(&parts, None),
+            parts,
+            deletes: Vec::new(),
+            state: State::Start,
+        }
+    }
+}
+
+impl<T: BlockingCacheManager> HttpCache<T> {
+    /// Runs the cache for the request described by `parts` on the calling thread, for
+    /// synchronous clients that don't run an async runtime. Drives a [`CacheTransaction`]
+    /// with the blocking methods of the manager, calling `fetch` with the headers to send
+    /// when the request has to go upstream, at most once. A failed fetch may still be
+    /// answered from the cache, e.g. with a stale response.
+    pub fn run_blocking(
+        &self,
+        parts: request::Parts,
+        fetch: impl FnOnce(HeaderMap) -> Result<HttpResponse>,
+    ) -> Result<HttpResponse> {
+        let mut fetch = Some(fetch);
+        let mut transaction = self.transaction(parts);
+        let mut step = transaction.start()?;
+        loop {
+            let event = match step {
+                Step::Delete(key) => {
+                    self.manager.delete_blocking(&key).map_err(Into::into)?;
+                    Event::Deleted
+                }
+                Step::Lookup(key) => Event::LookedUp(
+                    self.manager.get_blocking(&key).map_err(Into::into)?,
+                ),
+                Step::Fetch(headers) => {
+                    // The transaction fetches at most once
+                    let fetch = fetch.take().ok_or("request already sent")?;
+                    match fetch(headers) {
+                        Ok(response) => Event::Fetched(response),
+                        Err(e) => Event::FetchFailed(e),
+                    }
+                }
+                Step::Store { cache_key, response, policy } => Event::Stored(
+                    self.manager
+                        .put_blocking(cache_key, response, policy)
+                        .map_err(Into::into)?,
+                ),
+                Step::Respond(response) => return Ok(response),
+            };
+            step = transaction.next(event)?;
+        }
+    }
+}
+
+impl<T: CacheManager> CacheTransaction<'_, T> {
+    /// Returns the first step of the transaction. Fails if it was already started.
+    pub fn start(&mut self) -> Result<Step> {
+        if !matches!(self.state, State::Start) {
+            return Err(Box::new(UnexpectedEvent::new("start", &self.state)));
+        }
+        let is_cacheable = self.mode == CacheMode::IgnoreRules
+            || self.is_get_head()
+                && self.mode != CacheMode::NoStore
+                && self.mode != CacheMode::Reload;
+        if is_cacheable {
+            self.deletes = self.cache.keys_to_delete(&self.parts);
+            self.state = State::Busting;
+        } else {
+            self.deletes = self.cache.keys_to_delete_bypassing(&self.parts);
+            self.state = State::Bypass;
+        }
+        self.advance()
+    }
+
+    /// Returns the step following `event`, the outcome of the previous step. Fails if the
+    /// event doesn't answer the previous step, or if the response headers are invalid.
+    pub fn next(&mut self, event: Event) -> Result<Step> {
+        match (mem::replace(&mut self.state, State::Done), event) {
+            (
+                state @ (State::Bypass
+                | State::Busting
+                | State::Invalidating { .. }),
+                Event::Deleted,
+            ) => {
+                self.state = state;
+                self.advance()
+            }
+            (State::PassingThrough, Event::Fetched(mut response)) => {
+                if self.cache.options.cache_state_header {
+                    response.cache_state(CacheState::Bypass);
+                } else {
+                    response.cache_status(HitOrMiss::MISS);
+                }
+                response.cache_lookup_status(HitOrMiss::MISS);
+                if self.cache.options.cache_reason_header {
+                    let decision = self.cache.explain(&self.parts, None)?;
+                    response.cache_reason(decision);
+                }
+                Ok(Step::Respond(response))
+            }
+            (State::LookingUp, Event::LookedUp(stored)) => {
+                self.looked_up(stored)
+            }
+            (
+                State::Fetching { lookup, started, shadowed },
+                Event::Fetched(response),
+            ) => self.fetched(response, lookup, started, shadowed),
+            (
+                State::Revalidating { cached, policy, started },
+                Event::Fetched(response),
+            ) => self.revalidated(cached, policy, started, response),
+            (State::Revalidating { cached, .. }, Event::FetchFailed(e)) => {
+                let response =
+                    self.cache.revalidation_failed(&self.url()?, cached, e)?;
+                self.respond(response)
+            }
+            (_, Event::FetchFailed(e)) => Err(e),
+            (
+                State::Storing { served, lookup, reason },
+                Event::Stored(stored),
+            ) => self.respond_stored(served.unwrap_or(stored), lookup, reason),
+            (state, event) => {
+                let error = UnexpectedEvent::new(event.name(), &state);
+                self.state = state;
+                Err(Box::new(error))
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Result<Step> {
+        if let Some(cache_key) = self.deletes.pop() {
+            return Ok(Step::Delete(cache_key));
+        }
+        match mem::replace(&mut self.state, State::Done) {
+            State::Bypass => {
+                let mut headers = self.parts.headers.clone();
+                if let Some(refresh_header) = &self.cache.options.refresh_header
+                {
+                    headers.remove(refresh_header);
+                }
+                self.state = State::PassingThrough;
+                Ok(Step::Fetch(headers))
+            }
+            State::Busting => {
+                self.state = State::LookingUp;
+                Ok(Step::Lookup(self.cache_key.clone()))
+            }
+            State::Invalidating { response } => self.respond(response),
+            state => Err(Box::new(UnexpectedEvent::new("deleted", &state))),
+        }
+    }
+
+    fn looked_up(
+        &mut self,
+        stored: Option<(HttpResponse, CachePolicy)>,
+    ) -> Result<Step> {
+        self.cache.observe_lookup(
+            &self.cache_key,
+            &self.parts,
+            stored.as_ref(),
+        );
+        let Some((mut cached, policy)) = stored else {
+            if self.mode == CacheMode::OnlyIfCached {
+                return self.respond(self.cache.not_cached(self.url()?));
+            }
+            return Ok(self.fetch(HitOrMiss::MISS, None));
+        };
+        stored_hit(&mut cached);
+        match self.mode {
+            CacheMode::Default => self.conditional(cached, policy),
+            CacheMode::Shadow => {
+                // Only compare responses that would have been served without the network
+                let parts = self.cache.options.variant_parts(&self.parts)?;
+                let fresh = matches!(
+                    policy.before_request(&parts, SystemTime::now()),
+                    BeforeRequest::Fresh(_)
+                );
+                Ok(self.fetch(HitOrMiss::MISS, fresh.then_some(cached)))
+            }
+            CacheMode::NoCache => {
+                let mut step = self.fetch(HitOrMiss::HIT, None);
+                if let Step::Fetch(headers) = &mut step {
+                    headers.insert(
+                        CACHE_CONTROL,
+                        HeaderValue::from_static("no-cache"),
+                    );
+                }
+                Ok(step)
+            }
+            CacheMode::ForceCache
+            | CacheMode::OnlyIfCached
+            | CacheMode::IgnoreRules => self.respond(disconnected_hit(cached)),
+            CacheMode::ReadOnly => {
+                match self.cache.read_only_hit(&self.parts, cached, &policy)? {
+                    Some(response) => self.respond(response),
+                    None => Ok(self.fetch(HitOrMiss::HIT, None)),
+                }
+            }
+            _ => Ok(self.fetch(HitOrMiss::MISS, None)),
+        }
+    }
+
+    // Fetches the request with its own headers
+    fn fetch(
+        &mut self,
+        lookup: HitOrMiss,
+        shadowed: Option<HttpResponse>,
+    ) -> Step {
+        self.state =
+            State::Fetching { lookup, started: Instant::now(), shadowed };
+        Step::Fetch(self.parts.headers.clone())
+    }
+
+    fn conditional(
+        &mut self,
+        cached: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<Step> {
+        let url = self.url()?;
+        match self.cache.plan_revalidation(
+            &self.parts,
+            &url,
+            cached,
+            &policy,
+        )? {
+            Revalidation::Serve(response) => self.respond(response),
+            Revalidation::Fetch { cached, parts } => {
+                let headers = match parts {
+                    Some(parts) => parts.headers,
+                    None => self.parts.headers.clone(),
+                };
+                self.state = State::Revalidating {
+                    cached,
+                    policy,
+                    started: Instant::now(),
+                };
+                Ok(Step::Fetch(headers))
+            }
+        }
+    }
+
+    fn fetched(
+        &mut self,
+        mut response: HttpResponse,
+        lookup: HitOrMiss,
+        started: Instant,
+        shadowed: Option<HttpResponse>,
+    ) -> Result<Step> {
+        let fetch_latency = started.elapsed();
+        let options = &self.cache.options;
+        options.prepare_response(&self.parts, &mut response);
+        if self.mode == CacheMode::ReadOnly && matches!(lookup, HitOrMiss::HIT)
+        {
+            // The stored response was stale
+            response.cache_state(CacheState::Expired);
+        } else {
+            response.cache_status(HitOrMiss::MISS);
+        }
+        response.cache_lookup_status(HitOrMiss::MISS);
+        if let Some(cached) = shadowed {
+            options.observe(|| CacheEvent::ShadowCompared {
+                cache_key: self.cache_key.clone(),
+                divergences: Divergence::compare(&cached, &response),
+            });
+        }
+        let qualified = options.qualified_directives(&response);
+        let policy = self.policy(qualified.as_ref().unwrap_or(&response))?;
+        if self.cache.storable(self.mode, &self.parts, &response, &policy) {
+            if !self.cache.admit(
+                &self.parts,
+                &self.cache_key,
+                &mut response,
+                fetch_latency,
+            )? {
+                return self.respond_fetched(response, lookup);
+            }
+            self.store(response, policy, true, Some(lookup), None)
+        } else if !self.is_get_head() {
+            self.deletes
+                .push(options.create_cache_key(&self.parts, Some("GET")));
+            response.cache_lookup_status(lookup);
+            self.state = State::Invalidating { response };
+            self.advance()
+        } else {
+            self.respond_fetched(response, lookup)
+        }
+    }
+
+    fn revalidated(
+        &mut self,
+        cached: HttpResponse,
+        policy: CachePolicy,
+        started: Instant,
+        response: HttpResponse,
+    ) -> Result<Step> {
+        let fetch_latency = started.elapsed();
+        let url = self.url()?;
+        match self.cache.revalidated(
+            &self.parts,
+            &url,
+            cached,
+            policy,
+            response,
+        )? {
+            Revalidated::Serve(response) => self.respond(response),
+            Revalidated::NotModified(cached, policy) => self.store(
+                cached,
+                policy,
+                false,
+                None,
+                Some(CacheDecision::Revalidated),
+            ),
+            Revalidated::Modified(mut response) => {
+                let options = &self.cache.options;
+                let qualified = options.qualified_directives(&response);
+                let policy =
+                    self.policy(qualified.as_ref().unwrap_or(&response))?;
+                if !self.cache.storable(
+                    self.mode,
+                    &self.parts,
+                    &response,
+                    &policy,
+                ) || !self.cache.admit(
+                    &self.parts,
+                    &self.cache_key,
+                    &mut response,
+                    fetch_latency,
+                )? {
+                    return self.respond(response);
+                }
+                self.store(response, policy, true, None, None)
+            }
+        }
+    }
+
+    // With `qualified`, stores the response without the fields listed by qualified
+    // `private` and `no-cache` directives, but returns them to this request. The lookup
+    // status and reason are set on the returned response only.
+    fn store(
+        &mut self,
+        response: HttpResponse,
+        policy: CachePolicy,
+        qualified: bool,
+        lookup: Option<HitOrMiss>,
+        reason: Option<CacheDecision>,
+    ) -> Result<Step> {
+        let options = &self.cache.options;
+        let cache_key = self.cache_key.clone();
+        let (stored, served) = match qualified
+            .then(|| options.qualified_directives(&response))
+            .flatten()
+        {
+            Some(stored) => (stored, Some(response)),
+            None => (response, None),
+        };
+        if !options.headers_allowed(&cache_key, &stored) {
+            return self.respond_stored(
+                served.unwrap_or(stored),
+                lookup,
+                reason,
+            );
+        }
+        options.observe(|| CacheEvent::Stored {
+            cache_key: cache_key.clone(),
+            body_size: stored.body.len(),
+        });
+        self.state = State::Storing { served, lookup, reason };
+        Ok(Step::Store { cache_key, response: stored, policy })
+    }
+
+    fn respond_stored(
+        &mut self,
+        mut response: HttpResponse,
+        lookup: Option<HitOrMiss>,
+        reason: Option<CacheDecision>,
+    ) -> Result<Step> {
+        if let Some(lookup) = lookup {
+            response.cache_lookup_status(lookup);
+        }
+        if let Some(reason) = reason {
+            self.cache.set_cache_reason(&mut response, reason);
+        }
+        self.respond(response)
+    }
+
+    fn respond_fetched(
+        &mut self,
+        mut response: HttpResponse,
+        lookup: HitOrMiss,
+    ) -> Result<Step> {
+        response.cache_lookup_status(lookup);
+        self.respond(response)
+    }
+
+    fn respond(&mut self, response: HttpResponse) -> Result<Step> {
+        self.state = State::Done;
+        Ok(Step::Respond(self.cache.finish(&self.parts, response)?))
+    }
+
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        let options = &self.cache.options;
+        Ok(CachePolicy::new_options(
+            &options.variant_parts(&self.parts)?,
+            &response.parts()?,
+            SystemTime::now(),
+            options.cache_options.unwrap_or_default(),
+        ))
+    }
+
+    fn is_get_head(&self) -> bool {
+        self.parts.method == Method::GET || self.parts.method == Method::HEAD
+    }
+
+    fn url(&self) -> Result<Url> {
+        Ok(Url::parse(&self.parts.uri.to_string())?)
+    }
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Deleted => "deleted",
+            Self::LookedUp(_) => "looked-up",
+            Self::Fetched(_) => "fetched",
+            Self::FetchFailed(_) => "fetch-failed",
+            Self::Stored(_) => "stored",
+        }
+    }
+}
+
+impl UnexpectedEvent {
+    fn new(event: &'static str, state: &State) -> Self {
+        Self { event, state: state.name() }
+    }
+}